@@ -1,18 +1,60 @@
+pub mod revert;
 pub mod stablex_auction_element;
 pub mod stablex_contract;
+pub mod token_address;
+pub mod token_info;
 
 use crate::http::HttpFactory;
-use crate::transport::HttpTransport;
-use anyhow::Result;
+use crate::transport::{HttpTransport, NodeTransport, RetryPolicy};
+use anyhow::{ensure, Result};
 use ethcontract::contract::MethodDefaults;
 use ethcontract::{Account, PrivateKey};
 use std::time::Duration;
 
-pub type Web3 = ethcontract::web3::api::Web3<HttpTransport>;
+pub type Web3 = ethcontract::web3::api::Web3<NodeTransport>;
 
-pub fn web3_provider(http_factory: &HttpFactory, url: &str, timeout: Duration) -> Result<Web3> {
-    let http = HttpTransport::new(http_factory, url, timeout)?;
-    let web3 = Web3::new(http);
+/// Creates a new web3 provider for the specified node URL. Nodes with a `ws` or
+/// `wss` URL are connected to over WebSockets, all others over HTTP with the
+/// specified retry policy.
+pub fn web3_provider(
+    http_factory: &HttpFactory,
+    url: &str,
+    timeout: Duration,
+    rpc_log_max_body_length: Option<usize>,
+    retry_policy: RetryPolicy,
+) -> Result<Web3> {
+    let transport = NodeTransport::new(http_factory, url, timeout, rpc_log_max_body_length)?
+        .with_retry_policy(retry_policy);
+    let web3 = Web3::new(transport);
+
+    Ok(web3)
+}
+
+/// Creates a new web3 provider that connects to the first of the specified
+/// node URLs over HTTP and fails over to the following ones when a node can't
+/// be connected to.
+pub fn web3_provider_with_failover(
+    http_factory: &HttpFactory,
+    urls: Vec<String>,
+    failover_cooldown: Duration,
+    timeout: Duration,
+    rpc_log_max_body_length: Option<usize>,
+    retry_policy: RetryPolicy,
+) -> Result<Web3> {
+    ensure!(
+        urls.iter()
+            .all(|url| !url.starts_with("ws://") && !url.starts_with("wss://")),
+        "failing over is only supported for HTTP nodes"
+    );
+    let transport = HttpTransport::with_failover(
+        http_factory,
+        urls,
+        failover_cooldown,
+        timeout,
+        rpc_log_max_body_length,
+    )?
+    .with_retry_policy(retry_policy);
+    let web3 = Web3::new(NodeTransport::Http(transport));
 
     Ok(web3)
 }
@@ -27,3 +69,31 @@ fn method_defaults(key: PrivateKey, network_id: u64) -> Result<MethodDefaults> {
 
     Ok(defaults)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethcontract::web3::futures::Future as _;
+
+    #[test]
+    #[ignore]
+    fn online_websocket_connection() {
+        // Connect to a node over WebSockets, this test is ignored by default as
+        // it requires a running node. To run this test against a local node:
+        // ```
+        // NODE_WS_URL=ws://localhost:8546 cargo test online_websocket_connection -- --ignored --nocapture
+        // ```
+
+        let url = std::env::var("NODE_WS_URL").unwrap_or_else(|_| "ws://localhost:8546".into());
+        let web3 = web3_provider(
+            &HttpFactory::default(),
+            &url,
+            Duration::from_secs(10),
+            None,
+            RetryPolicy::default(),
+        )
+        .unwrap();
+        let block_number = web3.eth().block_number().wait().unwrap();
+        println!("{:?}", block_number);
+    }
+}
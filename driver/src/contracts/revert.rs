@@ -0,0 +1,202 @@
+//! Module for extracting the human-readable reason from reverted contract
+//! calls and transactions.
+
+use ethcontract::{
+    errors::{ExecutionError, MethodError},
+    jsonrpc::types::Error as RpcError,
+    web3::Error as Web3Error,
+    U256,
+};
+use rustc_hex::{FromHex, ToHex};
+use std::fmt::{self, Display, Formatter};
+
+/// The function selector of `Error(string)` that prefixes revert data.
+pub const REVERT_REASON_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// The data a contract reverted with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RevertReason {
+    /// The message of a standard `Error(string)` revert.
+    Message(String),
+    /// Revert data that is not a standard `Error(string)`, for example a
+    /// custom error or a truncated payload.
+    Raw(Vec<u8>),
+}
+
+impl Display for RevertReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RevertReason::Message(message) => f.write_str(message),
+            RevertReason::Raw(data) => write!(f, "0x{}", data.to_hex::<String>()),
+        }
+    }
+}
+
+/// Decodes revert data, falling back to the raw bytes if the data is not an
+/// ABI encoded `Error(string)`.
+pub fn decode_revert_data(data: &[u8]) -> RevertReason {
+    match decode_error_string(data) {
+        Some(message) => RevertReason::Message(message),
+        None => RevertReason::Raw(data.to_vec()),
+    }
+}
+
+/// Decodes the reason string from ABI encoded `Error(string)` revert data.
+/// Returns `None` if the data is not a revert reason.
+pub fn decode_error_string(data: &[u8]) -> Option<String> {
+    if data.len() < 4 || data[..4] != REVERT_REASON_SELECTOR {
+        return None;
+    }
+    let data = &data[4..];
+    let read_usize = |offset: usize| -> Option<usize> {
+        let word = data.get(offset..offset.checked_add(32)?)?;
+        let value = U256::from_big_endian(word);
+        if value > U256::from(usize::max_value()) {
+            return None;
+        }
+        Some(value.as_usize())
+    };
+
+    let offset = read_usize(0)?;
+    let length = read_usize(offset)?;
+    let start = offset.checked_add(32)?;
+    let reason = data.get(start..start.checked_add(length)?)?;
+    String::from_utf8(reason.to_vec()).ok()
+}
+
+/// Extracts the revert reason from the hex encoded data of an RPC error, as
+/// returned by nodes for reverted `eth_call` and `eth_estimateGas` requests.
+pub fn rpc_revert_reason(err: &RpcError) -> Option<RevertReason> {
+    let data = err
+        .data
+        .as_ref()?
+        .as_str()?
+        .trim_start_matches("0x")
+        .from_hex::<Vec<u8>>()
+        .ok()?;
+    if data.is_empty() {
+        return None;
+    }
+    Some(decode_revert_data(&data))
+}
+
+/// Extracts the revert reason from an execution error, if there is one.
+pub fn revert_reason(err: &ExecutionError) -> Option<RevertReason> {
+    match err {
+        ExecutionError::Revert(Some(message)) => Some(RevertReason::Message(message.clone())),
+        ExecutionError::Web3(Web3Error::Rpc(err)) => rpc_revert_reason(err),
+        _ => None,
+    }
+}
+
+/// Normalizes a method error so that reverts whose data was only included in
+/// the underlying RPC error are reported as `ExecutionError::Revert` with the
+/// decoded reason. This applies to both view queries and transactions.
+pub fn with_revert_reason(err: MethodError) -> MethodError {
+    if let ExecutionError::Revert(Some(_)) = err.inner {
+        return err;
+    }
+    match revert_reason(&err.inner) {
+        Some(reason) => MethodError::from_parts(
+            err.signature,
+            ExecutionError::Revert(Some(reason.to_string())),
+        ),
+        None => err,
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use super::*;
+
+    /// ABI encodes a reason string as `Error(string)` revert data.
+    pub fn encode_revert_reason(reason: &str) -> Vec<u8> {
+        let mut data = REVERT_REASON_SELECTOR.to_vec();
+        let mut word = [0u8; 32];
+        U256::from(32).to_big_endian(&mut word);
+        data.extend_from_slice(&word);
+        U256::from(reason.len()).to_big_endian(&mut word);
+        data.extend_from_slice(&word);
+        data.extend_from_slice(reason.as_bytes());
+        data.resize(data.len() + (32 - reason.len() % 32) % 32, 0);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::encode_revert_reason;
+    use super::*;
+    use ethcontract::jsonrpc::types::ErrorCode;
+    use serde_json::json;
+
+    fn rpc_error(data: Option<&str>) -> MethodError {
+        MethodError::from_parts(
+            "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
+                .to_owned(),
+            ExecutionError::Web3(Web3Error::Rpc(RpcError {
+                code: ErrorCode::ServerError(-32000),
+                message: "execution reverted".to_owned(),
+                data: data.map(|data| json!(data)),
+            })),
+        )
+    }
+
+    #[test]
+    fn decodes_revert_reason() {
+        let reason = "SafeMath: subtraction overflow";
+        assert_eq!(
+            decode_error_string(&encode_revert_reason(reason)),
+            Some(reason.to_owned())
+        );
+        assert_eq!(
+            decode_revert_data(&encode_revert_reason(reason)),
+            RevertReason::Message(reason.to_owned())
+        );
+        assert_eq!(decode_error_string(&[0u8; 32]), None);
+        assert_eq!(decode_error_string(&REVERT_REASON_SELECTOR), None);
+    }
+
+    #[test]
+    fn non_standard_revert_data_degrades_to_raw_bytes() {
+        let reason = decode_revert_data(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(reason, RevertReason::Raw(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(reason.to_string(), "0xdeadbeef");
+
+        let truncated = &encode_revert_reason("truncated")[..40];
+        assert_eq!(
+            decode_revert_data(truncated),
+            RevertReason::Raw(truncated.to_vec())
+        );
+    }
+
+    #[test]
+    fn attaches_revert_reason_to_method_error() {
+        let hex: String = encode_revert_reason("not enough balance").to_hex();
+        let err = with_revert_reason(rpc_error(Some(&format!("0x{}", hex))));
+        match err.inner {
+            ExecutionError::Revert(Some(reason)) => assert_eq!(reason, "not enough balance"),
+            inner => panic!("unexpected error {:?}", inner),
+        }
+
+        let err = with_revert_reason(rpc_error(Some("0x12345678")));
+        match err.inner {
+            ExecutionError::Revert(Some(reason)) => assert_eq!(reason, "0x12345678"),
+            inner => panic!("unexpected error {:?}", inner),
+        }
+    }
+
+    #[test]
+    fn leaves_errors_without_revert_data_untouched() {
+        let err = with_revert_reason(rpc_error(None));
+        assert!(matches!(err.inner, ExecutionError::Web3(_)));
+
+        let err = with_revert_reason(MethodError::from_parts(
+            String::new(),
+            ExecutionError::Revert(Some("reason".to_owned())),
+        ));
+        assert!(
+            matches!(err.inner, ExecutionError::Revert(Some(ref reason)) if reason == "reason")
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::models::Order;
 use byteorder::{BigEndian, ByteOrder};
 use ethcontract::{Address, U256};
+use thiserror::Error;
 
 use crate::util::CeiledDiv;
 
@@ -10,69 +11,118 @@ pub const INDEXED_AUCTION_ELEMENT_WIDTH: usize = AUCTION_ELEMENT_WIDTH + 2;
 
 #[derive(Debug, PartialEq)]
 pub struct StableXAuctionElement {
-    valid_from: U256,
-    valid_until: U256,
     pub sell_token_balance: u128,
     pub order: Order,
 }
 
 impl StableXAuctionElement {
     pub fn in_auction(&self, index: U256) -> bool {
-        self.valid_from <= index && index <= self.valid_until
+        index <= U256::from(u32::max_value()) && self.order.is_valid_at(index.low_u32())
     }
 
     /// Deserialize an auction element that has been serialized by the smart
     /// contract's `encodeAuctionElement` function.
     /// Sets `id` to `0` because this information is not contained in the
     /// serialized information.
-    pub fn from_bytes(bytes: &[u8; AUCTION_ELEMENT_WIDTH]) -> Self {
-        let mut indexed_bytes = [0u8; INDEXED_AUCTION_ELEMENT_WIDTH];
-        indexed_bytes[0..AUCTION_ELEMENT_WIDTH].copy_from_slice(bytes);
-        Self::from_indexed_bytes(&indexed_bytes)
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        check_length(bytes, AUCTION_ELEMENT_WIDTH)?;
+        Self::decode_fields(bytes, 0)
     }
 
     /// Deserialize an auction element that has been serialized by the smart
     /// contract's `getFilteredOrdersPaginated` function.
-    pub fn from_indexed_bytes(bytes: &[u8; INDEXED_AUCTION_ELEMENT_WIDTH]) -> Self {
+    pub fn decode_indexed(bytes: &[u8]) -> Result<Self, DecodeError> {
+        check_length(bytes, INDEXED_AUCTION_ELEMENT_WIDTH)?;
+        let id = u16::from_le_bytes([bytes[113], bytes[112]]);
+        Self::decode_fields(bytes, id)
+    }
+
+    /// Serialize an auction element into the layout emitted by the smart
+    /// contract's `encodeAuctionElement` function, the inverse of `decode`.
+    ///
+    /// Since decoding only keeps the remaining buy and sell amounts, the order
+    /// is encoded with its buy and sell amounts as price numerator and
+    /// denominator and its sell amount as the remaining amount. Re-encoding a
+    /// decoded element therefore only reproduces the original bytes if they
+    /// were in this form.
+    #[cfg(test)]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; AUCTION_ELEMENT_WIDTH];
+        bytes[0..20].copy_from_slice(self.order.account_id.as_bytes());
+        BigEndian::write_u128(&mut bytes[36..52], self.sell_token_balance);
+        BigEndian::write_u16(&mut bytes[52..54], self.order.buy_token);
+        BigEndian::write_u16(&mut bytes[54..56], self.order.sell_token);
+        BigEndian::write_u32(&mut bytes[56..60], self.order.valid_from);
+        BigEndian::write_u32(&mut bytes[60..64], self.order.valid_until);
+        BigEndian::write_u128(&mut bytes[64..80], self.order.buy_amount);
+        BigEndian::write_u128(&mut bytes[80..96], self.order.sell_amount);
+        BigEndian::write_u128(&mut bytes[96..112], self.order.sell_amount);
+        bytes
+    }
+
+    /// Decodes the fields shared by both auction element encodings. The
+    /// length of `bytes` must already have been checked.
+    fn decode_fields(bytes: &[u8], id: u16) -> Result<Self, DecodeError> {
         let account_id = Address::from_slice(&bytes[0..20]);
 
         // these go together (since sell_token_balance is emitted as u256 and treated as u128
         let sell_token_balance = BigEndian::read_u128(&bytes[36..52]);
         let sell_token_balance_padding = BigEndian::read_u128(&bytes[20..36]);
-        assert_eq!(
-            sell_token_balance_padding, 0,
-            "User has too large balance to handle."
-        );
+        if sell_token_balance_padding != 0 {
+            return Err(DecodeError::BalanceOverflow);
+        }
 
         let buy_token = u16::from_le_bytes([bytes[53], bytes[52]]);
         let sell_token = u16::from_le_bytes([bytes[55], bytes[54]]);
-        let valid_from = U256::from(u32::from_le_bytes([
-            bytes[59], bytes[58], bytes[57], bytes[56],
-        ]));
-        let valid_until = U256::from(u32::from_le_bytes([
-            bytes[63], bytes[62], bytes[61], bytes[60],
-        ]));
+        let valid_from = u32::from_le_bytes([bytes[59], bytes[58], bytes[57], bytes[56]]);
+        let valid_until = u32::from_le_bytes([bytes[63], bytes[62], bytes[61], bytes[60]]);
         let numerator = BigEndian::read_u128(&bytes[64..80]);
         let denominator = BigEndian::read_u128(&bytes[80..96]);
         let remaining = BigEndian::read_u128(&bytes[96..112]);
-        let id = u16::from_le_bytes([bytes[113], bytes[112]]);
+        if remaining > denominator {
+            return Err(DecodeError::RemainingExceedsDenominator {
+                remaining,
+                denominator,
+            });
+        }
+
         let (buy_amount, sell_amount) = compute_buy_sell_amounts(numerator, denominator, remaining);
-        StableXAuctionElement {
-            valid_from,
-            valid_until,
+        Ok(StableXAuctionElement {
             sell_token_balance,
-            order: Order {
-                id,
-                account_id,
-                buy_token,
-                sell_token,
-                buy_amount,
-                sell_amount,
-            },
-        }
+            order: Order::builder()
+                .id(id)
+                .account_id(account_id)
+                .buy_token(buy_token)
+                .sell_token(sell_token)
+                .buy_amount(buy_amount)
+                .sell_amount(sell_amount)
+                .validity(valid_from, valid_until)
+                .build(),
+        })
     }
 }
 
+/// An error decoding a serialized auction element.
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum DecodeError {
+    #[error("auction element has {actual} bytes but expected {expected}")]
+    InvalidLength { expected: usize, actual: usize },
+    #[error("user has too large balance to handle")]
+    BalanceOverflow,
+    #[error("remaining amount {remaining} exceeds price denominator {denominator}")]
+    RemainingExceedsDenominator { remaining: u128, denominator: u128 },
+}
+
+fn check_length(bytes: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if bytes.len() != expected {
+        return Err(DecodeError::InvalidLength {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    Ok(())
+}
+
 pub fn compute_buy_sell_amounts(
     numerator: u128,
     denominator: u128,
@@ -100,8 +150,6 @@ pub mod tests {
 
     fn emptyish_auction_element() -> StableXAuctionElement {
         StableXAuctionElement {
-            valid_from: U256::from(0),
-            valid_until: U256::from(0),
             sell_token_balance: 0,
             order: Order {
                 id: 0,
@@ -110,13 +158,15 @@ pub mod tests {
                 sell_token: 0,
                 buy_amount: 0,
                 sell_amount: 0,
+                valid_from: 0,
+                valid_until: 0,
             },
         }
     }
 
     #[test]
     fn null_auction_element_from_bytes() {
-        let res = StableXAuctionElement::from_bytes(&[0u8; 112]);
+        let res = StableXAuctionElement::decode(&[0u8; 112]).unwrap();
 
         assert_eq!(res, emptyish_auction_element());
     }
@@ -143,10 +193,8 @@ pub mod tests {
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 3, // priceDenominator: 259
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, // remainingAmount: 2**8 + 1 = 257
         ];
-        let res = StableXAuctionElement::from_bytes(&bytes);
+        let res = StableXAuctionElement::decode(&bytes).unwrap();
         let auction_element = StableXAuctionElement {
-            valid_from: U256::from(2),
-            valid_until: U256::from(261),
             sell_token_balance: 3,
             order: Order {
                 id: 0,
@@ -155,11 +203,47 @@ pub mod tests {
                 sell_token: 257,
                 buy_amount: (258 * 257 + 258) / 259,
                 sell_amount: 257,
+                valid_from: 2,
+                valid_until: 261,
             },
         };
         assert_eq!(res, auction_element);
     }
 
+    #[test]
+    fn encode_is_inverse_of_decode() {
+        let bytes: [u8; 112] = [
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // user: 20 elements
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 3, // sellTokenBalance: 3, 32 elements
+            1, 2, // buyToken: 256+2,
+            1, 1, // sellToken: 256+1,
+            0, 0, 0, 2, // validFrom: 2
+            0, 0, 1, 5, // validUntil: 256+5
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, // priceNumerator: 258
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 3, // priceDenominator: 259
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 3, // remainingAmount: 259
+        ];
+        let element = StableXAuctionElement::decode(&bytes).unwrap();
+        assert_eq!(element.encode(), bytes.to_vec());
+        assert_eq!(
+            StableXAuctionElement::decode(&element.encode()).unwrap(),
+            element
+        );
+
+        let mut element = emptyish_auction_element();
+        element.sell_token_balance = u128::max_value();
+        element.order.account_id = Address::repeat_byte(0xff);
+        element.order.buy_token = u16::max_value();
+        element.order.sell_amount = u128::max_value();
+        element.order.buy_amount = 1;
+        element.order.valid_until = u32::max_value();
+        assert_eq!(
+            StableXAuctionElement::decode(&element.encode()).unwrap(),
+            element
+        );
+    }
+
     #[test]
     fn test_index_auction_element() {
         let bytes: [u8; 114] = [
@@ -175,10 +259,8 @@ pub mod tests {
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, // remainingAmount: 2**8 + 1 = 257
             0, 1, // order index
         ];
-        let res = StableXAuctionElement::from_indexed_bytes(&bytes);
+        let res = StableXAuctionElement::decode_indexed(&bytes).unwrap();
         let auction_element = StableXAuctionElement {
-            valid_from: U256::from(2),
-            valid_until: U256::from(261),
             sell_token_balance: 3,
             order: Order {
                 id: 1,
@@ -187,47 +269,83 @@ pub mod tests {
                 sell_token: 257,
                 buy_amount: (258 * 257 + 258) / 259,
                 sell_amount: 257,
+                valid_from: 2,
+                valid_until: 261,
             },
         };
         assert_eq!(res, auction_element);
     }
 
     #[test]
-    #[should_panic]
-    fn test_from_bytes_fails_on_hopefully_null() {
-        StableXAuctionElement::from_bytes(&[1u8; 112]);
+    fn test_decode_fails_on_hopefully_null() {
+        assert_eq!(
+            StableXAuctionElement::decode(&[1u8; 112]),
+            Err(DecodeError::BalanceOverflow)
+        );
+    }
+
+    #[test]
+    fn decode_fails_on_invalid_length() {
+        assert_eq!(
+            StableXAuctionElement::decode(&[0u8; 111]),
+            Err(DecodeError::InvalidLength {
+                expected: 112,
+                actual: 111
+            })
+        );
+        assert_eq!(
+            StableXAuctionElement::decode_indexed(&[0u8; 112]),
+            Err(DecodeError::InvalidLength {
+                expected: 114,
+                actual: 112
+            })
+        );
+    }
+
+    #[test]
+    fn decode_fails_on_remaining_exceeding_denominator() {
+        let mut bytes = [0u8; 112];
+        bytes[95] = 2; // priceDenominator: 2
+        bytes[111] = 3; // remainingAmount: 3
+        assert_eq!(
+            StableXAuctionElement::decode(&bytes),
+            Err(DecodeError::RemainingExceedsDenominator {
+                remaining: 3,
+                denominator: 2
+            })
+        );
     }
 
     // Testing in_auction
     #[test]
     fn not_in_auction_left() {
         let mut element = emptyish_auction_element();
-        element.valid_from = U256::from(2);
-        element.valid_until = U256::from(5);
+        element.order.valid_from = 2;
+        element.order.valid_until = 5;
         assert_eq!(element.in_auction(U256::from(1)), false);
     }
 
     #[test]
     fn not_in_auction_right() {
         let mut element = emptyish_auction_element();
-        element.valid_from = U256::from(2);
-        element.valid_until = U256::from(5);
+        element.order.valid_from = 2;
+        element.order.valid_until = 5;
         assert_eq!(element.in_auction(U256::from(6)), false);
     }
 
     #[test]
     fn in_auction_interior() {
         let mut element = emptyish_auction_element();
-        element.valid_from = U256::from(2);
-        element.valid_until = U256::from(5);
+        element.order.valid_from = 2;
+        element.order.valid_until = 5;
         assert_eq!(element.in_auction(U256::from(3)), true);
     }
 
     #[test]
     fn in_auction_boundary() {
         let mut element = emptyish_auction_element();
-        element.valid_from = U256::from(2);
-        element.valid_until = U256::from(5);
+        element.order.valid_from = 2;
+        element.order.valid_until = 5;
         assert_eq!(
             element.in_auction(U256::from(5)),
             true,
@@ -2,19 +2,24 @@
 #![cfg_attr(test, allow(clippy::ptr_arg))]
 
 use crate::{
-    contracts,
+    contracts::{self, revert},
     models::{ExecutedOrder, Solution},
+    transport::{NodeTransport, WebSocketTransport},
     util::FutureWaitExt,
 };
 use anyhow::{anyhow, Result};
 use ethcontract::{
-    contract::Event,
+    contract::{Event, EventData, EventMetadata, ParseLog as _},
     errors::{ExecutionError, MethodError},
     transaction::{confirm::ConfirmParams, GasPrice, ResolveCondition},
-    web3::{futures::Future as _, types::Block},
-    Address, BlockNumber, PrivateKey, U256,
+    web3::{
+        futures::Future as _,
+        types::{Block, CallRequest, FilterBuilder, Log, SyncState},
+    },
+    Address, BlockNumber, PrivateKey, RawLog, U256,
 };
 use futures::{
+    compat::Future01CompatExt as _,
     future::{BoxFuture, FutureExt},
     stream::{BoxStream, StreamExt},
 };
@@ -22,6 +27,7 @@ use lazy_static::lazy_static;
 #[cfg(test)]
 use mockall::automock;
 use std::collections::HashMap;
+use std::future::Future;
 use std::time::Duration;
 
 lazy_static! {
@@ -32,11 +38,26 @@ lazy_static! {
 
 include!(concat!(env!("OUT_DIR"), "/batch_exchange.rs"));
 include!(concat!(env!("OUT_DIR"), "/batch_exchange_viewer.rs"));
+include!(concat!(env!("OUT_DIR"), "/erc20_detailed.rs"));
 
 #[derive(Clone)]
 pub struct StableXContractImpl {
+    web3: contracts::Web3,
     instance: BatchExchange,
     viewer: BatchExchangeViewer,
+    confirmations: usize,
+    past_events_chunking: Option<PastEventsChunking>,
+}
+
+/// Configures fetching past events in chunks of blocks, for nodes that limit
+/// the block range or number of logs of a single `eth_getLogs` request.
+#[derive(Clone, Copy, Debug)]
+pub struct PastEventsChunking {
+    /// The block from which to fetch events, usually the block in which the
+    /// contract was deployed.
+    pub from_block: u64,
+    /// The maximum number of blocks to fetch events for in one request.
+    pub chunk_size: u64,
 }
 
 impl StableXContractImpl {
@@ -47,7 +68,48 @@ impl StableXContractImpl {
         let mut instance = BatchExchange::deployed(&web3).wait()?;
         *instance.defaults_mut() = defaults;
 
-        Ok(StableXContractImpl { instance, viewer })
+        Ok(StableXContractImpl {
+            web3: web3.clone(),
+            instance,
+            viewer,
+            confirmations: 0,
+            past_events_chunking: None,
+        })
+    }
+
+    /// Fetches past events in chunks of blocks instead of with a single
+    /// request for the whole history of the contract.
+    pub fn with_past_events_chunking(mut self, chunking: PastEventsChunking) -> Self {
+        self.past_events_chunking = Some(chunking);
+        self
+    }
+
+    /// Sets the number of blocks to wait for after a solution submission was
+    /// mined before considering it final.
+    pub fn with_confirmations(mut self, confirmations: usize) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Returns the parameters with which submitted transactions are confirmed.
+    fn confirm_params(&self, block_timeout: Option<usize>) -> ConfirmParams {
+        ConfirmParams {
+            confirmations: self.confirmations,
+            block_timeout,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the viewer contract instance using the specified timeout for
+    /// its calls, or the default instance if no timeout is specified.
+    fn viewer_with_timeout(&self, timeout: Option<Duration>) -> BatchExchangeViewer {
+        match timeout {
+            Some(timeout) => {
+                let web3 = contracts::Web3::new(self.web3.transport().with_timeout(timeout));
+                BatchExchangeViewer::at(&web3, self.viewer.address())
+            }
+            None => self.viewer.clone(),
+        }
     }
 
     pub fn account(&self) -> Address {
@@ -64,6 +126,15 @@ impl StableXContractImpl {
     }
 }
 
+/// The outcome of simulating a solution submission.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DryRunResult {
+    /// The solution would be accepted by the contract.
+    Accepted,
+    /// The submission would revert with the decoded revert reason, if any.
+    Reverted(Option<String>),
+}
+
 /// Information about an order page that where filtered
 /// was applied inside the smart contract.
 pub struct FilteredOrderPage {
@@ -82,6 +153,29 @@ pub trait StableXContract {
     /// Retrieve the time remaining in the batch.
     fn get_current_auction_remaining_time(&self) -> Result<Duration>;
 
+    /// Retrieve the current gas price of the node in wei.
+    fn get_gas_price(&self) -> Result<U256>;
+
+    /// Returns true if the node reports that it is still syncing, in which
+    /// case the state it returns may be stale.
+    fn is_syncing(&self) -> Result<bool>;
+
+    /// Retrieve the nonce to use for the next transaction of the submitting
+    /// account, taking pending transactions into account.
+    fn get_transaction_count(&self) -> Result<U256>;
+
+    /// Retrieve the number of the most recent block.
+    fn get_block_number(&self) -> Result<u64>;
+
+    /// Retrieve the address of the ERC20 token listed with the specified ID.
+    fn get_token_address(&self, token_id: u16) -> Result<Address>;
+
+    /// Retrieve the symbol of an ERC20 token.
+    fn get_token_symbol(&self, token: Address) -> Result<String>;
+
+    /// Retrieve the number of decimals of an ERC20 token.
+    fn get_token_decimals(&self, token: Address) -> Result<u8>;
+
     /// Searches for the block number of the last block of the given batch. If
     /// the batch has not yet been finalized, then `None` is returned.
     fn get_last_block_for_batch(&self, batch_id: u32) -> Result<Option<u64>>;
@@ -102,12 +196,15 @@ pub trait StableXContract {
     /// `block` is needed because the state of the smart contract could change
     /// between blocks which would make the returned auction data inconsistent
     /// between calls.
+    /// `timeout` overrides the default timeout of the node connection for this
+    /// call, as large pages can take significantly longer than other calls.
     fn get_auction_data_paginated(
         &self,
         page_size: u16,
         previous_page_user: Address,
         previous_page_user_offset: u16,
         block_number: Option<BlockNumber>,
+        timeout: Option<Duration>,
     ) -> Result<Vec<u8>>;
 
     fn get_solution_objective_value(
@@ -117,19 +214,45 @@ pub trait StableXContract {
         block_number: Option<BlockNumber>,
     ) -> Result<U256>;
 
+    /// Submits a solution with the specified nonce and gas price. Reusing the
+    /// nonce of a pending submission replaces it, so that only one of them can
+    /// be mined.
     fn submit_solution(
         &self,
         batch_index: U256,
         solution: Solution,
         claimed_objective_value: U256,
+        nonce: U256,
         gas_price: U256,
         block_timeout: Option<usize>,
     ) -> Result<(), MethodError>;
 
+    /// Simulates submitting a solution with an `eth_call` against the pending
+    /// block, without broadcasting a transaction.
+    fn submit_solution_dry_run(
+        &self,
+        batch_index: U256,
+        solution: Solution,
+        claimed_objective_value: U256,
+    ) -> Result<DryRunResult>;
+
     fn past_events(
         &self,
     ) -> BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>, ExecutionError>>;
 
+    /// Retrieve all events emitted up to and including the specified block.
+    fn past_events_until(
+        &self,
+        to_block: u64,
+    ) -> BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>, ExecutionError>>;
+
+    /// Retrieve the events emitted in the specified inclusive range of blocks.
+    fn past_events_in_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>, ExecutionError>>;
+
     fn stream_events(
         &self,
     ) -> BoxStream<'static, Result<Event<batch_exchange::Event>, ExecutionError>>;
@@ -150,6 +273,58 @@ impl StableXContract for StableXContractImpl {
         Ok(Duration::from_secs(remaining_seconds.as_u64()))
     }
 
+    fn get_gas_price(&self) -> Result<U256> {
+        let gas_price = self.web3.eth().gas_price().wait()?;
+        Ok(gas_price)
+    }
+
+    fn is_syncing(&self) -> Result<bool> {
+        let sync_state = self.web3.eth().syncing().wait()?;
+        Ok(match sync_state {
+            SyncState::Syncing(_) => true,
+            SyncState::NotSyncing => false,
+        })
+    }
+
+    fn get_transaction_count(&self) -> Result<U256> {
+        let nonce = self
+            .web3
+            .eth()
+            .transaction_count(self.account(), Some(BlockNumber::Pending))
+            .wait()?;
+        Ok(nonce)
+    }
+
+    fn get_block_number(&self) -> Result<u64> {
+        let block_number = self.web3.eth().block_number().wait()?;
+        Ok(block_number.as_u64())
+    }
+
+    fn get_token_address(&self, token_id: u16) -> Result<Address> {
+        let address = self
+            .instance
+            .token_id_to_address_map(token_id)
+            .call()
+            .wait()?;
+        Ok(address)
+    }
+
+    fn get_token_symbol(&self, token: Address) -> Result<String> {
+        let symbol = ERC20Detailed::at(&self.web3, token)
+            .symbol()
+            .call()
+            .wait()?;
+        Ok(symbol)
+    }
+
+    fn get_token_decimals(&self, token: Address) -> Result<u8> {
+        let decimals = ERC20Detailed::at(&self.web3, token)
+            .decimals()
+            .call()
+            .wait()?;
+        Ok(decimals)
+    }
+
     fn get_last_block_for_batch(&self, batch_id: u32) -> Result<Option<u64>> {
         let web3 = self.instance.raw_instance().web3();
         let get_block = |block_number: BlockNumber| -> Result<_> {
@@ -201,7 +376,7 @@ impl StableXContract for StableXContractImpl {
         builder.block = block_number;
         builder.m.tx.gas = None;
         let (indexed_elements, has_next_page, next_page_user, next_page_user_offset) =
-            builder.call().wait()?;
+            builder.call().wait().map_err(revert::with_revert_reason)?;
         Ok(FilteredOrderPage {
             indexed_elements,
             has_next_page,
@@ -216,15 +391,20 @@ impl StableXContract for StableXContractImpl {
         previous_page_user: Address,
         previous_page_user_offset: u16,
         block_number: Option<BlockNumber>,
+        timeout: Option<Duration>,
     ) -> Result<Vec<u8>> {
-        let mut orders_builder = self.viewer.get_encoded_orders_paginated(
+        let viewer = self.viewer_with_timeout(timeout);
+        let mut orders_builder = viewer.get_encoded_orders_paginated(
             previous_page_user,
             previous_page_user_offset,
             U256::from(page_size),
         );
         orders_builder.block = block_number;
         orders_builder.m.tx.gas = None;
-        orders_builder.call().wait().map_err(From::from)
+        orders_builder
+            .call()
+            .wait()
+            .map_err(|err| revert::with_revert_reason(err).into())
     }
 
     fn get_solution_objective_value(
@@ -248,7 +428,7 @@ impl StableXContract for StableXContractImpl {
             )
             .view();
         builder.block = block_number;
-        let objective_value = builder.call().wait()?;
+        let objective_value = builder.call().wait().map_err(revert::with_revert_reason)?;
         Ok(objective_value)
     }
 
@@ -257,6 +437,7 @@ impl StableXContract for StableXContractImpl {
         batch_index: U256,
         solution: Solution,
         claimed_objective_value: U256,
+        nonce: U256,
         gas_price: U256,
         block_timeout: Option<usize>,
     ) -> Result<(), MethodError> {
@@ -273,28 +454,122 @@ impl StableXContract for StableXContractImpl {
                 prices,
                 token_ids_for_price,
             )
+            .nonce(nonce)
             .gas_price(GasPrice::Value(gas_price))
             // NOTE: Gas estimate might be off, as we race with other solution
             //   submissions and thus might have to revert trades which costs
             //   more gas than expected.
             .gas(5_500_000.into());
 
-        method.tx.resolve = Some(ResolveCondition::Confirmed(ConfirmParams {
-            block_timeout,
-            ..Default::default()
-        }));
-        method.send().wait()?;
+        method.tx.resolve = Some(ResolveCondition::Confirmed(
+            self.confirm_params(block_timeout),
+        ));
+        method.send().wait().map_err(revert::with_revert_reason)?;
 
         Ok(())
     }
 
+    fn submit_solution_dry_run(
+        &self,
+        batch_index: U256,
+        solution: Solution,
+        claimed_objective_value: U256,
+    ) -> Result<DryRunResult> {
+        let (prices, token_ids_for_price) = encode_prices_for_contract(&solution.prices);
+        let (owners, order_ids, volumes) = encode_execution_for_contract(&solution.executed_orders);
+        let method = self.instance.submit_solution(
+            batch_index.low_u32(),
+            claimed_objective_value,
+            owners,
+            order_ids,
+            volumes,
+            prices,
+            token_ids_for_price,
+        );
+        let call = CallRequest {
+            from: Some(self.account()),
+            to: self.address(),
+            gas: None,
+            gas_price: None,
+            value: None,
+            data: method.tx.data,
+        };
+
+        match self
+            .web3
+            .eth()
+            .call(call, Some(BlockNumber::Pending))
+            .wait()
+        {
+            // NOTE: Geth returns the revert data as the call result.
+            Ok(output) => Ok(match revert::decode_error_string(&output.0) {
+                Some(reason) => DryRunResult::Reverted(Some(reason)),
+                None => DryRunResult::Accepted,
+            }),
+            // NOTE: Ganache and Parity return reverts as RPC errors.
+            Err(ethcontract::web3::Error::Rpc(err)) if err.message.contains("revert") => {
+                let reason = revert::rpc_revert_reason(&err).map(|reason| reason.to_string());
+                Ok(DryRunResult::Reverted(reason))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
     fn past_events(
         &self,
+    ) -> BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>, ExecutionError>> {
+        let chunking = match self.past_events_chunking {
+            Some(chunking) => chunking,
+            None => {
+                return self
+                    .instance
+                    .all_events()
+                    .from_block(ethcontract::BlockNumber::Earliest)
+                    .to_block(ethcontract::BlockNumber::Latest)
+                    .query_past_events_paginated()
+                    .boxed();
+            }
+        };
+
+        let web3 = self.web3.clone();
+        let instance = self.instance.clone();
+        async move {
+            let latest_block = web3.eth().block_number().compat().await?.as_u64();
+            query_past_events_in_chunks(&instance, chunking, latest_block).await
+        }
+        .boxed()
+    }
+
+    fn past_events_until(
+        &self,
+        to_block: u64,
+    ) -> BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>, ExecutionError>> {
+        let chunking = match self.past_events_chunking {
+            Some(chunking) => chunking,
+            None => {
+                return self
+                    .instance
+                    .all_events()
+                    .from_block(ethcontract::BlockNumber::Earliest)
+                    .to_block(BlockNumber::from(to_block))
+                    .query_past_events_paginated()
+                    .boxed();
+            }
+        };
+
+        let instance = self.instance.clone();
+        async move { query_past_events_in_chunks(&instance, chunking, to_block).await }.boxed()
+    }
+
+    fn past_events_in_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
     ) -> BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>, ExecutionError>> {
         self.instance
             .all_events()
-            .from_block(ethcontract::BlockNumber::Earliest)
-            .to_block(ethcontract::BlockNumber::Latest)
+            .from_block(BlockNumber::from(from_block))
+            .to_block(BlockNumber::from(to_block))
             .query_past_events_paginated()
             .boxed()
     }
@@ -302,6 +577,9 @@ impl StableXContract for StableXContractImpl {
     fn stream_events(
         &self,
     ) -> BoxStream<'static, Result<Event<batch_exchange::Event>, ExecutionError>> {
+        if let NodeTransport::WebSocket(transport) = self.web3.transport() {
+            return subscribe_events(transport, self.address());
+        }
         self.instance
             .all_events()
             .from_block(BlockNumber::Latest)
@@ -310,6 +588,106 @@ impl StableXContract for StableXContractImpl {
     }
 }
 
+/// Subscribes to the events of the contract at the specified address over a
+/// WebSocket connection, so that new events are pushed by the node instead of
+/// being polled for with a filter.
+fn subscribe_events(
+    transport: &WebSocketTransport,
+    address: Address,
+) -> BoxStream<'static, Result<Event<batch_exchange::Event>, ExecutionError>> {
+    let filter = FilterBuilder::default().address(vec![address]).build();
+    transport
+        .subscribe_logs(filter)
+        .map(|log| event_from_log(log?))
+        .boxed()
+}
+
+/// Parses a log of the contract into an event. Logs that were removed because
+/// of a reorg result in removed events.
+fn event_from_log(log: Log) -> Result<Event<batch_exchange::Event>, ExecutionError> {
+    let meta = match (
+        log.block_hash,
+        log.block_number,
+        log.transaction_hash,
+        log.transaction_index,
+        log.log_index,
+    ) {
+        (
+            Some(block_hash),
+            Some(block_number),
+            Some(transaction_hash),
+            Some(transaction_index),
+            Some(log_index),
+        ) => Some(EventMetadata {
+            block_hash,
+            block_number: block_number.as_u64(),
+            transaction_hash,
+            transaction_index: transaction_index.as_usize(),
+            log_index: log_index.as_usize(),
+            transaction_log_index: log.transaction_log_index.map(|index| index.as_usize()),
+            log_type: log.log_type.clone(),
+        }),
+        _ => None,
+    };
+    let removed = log.removed == Some(true);
+    let data = batch_exchange::Event::parse_log(RawLog {
+        topics: log.topics,
+        data: log.data.0,
+    })?;
+    let data = if removed {
+        EventData::Removed(data)
+    } else {
+        EventData::Added(data)
+    };
+    Ok(Event { data, meta })
+}
+
+/// Queries all events of the contract up to and including `to_block` in
+/// chunks of blocks.
+async fn query_past_events_in_chunks(
+    instance: &BatchExchange,
+    chunking: PastEventsChunking,
+    to_block: u64,
+) -> Result<Vec<Event<batch_exchange::Event>>, ExecutionError> {
+    query_in_chunks(chunking, to_block, |from_block, to_block| {
+        instance
+            .all_events()
+            .from_block(BlockNumber::from(from_block))
+            .to_block(BlockNumber::from(to_block))
+            .query_past_events_paginated()
+    })
+    .await
+}
+
+/// Queries items for consecutive, inclusive block ranges of at most the
+/// configured chunk size up to and including `latest_block`, and concatenates
+/// the results.
+async fn query_in_chunks<T, F, Fut>(
+    chunking: PastEventsChunking,
+    latest_block: u64,
+    mut query: F,
+) -> Result<Vec<T>, ExecutionError>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, ExecutionError>>,
+{
+    let chunk_size = chunking.chunk_size.max(1);
+    let mut items = Vec::new();
+    let mut from_block = chunking.from_block;
+    while from_block <= latest_block {
+        let to_block = from_block.saturating_add(chunk_size - 1).min(latest_block);
+        items.extend(query(from_block, to_block).await?);
+        log::info!(
+            "fetched past events up to block {} of {}, {} events so far",
+            to_block,
+            latest_block,
+            items.len()
+        );
+        from_block = to_block + 1;
+    }
+    Ok(items)
+}
+
 fn encode_prices_for_contract(price_map: &HashMap<u16, u128>) -> (Vec<u128>, Vec<u16>) {
     // Representing the solution's price vector as:
     // sorted_touched_token_ids, non_zero_prices (excluding price at token with id 0)
@@ -352,7 +730,142 @@ fn get_block_batch_id<T>(block: &Block<T>) -> u32 {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::contracts::revert::test_util::encode_revert_reason;
+    use crate::http::HttpFactory;
+    use crate::transport::{test_util::mock_node, RetryPolicy};
     use crate::util::test_util::map_from_slice;
+    use rustc_hex::ToHex;
+
+    /// Creates a contract instance that is connected to a mock node that
+    /// responds to one request with the specified response.
+    fn contract_with_mock_node(response: &str) -> StableXContractImpl {
+        let url = mock_node(response);
+        let web3 = contracts::web3_provider(
+            &HttpFactory::default(),
+            &url,
+            Duration::from_secs(10),
+            None,
+            RetryPolicy::default(),
+        )
+        .unwrap();
+        StableXContractImpl {
+            instance: BatchExchange::at(&web3, Address::from_low_u64_be(1)),
+            viewer: BatchExchangeViewer::at(&web3, Address::from_low_u64_be(2)),
+            web3,
+            confirmations: 0,
+            past_events_chunking: None,
+        }
+    }
+
+    #[test]
+    fn dry_run_surfaces_revert_reason() {
+        let reason = "Claimed objective doesn't sufficiently improve current solution";
+        let hex: String = encode_revert_reason(reason).to_hex();
+        let contract = contract_with_mock_node(&format!(
+            r#"{{"jsonrpc":"2.0","id":0,"result":"0x{}"}}"#,
+            hex
+        ));
+
+        let result = contract
+            .submit_solution_dry_run(U256::one(), Solution::trivial(), U256::one())
+            .unwrap();
+        assert_eq!(result, DryRunResult::Reverted(Some(reason.to_owned())));
+    }
+
+    #[test]
+    fn dry_run_surfaces_rpc_revert_error() {
+        let contract = contract_with_mock_node(
+            r#"{"jsonrpc":"2.0","id":0,"error":{"code":-32000,"message":"VM Exception while processing transaction: revert"}}"#,
+        );
+
+        let result = contract
+            .submit_solution_dry_run(U256::one(), Solution::trivial(), U256::one())
+            .unwrap();
+        assert_eq!(result, DryRunResult::Reverted(None));
+    }
+
+    #[test]
+    fn reports_syncing_node() {
+        let contract = contract_with_mock_node(
+            r#"{"jsonrpc":"2.0","id":0,"result":{"startingBlock":"0x0","currentBlock":"0x10","highestBlock":"0x100"}}"#,
+        );
+        assert!(contract.is_syncing().unwrap());
+
+        let contract = contract_with_mock_node(r#"{"jsonrpc":"2.0","id":0,"result":false}"#);
+        assert!(!contract.is_syncing().unwrap());
+    }
+
+    #[test]
+    fn dry_run_accepts_solution() {
+        let contract = contract_with_mock_node(
+            r#"{"jsonrpc":"2.0","id":0,"result":"0x000000000000000000000000000000000000000000000000000000000000002a"}"#,
+        );
+
+        let result = contract
+            .submit_solution_dry_run(U256::one(), Solution::trivial(), U256::one())
+            .unwrap();
+        assert_eq!(result, DryRunResult::Accepted);
+    }
+
+    #[test]
+    fn submissions_wait_for_configured_confirmations() {
+        let contract = contract_with_mock_node("").with_confirmations(3);
+        let params = contract.confirm_params(Some(10));
+        assert_eq!(params.confirmations, 3);
+        assert_eq!(params.block_timeout, Some(10));
+
+        let params = contract.with_confirmations(0).confirm_params(None);
+        assert_eq!(params.confirmations, 0);
+        assert_eq!(params.block_timeout, None);
+    }
+
+    fn too_many_blocks() -> ExecutionError {
+        ExecutionError::Web3(ethcontract::web3::Error::Transport(
+            "too many blocks".to_owned(),
+        ))
+    }
+
+    #[test]
+    fn queries_past_events_in_chunks() {
+        let chunking = PastEventsChunking {
+            from_block: 5,
+            chunk_size: 10,
+        };
+        let mut ranges = Vec::new();
+        let blocks = query_in_chunks(chunking, 32, |from_block, to_block| {
+            ranges.push((from_block, to_block));
+            // Mimic a node that rejects requests for too many blocks.
+            let result = if to_block - from_block >= 10 {
+                Err(too_many_blocks())
+            } else {
+                Ok((from_block..=to_block).collect::<Vec<_>>())
+            };
+            async move { result }
+        })
+        .wait()
+        .unwrap();
+
+        assert_eq!(ranges, vec![(5, 14), (15, 24), (25, 32)]);
+        assert_eq!(blocks, (5..=32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn chunked_query_fails_if_chunk_is_rejected() {
+        let chunking = PastEventsChunking {
+            from_block: 0,
+            chunk_size: 100,
+        };
+        let result = query_in_chunks(chunking, 32, |from_block, to_block| {
+            let result = if to_block - from_block >= 10 {
+                Err(too_many_blocks())
+            } else {
+                Ok(Vec::<u64>::new())
+            };
+            async move { result }
+        })
+        .wait();
+        assert!(result.is_err());
+    }
 
     #[test]
     fn generic_encode_execution_test() {
@@ -0,0 +1,119 @@
+//! Module for resolving the token IDs used by the exchange to the addresses of
+//! the ERC20 tokens they represent.
+
+use crate::contracts::stablex_contract::StableXContract;
+use crate::models::TokenId;
+use anyhow::{Context, Result};
+use ethcontract::Address;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Resolves token IDs to token addresses by reading the exchange's
+/// `tokenIdToAddressMap` and caches the results. Since listed tokens can
+/// never be changed or removed, cached addresses never become stale.
+pub struct TokenAddressResolver {
+    contract: Arc<dyn StableXContract + Send + Sync>,
+    cache: Mutex<HashMap<TokenId, Address>>,
+}
+
+impl TokenAddressResolver {
+    pub fn new(contract: Arc<dyn StableXContract + Send + Sync>) -> Self {
+        TokenAddressResolver {
+            contract,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the address of the token with the specified ID, reading it from
+    /// the exchange if it is not yet cached.
+    pub fn get_token_address(&self, token_id: TokenId) -> Result<Address> {
+        if let Some(address) = self.cache().get(&token_id) {
+            return Ok(*address);
+        }
+
+        let address = self
+            .contract
+            .get_token_address(token_id.0)
+            .with_context(|| format!("failed to resolve address of token {:?}", token_id))?;
+        self.cache().insert(token_id, address);
+        Ok(address)
+    }
+
+    /// Reads the addresses of all the specified tokens that are not yet
+    /// cached, so that subsequent lookups for them do not need to query the
+    /// exchange.
+    pub fn prefetch(&self, token_ids: impl IntoIterator<Item = TokenId>) -> Result<()> {
+        let missing_token_ids = {
+            let cache = self.cache();
+            token_ids
+                .into_iter()
+                .filter(|token_id| !cache.contains_key(token_id))
+                .collect::<Vec<_>>()
+        };
+        for token_id in missing_token_ids {
+            self.get_token_address(token_id)?;
+        }
+        Ok(())
+    }
+
+    fn cache(&self) -> MutexGuard<HashMap<TokenId, Address>> {
+        self.cache
+            .lock()
+            .expect("token address cache mutex poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::stablex_contract::MockStableXContract;
+    use anyhow::anyhow;
+
+    #[test]
+    fn resolves_and_caches_token_addresses() {
+        let mut contract = MockStableXContract::new();
+        for token_id in 0..3 {
+            contract
+                .expect_get_token_address()
+                .withf(move |id| *id == token_id)
+                .times(1)
+                .returning(|id| Ok(Address::from_low_u64_be(id as u64 + 100)));
+        }
+
+        let resolver = TokenAddressResolver::new(Arc::new(contract));
+        resolver.prefetch(vec![TokenId(0), TokenId(1)]).unwrap();
+        for _ in 0..2 {
+            for token_id in 0..3 {
+                assert_eq!(
+                    resolver.get_token_address(TokenId(token_id)).unwrap(),
+                    Address::from_low_u64_be(token_id as u64 + 100)
+                );
+            }
+        }
+        resolver
+            .prefetch(vec![TokenId(0), TokenId(1), TokenId(2)])
+            .unwrap();
+    }
+
+    #[test]
+    fn prefetch_fails_if_any_token_cannot_be_resolved() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_token_address()
+            .withf(|id| *id == 0)
+            .times(1)
+            .returning(|_| Ok(Address::from_low_u64_be(100)));
+        contract
+            .expect_get_token_address()
+            .withf(|id| *id == 1)
+            .returning(|_| Err(anyhow!("invalid token id")));
+
+        let resolver = TokenAddressResolver::new(Arc::new(contract));
+        assert!(resolver.prefetch(vec![TokenId(0), TokenId(1)]).is_err());
+        assert_eq!(
+            resolver.get_token_address(TokenId(0)).unwrap(),
+            Address::from_low_u64_be(100)
+        );
+        assert!(resolver.get_token_address(TokenId(1)).is_err());
+    }
+}
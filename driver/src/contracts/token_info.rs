@@ -0,0 +1,123 @@
+//! Module for fetching the symbol and decimals of the tokens listed on the
+//! exchange from their ERC20 contracts.
+
+use crate::contracts::stablex_contract::StableXContract;
+use crate::contracts::token_address::TokenAddressResolver;
+use crate::models::{TokenId, TokenInfo};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Fetches token information for listed tokens from the chain and caches it,
+/// so that every token is only queried once.
+///
+/// Note that the external price of the fetched token information is always
+/// `0`, as it is not available on chain.
+pub struct TokenInfoFetcher {
+    contract: Arc<dyn StableXContract + Send + Sync>,
+    addresses: TokenAddressResolver,
+    cache: Mutex<HashMap<TokenId, TokenInfo>>,
+}
+
+impl TokenInfoFetcher {
+    pub fn new(contract: Arc<dyn StableXContract + Send + Sync>) -> Self {
+        TokenInfoFetcher {
+            addresses: TokenAddressResolver::new(contract.clone()),
+            contract,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the token information for the specified token ID, querying the
+    /// token's contract if it is not yet cached.
+    pub fn get_token_info(&self, token_id: TokenId) -> Result<TokenInfo> {
+        if let Some(info) = self.cache().get(&token_id) {
+            return Ok(info.clone());
+        }
+
+        let info = self
+            .fetch_token_info(token_id)
+            .with_context(|| format!("failed to fetch token info for {:?}", token_id))?;
+        self.cache().insert(token_id, info.clone());
+        Ok(info)
+    }
+
+    /// Resolves the addresses of all the specified tokens at once.
+    pub fn prefetch_addresses(&self, token_ids: impl IntoIterator<Item = TokenId>) -> Result<()> {
+        self.addresses.prefetch(token_ids)
+    }
+
+    fn fetch_token_info(&self, token_id: TokenId) -> Result<TokenInfo> {
+        let address = self.addresses.get_token_address(token_id)?;
+        Ok(TokenInfo {
+            alias: self.contract.get_token_symbol(address)?,
+            decimals: self.contract.get_token_decimals(address)?,
+            external_price: 0,
+        })
+    }
+
+    fn cache(&self) -> MutexGuard<HashMap<TokenId, TokenInfo>> {
+        self.cache.lock().expect("token info cache mutex poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::stablex_contract::MockStableXContract;
+    use anyhow::anyhow;
+    use ethcontract::Address;
+
+    #[test]
+    fn fetches_and_caches_token_info() {
+        let token = Address::from_low_u64_be(42);
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_token_address()
+            .withf(|token_id| *token_id == 1)
+            .times(1)
+            .returning(move |_| Ok(token));
+        contract
+            .expect_get_token_symbol()
+            .withf(move |address| *address == token)
+            .times(1)
+            .returning(|_| Ok("WETH".to_owned()));
+        contract
+            .expect_get_token_decimals()
+            .withf(move |address| *address == token)
+            .times(1)
+            .returning(|_| Ok(18));
+
+        let fetcher = TokenInfoFetcher::new(Arc::new(contract));
+        let expected = TokenInfo::new("WETH", 18, 0);
+        assert_eq!(fetcher.get_token_info(TokenId(1)).unwrap(), expected);
+        assert_eq!(fetcher.get_token_info(TokenId(1)).unwrap(), expected);
+    }
+
+    #[test]
+    fn does_not_cache_failures() {
+        let mut contract = MockStableXContract::new();
+        let mut sequence = mockall::Sequence::new();
+        contract
+            .expect_get_token_address()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Err(anyhow!("node unavailable")));
+        contract
+            .expect_get_token_address()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(Address::from_low_u64_be(42)));
+        contract
+            .expect_get_token_symbol()
+            .returning(|_| Ok("USDC".to_owned()));
+        contract.expect_get_token_decimals().returning(|_| Ok(6));
+
+        let fetcher = TokenInfoFetcher::new(Arc::new(contract));
+        assert!(fetcher.get_token_info(TokenId(2)).is_err());
+        assert_eq!(
+            fetcher.get_token_info(TokenId(2)).unwrap(),
+            TokenInfo::new("USDC", 6, 0)
+        );
+    }
+}
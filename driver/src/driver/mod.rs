@@ -1,2 +1,3 @@
 pub mod scheduler;
 pub mod stablex_driver;
+pub mod standby;
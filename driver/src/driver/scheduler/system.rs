@@ -161,6 +161,7 @@ impl<'a> Scheduler for SystemScheduler<'a> {
 mod tests {
     use super::*;
     use crate::driver::stablex_driver::MockStableXDriver;
+    use crate::logging::LogFormat;
     use anyhow::anyhow;
 
     #[test]
@@ -301,7 +302,7 @@ mod tests {
     #[ignore]
     fn test_real() {
         use crate::driver::stablex_driver::DriverResult;
-        let (_, _guard) = crate::logging::init("info");
+        let (_, _guard, _) = crate::logging::init("info", LogFormat::Text, None);
 
         let mut driver = MockStableXDriver::new();
 
@@ -1,12 +1,24 @@
+use super::standby::StandbyMode;
+use crate::contracts::stablex_contract::StableXContract;
 use crate::metrics::StableXMetrics;
-use crate::models::{account_state::AccountState, order::Order, Solution};
+use crate::models::{
+    account_state::AccountState,
+    order::{self, Order},
+    solution::{fill_ratio, write_executed_orders_csv},
+    Solution,
+};
 use crate::orderbook::StableXOrderBookReading;
 use crate::price_finding::PriceFinding;
 use crate::solution_submission::{SolutionSubmissionError, StableXSolutionSubmitting};
-use anyhow::{Error, Result};
+use crate::util::{Clock, SystemClock};
+use anyhow::{Context as _, Error, Result};
 use ethcontract::U256;
-use log::{info, warn};
-use std::time::{Duration, Instant};
+use log::{debug, info, log_enabled, warn, Level};
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum DriverResult {
@@ -25,6 +37,11 @@ pub struct StableXDriverImpl<'a> {
     orderbook_reader: &'a (dyn StableXOrderBookReading + Sync),
     solution_submitter: &'a (dyn StableXSolutionSubmitting + Sync),
     metrics: &'a StableXMetrics,
+    standby_mode: Option<&'a StandbyMode>,
+    node_sync_check: Option<&'a (dyn StableXContract + Sync)>,
+    executed_orders_export_dir: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
+    last_account_state: Mutex<Option<AccountState>>,
 }
 
 impl<'a> StableXDriverImpl<'a> {
@@ -39,13 +56,105 @@ impl<'a> StableXDriverImpl<'a> {
             orderbook_reader,
             solution_submitter,
             metrics,
+            standby_mode: None,
+            node_sync_check: None,
+            executed_orders_export_dir: None,
+            clock: Arc::new(SystemClock),
+            last_account_state: Mutex::new(None),
         }
     }
 
+    /// Makes the driver only read the orderbook without solving while the
+    /// standby mode is in standby.
+    pub fn with_standby_mode(mut self, standby_mode: &'a StandbyMode) -> Self {
+        self.standby_mode = Some(standby_mode);
+        self
+    }
+
+    /// Makes the driver refuse to solve batches while the node the contract
+    /// is connected to is still syncing, as it may return stale orderbooks.
+    pub fn with_node_sync_check(mut self, contract: &'a (dyn StableXContract + Sync)) -> Self {
+        self.node_sync_check = Some(contract);
+        self
+    }
+
+    /// Makes the driver export the executed orders of every successfully
+    /// submitted solution as CSV to a file per batch in the specified
+    /// directory.
+    pub fn with_executed_orders_export_dir(mut self, directory: PathBuf) -> Self {
+        self.executed_orders_export_dir = Some(directory);
+        self
+    }
+
+    /// Measures the time left for solving with the specified clock instead of
+    /// the system clock.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn export_executed_orders(
+        &self,
+        batch_id: U256,
+        solution: &Solution,
+        orders: &[Order],
+    ) -> Result<()> {
+        let directory = match &self.executed_orders_export_dir {
+            Some(directory) => directory,
+            None => return Ok(()),
+        };
+        let path = directory.join(format!("executed_orders_{}.csv", batch_id));
+        let file = File::create(&path)
+            .with_context(|| format!("error creating file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        write_executed_orders_csv(&mut writer, solution, orders)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn is_node_syncing(&self) -> Result<bool> {
+        match self.node_sync_check {
+            Some(contract) => contract.is_syncing(),
+            None => Ok(false),
+        }
+    }
+
+    fn is_standby(&self) -> bool {
+        self.standby_mode
+            .map(|mode| mode.is_standby())
+            .unwrap_or(false)
+    }
+
     fn get_orderbook(&self, batch_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
         let get_auction_data_result = self.orderbook_reader.get_auction_data(batch_to_solve);
         self.metrics
             .auction_orders_fetched(batch_to_solve, &get_auction_data_result);
+        if let Ok((account_state, orders)) = &get_auction_data_result {
+            debug!(
+                "Orderbook for batch {}: {:?}",
+                batch_to_solve,
+                order::orderbook_stats(orders)
+            );
+            debug!(
+                "Total balances for batch {}: {:?}",
+                batch_to_solve,
+                account_state
+                    .tokens()
+                    .map(|token_id| (token_id, account_state.total_balance(token_id)))
+                    .collect::<Vec<_>>()
+            );
+            if log_enabled!(Level::Debug) {
+                let mut last_account_state = self.last_account_state.lock().unwrap();
+                if let Some(last_account_state) = last_account_state.as_ref() {
+                    debug!(
+                        "Balance changes since the last orderbook read: {:?}",
+                        last_account_state.diff(account_state)
+                    );
+                }
+                *last_account_state = Some(account_state.clone());
+            }
+        }
         get_auction_data_result
     }
 
@@ -71,6 +180,25 @@ impl<'a> StableXDriverImpl<'a> {
                 "Computed solution for batch {}: {:?}",
                 batch_to_solve, &solution
             );
+            debug!(
+                "Fill ratios of executed orders for batch {}: {:?}",
+                batch_to_solve,
+                solution
+                    .executed_orders
+                    .iter()
+                    .filter_map(|executed_order| {
+                        let order = orders.iter().find(|order| {
+                            order.account_id == executed_order.account_id
+                                && order.id == executed_order.order_id
+                        })?;
+                        Some((
+                            order.account_id,
+                            order.id,
+                            fill_ratio(order, executed_order),
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            );
 
             solution
         };
@@ -114,14 +242,25 @@ impl<'a> StableXDriverImpl<'a> {
         };
 
         let submitted = if let Some(objective_value) = verified {
-            let submission_result =
-                self.solution_submitter
-                    .submit_solution(batch_to_solve, solution, objective_value);
+            let solver_name = solution.solver_name.clone().unwrap_or_default();
+            let submission_result = self.solution_submitter.submit_solution(
+                batch_to_solve,
+                solution.clone(),
+                objective_value,
+            );
             self.metrics
                 .auction_solution_submitted(batch_to_solve, &submission_result);
             match submission_result {
                 Ok(_) => {
-                    info!("Successfully applied solution to batch {}", batch_to_solve);
+                    info!(
+                        "Successfully applied solution from solver '{}' to batch {}",
+                        solver_name, batch_to_solve
+                    );
+                    if let Err(err) =
+                        self.export_executed_orders(batch_to_solve, &solution, &orders)
+                    {
+                        warn!("failed to export executed orders: {:?}", err);
+                    }
                     true
                 }
                 Err(err) => match err {
@@ -147,18 +286,37 @@ impl<'a> StableXDriverImpl<'a> {
 
 impl<'a> StableXDriver for StableXDriverImpl<'a> {
     fn run(&self, batch_to_solve: U256, time_limit: Duration) -> DriverResult {
-        let deadline = Instant::now() + time_limit;
+        let deadline = self.clock.now() + time_limit;
 
         self.metrics.auction_processing_started(&Ok(batch_to_solve));
+        // NOTE: Check whether the node is syncing before reading the orderbook
+        //   so that a lagging node does not cost a full read.
+        match self.is_node_syncing() {
+            Ok(false) => (),
+            Ok(true) => {
+                warn!(
+                    "Node is still syncing, not solving batch {}",
+                    batch_to_solve
+                );
+                return DriverResult::Ok;
+            }
+            Err(err) => return DriverResult::Retry(err),
+        }
+
         let (account_state, orders) = match self.get_orderbook(batch_to_solve) {
             Ok(ok) => ok,
             Err(err) => return DriverResult::Retry(err),
         };
 
+        if self.is_standby() {
+            info!("Driver is in standby, not solving batch {}", batch_to_solve);
+            return DriverResult::Ok;
+        }
+
         // Make sure the solver has at least some minimal time to run to have a chance for a
         // solution. This also fixes an assert where the solver fails if the timelimit gets rounded
         // to 0.
-        let price_finding_time_limit = match deadline.checked_duration_since(Instant::now()) {
+        let price_finding_time_limit = match deadline.checked_duration_since(self.clock.now()) {
             Some(time_limit) if time_limit > Duration::from_secs(1) => time_limit,
             _ => {
                 warn!("orderbook retrieval exceeded time limit");
@@ -181,15 +339,19 @@ impl<'a> StableXDriver for StableXDriverImpl<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::contracts::stablex_contract::MockStableXContract;
+    use crate::logging::test_util::capture_logs;
     use crate::models::order::test_util::{create_order_for_test, order_to_executed_order};
     use crate::models::AccountState;
     use crate::orderbook::MockStableXOrderBookReading;
     use crate::price_finding::price_finder_interface::MockPriceFinding;
     use crate::solution_submission::MockStableXSolutionSubmitting;
-    use crate::util::test_util::map_from_slice;
+    use crate::util::test_util::{map_from_slice, MockClock};
     use anyhow::anyhow;
+    use ethcontract::Address;
     use mockall::predicate::*;
     use std::thread;
+    use std::time::Instant;
 
     impl DriverResult {
         fn is_ok(&self) -> bool {
@@ -242,7 +404,11 @@ mod tests {
 
         submitter
             .expect_submit_solution()
-            .with(eq(batch), always(), eq(U256::from(1337)))
+            .withf(move |b, solution, objective_value| {
+                *b == batch
+                    && solution.solver_name.as_deref() == Some("naive-solver")
+                    && *objective_value == U256::from(1337)
+            })
             .returning(|_, _, _| Ok(()));
 
         let solution = Solution {
@@ -251,6 +417,7 @@ mod tests {
                 order_to_executed_order(&orders[0], 0, 0),
                 order_to_executed_order(&orders[1], 2, 2),
             ],
+            solver_name: Some("naive-solver".to_owned()),
         };
         pf.expect_find_prices()
             .withf(move |o, s, t| o == orders.as_slice() && *s == state && *t <= time_limit)
@@ -260,6 +427,102 @@ mod tests {
         assert!(driver.run(batch, time_limit).is_ok());
     }
 
+    #[test]
+    fn does_not_solve_in_standby_until_promoted() {
+        let mut reader = MockStableXOrderBookReading::default();
+        let mut submitter = MockStableXSolutionSubmitting::default();
+        let mut pf = MockPriceFinding::default();
+        let metrics = StableXMetrics::default();
+
+        let orders = vec![create_order_for_test(), create_order_for_test()];
+        let state = AccountState::with_balance_for(&orders);
+
+        let batch = U256::from(42);
+        let time_limit = Duration::from_secs(120);
+
+        reader
+            .expect_get_auction_data()
+            .with(eq(batch))
+            .times(2)
+            .returning(move |_| Ok((state.clone(), orders.clone())));
+
+        let solution = Solution {
+            prices: map_from_slice(&[(0, 1), (1, 2)]),
+            executed_orders: vec![
+                order_to_executed_order(&create_order_for_test(), 1, 1),
+                order_to_executed_order(&create_order_for_test(), 2, 2),
+            ],
+            solver_name: None,
+        };
+        pf.expect_find_prices()
+            .times(1)
+            .return_once(move |_, _, _| Ok(solution));
+        submitter
+            .expect_get_solution_objective_value()
+            .times(1)
+            .returning(|_, _| Ok(U256::from(1337)));
+        submitter
+            .expect_submit_solution()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let standby_mode = StandbyMode::new(true);
+        let driver = StableXDriverImpl::new(&pf, &reader, &submitter, &metrics)
+            .with_standby_mode(&standby_mode);
+
+        // The mocks panic if the price finder or submitter are called more
+        // often than expected, i.e. while in standby.
+        assert!(driver.run(batch, time_limit).is_ok());
+        assert!(standby_mode.promote());
+        assert!(driver.run(batch, time_limit).is_ok());
+    }
+
+    #[test]
+    fn does_not_solve_while_node_is_syncing() {
+        let mut reader = MockStableXOrderBookReading::default();
+        let mut submitter = MockStableXSolutionSubmitting::default();
+        let mut pf = MockPriceFinding::default();
+        let mut contract = MockStableXContract::default();
+        let metrics = StableXMetrics::default();
+
+        reader.expect_get_auction_data().times(0);
+        contract.expect_is_syncing().times(1).returning(|| Ok(true));
+        pf.expect_find_prices().times(0);
+        submitter.expect_get_solution_objective_value().times(0);
+        submitter.expect_submit_solution().times(0);
+
+        let driver = StableXDriverImpl::new(&pf, &reader, &submitter, &metrics)
+            .with_node_sync_check(&contract);
+        assert!(driver.run(U256::from(42), Duration::from_secs(120)).is_ok());
+    }
+
+    #[test]
+    fn does_not_solve_if_orderbook_retrieval_exceeds_time_limit() {
+        let mut reader = MockStableXOrderBookReading::default();
+        let mut submitter = MockStableXSolutionSubmitting::default();
+        let mut pf = MockPriceFinding::default();
+        let metrics = StableXMetrics::default();
+        let clock = Arc::new(MockClock::new());
+
+        let orders = vec![create_order_for_test(), create_order_for_test()];
+        let state = AccountState::with_balance_for(&orders);
+
+        reader.expect_get_auction_data().returning({
+            let clock = clock.clone();
+            move |_| {
+                clock.advance(Duration::from_secs(119));
+                Ok((state.clone(), orders.clone()))
+            }
+        });
+        pf.expect_find_prices().times(0);
+        submitter.expect_get_solution_objective_value().times(0);
+        submitter.expect_submit_solution().times(0);
+
+        let driver =
+            StableXDriverImpl::new(&pf, &reader, &submitter, &metrics).with_clock(clock.clone());
+        assert!(driver.run(U256::from(42), Duration::from_secs(120)).is_ok());
+    }
+
     #[test]
     fn test_errors_on_failing_reader() {
         let mut reader = MockStableXOrderBookReading::default();
@@ -406,6 +669,7 @@ mod tests {
                 order_to_executed_order(&orders[0], 0, 0),
                 order_to_executed_order(&orders[1], 2, 2),
             ],
+            solver_name: None,
         };
         pf.expect_find_prices()
             .withf(move |o, s, t| o == orders.as_slice() && *s == state && *t <= time_limit)
@@ -448,6 +712,7 @@ mod tests {
                 order_to_executed_order(&orders[0], 0, 0),
                 order_to_executed_order(&orders[1], 2, 2),
             ],
+            solver_name: None,
         };
         pf.expect_find_prices()
             .withf(move |o, s, t| o == orders.as_slice() && *s == state && *t <= time_limit)
@@ -493,6 +758,7 @@ mod tests {
                 order_to_executed_order(&orders[0], 0, 0),
                 order_to_executed_order(&orders[1], 2, 2),
             ],
+            solver_name: None,
         };
         pf.expect_find_prices()
             .withf(move |o, s, t| o == orders.as_slice() && *s == state && *t <= time_limit)
@@ -531,4 +797,35 @@ mod tests {
         let driver = StableXDriverImpl::new(&pf, &reader, &submitter, &metrics);
         assert!(driver.run(batch, time_limit).is_ok());
     }
+
+    #[test]
+    fn logs_balance_changes_between_orderbook_reads() {
+        let mut reader = MockStableXOrderBookReading::default();
+        let submitter = MockStableXSolutionSubmitting::default();
+        let pf = MockPriceFinding::default();
+        let metrics = StableXMetrics::default();
+
+        let mut states = vec![
+            AccountState::new(vec![100, 200], 2),
+            AccountState::new(vec![150, 200], 2),
+        ]
+        .into_iter();
+        reader
+            .expect_get_auction_data()
+            .times(2)
+            .returning(move |_| Ok((states.next().unwrap(), Vec::new())));
+
+        let driver = StableXDriverImpl::new(&pf, &reader, &submitter, &metrics);
+        let logs = capture_logs(Level::Debug, || {
+            driver.get_orderbook(U256::from(1)).unwrap();
+            driver.get_orderbook(U256::from(2)).unwrap();
+        });
+
+        let changes = logs
+            .iter()
+            .filter(|message| message.starts_with("Balance changes"))
+            .collect::<Vec<_>>();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].ends_with(&format!("{:?}", vec![(Address::zero(), 0u16, 50i128)])));
+    }
 }
@@ -0,0 +1,49 @@
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Switch for running the driver as a warm standby.
+///
+/// A driver in standby keeps reading the orderbook so that it stays in sync
+/// but neither solves nor submits solutions, so that it can take over from a
+/// failing primary driver instantly once promoted.
+#[derive(Debug, Default)]
+pub struct StandbyMode {
+    standby: AtomicBool,
+}
+
+impl StandbyMode {
+    pub fn new(standby: bool) -> Self {
+        StandbyMode {
+            standby: AtomicBool::new(standby),
+        }
+    }
+
+    /// Returns true if the driver is in standby.
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::SeqCst)
+    }
+
+    /// Promotes the driver to be active. Returns whether the driver was in
+    /// standby before.
+    pub fn promote(&self) -> bool {
+        let was_standby = self.standby.swap(false, Ordering::SeqCst);
+        if was_standby {
+            info!("promoting driver from standby to active");
+        }
+        was_standby
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promotion_is_only_reported_once() {
+        let mode = StandbyMode::new(true);
+        assert!(mode.is_standby());
+        assert!(mode.promote());
+        assert!(!mode.is_standby());
+        assert!(!mode.promote());
+    }
+}
@@ -0,0 +1,202 @@
+//! Module containing strategies for computing the gas price of solution
+//! submissions from the node's current gas price.
+
+use anyhow::{anyhow, Error, Result};
+use ethcontract::U256;
+use std::str::FromStr;
+
+/// The precision with which gas price multipliers are applied.
+const MULTIPLIER_PRECISION: f64 = 1_000_000.0;
+
+#[cfg_attr(test, mockall::automock)]
+pub trait GasPriceStrategy {
+    /// Computes the gas price in wei to submit a transaction with given the
+    /// current gas price reported by the node (`eth_gasPrice`).
+    fn gas_price(&self, base_gas_price: U256) -> U256;
+}
+
+impl<S: GasPriceStrategy + ?Sized> GasPriceStrategy for Box<S> {
+    fn gas_price(&self, base_gas_price: U256) -> U256 {
+        (**self).gas_price(base_gas_price)
+    }
+}
+
+/// Always uses the same gas price regardless of the base gas price.
+pub struct FixedGasPrice(pub U256);
+
+impl GasPriceStrategy for FixedGasPrice {
+    fn gas_price(&self, _: U256) -> U256 {
+        self.0
+    }
+}
+
+/// Uses a multiple of the base gas price.
+pub struct MultipliedGasPrice {
+    factor: f64,
+}
+
+impl MultipliedGasPrice {
+    pub fn new(factor: f64) -> Self {
+        assert!(factor >= 0.0, "gas price factor must not be negative");
+        MultipliedGasPrice { factor }
+    }
+}
+
+impl GasPriceStrategy for MultipliedGasPrice {
+    fn gas_price(&self, base_gas_price: U256) -> U256 {
+        let factor = U256::from((self.factor * MULTIPLIER_PRECISION).round() as u64);
+        let precision = U256::from(MULTIPLIER_PRECISION as u64);
+        // Divide before multiplying so that large gas prices saturate instead
+        // of overflowing, multiplying the remainder separately keeps the
+        // result exact.
+        (base_gas_price / precision)
+            .saturating_mul(factor)
+            .saturating_add((base_gas_price % precision) * factor / precision)
+    }
+}
+
+/// Caps the gas price computed by another strategy.
+pub struct CappedGasPrice<S> {
+    strategy: S,
+    cap: U256,
+}
+
+impl<S> CappedGasPrice<S> {
+    pub fn new(strategy: S, cap: U256) -> Self {
+        CappedGasPrice { strategy, cap }
+    }
+}
+
+impl<S: GasPriceStrategy> GasPriceStrategy for CappedGasPrice<S> {
+    fn gas_price(&self, base_gas_price: U256) -> U256 {
+        self.strategy.gas_price(base_gas_price).min(self.cap)
+    }
+}
+
+/// The kind of gas price strategy to use, as specified on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GasPriceStrategyKind {
+    /// A fixed gas price in wei, specified as `fixed:<wei>`.
+    Fixed(U256),
+    /// A multiple of the node's gas price, specified as `multiplier:<factor>`.
+    Multiplier(f64),
+}
+
+impl GasPriceStrategyKind {
+    /// Creates the gas price strategy, capping the gas price if a cap is
+    /// specified.
+    pub fn create(self, cap: Option<U256>) -> Box<dyn GasPriceStrategy + Sync> {
+        let strategy: Box<dyn GasPriceStrategy + Sync> = match self {
+            GasPriceStrategyKind::Fixed(gas_price) => Box::new(FixedGasPrice(gas_price)),
+            GasPriceStrategyKind::Multiplier(factor) => Box::new(MultipliedGasPrice::new(factor)),
+        };
+        match cap {
+            Some(cap) => Box::new(CappedGasPrice::new(strategy, cap)),
+            None => strategy,
+        }
+    }
+}
+
+impl FromStr for GasPriceStrategyKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut parts = value.splitn(2, ':');
+        let kind = parts.next().unwrap_or_default().to_lowercase();
+        let parameter = parts
+            .next()
+            .ok_or_else(|| anyhow!("missing gas price strategy parameter in '{}'", value))?;
+        match kind.as_str() {
+            "fixed" => {
+                let gas_price = U256::from_dec_str(parameter)
+                    .map_err(|_| anyhow!("invalid gas price '{}'", parameter))?;
+                Ok(GasPriceStrategyKind::Fixed(gas_price))
+            }
+            "multiplier" => {
+                let factor: f64 = parameter.parse()?;
+                if !factor.is_finite() || factor < 0.0 {
+                    return Err(anyhow!("invalid gas price factor '{}'", parameter));
+                }
+                Ok(GasPriceStrategyKind::Multiplier(factor))
+            }
+            _ => Err(anyhow!("unknown gas price strategy '{}'", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_GAS_PRICE: u64 = 20_000_000_000;
+
+    #[test]
+    fn fixed_gas_price_ignores_base() {
+        let strategy = FixedGasPrice(U256::from(42));
+        assert_eq!(strategy.gas_price(BASE_GAS_PRICE.into()), U256::from(42));
+    }
+
+    #[test]
+    fn multiplied_gas_price() {
+        assert_eq!(
+            MultipliedGasPrice::new(1.5).gas_price(BASE_GAS_PRICE.into()),
+            U256::from(30_000_000_000u64)
+        );
+        assert_eq!(
+            MultipliedGasPrice::new(0.0).gas_price(BASE_GAS_PRICE.into()),
+            U256::zero()
+        );
+        assert_eq!(
+            MultipliedGasPrice::new(2.0).gas_price(U256::max_value()),
+            U256::max_value()
+        );
+        assert_eq!(
+            MultipliedGasPrice::new(0.5).gas_price(U256::max_value()),
+            U256::max_value() / 2
+        );
+        assert_eq!(
+            MultipliedGasPrice::new(1.5).gas_price(1_000_001.into()),
+            U256::from(1_500_001)
+        );
+    }
+
+    #[test]
+    fn capped_gas_price() {
+        let mut inner = MockGasPriceStrategy::new();
+        inner
+            .expect_gas_price()
+            .returning(|base_gas_price| base_gas_price * 2);
+        let strategy = CappedGasPrice::new(inner, U256::from(30_000_000_000u64));
+
+        assert_eq!(
+            strategy.gas_price(10_000_000_000u64.into()),
+            U256::from(20_000_000_000u64)
+        );
+        assert_eq!(
+            strategy.gas_price(BASE_GAS_PRICE.into()),
+            U256::from(30_000_000_000u64)
+        );
+    }
+
+    #[test]
+    fn parses_gas_price_strategy_kind() {
+        assert_eq!(
+            "fixed:42".parse::<GasPriceStrategyKind>().unwrap(),
+            GasPriceStrategyKind::Fixed(42.into())
+        );
+        assert_eq!(
+            "Multiplier:1.5".parse::<GasPriceStrategyKind>().unwrap(),
+            GasPriceStrategyKind::Multiplier(1.5)
+        );
+        assert!("fixed".parse::<GasPriceStrategyKind>().is_err());
+        assert!("multiplier:-1".parse::<GasPriceStrategyKind>().is_err());
+        assert!("oracle:1".parse::<GasPriceStrategyKind>().is_err());
+    }
+
+    #[test]
+    fn creates_capped_strategy() {
+        let strategy = GasPriceStrategyKind::Multiplier(2.0).create(Some(25.into()));
+        assert_eq!(strategy.gas_price(10.into()), U256::from(20));
+        assert_eq!(strategy.gas_price(20.into()), U256::from(25));
+    }
+}
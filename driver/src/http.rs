@@ -3,46 +3,68 @@
 
 pub use crate::metrics::HttpLabel;
 use crate::metrics::HttpMetrics;
-use anyhow::{anyhow, Result};
-use isahc::http::{Error as HttpError, Uri};
+use anyhow::Result;
+use isahc::http::{Error as HttpError, StatusCode, Uri};
 use isahc::prelude::{Configurable, Request};
 use isahc::{HttpClientBuilder, ResponseExt};
 use serde::de::DeserializeOwned;
 use std::convert::TryFrom;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use thiserror::Error;
 
 /// A factory type for creating HTTP clients.
 #[derive(Debug)]
 pub struct HttpFactory {
-    default_timeout: Duration,
+    request_timeout: Duration,
+    connect_timeout: Option<Duration>,
     metrics: Arc<HttpMetrics>,
 }
 
 impl HttpFactory {
-    /// Creates a new HTTP client factory.
-    pub fn new(default_timeout: Duration, metrics: HttpMetrics) -> Self {
+    /// Creates a new HTTP client factory. The request timeout covers the whole
+    /// request of clients created with the default configuration.
+    pub fn new(request_timeout: Duration, metrics: HttpMetrics) -> Self {
         HttpFactory {
-            default_timeout,
+            request_timeout,
+            connect_timeout: None,
             metrics: Arc::new(metrics),
         }
     }
 
+    /// Limits the time all created clients spend establishing a connection,
+    /// so that an unreachable host fails fast while slow responses from a
+    /// reachable one are still bounded by the request timeout only.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
     /// Creates a new HTTP client with the default configuration.
     pub fn create(&self) -> Result<HttpClient> {
-        self.with_config(|builder| builder.timeout(self.default_timeout))
+        self.with_config(|builder| builder.timeout(self.request_timeout))
     }
 
-    /// Creates a new HTTP Client with the given configuration.
+    /// Creates a new HTTP Client with the given configuration. The connect
+    /// timeout is applied before the configuration, so it can be overridden.
     pub fn with_config(
         &self,
         configure: impl FnOnce(HttpClientBuilder) -> HttpClientBuilder,
     ) -> Result<HttpClient> {
-        let inner = configure(isahc::HttpClient::builder()).build()?;
+        let inner = configure(self.builder()).build()?;
         let metrics = self.metrics.clone();
 
         Ok(HttpClient { inner, metrics })
     }
+
+    /// Returns a client builder with the connect timeout applied.
+    fn builder(&self) -> HttpClientBuilder {
+        let builder = isahc::HttpClient::builder();
+        match self.connect_timeout {
+            Some(connect_timeout) => builder.connect_timeout(connect_timeout),
+            None => builder,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -52,6 +74,15 @@ impl Default for HttpFactory {
     }
 }
 
+/// The error returned when a remote service responds with an unsuccessful
+/// HTTP status.
+#[derive(Debug, Error)]
+#[error("HTTP error status {status}: '{body}'")]
+pub struct HttpStatusError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
 /// An HTTP client instance with metrics.
 #[derive(Debug)]
 pub struct HttpClient {
@@ -61,21 +92,25 @@ pub struct HttpClient {
 
 impl HttpClient {
     /// Post raw JSON data and return a future that resolves once the HTTP
-    /// request has been completed.
+    /// request has been completed. The timeout overrides the client's default
+    /// timeout if specified.
     pub async fn post_raw_json_async<U>(
         &self,
         url: U,
         data: impl Into<String>,
         label: HttpLabel,
+        timeout: Option<Duration>,
     ) -> Result<String>
     where
         Uri: TryFrom<U>,
         <Uri as TryFrom<U>>::Error: Into<HttpError>,
     {
         let start = Instant::now();
-        let http_request = Request::post(url)
-            .header("Content-Type", "application/json")
-            .body(data.into())?;
+        let mut http_request = Request::post(url).header("Content-Type", "application/json");
+        if let Some(timeout) = timeout {
+            http_request = http_request.timeout(timeout);
+        }
+        let http_request = http_request.body(data.into())?;
         let mut response = self.inner.send_async(http_request).await?;
         let content = response.text()?;
 
@@ -83,11 +118,11 @@ impl HttpClient {
             self.metrics.request(label, start.elapsed(), content.len());
             Ok(content)
         } else {
-            Err(anyhow!(
-                "HTTP error status {}: '{}'",
-                response.status(),
-                content.trim()
-            ))
+            Err(HttpStatusError {
+                status: response.status(),
+                body: content.trim().to_owned(),
+            }
+            .into())
         }
     }
 
@@ -125,3 +160,20 @@ impl HttpClient {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_timeout_is_optional() {
+        let factory = HttpFactory::default();
+        assert_eq!(factory.request_timeout, Duration::from_secs(10));
+        assert_eq!(factory.connect_timeout, None);
+
+        let factory = factory.with_connect_timeout(Duration::from_secs(1));
+        assert_eq!(factory.request_timeout, Duration::from_secs(10));
+        assert_eq!(factory.connect_timeout, Some(Duration::from_secs(1)));
+        assert!(factory.create().is_ok());
+    }
+}
@@ -1,30 +1,322 @@
+use anyhow::{anyhow, ensure, Error, Result};
+use chrono::Utc;
 use slog::Level;
-use slog::{o, Drain, Logger, OwnedKVList, Record};
+use slog::{
+    b, o, record_static, Discard, Drain, FilterLevel, FnValue, Logger, Never, OwnedKVList,
+    PushFnValue, Record,
+};
 use slog_async::Async;
-use slog_envlogger::LogBuilder;
+use slog_envlogger::{EnvLogger, LogBuilder};
+use slog_json::Json;
 use slog_scope::GlobalLoggerGuard;
 use slog_term::{Decorator, TermDecorator};
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// The channel size for async logging.
 const BUFFER_SIZE: usize = 1024;
 
-/// Initialize driver logging.
-pub fn init(filter: impl AsRef<str>) -> (Logger, GlobalLoggerGuard) {
-    // Log errors to stderr and lower severities to stdout.
-    let format = CustomFormatter::new(
-        TermDecorator::new().stderr().build(),
-        TermDecorator::new().stdout().build(),
-    )
-    .fuse();
-    let drain = Async::new(LogBuilder::new(format).parse(filter.as_ref()).build())
-        .chan_size(BUFFER_SIZE)
-        .build();
+/// The interval for which the number of messages is limited when rate limiting.
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The output format of log records.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human readable text, with errors logged to stderr and the rest to stdout.
+    Text,
+    /// One JSON object per line with `timestamp`, `level`, `module` and
+    /// `message` fields, all logged to stdout.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("unknown log format '{}'", value)),
+        }
+    }
+}
+
+/// Initialize driver logging. Messages from any single logging statement are
+/// limited to `rate_limit` per second if set. The returned handle can be used
+/// to change the log filter at runtime.
+pub fn init(
+    filter: impl AsRef<str>,
+    format: LogFormat,
+    rate_limit: Option<usize>,
+) -> (Logger, GlobalLoggerGuard, LogFilterHandle) {
+    let (drain, filter_handle) = match format {
+        LogFormat::Text => {
+            // Log errors to stderr and lower severities to stdout.
+            let format = CustomFormatter::new(
+                TermDecorator::new().stderr().build(),
+                TermDecorator::new().stdout().build(),
+            );
+            async_drain(format.fuse(), filter.as_ref(), rate_limit)
+        }
+        LogFormat::Json => async_drain(
+            json_drain(std::io::stdout()).fuse(),
+            filter.as_ref(),
+            rate_limit,
+        ),
+    };
     let logger = Logger::root(drain.fuse(), o!());
 
     let guard = slog_scope::set_global_logger(logger.clone());
     slog_stdlog::init().expect("failed to register logger");
 
-    (logger, guard)
+    (logger, guard, filter_handle)
+}
+
+/// Filters the records of a drain with the specified `slog-envlogger` filter,
+/// optionally rate limits them and logs them asynchronously.
+fn async_drain<D>(drain: D, filter: &str, rate_limit: Option<usize>) -> (Async, LogFilterHandle)
+where
+    D: Drain<Ok = (), Err = Never> + Send + 'static,
+{
+    fn build<D>(drain: D, filter: &str) -> (Async, LogFilterHandle)
+    where
+        D: Drain<Ok = (), Err = Never> + Send + 'static,
+    {
+        let filter = Arc::new(ReloadableFilter::new(drain, filter));
+        let drain = Async::new(filter.clone()).chan_size(BUFFER_SIZE).build();
+        (drain, LogFilterHandle(filter))
+    }
+
+    match rate_limit {
+        Some(max_records) => build(
+            RateLimit::new(drain, max_records, RATE_LIMIT_INTERVAL),
+            filter,
+        ),
+        None => build(drain, filter),
+    }
+}
+
+/// Creates a drain that writes every record as a JSON object on its own line.
+fn json_drain<W: Write>(writer: W) -> Json<W> {
+    Json::new(writer)
+        .add_key_value(o!(
+            "timestamp" => PushFnValue(|_: &Record, serializer| {
+                serializer.emit(Utc::now().to_rfc3339())
+            }),
+            "level" => FnValue(|record: &Record| record.level().as_str()),
+            "module" => FnValue(|record: &Record| record.module()),
+            "message" => PushFnValue(|record: &Record, serializer| {
+                serializer.emit(record.msg())
+            }),
+        ))
+        .build()
+}
+
+/// A handle for replacing the log filter of a running logger.
+#[derive(Clone)]
+pub struct LogFilterHandle(Arc<dyn SetFilter>);
+
+impl LogFilterHandle {
+    /// Replaces the log filter. The filter follows the `slog-envlogger` syntax
+    /// (e.g. 'info,driver=debug') and applies to all records logged after it
+    /// was set. Returns an error and keeps the current filter if the filter
+    /// is invalid.
+    pub fn set_filter(&self, filter: &str) -> Result<()> {
+        validate_filter(filter)?;
+        self.0.set_filter(filter);
+        Ok(())
+    }
+}
+
+/// Checks that a filter is valid `slog-envlogger` syntax. This is needed
+/// because `slog-envlogger` silently ignores invalid parts of a filter.
+fn validate_filter(filter: &str) -> Result<()> {
+    let (directives, regex) = match filter.find('/') {
+        Some(index) => (&filter[..index], Some(&filter[index + 1..])),
+        None => (filter, None),
+    };
+    if let Some(regex) = regex {
+        ensure!(!regex.contains('/'), "too many '/'s in log filter");
+        regex::Regex::new(regex)?;
+    }
+    for directive in directives
+        .split(',')
+        .filter(|directive| !directive.is_empty())
+    {
+        ensure!(
+            is_applied(directive),
+            "invalid log directive '{}'",
+            directive
+        );
+    }
+    Ok(())
+}
+
+/// Returns whether `slog-envlogger` applies a single directive instead of
+/// ignoring it. This is detected by building the filter with and without a
+/// preceding directive that turns logging off: as filters without directives
+/// default to the error level, their maximum levels only differ if the
+/// directive was ignored.
+fn is_applied(directive: &str) -> bool {
+    let max_level = |builder: LogBuilder<Discard>| builder.parse(directive).build().filter();
+    max_level(LogBuilder::new(Discard))
+        == max_level(LogBuilder::new(Discard).filter(None, FilterLevel::Off))
+}
+
+trait SetFilter: Send + Sync {
+    fn set_filter(&self, filter: &str);
+}
+
+/// A `slog-envlogger` filter that can be replaced at runtime. The filtered
+/// drain is shared between the current and replaced filters.
+struct ReloadableFilter<D: Drain> {
+    drain: Arc<Mutex<D>>,
+    filter: Mutex<EnvLogger<SharedDrain<D>>>,
+}
+
+impl<D: Drain> ReloadableFilter<D> {
+    fn new(drain: D, filter: &str) -> Self {
+        let drain = Arc::new(Mutex::new(drain));
+        Self {
+            filter: Mutex::new(
+                LogBuilder::new(SharedDrain(drain.clone()))
+                    .parse(filter)
+                    .build(),
+            ),
+            drain,
+        }
+    }
+}
+
+impl<D: Drain + Send> SetFilter for ReloadableFilter<D> {
+    fn set_filter(&self, filter: &str) {
+        let filter = LogBuilder::new(SharedDrain(self.drain.clone()))
+            .parse(filter)
+            .build();
+        *self.filter.lock().expect("log filter mutex poisoned") = filter;
+    }
+}
+
+impl<D: Drain<Ok = (), Err = Never>> Drain for ReloadableFilter<D> {
+    type Ok = ();
+    type Err = Never;
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> std::result::Result<Self::Ok, Self::Err> {
+        self.filter
+            .lock()
+            .expect("log filter mutex poisoned")
+            .log(record, values)
+            .map(|_| ())
+    }
+}
+
+struct SharedDrain<D>(Arc<Mutex<D>>);
+
+impl<D: Drain> Drain for SharedDrain<D> {
+    type Ok = D::Ok;
+    type Err = D::Err;
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> std::result::Result<Self::Ok, Self::Err> {
+        self.0
+            .lock()
+            .expect("log drain mutex poisoned")
+            .log(record, values)
+    }
+}
+
+/// Limits the number of messages from the same logging statement, identified
+/// by its module and source location, to `max_records` per interval.
+///
+/// Suppressed messages are only counted and a summary of how many messages
+/// were suppressed is logged along with the first message of the next
+/// interval.
+pub struct RateLimit<D> {
+    drain: D,
+    max_records: usize,
+    interval: Duration,
+    windows: Mutex<HashMap<(&'static str, u32, u32), RateLimitWindow>>,
+}
+
+struct RateLimitWindow {
+    start: Instant,
+    logged: usize,
+    suppressed: usize,
+}
+
+impl RateLimitWindow {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            logged: 0,
+            suppressed: 0,
+        }
+    }
+}
+
+impl<D> RateLimit<D> {
+    pub fn new(drain: D, max_records: usize, interval: Duration) -> Self {
+        Self {
+            drain,
+            max_records,
+            interval,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<D: Drain<Ok = ()>> Drain for RateLimit<D> {
+    type Ok = ();
+    type Err = D::Err;
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> std::result::Result<Self::Ok, Self::Err> {
+        let now = Instant::now();
+        let suppressed = {
+            let mut windows = self.windows.lock().expect("rate limit mutex poisoned");
+            let window = windows
+                .entry((record.module(), record.line(), record.column()))
+                .or_insert_with(|| RateLimitWindow::new(now));
+            let suppressed = if now.duration_since(window.start) >= self.interval {
+                std::mem::replace(window, RateLimitWindow::new(now)).suppressed
+            } else {
+                0
+            };
+            if window.logged >= self.max_records {
+                window.suppressed += 1;
+                return Ok(());
+            }
+            window.logged += 1;
+            suppressed
+        };
+
+        if suppressed > 0 {
+            let summary = record_static!(record.level(), record.tag());
+            self.drain.log(
+                &Record::new(
+                    &summary,
+                    &format_args!(
+                        "...suppressed {} similar messages from {}:{}",
+                        suppressed,
+                        record.module(),
+                        record.line()
+                    ),
+                    b!(),
+                ),
+                values,
+            )?;
+        }
+        self.drain.log(record, values)
+    }
 }
 
 /// Uses one decorator for `Error` and `Critical` log messages and the other for
@@ -90,3 +382,205 @@ fn log_to_decorator(
         Ok(())
     })
 }
+
+#[cfg(test)]
+pub mod test_util {
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    thread_local! {
+        /// The level up to which messages are captured on this thread and the
+        /// captured messages, or `None` if this thread isn't capturing.
+        static CAPTURED: RefCell<Option<(Level, Vec<String>)>> = RefCell::new(None);
+    }
+
+    /// A `log` logger that records messages per thread, so that tests running
+    /// in parallel only see their own messages.
+    struct CapturingLogger;
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            CAPTURED.with(|captured| match &*captured.borrow() {
+                Some((level, _)) => metadata.level() <= *level,
+                None => false,
+            })
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            CAPTURED.with(|captured| {
+                if let Some((_, messages)) = &mut *captured.borrow_mut() {
+                    messages.push(record.args().to_string());
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Runs the closure and returns the messages it logged on the current
+    /// thread at the specified level or more severe levels. Messages of less
+    /// severe levels are not even formatted, as `log_enabled!` is false for
+    /// them.
+    pub fn capture_logs(level: Level, f: impl FnOnce()) -> Vec<String> {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CapturingLogger).expect("failed to install capturing logger");
+            log::set_max_level(LevelFilter::Trace);
+        });
+
+        CAPTURED.with(|captured| *captured.borrow_mut() = Some((level, Vec::new())));
+        f();
+        CAPTURED
+            .with(|captured| captured.borrow_mut().take())
+            .map(|(_, messages)| messages)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_format_logs_json_objects() {
+        let buffer = SharedBuffer::default();
+        let logger = Logger::root(Mutex::new(json_drain(buffer.clone())).fuse(), o!());
+        slog::info!(logger, "first {}", 1);
+        slog::error!(logger, "second");
+
+        let output = buffer.0.lock().unwrap().clone();
+        let records = output
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["level"], "INFO");
+        assert_eq!(records[0]["module"], module_path!());
+        assert_eq!(records[0]["message"], "first 1");
+        assert!(records[0]["timestamp"].is_string());
+        assert_eq!(records[1]["level"], "ERROR");
+        assert_eq!(records[1]["message"], "second");
+    }
+
+    #[test]
+    fn validates_log_filters() {
+        for filter in &[
+            "",
+            "info",
+            "info,driver=debug",
+            "driver",
+            "driver=",
+            "driver=off",
+            "warn/abc",
+        ] {
+            assert!(validate_filter(filter).is_ok(), "rejected {}", filter);
+        }
+        for filter in &["driver=verbose", "driver=warn=info", "info/(", "info/a/b"] {
+            assert!(validate_filter(filter).is_err(), "accepted {}", filter);
+        }
+    }
+
+    #[test]
+    fn parses_log_format() {
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    struct RecordingDrain(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for RecordingDrain {
+        type Ok = ();
+        type Err = Never;
+        fn log(&self, record: &Record, _: &OwnedKVList) -> std::result::Result<(), Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rate_limit_suppresses_similar_messages() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let interval = Duration::from_millis(200);
+        let logger = Logger::root(
+            RateLimit::new(RecordingDrain(messages.clone()), 10, interval),
+            o!(),
+        );
+        let log = |i| slog::info!(logger, "message {}", i);
+
+        for i in 0..1000 {
+            log(i);
+        }
+        slog::info!(logger, "other message");
+        assert_eq!(messages.lock().unwrap().len(), 11);
+
+        std::thread::sleep(interval);
+        log(1000);
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 13);
+        let expected = (0..10)
+            .map(|i| format!("message {}", i))
+            .collect::<Vec<_>>();
+        assert_eq!(messages[..10], expected[..]);
+        assert_eq!(messages[10], "other message");
+        assert!(messages[11].starts_with("...suppressed 990 similar messages from "));
+        assert_eq!(messages[12], "message 1000");
+    }
+
+    #[test]
+    fn reloadable_filter_applies_new_filter() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let filter = Arc::new(ReloadableFilter::new(
+            RecordingDrain(messages.clone()),
+            "info",
+        ));
+        let handle = LogFilterHandle(filter.clone());
+        let values = OwnedKVList::from(o!());
+
+        filter
+            .log(
+                &slog::record!(Level::Debug, "", &format_args!("first"), b!()),
+                &values,
+            )
+            .unwrap();
+        handle.set_filter("debug").unwrap();
+        filter
+            .log(
+                &slog::record!(Level::Debug, "", &format_args!("second"), b!()),
+                &values,
+            )
+            .unwrap();
+        assert!(handle.set_filter("error,driver=verbose").is_err());
+        filter
+            .log(
+                &slog::record!(Level::Debug, "", &format_args!("third"), b!()),
+                &values,
+            )
+            .unwrap();
+
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec!["second".to_owned(), "third".to_owned()]
+        );
+    }
+}
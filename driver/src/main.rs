@@ -5,6 +5,7 @@ mod macros;
 
 mod contracts;
 mod driver;
+mod gas_price_strategy;
 mod gas_station;
 mod http;
 mod logging;
@@ -17,26 +18,43 @@ mod solution_submission;
 mod transport;
 mod util;
 
-use crate::contracts::{stablex_contract::StableXContractImpl, web3_provider};
+use crate::contracts::{
+    stablex_contract::{PastEventsChunking, StableXContract, StableXContractImpl},
+    token_info::TokenInfoFetcher,
+    web3_provider, web3_provider_with_failover, Web3,
+};
 use crate::driver::{
     scheduler::{AuctionTimingConfiguration, SchedulerKind},
     stablex_driver::StableXDriverImpl,
+    standby::StandbyMode,
 };
+use crate::gas_price_strategy::GasPriceStrategyKind;
 use crate::gas_station::GnosisSafeGasStation;
 use crate::http::HttpFactory;
-use crate::metrics::{HttpMetrics, MetricsServer, StableXMetrics};
+use crate::logging::LogFormat;
+use crate::metrics::{AdminToken, ComponentMetrics, HttpMetrics, MetricsServer, StableXMetrics};
 use crate::orderbook::{
-    FilteredOrderbookReader, OnchainFilteredOrderBookReader, OrderbookFilter, OrderbookReaderKind,
-    ShadowedOrderbookReader, StableXOrderBookReading,
+    ExportingOrderbookReader, FilteredOrderbookReader, OnchainFilteredOrderBookReader,
+    OrderbookFilter, OrderbookReaderKind, ShadowedOrderbookReader, StableXOrderBookReading,
+};
+use crate::price_estimation::{
+    ClampMode, KrakenPriceMode, PriceOracle, PriceSourceSettings, RecordingFormat, TokenData,
 };
-use crate::price_estimation::{PriceOracle, TokenData};
-use crate::price_finding::{Fee, SolverType};
-use crate::solution_submission::StableXSolutionSubmitter;
+use crate::price_finding::{
+    naive_solver::{self, OrderReference},
+    Fee, PriceFinderConfig, SolverType, UnpricedTokenPolicy,
+};
+use crate::solution_submission::{SolutionGasModel, StableXSolutionSubmitter};
+use crate::transport::RetryPolicy;
 
-use ethcontract::PrivateKey;
-use log::info;
+use anyhow::{anyhow, Result};
+use ethcontract::{Address, PrivateKey, U256};
+use log::{info, warn};
 use prometheus::Registry;
+use std::collections::HashSet;
+use std::iter;
 use std::num::ParseIntError;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -56,11 +74,41 @@ struct Options {
     #[structopt(long, env = "DFUSION_LOG", default_value = "info")]
     log_filter: String,
 
+    /// The format of log output, either human readable "text" or "json" with
+    /// one object per log record.
+    #[structopt(long, env = "LOG_FORMAT", default_value = "text")]
+    log_format: LogFormat,
+
+    /// The maximum number of messages per second that are logged by any single
+    /// logging statement. Further messages are suppressed and a summary of how
+    /// many were suppressed is logged instead. Unlimited if not set.
+    #[structopt(long, env = "LOG_RATE_LIMIT")]
+    log_rate_limit: Option<usize>,
+
     /// The Ethereum node URL to connect to. Make sure that the node allows for
-    /// queries without a gas limit to be able to fetch the orderbook.
+    /// queries without a gas limit to be able to fetch the orderbook. Use a
+    /// `ws://` or `wss://` URL to connect to the node over WebSockets, in which
+    /// case the event based orderbook subscribes to new events instead of
+    /// polling for them.
     #[structopt(short, long, env = "ETHEREUM_NODE_URL")]
     node_url: Url,
 
+    /// Additional Ethereum node URLs, separated by commas, that requests are
+    /// sent to in order when the node at the node URL can't be connected to.
+    /// Only supported for nodes connected to over HTTP.
+    #[structopt(long, env = "FALLBACK_NODE_URLS", use_delimiter = true)]
+    fallback_node_urls: Vec<Url>,
+
+    /// The time in milliseconds after failing over to a fallback node before
+    /// requests are sent to the node at the node URL again.
+    #[structopt(
+        long,
+        env = "NODE_FAILOVER_COOLDOWN",
+        default_value = "60000",
+        parse(try_from_str = duration_millis),
+    )]
+    node_failover_cooldown: Duration,
+
     /// The network ID used for signing transactions (e.g. 1 for mainnet, 4 for
     /// rinkeby, 5777 for ganache).
     #[structopt(short = "i", long, env = "NETWORK_ID")]
@@ -68,10 +116,32 @@ struct Options {
 
     /// Which style of solver to use. Can be one of: 'NAIVE' for the naive
     /// solver; 'MIP' for mixed integer programming solver; 'NLP' for non-linear
-    /// programming solver.
+    /// programming solver; 'max-volume-solver' for a naive solver that settles
+    /// the pair of orders with the most volume; 'external-solver' for the
+    /// solver binary specified with `--external-solver-command`.
     #[structopt(long, env = "SOLVER_TYPE", default_value = "naive-solver")]
     solver_type: SolverType,
 
+    /// Run the naive solver alongside the configured solver and use whichever
+    /// solution has the higher objective value.
+    #[structopt(
+        long,
+        env = "RACE_NAIVE_SOLVER",
+        default_value = "false",
+        parse(try_from_str)
+    )]
+    race_naive_solver: bool,
+
+    /// The solver binary to run for the external solver. It receives the
+    /// auction data as JSON on stdin and the time limit as `--time-limit`
+    /// argument, and is expected to write the solution as JSON to stdout.
+    #[structopt(
+        long,
+        env = "EXTERNAL_SOLVER_COMMAND",
+        required_if("solver-type", "external-solver")
+    )]
+    external_solver_command: Option<PathBuf>,
+
     /// JSON encoded backup token information to provide to the solver.
     ///
     /// For example: '{
@@ -85,9 +155,14 @@ struct Options {
     ///     "alias": "USDC",
     ///     "decimals": 6,
     ///     "externalPrice": 1000000000000000000000000000000,
-    ///     "shouldEstimatePrice": true
+    ///     "shouldEstimatePrice": true,
+    ///     "priceSource": "kraken"
     ///   }
     /// }'
+    ///
+    /// The optional "priceSource" field selects the external price source
+    /// ("kraken" or "dexag") used to estimate the token's price instead of the
+    /// average of all sources.
     #[structopt(long, env = "TOKEN_DATA", default_value = "{}")]
     token_data: TokenData,
 
@@ -98,7 +173,9 @@ struct Options {
     ///   "users": {
     ///     "0x7b60655Ca240AC6c76dD29c13C45BEd969Ee6F0A": { "OrderIds": [0, 1] },
     ///     "0x7b60655Ca240AC6c76dD29c13C45BEd969Ee6F0B": "All"
-    ///   }
+    ///   },
+    ///   "pair_directions": {"Blacklist": [{"buy": 1, "sell": 2}]},
+    ///   "max_amount": 1000000000000000000000000000000000
     ///  }'
     /// More examples can be found in the tests of orderbook/filtered_orderboook.rs
     #[structopt(long, env = "ORDERBOOK_FILTER", default_value = "{}")]
@@ -116,6 +193,32 @@ struct Options {
     #[structopt(long, env = "AUCTION_DATA_PAGE_SIZE", default_value = "100")]
     auction_data_page_size: u16,
 
+    /// The timeout in milliseconds for reading a page of orders from the smart
+    /// contract with the paginated orderbook reader. Uses the web3 JSON RPC
+    /// timeout if not specified.
+    #[structopt(
+        long,
+        env = "AUCTION_DATA_PAGE_TIMEOUT",
+        parse(try_from_str = duration_millis),
+    )]
+    auction_data_page_timeout: Option<Duration>,
+
+    /// The maximum number of orders the paginated orderbook reader reads
+    /// before failing, to guard against a node that keeps returning pages.
+    /// Unlimited if not specified.
+    #[structopt(long, env = "AUCTION_DATA_MAX_ORDERS")]
+    auction_data_max_orders: Option<usize>,
+
+    /// The duration in milliseconds after which the paginated orderbook reader
+    /// logs a warning with its progress when reading the orderbook. No warning
+    /// is logged if not specified.
+    #[structopt(
+        long,
+        env = "AUCTION_DATA_SLOW_READ_THRESHOLD",
+        parse(try_from_str = duration_millis),
+    )]
+    auction_data_slow_read_threshold: Option<Duration>,
+
     /// The timeout in milliseconds of web3 JSON RPC calls, defaults to 10000ms
     #[structopt(
         long,
@@ -125,6 +228,29 @@ struct Options {
     )]
     rpc_timeout: Duration,
 
+    /// Log every JSON RPC request to and response from the node at the trace
+    /// level, truncating bodies to this many bytes. Nothing is logged if not
+    /// specified.
+    #[structopt(long, env = "RPC_LOG_MAX_BODY_LENGTH")]
+    rpc_log_max_body_length: Option<usize>,
+
+    /// The JSON RPC methods, separated by commas, whose requests are retried
+    /// after transient failures like dropped connections or server errors.
+    /// Requests sending transactions and requests that timed out are never
+    /// retried.
+    #[structopt(
+        long,
+        env = "RPC_RETRY_METHODS",
+        default_value = "eth_call,eth_getLogs,eth_getBalance,eth_blockNumber",
+        use_delimiter = true
+    )]
+    rpc_retry_methods: Vec<String>,
+
+    /// The maximum number of times a JSON RPC request is retried, with a
+    /// backoff starting at 100ms and doubling up to 2s between retries.
+    #[structopt(long, env = "RPC_MAX_RETRIES", default_value = "3")]
+    rpc_max_retries: usize,
+
     /// The default timeout in milliseconds of HTTP requests to remote services
     /// such as the Gnosis Safe gas station and exchange REST APIs for fetching
     /// price estimates.
@@ -136,6 +262,15 @@ struct Options {
     )]
     http_timeout: Duration,
 
+    /// The timeout in milliseconds for establishing connections to the node
+    /// and remote services. Only the request timeouts apply if not specified.
+    #[structopt(
+        long,
+        env = "HTTP_CONNECT_TIMEOUT",
+        parse(try_from_str = duration_millis),
+    )]
+    http_connect_timeout: Option<Duration>,
+
     /// The offset from the start of a batch in seconds at which point we
     /// should start solving.
     #[structopt(
@@ -161,6 +296,146 @@ struct Options {
     #[structopt(long, env = "MIN_AVG_FEE_PER_ORDER", default_value = "0")]
     min_avg_fee_per_order: u128,
 
+    /// Log every pair of orders rejected by the naive solver along with the
+    /// reason it was rejected. Logs are emitted at the trace level.
+    #[structopt(
+        long,
+        env = "LOG_REJECTED_MATCHES",
+        default_value = "false",
+        parse(try_from_str)
+    )]
+    log_rejected_matches: bool,
+
+    /// Allow the naive solver to match orders on token pairs that don't
+    /// include the fee token by bridging them to the fee token with two other
+    /// orders.
+    #[structopt(
+        long,
+        env = "NAIVE_SOLVER_FEE_TOKEN_BRIDGING",
+        default_value = "false",
+        parse(try_from_str)
+    )]
+    naive_solver_fee_token_bridging: bool,
+
+    /// The accounts, separated by commas, whose orders the naive solver is
+    /// restricted to match, for example during incident response. Orders of
+    /// all accounts are matched if no accounts are specified.
+    #[structopt(
+        long,
+        env = "NAIVE_SOLVER_ALLOWED_ACCOUNTS",
+        use_delimiter = true,
+        parse(try_from_str = address)
+    )]
+    naive_solver_allowed_accounts: Vec<Address>,
+
+    /// How the optimization solvers handle orders on tokens without any price
+    /// information. Can be one of: 'ignore' to solve the batch anyway;
+    /// 'skip-batch' to not solve the batch; 'priced-markets-only' to remove
+    /// orders on unpriced tokens from the batch.
+    #[structopt(long, env = "UNPRICED_TOKEN_POLICY", default_value = "ignore")]
+    unpriced_token_policy: UnpricedTokenPolicy,
+
+    /// The strategy with which to compute the gas price of solution
+    /// submissions from the node's current gas price. Can be one of:
+    /// 'fixed:<wei>' to always use the same gas price; 'multiplier:<factor>'
+    /// to use a multiple of the node's gas price. Uses the Gnosis Safe gas
+    /// station estimate if not specified.
+    #[structopt(long, env = "GAS_PRICE_STRATEGY")]
+    gas_price_strategy: Option<GasPriceStrategyKind>,
+
+    /// The maximum gas price in wei computed by the gas price strategy.
+    #[structopt(long, env = "GAS_PRICE_STRATEGY_CAP")]
+    gas_price_strategy_cap: Option<u128>,
+
+    /// The maximum amount of gas a solution submission is estimated to use.
+    /// Solutions with a higher estimate are not submitted. There is no limit
+    /// if not specified.
+    #[structopt(long, env = "SOLUTION_GAS_BUDGET")]
+    solution_gas_budget: Option<u64>,
+
+    /// The fixed amount of gas a solution submission is assumed to use when
+    /// checking it against the gas budget.
+    #[structopt(long, env = "SOLUTION_BASE_GAS", default_value = "150000")]
+    solution_base_gas: u64,
+
+    /// The amount of gas a solution submission is assumed to use for every
+    /// executed order when checking it against the gas budget.
+    #[structopt(long, env = "SOLUTION_GAS_PER_EXECUTED_ORDER", default_value = "70000")]
+    solution_gas_per_executed_order: u64,
+
+    /// The amount of gas a solution submission is assumed to use for every
+    /// token price when checking it against the gas budget.
+    #[structopt(long, env = "SOLUTION_GAS_PER_PRICE", default_value = "15000")]
+    solution_gas_per_price: u64,
+
+    /// Simulate solution submissions with an `eth_call` instead of sending
+    /// transactions. This allows validating solver output against the
+    /// contract without spending gas.
+    #[structopt(
+        long,
+        env = "SOLUTION_DRY_RUN",
+        default_value = "false",
+        parse(try_from_str)
+    )]
+    solution_dry_run: bool,
+
+    /// The number of confirmations the block the orderbook is read at must
+    /// have. Reading the orderbook a few blocks behind the most recent block
+    /// avoids trusting balances from deposits that may still be reorged out.
+    /// The orderbook is read at the pending block if set to 0. Not supported
+    /// by the event based orderbook, see `EVENT_POLL_CONFIRMATIONS` instead.
+    #[structopt(long, env = "ORDERBOOK_MIN_CONFIRMATIONS", default_value = "0")]
+    orderbook_min_confirmations: u64,
+
+    /// The interval in seconds in which the event based orderbook polls the
+    /// node for new events. If not specified, new events are received with a
+    /// filter instead, which some node providers do not support reliably.
+    #[structopt(long, env = "EVENT_POLL_INTERVAL", parse(try_from_str = duration_secs))]
+    event_poll_interval: Option<Duration>,
+
+    /// The number of confirmations a block needs before its events are
+    /// applied when polling for events. Polled events are never reverted, so
+    /// this needs to be larger than the depth of any expected reorg.
+    #[structopt(long, env = "EVENT_POLL_CONFIRMATIONS", default_value = "10")]
+    event_poll_confirmations: u64,
+
+    /// The number of blocks to wait for after a solution submission was mined
+    /// before considering it final. Waiting for more confirmations protects
+    /// against acting on submissions that are later reorged out.
+    #[structopt(long, env = "SOLUTION_CONFIRMATIONS", default_value = "0")]
+    solution_confirmations: usize,
+
+    /// Explain why the specified order, given as '<account>:<order id>', is
+    /// or isn't matched with each of the other orders of a batch and exit
+    /// instead of running the driver.
+    #[structopt(long)]
+    explain_order: Option<OrderReference>,
+
+    /// The batch in which to explain the matching of `--explain-order`.
+    /// Defaults to the batch that is currently being solved.
+    #[structopt(long)]
+    explain_batch: Option<u32>,
+
+    /// The block at which to read the orderbook for `--explain-order`, to
+    /// explain the matching of an order as it was in the past. Defaults to
+    /// the block the orderbook is usually read at. Only supported by the
+    /// paginated and onchain filtered orderbooks.
+    #[structopt(long)]
+    explain_block: Option<u64>,
+
+    /// The maximum number of blocks for which the event based orderbook
+    /// fetches past events in a single request. Nodes often limit the block
+    /// range or number of logs of a request, so fetching the whole history of
+    /// the contract at once can fail. Past events are fetched in a single
+    /// request if not specified.
+    #[structopt(long, env = "PAST_EVENTS_CHUNK_SIZE")]
+    past_events_chunk_size: Option<u64>,
+
+    /// The block from which past events are fetched when fetching them in
+    /// chunks, usually the block in which the contract was deployed.
+    #[structopt(long, env = "PAST_EVENTS_FROM_BLOCK", default_value = "0")]
+    past_events_from_block: u64,
+
     /// The kind of scheduler to use.
     #[structopt(long, env = "SCHEDULER", default_value = "system")]
     scheduler: SchedulerKind,
@@ -174,6 +449,81 @@ struct Options {
     )]
     price_source_update_interval: Duration,
 
+    /// The minimum price in OWL atoms that an estimated token price can have.
+    /// Prices below this value are handled according to the price clamp mode.
+    /// By default no estimated price is considered too low.
+    #[structopt(long, env = "PRICE_SOURCE_MIN_PRICE", default_value = "0")]
+    price_source_min_price: u128,
+
+    /// The maximum price in OWL atoms that an estimated token price can have.
+    /// Prices above this value are handled according to the price clamp mode.
+    #[structopt(
+        long,
+        env = "PRICE_SOURCE_MAX_PRICE",
+        default_value = "340282366920938463463374607431768211455"
+    )]
+    price_source_max_price: u128,
+
+    /// How to handle estimated prices that are outside of the configured
+    /// bounds. Can be one of: 'drop' to ignore the estimate and use the backup
+    /// external price; 'clamp' to use the closest bound instead.
+    #[structopt(long, env = "PRICE_SOURCE_CLAMP_MODE", default_value = "drop")]
+    price_source_clamp_mode: ClampMode,
+
+    /// Estimate Kraken prices as the volume weighted average price of this
+    /// many of the most recent candles instead of the ticker's 24h average
+    /// price, as the latter lags behind the market during illiquid periods.
+    #[structopt(long, env = "KRAKEN_VWAP_CANDLES")]
+    kraken_vwap_candles: Option<usize>,
+
+    /// The interval in minutes covered by each candle when estimating Kraken
+    /// prices from candles.
+    #[structopt(long, env = "KRAKEN_VWAP_INTERVAL", default_value = "60")]
+    kraken_vwap_interval: u32,
+
+    /// Only use price estimates that Kraken and DEX.AG agree on within this
+    /// tolerance relative to the lower estimate, e.g. 0.02 for 2%, instead of
+    /// averaging them regardless. Does not apply to tokens with a preferred
+    /// price source.
+    #[structopt(long, env = "PRICE_SOURCE_AGREEMENT_TOLERANCE")]
+    price_source_agreement_tolerance: Option<f64>,
+
+    /// File to which every price source update gets appended, building a
+    /// price time series for backtesting. Prices are not recorded if not
+    /// specified.
+    #[structopt(long, env = "PRICE_SOURCE_RECORDING_PATH", parse(from_os_str))]
+    price_source_recording_path: Option<PathBuf>,
+
+    /// The format of the recorded prices. Can be one of: 'csv' for one
+    /// `timestamp,token_id,price` row per token; 'jsonl' for one JSON object
+    /// with all prices per update.
+    #[structopt(long, env = "PRICE_SOURCE_RECORDING_FORMAT", default_value = "jsonl")]
+    price_source_recording_format: RecordingFormat,
+
+    /// Run the driver as a warm standby that keeps its orderbook in sync but
+    /// does not solve batches until it gets promoted to active with a
+    /// `POST /promote` request to the metrics server. Requires an admin token.
+    #[structopt(long, env = "STANDBY", default_value = "false", parse(try_from_str))]
+    standby: bool,
+
+    /// The token that requests to admin endpoints of the metrics server, such
+    /// as `POST /promote`, have to include in an `X-Admin-Token` header. All
+    /// admin requests are rejected if not specified.
+    #[structopt(long, env = "ADMIN_TOKEN")]
+    admin_token: Option<AdminToken>,
+
+    /// Refuse to read the orderbook and solve batches while the node reports
+    /// that it is still syncing, as the orderbook it returns may be stale.
+    /// Disabled by default as it requires an additional `eth_syncing` request
+    /// for every batch.
+    #[structopt(
+        long,
+        env = "CHECK_NODE_SYNC",
+        default_value = "false",
+        parse(try_from_str)
+    )]
+    check_node_sync: bool,
+
     /// Use a shadowed orderbook reader along side a primary reader so that the
     /// queried data can be compared and produce log errors in case they
     /// disagree.
@@ -184,61 +534,182 @@ struct Options {
         parse(try_from_str)
     )]
     use_shadowed_orderbook: bool,
+
+    /// The fraction of orderbook reads that are compared with the shadow
+    /// reader when using a shadowed orderbook, between 0 and 1.
+    #[structopt(long, env = "SHADOWED_ORDERBOOK_SAMPLE_RATE", default_value = "1")]
+    shadowed_orderbook_sample_rate: f64,
+
+    /// Directory to which the auction data of every batch that is read gets
+    /// exported as JSON, for use with offline solver tooling. No data is
+    /// exported if not specified.
+    #[structopt(long, env = "AUCTION_DATA_EXPORT_DIR", parse(from_os_str))]
+    auction_data_export_dir: Option<PathBuf>,
+
+    /// Directory to which the executed orders of every successfully submitted
+    /// solution get exported as CSV, for reconciling settled trades. No data
+    /// is exported if not specified.
+    #[structopt(long, env = "EXECUTED_ORDERS_EXPORT_DIR", parse(from_os_str))]
+    executed_orders_export_dir: Option<PathBuf>,
 }
 
 fn main() {
     let options = Options::from_args();
-    let (_, _guard) = logging::init(&options.log_filter);
+    let (_, _guard, log_filter) = logging::init(
+        &options.log_filter,
+        options.log_format,
+        options.log_rate_limit,
+    );
     info!("Starting driver with runtime options: {:#?}", options);
+    assert!(
+        options.explain_block.is_none() || options.primary_orderbook.supports_reading_at_block(),
+        "the {:?} orderbook reader does not support reading the orderbook at a block",
+        options.primary_orderbook
+    );
 
     // Set up metrics and serve in separate thread.
     let prometheus_registry = Arc::new(Registry::new());
+    let component_metrics = ComponentMetrics::new(&prometheus_registry).unwrap();
     let stablex_metrics = StableXMetrics::new(prometheus_registry.clone());
     let http_metrics = HttpMetrics::new(&prometheus_registry).unwrap();
-    let metric_server = MetricsServer::new(prometheus_registry);
+    assert!(
+        !options.standby || options.admin_token.is_some(),
+        "a driver in standby can only be promoted with an admin token"
+    );
+    let standby_mode = Arc::new(StandbyMode::new(options.standby));
+    let mut metric_server = MetricsServer::new(prometheus_registry)
+        .with_standby_mode(standby_mode.clone())
+        .with_log_filter(log_filter);
+    if let Some(admin_token) = options.admin_token.clone() {
+        metric_server = metric_server.with_admin_token(admin_token);
+    }
     thread::spawn(move || {
         metric_server.serve(9586);
     });
 
     // Set up shared HTTP client and HTTP services.
-    let http_factory = HttpFactory::new(options.http_timeout, http_metrics);
-    let web3 = web3_provider(
-        &http_factory,
-        options.node_url.as_str(),
-        options.rpc_timeout,
-    )
+    let mut http_factory = HttpFactory::new(options.http_timeout, http_metrics);
+    if let Some(connect_timeout) = options.http_connect_timeout {
+        http_factory = http_factory.with_connect_timeout(connect_timeout);
+    }
+    let retry_policy = RetryPolicy {
+        methods: options.rpc_retry_methods.iter().cloned().collect(),
+        max_retries: options.rpc_max_retries,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_secs(2),
+    };
+    let web3 = if options.fallback_node_urls.is_empty() {
+        web3_provider(
+            &http_factory,
+            options.node_url.as_str(),
+            options.rpc_timeout,
+            options.rpc_log_max_body_length,
+            retry_policy,
+        )
+    } else {
+        web3_provider_with_failover(
+            &http_factory,
+            iter::once(&options.node_url)
+                .chain(&options.fallback_node_urls)
+                .map(|url| url.as_str().to_owned())
+                .collect(),
+            options.node_failover_cooldown,
+            options.rpc_timeout,
+            options.rpc_log_max_body_length,
+            retry_policy,
+        )
+    }
     .unwrap();
     let gas_station = GnosisSafeGasStation::new(&http_factory, gas_station::DEFAULT_URI).unwrap();
+    let recording_format = options.price_source_recording_format;
     let price_oracle = PriceOracle::new(
         &http_factory,
-        options.token_data,
+        options.token_data.clone(),
         options.price_source_update_interval,
+        (
+            options.price_source_min_price,
+            options.price_source_max_price,
+        ),
+        options.price_source_clamp_mode,
+        PriceSourceSettings {
+            kraken_price_mode: match options.kraken_vwap_candles {
+                Some(candles) => KrakenPriceMode::Vwap {
+                    interval_minutes: options.kraken_vwap_interval,
+                    candles,
+                },
+                None => KrakenPriceMode::Ticker,
+            },
+            agreement_tolerance: options.price_source_agreement_tolerance,
+        },
+        options
+            .price_source_recording_path
+            .map(|path| (path, recording_format)),
+        &component_metrics,
     )
-    .unwrap();
+    .expect("failed to create price oracle");
 
     // Set up web3 and contract connection.
-    let contract = Arc::new(
-        StableXContractImpl::new(&web3, options.private_key.clone(), options.network_id).unwrap(),
-    );
+    let mut contract =
+        StableXContractImpl::new(&web3, options.private_key.clone(), options.network_id)
+            .unwrap()
+            .with_confirmations(options.solution_confirmations);
+    if let Some(chunk_size) = options.past_events_chunk_size {
+        contract = contract.with_past_events_chunking(PastEventsChunking {
+            from_block: options.past_events_from_block,
+            chunk_size,
+        });
+    }
+    let contract = Arc::new(contract);
     info!("Using contract at {:?}", contract.address());
     info!("Using account {:?}", contract.account());
+    check_token_decimals(contract.clone(), &options.token_data);
 
     // Set up solver.
     let fee = Some(Fee::default());
+    let naive_solver_allowed_accounts = if options.naive_solver_allowed_accounts.is_empty() {
+        None
+    } else {
+        Some(
+            options
+                .naive_solver_allowed_accounts
+                .into_iter()
+                .collect::<HashSet<_>>(),
+        )
+    };
     let price_finder = price_finding::create_price_finder(
-        fee,
-        options.solver_type,
+        PriceFinderConfig {
+            fee: fee.clone(),
+            solver_type: options.solver_type,
+            min_avg_fee_per_order: options.min_avg_fee_per_order,
+            log_rejected_matches: options.log_rejected_matches,
+            fee_token_bridging: options.naive_solver_fee_token_bridging,
+            allowed_accounts: naive_solver_allowed_accounts,
+            unpriced_token_policy: options.unpriced_token_policy,
+            external_solver_command: options.external_solver_command,
+            race_naive_solver: options.race_naive_solver,
+        },
         price_oracle,
-        options.min_avg_fee_per_order,
-    );
+        component_metrics.clone(),
+    )
+    .expect("failed to create price finder");
 
     // Create the orderbook reader.
-    let primary_orderbook = options.primary_orderbook.create(
-        contract.clone(),
-        options.auction_data_page_size,
-        &options.orderbook_filter,
-        web3,
-    );
+    let primary_orderbook = options
+        .primary_orderbook
+        .create(
+            contract.clone(),
+            options.auction_data_page_size,
+            options.auction_data_page_timeout,
+            &options.orderbook_filter,
+            options.orderbook_min_confirmations,
+            options.auction_data_max_orders,
+            options.auction_data_slow_read_threshold,
+            options.event_poll_interval,
+            options.event_poll_confirmations,
+            web3,
+            &component_metrics,
+        )
+        .expect("failed to create orderbook reader");
 
     // NOTE: Keep the shadowed orderbook around so it doesn't get dropped and we
     //   can pass a reference to the filtered orderbook reader.
@@ -250,7 +721,8 @@ fn main() {
                 &options.orderbook_filter,
             );
             let shadowed_orderbook =
-                ShadowedOrderbookReader::new(primary_orderbook.as_ref(), shadow_orderbook);
+                ShadowedOrderbookReader::new(primary_orderbook.as_ref(), shadow_orderbook)
+                    .with_sample_rate(options.shadowed_orderbook_sample_rate);
             Box::new(shadowed_orderbook)
         } else {
             primary_orderbook
@@ -259,17 +731,60 @@ fn main() {
     info!("Orderbook filter: {:?}", options.orderbook_filter);
     let filtered_orderbook =
         FilteredOrderbookReader::new(&*unfiltered_orderbook, options.orderbook_filter);
+    let orderbook: Box<dyn StableXOrderBookReading + Sync> = match options.auction_data_export_dir {
+        Some(directory) => Box::new(ExportingOrderbookReader::new(
+            &filtered_orderbook,
+            directory,
+        )),
+        None => Box::new(filtered_orderbook),
+    };
+
+    if let Some(order) = options.explain_order {
+        explain_order(
+            order,
+            options.explain_batch,
+            options.explain_block,
+            &*contract,
+            &*orderbook,
+            &fee,
+        )
+        .expect("failed to explain order");
+        return;
+    }
 
     // Set up solution submitter.
-    let solution_submitter = StableXSolutionSubmitter::new(&*contract, &gas_station);
+    let gas_price_cap = options.gas_price_strategy_cap.map(U256::from);
+    let gas_price_strategy = options
+        .gas_price_strategy
+        .map(|strategy| strategy.create(gas_price_cap));
+    let solution_submitter = StableXSolutionSubmitter::new(
+        &*contract,
+        &gas_station,
+        gas_price_strategy.as_deref(),
+        options.solution_gas_budget,
+        component_metrics,
+    )
+    .with_gas_model(SolutionGasModel {
+        base: options.solution_base_gas,
+        per_executed_order: options.solution_gas_per_executed_order,
+        per_price: options.solution_gas_per_price,
+    })
+    .with_dry_run(options.solution_dry_run);
 
     // Set up the driver and start the run-loop.
-    let driver = StableXDriverImpl::new(
+    let mut driver = StableXDriverImpl::new(
         &*price_finder,
-        &filtered_orderbook,
+        &*orderbook,
         &solution_submitter,
         &stablex_metrics,
-    );
+    )
+    .with_standby_mode(&standby_mode);
+    if options.check_node_sync {
+        driver = driver.with_node_sync_check(&*contract);
+    }
+    if let Some(directory) = options.executed_orders_export_dir {
+        driver = driver.with_executed_orders_export_dir(directory);
+    }
 
     let scheduler_config =
         AuctionTimingConfiguration::new(options.target_start_solve_time, options.solver_time_limit);
@@ -280,6 +795,59 @@ fn main() {
     scheduler.start();
 }
 
+/// Warns about configured tokens whose number of decimals differs from the
+/// decimals reported by their ERC20 contract, as their estimated prices would
+/// be off by orders of magnitude.
+fn check_token_decimals(contract: Arc<dyn StableXContract + Send + Sync>, token_data: &TokenData) {
+    let token_info = TokenInfoFetcher::new(contract);
+    if let Err(err) = token_info.prefetch_addresses(token_data.iter().map(|(id, _)| id)) {
+        warn!("failed to resolve the token addresses: {:?}", err);
+        return;
+    }
+    for (token_id, configured) in token_data.iter() {
+        match token_info.get_token_info(token_id) {
+            Ok(info) if info.decimals != configured.decimals => warn!(
+                "token {:?} ({}) is configured with {} decimals but its contract reports {}",
+                token_id, configured.alias, configured.decimals, info.decimals,
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("failed to check the decimals of {:?}: {:?}", token_id, err),
+        }
+    }
+}
+
+/// Logs why the specified order is or isn't matched with each of the other
+/// orders of a batch, which defaults to the batch that is currently being
+/// solved.
+fn explain_order(
+    order: OrderReference,
+    batch_id: Option<u32>,
+    block_number: Option<u64>,
+    contract: &dyn StableXContract,
+    orderbook: &dyn StableXOrderBookReading,
+    fee: &Option<Fee>,
+) -> Result<()> {
+    let batch_id = match batch_id {
+        Some(batch_id) => batch_id,
+        None => contract
+            .get_current_auction_index()?
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("no batch has been closed yet"))?,
+    };
+    let (state, orders) = match block_number {
+        Some(block_number) => orderbook.get_auction_data_at_block(batch_id.into(), block_number)?,
+        None => orderbook.get_auction_data(batch_id.into())?,
+    };
+    info!(
+        "Matches of order {} of {:?} in batch {}:",
+        order.id, order.account_id, batch_id
+    );
+    for (other, outcome) in naive_solver::explain_order_matches(order, &orders, &state, fee)? {
+        info!("order {} of {:?}: {}", other.id, other.account_id, outcome);
+    }
+    Ok(())
+}
+
 fn duration_millis(s: &str) -> Result<Duration, ParseIntError> {
     Ok(Duration::from_millis(s.parse()?))
 }
@@ -287,3 +855,7 @@ fn duration_millis(s: &str) -> Result<Duration, ParseIntError> {
 fn duration_secs(s: &str) -> Result<Duration, ParseIntError> {
     Ok(Duration::from_secs(s.parse()?))
 }
+
+fn address(s: &str) -> Result<Address, rustc_hex::FromHexError> {
+    s.trim_start_matches("0x").parse()
+}
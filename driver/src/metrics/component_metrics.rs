@@ -0,0 +1,218 @@
+use anyhow::Result;
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Counters and histograms for instrumenting individual driver components.
+///
+/// The metrics are shared between clones, so that each component can own a
+/// clone while all of them report to the same registry.
+#[derive(Clone, Debug)]
+pub struct ComponentMetrics {
+    registry: Arc<Registry>,
+    orderbook_reads: IntCounter,
+    orderbook_read_errors: IntCounter,
+    orderbook_read_duration: Histogram,
+    price_finding_duration: Histogram,
+    solutions_submitted: IntCounter,
+    kraken_price_requests: IntCounter,
+    kraken_price_request_errors: IntCounter,
+}
+
+impl ComponentMetrics {
+    /// Creates the component metrics and registers them with the registry.
+    pub fn new(registry: &Arc<Registry>) -> Result<Self> {
+        let orderbook_reads = IntCounter::new(
+            "dfusion_service_orderbook_read_total",
+            "number of orderbook reads",
+        )?;
+        registry.register(Box::new(orderbook_reads.clone()))?;
+
+        let orderbook_read_errors = IntCounter::new(
+            "dfusion_service_orderbook_read_errors_total",
+            "number of failed orderbook reads",
+        )?;
+        registry.register(Box::new(orderbook_read_errors.clone()))?;
+
+        let orderbook_read_duration = Histogram::with_opts(HistogramOpts::new(
+            "dfusion_service_orderbook_read_duration_seconds",
+            "duration in seconds of orderbook reads",
+        ))?;
+        registry.register(Box::new(orderbook_read_duration.clone()))?;
+
+        let price_finding_duration = Histogram::with_opts(HistogramOpts::new(
+            "dfusion_service_price_finding_duration_seconds",
+            "duration in seconds of finding a solution for a batch",
+        ))?;
+        registry.register(Box::new(price_finding_duration.clone()))?;
+
+        let solutions_submitted = IntCounter::new(
+            "dfusion_service_solution_submitted_total",
+            "number of successfully submitted solutions",
+        )?;
+        registry.register(Box::new(solutions_submitted.clone()))?;
+
+        let kraken_price_requests = IntCounter::new(
+            "dfusion_service_kraken_price_requests_total",
+            "number of price requests to Kraken",
+        )?;
+        registry.register(Box::new(kraken_price_requests.clone()))?;
+
+        let kraken_price_request_errors = IntCounter::new(
+            "dfusion_service_kraken_price_request_errors_total",
+            "number of failed price requests to Kraken",
+        )?;
+        registry.register(Box::new(kraken_price_request_errors.clone()))?;
+
+        Ok(ComponentMetrics {
+            registry: registry.clone(),
+            orderbook_reads,
+            orderbook_read_errors,
+            orderbook_read_duration,
+            price_finding_duration,
+            solutions_submitted,
+            kraken_price_requests,
+            kraken_price_request_errors,
+        })
+    }
+
+    /// Records an orderbook read that took the specified time.
+    pub fn orderbook_read(&self, elapsed: Duration, success: bool) {
+        self.orderbook_reads.inc();
+        if !success {
+            self.orderbook_read_errors.inc();
+        }
+        self.orderbook_read_duration.observe(elapsed.as_secs_f64());
+    }
+
+    /// Records the time it took to find a solution for a batch.
+    pub fn price_finding(&self, elapsed: Duration) {
+        self.price_finding_duration.observe(elapsed.as_secs_f64());
+    }
+
+    /// Records a successfully submitted solution.
+    pub fn solution_submitted(&self) {
+        self.solutions_submitted.inc();
+    }
+
+    /// Records a price request to Kraken.
+    pub fn kraken_price_request(&self, success: bool) {
+        self.kraken_price_requests.inc();
+        if !success {
+            self.kraken_price_request_errors.inc();
+        }
+    }
+
+    /// Returns all metrics of the registry the component metrics are
+    /// registered with in the Prometheus text exposition format.
+    pub fn gather(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("could not encode metrics");
+        String::from_utf8(buffer).expect("metrics are not valid UTF-8")
+    }
+}
+
+/// Component metrics that are registered with a registry of their own, so
+/// that components can be created in tests without one.
+#[cfg(test)]
+impl Default for ComponentMetrics {
+    fn default() -> Self {
+        ComponentMetrics::new(&Default::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use prometheus::Registry;
+
+    /// Returns the value of a counter, or the number of observations of a
+    /// histogram, gathered from the registry.
+    pub fn metric_value(registry: &Registry, name: &str) -> u64 {
+        let families = registry.gather();
+        let family = families
+            .iter()
+            .find(|family| family.get_name() == name)
+            .unwrap_or_else(|| panic!("metric {} is not registered", name));
+        let metric = &family.get_metric()[0];
+        if metric.has_histogram() {
+            metric.get_histogram().get_sample_count()
+        } else {
+            metric.get_counter().get_value() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::metric_value;
+    use super::*;
+
+    #[test]
+    fn registers_component_metrics() {
+        let registry = Arc::new(Registry::new());
+        ComponentMetrics::new(&registry).unwrap();
+        let names = registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_owned())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"dfusion_service_solution_submitted_total".to_owned()));
+        assert!(names.contains(&"dfusion_service_orderbook_read_duration_seconds".to_owned()));
+    }
+
+    #[test]
+    fn gathers_text_exposition_format() {
+        let metrics = ComponentMetrics::new(&Arc::new(Registry::new())).unwrap();
+        metrics.solution_submitted();
+        assert!(metrics
+            .gather()
+            .lines()
+            .any(|line| line == "dfusion_service_solution_submitted_total 1"));
+    }
+
+    #[test]
+    fn clones_share_metrics() {
+        let registry = Arc::new(Registry::new());
+        let metrics = ComponentMetrics::new(&registry).unwrap();
+        metrics.clone().solution_submitted();
+        metrics.solution_submitted();
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_solution_submitted_total"),
+            2
+        );
+    }
+
+    #[test]
+    fn counts_errors_separately() {
+        let registry = Arc::new(Registry::new());
+        let metrics = ComponentMetrics::new(&registry).unwrap();
+        metrics.orderbook_read(Duration::from_secs(1), true);
+        metrics.orderbook_read(Duration::from_secs(1), false);
+        metrics.kraken_price_request(false);
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_total"),
+            2
+        );
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_errors_total"),
+            1
+        );
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_duration_seconds"),
+            2
+        );
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_kraken_price_requests_total"),
+            1
+        );
+        assert_eq!(
+            metric_value(
+                &registry,
+                "dfusion_service_kraken_price_request_errors_total"
+            ),
+            1
+        );
+    }
+}
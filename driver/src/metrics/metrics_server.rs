@@ -1,22 +1,114 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io::Read;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::driver::standby::StandbyMode;
+use crate::logging::LogFilterHandle;
+use anyhow::{ensure, Error, Result};
 use prometheus::{Encoder, Registry, TextEncoder};
-use rouille::{start_server, Response};
+use rouille::{start_server, Request, Response};
+
+/// The header with which requests to admin endpoints get authorized.
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+/// A shared secret that requests to admin endpoints, which change the state of
+/// the driver, have to include in the `X-Admin-Token` header.
+#[derive(Clone, PartialEq)]
+pub struct AdminToken(String);
+
+impl FromStr for AdminToken {
+    type Err = Error;
+
+    fn from_str(token: &str) -> Result<Self> {
+        ensure!(!token.is_empty(), "admin token must not be empty");
+        Ok(AdminToken(token.to_owned()))
+    }
+}
+
+impl Debug for AdminToken {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // NOTE: Never log the secret itself.
+        f.write_str("AdminToken(..)")
+    }
+}
 
 pub struct MetricsServer {
     registry: Arc<Registry>,
+    standby_mode: Option<Arc<StandbyMode>>,
+    admin_token: Option<AdminToken>,
+    log_filter: Option<LogFilterHandle>,
 }
 
 impl MetricsServer {
     pub fn new(registry: Arc<Registry>) -> Self {
-        Self { registry }
+        Self {
+            registry,
+            standby_mode: None,
+            admin_token: None,
+            log_filter: None,
+        }
+    }
+
+    /// Additionally serves a `POST /promote` admin endpoint that promotes a
+    /// driver running in standby to be active.
+    pub fn with_standby_mode(mut self, standby_mode: Arc<StandbyMode>) -> Self {
+        self.standby_mode = Some(standby_mode);
+        self
+    }
+
+    /// Sets the token that requests to admin endpoints have to include. Admin
+    /// endpoints reject all requests if no token is set.
+    pub fn with_admin_token(mut self, admin_token: AdminToken) -> Self {
+        self.admin_token = Some(admin_token);
+        self
+    }
+
+    /// Additionally serves a `POST /log-filter` admin endpoint that replaces
+    /// the log filter with the one in the request body.
+    pub fn with_log_filter(mut self, log_filter: LogFilterHandle) -> Self {
+        self.log_filter = Some(log_filter);
+        self
     }
+
     pub fn serve(&self, port: u16) {
         let addr: SocketAddr = ([0, 0, 0, 0], port).into();
         let registry = self.registry.clone();
+        let standby_mode = self.standby_mode.clone();
+        let admin_token = self.admin_token.clone();
+        let log_filter = self.log_filter.clone();
         let encoder = TextEncoder::new();
-        start_server(addr, move |_| {
+        start_server(addr, move |request| {
+            if let Some(standby_mode) = &standby_mode {
+                if request.method() == "POST" && request.url() == "/promote" {
+                    if !is_authorized(request, admin_token.as_ref()) {
+                        return Response::text("forbidden").with_status_code(403);
+                    }
+                    standby_mode.promote();
+                    return Response::text("active");
+                }
+            }
+            if let Some(log_filter) = &log_filter {
+                if request.method() == "POST" && request.url() == "/log-filter" {
+                    if !is_authorized(request, admin_token.as_ref()) {
+                        return Response::text("forbidden").with_status_code(403);
+                    }
+                    let mut filter = String::new();
+                    if let Some(mut body) = request.data() {
+                        if body.read_to_string(&mut filter).is_err() {
+                            return Response::text("invalid log filter").with_status_code(400);
+                        }
+                    }
+                    let filter = filter.trim();
+                    if let Err(err) = log_filter.set_filter(filter) {
+                        return Response::text(format!("invalid log filter: {}", err))
+                            .with_status_code(400);
+                    }
+                    return Response::text(filter);
+                }
+            }
+
             let metric_families = registry.gather();
             let mut buffer = vec![];
             encoder
@@ -26,3 +118,54 @@ impl MetricsServer {
         })
     }
 }
+
+/// Returns whether a request to an admin endpoint includes the admin token.
+fn is_authorized(request: &Request, admin_token: Option<&AdminToken>) -> bool {
+    match admin_token {
+        Some(AdminToken(token)) => request.header(ADMIN_TOKEN_HEADER) == Some(token.as_str()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn promote_request(headers: &[(&str, &str)]) -> Request {
+        Request::fake_http(
+            "POST",
+            "/promote",
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn admin_requests_require_token() {
+        let token = "secret".parse::<AdminToken>().unwrap();
+
+        assert!(is_authorized(
+            &promote_request(&[("X-Admin-Token", "secret")]),
+            Some(&token)
+        ));
+        assert!(!is_authorized(
+            &promote_request(&[("X-Admin-Token", "wrong")]),
+            Some(&token)
+        ));
+        assert!(!is_authorized(&promote_request(&[]), Some(&token)));
+        assert!(!is_authorized(
+            &promote_request(&[("X-Admin-Token", "secret")]),
+            None
+        ));
+    }
+
+    #[test]
+    fn admin_token_is_not_logged() {
+        let token = "secret".parse::<AdminToken>().unwrap();
+        assert!(!format!("{:?}", token).contains("secret"));
+        assert!("".parse::<AdminToken>().is_err());
+    }
+}
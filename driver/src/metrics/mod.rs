@@ -1,7 +1,9 @@
+pub mod component_metrics;
 mod http_metrics;
 mod metrics_server;
 mod stablex_metrics;
 
+pub use component_metrics::ComponentMetrics;
 pub use http_metrics::{HttpLabel, HttpMetrics};
-pub use metrics_server::MetricsServer;
+pub use metrics_server::{AdminToken, MetricsServer};
 pub use stablex_metrics::StableXMetrics;
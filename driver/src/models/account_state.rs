@@ -1,10 +1,25 @@
 use ethcontract::Address;
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::rust::display_fromstr;
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
 
 /// Maps a user and a token id to the balance the user has of this token.
+///
+/// Since JSON object keys can't be tuples, the account state is serialized as
+/// a list of balances sorted by user and token.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct AccountState(pub HashMap<(Address, u16), u128>);
 
+/// The serialization format of a single entry of the account state.
+#[derive(Deserialize, Serialize)]
+struct Balance {
+    account: Address,
+    token: u16,
+    #[serde(with = "display_fromstr")]
+    balance: u128,
+}
+
 impl AccountState {
     pub fn read_balance(&self, token_id: u16, account_id: Address) -> u128 {
         self.0.get(&(account_id, token_id)).cloned().unwrap_or(0)
@@ -13,6 +28,85 @@ impl AccountState {
     pub fn user_token_pairs(&self) -> impl Iterator<Item = (Address, u16)> + '_ {
         self.0.iter().map(|(&pair, _)| pair)
     }
+
+    /// Returns the sum of the balances of all users for a token, saturating
+    /// at `u128::MAX`.
+    pub fn total_balance(&self, token_id: u16) -> u128 {
+        self.0
+            .iter()
+            .filter(|((_, token), _)| *token == token_id)
+            .fold(0u128, |total, (_, balance)| total.saturating_add(*balance))
+    }
+
+    /// Returns the distinct ids of the tokens that users have balances of, in
+    /// ascending order.
+    pub fn tokens(&self) -> impl Iterator<Item = u16> {
+        self.user_token_pairs()
+            .map(|(_, token_id)| token_id)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    /// Returns the change in balance from this account state to the other one
+    /// for every user and token whose balance differs, where missing balances
+    /// are treated as zero. Changes that exceed the range of an `i128` are
+    /// clamped to it. The changes are sorted by user and token.
+    pub fn diff(&self, other: &AccountState) -> Vec<(Address, u16, i128)> {
+        let clamp = |delta: u128| i128::try_from(delta).unwrap_or(i128::max_value());
+        self.user_token_pairs()
+            .chain(other.user_token_pairs())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter_map(|(account_id, token_id)| {
+                let before = self.read_balance(token_id, account_id);
+                let after = other.read_balance(token_id, account_id);
+                let delta = if after >= before {
+                    clamp(after - before)
+                } else {
+                    -clamp(before - after)
+                };
+                if delta != 0 {
+                    Some((account_id, token_id, delta))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl Serialize for AccountState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut balances = self
+            .0
+            .iter()
+            .map(|(&(account, token), &balance)| Balance {
+                account,
+                token,
+                balance,
+            })
+            .collect::<Vec<_>>();
+        balances.sort_by_key(|balance| (balance.account, balance.token));
+        balances.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AccountState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let balances = Vec::<Balance>::deserialize(deserializer)?;
+        Ok(AccountState(
+            balances
+                .into_iter()
+                .map(|balance| ((balance.account, balance.token), balance.balance))
+                .collect(),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -70,4 +164,93 @@ pub mod tests {
     fn test_cannot_create_with_bad_balance_length() {
         AccountState::new(vec![100, 200], 30);
     }
+
+    #[test]
+    fn test_total_balance() {
+        let state = AccountState::new(vec![100, 200, 0, 10, 20, 0, 1, 2, 0], 3);
+        assert_eq!(state.total_balance(0), 111);
+        assert_eq!(state.total_balance(1), 222);
+        assert_eq!(state.total_balance(2), 0);
+        assert_eq!(state.total_balance(3), 0);
+    }
+
+    #[test]
+    fn test_total_balance_saturates() {
+        let state = AccountState::new(vec![u128::max_value(), 1, 1, 1], 2);
+        assert_eq!(state.total_balance(0), u128::max_value());
+        assert_eq!(state.total_balance(1), 2);
+    }
+
+    #[test]
+    fn test_diff() {
+        let user = Address::from_low_u64_be;
+        let mut before = AccountState::default();
+        before.increase_balance(user(0), 0, 100);
+        before.increase_balance(user(0), 1, 50);
+        before.increase_balance(user(1), 0, 30);
+        before.increase_balance(user(2), 1, 10);
+
+        let mut after = AccountState::default();
+        // user 0 deposited token 0 and withdrew all of token 1
+        after.increase_balance(user(0), 0, 150);
+        // user 1 is unchanged
+        after.increase_balance(user(1), 0, 30);
+        // user 2 withdrew some of token 1
+        after.increase_balance(user(2), 1, 4);
+        // user 3 is new
+        after.increase_balance(user(3), 2, 7);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                (user(0), 0, 50),
+                (user(0), 1, -50),
+                (user(2), 1, -6),
+                (user(3), 2, 7),
+            ]
+        );
+        assert_eq!(after.diff(&after), vec![]);
+    }
+
+    #[test]
+    fn test_diff_clamps_large_changes() {
+        let user = Address::from_low_u64_be(0);
+        let empty = AccountState::default();
+        let mut full = AccountState::default();
+        full.increase_balance(user, 0, u128::max_value());
+
+        assert_eq!(empty.diff(&full), vec![(user, 0, i128::max_value())]);
+        assert_eq!(full.diff(&empty), vec![(user, 0, -i128::max_value())]);
+    }
+
+    #[test]
+    fn test_tokens_are_distinct() {
+        let mut state = AccountState::default();
+        state.increase_balance(Address::from_low_u64_be(0), 3, 100);
+        state.increase_balance(Address::from_low_u64_be(1), 1, 100);
+        state.increase_balance(Address::from_low_u64_be(2), 3, 100);
+        state.increase_balance(Address::from_low_u64_be(2), 1, 0);
+        assert_eq!(state.tokens().collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(AccountState::default().tokens().count(), 0);
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        let mut state = AccountState::default();
+        state.increase_balance(Address::from_low_u64_be(1), 2, 42);
+        state.increase_balance(Address::from_low_u64_be(0), 3, u128::max_value());
+        state.increase_balance(Address::from_low_u64_be(0), 1, 0);
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"account":"0x0000000000000000000000000000000000000000","token":1,"balance":"0"},"#
+                .to_owned()
+                + r#"{"account":"0x0000000000000000000000000000000000000000","token":3,"#
+                + r#""balance":"340282366920938463463374607431768211455"},"#
+                + r#"{"account":"0x0000000000000000000000000000000000000001","token":2,"#
+                + r#""balance":"42"}]"#
+        );
+        assert_eq!(serde_json::from_str::<AccountState>(&json).unwrap(), state);
+    }
 }
@@ -1,18 +1,70 @@
 use ethcontract::Address;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_with::rust::display_fromstr;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Clone, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Order {
     pub id: u16,
     pub account_id: Address,
     pub buy_token: u16,
     pub sell_token: u16,
+    #[serde(with = "display_fromstr")]
     pub buy_amount: u128,
+    #[serde(with = "display_fromstr")]
     pub sell_amount: u128,
+    /// The first batch in which the order is valid.
+    pub valid_from: u32,
+    /// The last batch in which the order is valid.
+    pub valid_until: u32,
+}
+
+/// The default order has id 0, belongs to the zero address, trades token 0 for
+/// token 0 with zero amounts and is valid in every batch.
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            id: 0,
+            account_id: Address::zero(),
+            buy_token: 0,
+            sell_token: 0,
+            buy_amount: 0,
+            sell_amount: 0,
+            valid_from: 0,
+            valid_until: u32::max_value(),
+        }
+    }
 }
 
 impl Order {
+    /// Returns a builder for an order, see `OrderBuilder` for the defaults.
+    pub fn builder() -> OrderBuilder {
+        OrderBuilder::default()
+    }
+
+    /// Returns whether the order is valid in the specified batch.
+    pub fn is_valid_at(&self, batch_id: u32) -> bool {
+        self.valid_from <= batch_id && batch_id <= self.valid_until
+    }
+
+    /// Returns the lifecycle stage of the order in the specified batch or `None`
+    /// if the order is not valid in that batch.
+    ///
+    /// An order that is only valid for a single batch is reported as freshly
+    /// valid.
+    pub fn lifecycle_at(&self, batch_id: u32) -> Option<OrderLifecycle> {
+        if !self.is_valid_at(batch_id) {
+            None
+        } else if batch_id == self.valid_from {
+            Some(OrderLifecycle::FreshlyValid)
+        } else if batch_id == self.valid_until {
+            Some(OrderLifecycle::Expiring)
+        } else {
+            Some(OrderLifecycle::Continuing)
+        }
+    }
+
     /// Creates a fake order in between a token pair for unit testing.
     #[cfg(test)]
     pub fn for_token_pair(buy_token: u16, sell_token: u16) -> Self {
@@ -23,10 +75,120 @@ impl Order {
             sell_token,
             buy_amount: 1_000_000_000_000_000_000,
             sell_amount: 1_000_000_000_000_000_000,
+            ..Default::default()
+        }
+    }
+}
+
+/// Builder for orders. Unless specified otherwise the order has id 0, belongs
+/// to the zero address, trades token 0 for token 0 with zero amounts and is
+/// valid in every batch.
+#[derive(Clone, Debug)]
+pub struct OrderBuilder {
+    order: Order,
+}
+
+impl Default for OrderBuilder {
+    fn default() -> Self {
+        OrderBuilder {
+            order: Order {
+                valid_until: u32::max_value(),
+                ..Order::default()
+            },
         }
     }
 }
 
+impl OrderBuilder {
+    pub fn id(mut self, id: u16) -> Self {
+        self.order.id = id;
+        self
+    }
+
+    pub fn account_id(mut self, account_id: Address) -> Self {
+        self.order.account_id = account_id;
+        self
+    }
+
+    pub fn buy_token(mut self, buy_token: u16) -> Self {
+        self.order.buy_token = buy_token;
+        self
+    }
+
+    pub fn sell_token(mut self, sell_token: u16) -> Self {
+        self.order.sell_token = sell_token;
+        self
+    }
+
+    pub fn buy_amount(mut self, buy_amount: u128) -> Self {
+        self.order.buy_amount = buy_amount;
+        self
+    }
+
+    pub fn sell_amount(mut self, sell_amount: u128) -> Self {
+        self.order.sell_amount = sell_amount;
+        self
+    }
+
+    /// Sets the first and last batch in which the order is valid.
+    pub fn validity(mut self, valid_from: u32, valid_until: u32) -> Self {
+        self.order.valid_from = valid_from;
+        self.order.valid_until = valid_until;
+        self
+    }
+
+    pub fn build(self) -> Order {
+        self.order
+    }
+}
+
+/// The stage of an order's validity window that a batch falls into.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OrderLifecycle {
+    /// The batch is the first one in which the order is valid.
+    FreshlyValid,
+    /// The order was already valid in the previous batch and remains valid in
+    /// the next one.
+    Continuing,
+    /// The batch is the last one in which the order is valid.
+    Expiring,
+}
+
+/// Summary of the composition of a set of orders.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OrderbookStats {
+    /// The total number of orders.
+    pub num_orders: usize,
+    /// The number of orders selling each token.
+    pub num_orders_by_sell_token: HashMap<u16, usize>,
+    /// The number of orders buying each token.
+    pub num_orders_by_buy_token: HashMap<u16, usize>,
+    /// The number of distinct accounts that placed orders.
+    pub num_accounts: usize,
+}
+
+/// Computes statistics about the composition of a set of orders.
+pub fn orderbook_stats(orders: &[Order]) -> OrderbookStats {
+    let mut stats = OrderbookStats {
+        num_orders: orders.len(),
+        ..OrderbookStats::default()
+    };
+    let mut accounts = HashSet::new();
+    for order in orders {
+        *stats
+            .num_orders_by_sell_token
+            .entry(order.sell_token)
+            .or_default() += 1;
+        *stats
+            .num_orders_by_buy_token
+            .entry(order.buy_token)
+            .or_default() += 1;
+        accounts.insert(order.account_id);
+    }
+    stats.num_accounts = accounts.len();
+    stats
+}
+
 #[cfg(test)]
 pub mod test_util {
     use super::*;
@@ -40,6 +202,7 @@ pub mod test_util {
             sell_token: 2,
             buy_amount: 5,
             sell_amount: 4,
+            ..Default::default()
         }
     }
 
@@ -56,3 +219,151 @@ pub mod test_util {
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn orderbook_stats_counts_orders_by_token_and_accounts() {
+        let order = |account, sell_token, buy_token| Order {
+            account_id: Address::from_low_u64_be(account),
+            sell_token,
+            buy_token,
+            ..Order::default()
+        };
+        let orders = [
+            order(0, 1, 2),
+            order(0, 1, 3),
+            order(1, 2, 1),
+            order(2, 1, 2),
+        ];
+
+        assert_eq!(
+            orderbook_stats(&orders),
+            OrderbookStats {
+                num_orders: 4,
+                num_orders_by_sell_token: hash_map! { 1 => 3, 2 => 1 },
+                num_orders_by_buy_token: hash_map! { 1 => 1, 2 => 2, 3 => 1 },
+                num_accounts: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn default_order_is_valid_in_every_batch() {
+        let order = Order::default();
+        assert!(order.is_valid_at(0));
+        assert!(order.is_valid_at(u32::max_value()));
+    }
+
+    #[test]
+    fn order_is_valid_at() {
+        let order = Order {
+            valid_from: 2,
+            valid_until: 5,
+            ..Order::default()
+        };
+        assert!(!order.is_valid_at(1));
+        assert!(order.is_valid_at(2));
+        assert!(order.is_valid_at(3));
+        assert!(order.is_valid_at(5));
+        assert!(!order.is_valid_at(6));
+    }
+
+    #[test]
+    fn order_lifecycle_at() {
+        let order = |valid_from, valid_until| Order {
+            valid_from,
+            valid_until,
+            ..Order::default()
+        };
+        assert_eq!(order(2, 5).lifecycle_at(1), None);
+        assert_eq!(
+            order(2, 5).lifecycle_at(2),
+            Some(OrderLifecycle::FreshlyValid)
+        );
+        assert_eq!(
+            order(2, 5).lifecycle_at(3),
+            Some(OrderLifecycle::Continuing)
+        );
+        assert_eq!(order(2, 5).lifecycle_at(5), Some(OrderLifecycle::Expiring));
+        assert_eq!(order(2, 5).lifecycle_at(6), None);
+        assert_eq!(
+            order(3, 3).lifecycle_at(3),
+            Some(OrderLifecycle::FreshlyValid)
+        );
+    }
+
+    #[test]
+    fn orderbook_stats_for_empty_orderbook() {
+        assert_eq!(orderbook_stats(&[]), OrderbookStats::default());
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let order = Order {
+            id: 1,
+            account_id: Address::from_low_u64_be(2),
+            buy_token: 3,
+            sell_token: 4,
+            buy_amount: u128::max_value(),
+            sell_amount: 5,
+            valid_from: 6,
+            valid_until: u32::max_value(),
+        };
+        let json = serde_json::to_string(&order).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":1,"accountId":"0x0000000000000000000000000000000000000002","buyToken":3,"#
+                .to_owned()
+                + r#""sellToken":4,"buyAmount":"340282366920938463463374607431768211455","#
+                + r#""sellAmount":"5","validFrom":6,"validUntil":4294967295}"#
+        );
+        assert_eq!(serde_json::from_str::<Order>(&json).unwrap(), order);
+    }
+
+    #[test]
+    fn builder_builds_equivalent_order() {
+        let order = Order::builder()
+            .id(1)
+            .account_id(Address::from_low_u64_be(2))
+            .buy_token(3)
+            .sell_token(4)
+            .buy_amount(5)
+            .sell_amount(6)
+            .validity(7, 8)
+            .build();
+        assert_eq!(
+            order,
+            Order {
+                id: 1,
+                account_id: Address::from_low_u64_be(2),
+                buy_token: 3,
+                sell_token: 4,
+                buy_amount: 5,
+                sell_amount: 6,
+                valid_from: 7,
+                valid_until: 8,
+            }
+        );
+    }
+
+    #[test]
+    fn builder_defaults_to_always_valid_order() {
+        assert_eq!(
+            Order::builder().build(),
+            Order {
+                id: 0,
+                account_id: Address::zero(),
+                buy_token: 0,
+                sell_token: 0,
+                buy_amount: 0,
+                sell_amount: 0,
+                valid_from: 0,
+                valid_until: u32::max_value(),
+            }
+        );
+        assert!(Order::builder().build().is_valid_at(u32::max_value()));
+    }
+}
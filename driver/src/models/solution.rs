@@ -1,19 +1,65 @@
-use ethcontract::Address;
+use super::Order;
+use crate::util::{CeiledDiv, CheckedConvertU128};
+use anyhow::{anyhow, Context as _, Result};
+use ethcontract::{Address, U256};
+use serde::{Deserialize, Serialize};
+use serde_with::rust::display_fromstr;
 use std::collections::HashMap;
+use std::io::Write;
 
-#[derive(Clone, Debug, PartialEq)]
+/// The id of the token in which fees are paid.
+const FEE_TOKEN: u16 = 0;
+/// The price of the fee token, which all other prices are relative to.
+const FEE_TOKEN_PRICE: u128 = 1_000_000_000_000_000_000;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ExecutedOrder {
     pub account_id: Address,
     pub order_id: u16,
+    #[serde(with = "display_fromstr")]
     pub sell_amount: u128,
+    #[serde(with = "display_fromstr")]
     pub buy_amount: u128,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Solution {
     /// token_id => price
+    #[serde(with = "prices_as_strings")]
     pub prices: HashMap<u16, u128>,
     pub executed_orders: Vec<ExecutedOrder>,
+    /// The name of the solver that computed the solution, if known.
+    pub solver_name: Option<String>,
+}
+
+/// Serializes prices as strings, as JSON numbers cannot represent every
+/// `u128` exactly.
+mod prices_as_strings {
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S>(prices: &HashMap<u16, u128>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            prices
+                .iter()
+                .map(|(token, price)| (token, price.to_string())),
+        )
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<HashMap<u16, u128>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        HashMap::<u16, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(token, price)| Ok((token, price.parse().map_err(D::Error::custom)?)))
+            .collect()
+    }
 }
 
 impl Solution {
@@ -21,15 +67,150 @@ impl Solution {
         Solution {
             prices: HashMap::new(),
             executed_orders: Vec::new(),
+            solver_name: None,
         }
     }
 
+    /// Attributes the solution to the solver with the specified name.
+    pub fn with_solver_name(mut self, solver_name: impl Into<String>) -> Self {
+        self.solver_name = Some(solver_name.into());
+        self
+    }
+
     /// Returns true if a solution is non-trivial and false otherwise
     pub fn is_non_trivial(&self) -> bool {
         self.executed_orders
             .iter()
             .any(|order| order.sell_amount > 0)
     }
+
+    /// Removes executed orders that are not traded and zero prices. Neither
+    /// affects the outcome of the solution, but both add to the gas used for
+    /// submitting it.
+    pub fn trim(&mut self) {
+        self.executed_orders
+            .retain(|executed_order| executed_order.buy_amount > 0);
+        self.prices.retain(|_, price| *price > 0);
+    }
+
+    /// Scales all prices so that the price of the reference token becomes the
+    /// base price of 10^18, preserving the ratios between prices up to
+    /// rounding. Returns an error without modifying the prices if the
+    /// reference token has no price or if a scaled price overflows.
+    pub fn normalize_to(&mut self, reference_token: u16) -> Result<()> {
+        let reference_price = match self.prices.get(&reference_token) {
+            Some(&price) if price > 0 => U256::from(price),
+            _ => return Err(anyhow!("reference token {} has no price", reference_token)),
+        };
+        let prices = self
+            .prices
+            .iter()
+            .map(|(&token_id, &price)| {
+                // upcast to u256 to avoid overflows
+                let normalized_price = (U256::from(price) * U256::from(FEE_TOKEN_PRICE))
+                    .ceiled_div(reference_price)
+                    .as_u128_checked()
+                    .ok_or_else(|| anyhow!("normalized price of token {} overflows", token_id))?;
+                Ok((token_id, normalized_price))
+            })
+            .collect::<Result<_>>()?;
+        self.prices = prices;
+        Ok(())
+    }
+
+    /// Returns an estimate of the objective value that the exchange contract
+    /// computes for the solution, so that solutions can be compared. This is
+    /// the surplus of the executed orders over their limit prices in units of
+    /// the fee token plus half of the burnt fees. Executed orders that are not
+    /// part of `orders` are ignored.
+    pub fn objective_value(&self, orders: &[Order]) -> U256 {
+        let price = |token_id: u16| U256::from(self.prices.get(&token_id).copied().unwrap_or(0));
+        let mut surplus = U256::zero();
+        let (mut fee_token_sold, mut fee_token_bought) = (U256::zero(), U256::zero());
+        for executed_order in &self.executed_orders {
+            let order = match orders.iter().find(|order| {
+                order.account_id == executed_order.account_id && order.id == executed_order.order_id
+            }) {
+                Some(order) if order.sell_amount > 0 => order,
+                _ => continue,
+            };
+
+            // The amount of the buy token the order asks for at least in
+            // exchange for the executed sell amount.
+            let limit_buy_amount = U256::from(executed_order.sell_amount)
+                * U256::from(order.buy_amount)
+                / U256::from(order.sell_amount);
+            let order_surplus = U256::from(executed_order.buy_amount)
+                .saturating_sub(limit_buy_amount)
+                .saturating_mul(price(order.buy_token))
+                / U256::from(FEE_TOKEN_PRICE);
+            surplus = surplus.saturating_add(order_surplus);
+
+            if order.sell_token == FEE_TOKEN {
+                fee_token_sold += U256::from(executed_order.sell_amount);
+            }
+            if order.buy_token == FEE_TOKEN {
+                fee_token_bought += U256::from(executed_order.buy_amount);
+            }
+        }
+        surplus.saturating_add(fee_token_sold.saturating_sub(fee_token_bought) / 2)
+    }
+}
+
+/// Returns the ratio of the sell amount of an order that was executed, which
+/// is `1.0` for fully filled orders. Orders with a zero sell amount have a fill
+/// ratio of `0.0`.
+pub fn fill_ratio(order: &Order, executed: &ExecutedOrder) -> f64 {
+    if order.sell_amount == 0 {
+        return 0.0;
+    }
+    (executed.sell_amount as f64 / order.sell_amount as f64)
+        .max(0.0)
+        .min(1.0)
+}
+
+/// The header of the executed orders CSV export.
+pub const EXECUTED_ORDERS_CSV_HEADER: &str = "account,order_id,sell_token,buy_token,sell_amount,\
+                                              buy_amount,executed_sell_amount,executed_buy_amount";
+
+/// Writes the executed orders of a solution as CSV, with one row per order
+/// containing the order and its executed amounts. Orders that were not
+/// executed are omitted. Fails if an executed order is not part of `orders`.
+pub fn write_executed_orders_csv(
+    mut writer: impl Write,
+    solution: &Solution,
+    orders: &[Order],
+) -> Result<()> {
+    writeln!(writer, "{}", EXECUTED_ORDERS_CSV_HEADER)?;
+    for executed_order in &solution.executed_orders {
+        if executed_order.sell_amount == 0 && executed_order.buy_amount == 0 {
+            continue;
+        }
+        let order = orders
+            .iter()
+            .find(|order| {
+                order.account_id == executed_order.account_id && order.id == executed_order.order_id
+            })
+            .with_context(|| {
+                format!(
+                    "executed order {} of {:?} is not in the orderbook",
+                    executed_order.order_id, executed_order.account_id
+                )
+            })?;
+        writeln!(
+            writer,
+            "{:?},{},{},{},{},{},{},{}",
+            order.account_id,
+            order.id,
+            order.sell_token,
+            order.buy_token,
+            order.sell_amount,
+            order.buy_amount,
+            executed_order.sell_amount,
+            executed_order.buy_amount,
+        )?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -77,6 +258,7 @@ pub mod tests {
                     buy_amount: 6,
                 },
             ],
+            solver_name: None,
         }
     }
 
@@ -86,9 +268,203 @@ pub mod tests {
         assert!(!Solution::trivial().is_non_trivial());
     }
 
+    #[test]
+    fn test_with_solver_name() {
+        let solution = generic_non_trivial_solution().with_solver_name("naive-solver");
+        assert_eq!(solution.solver_name.as_deref(), Some("naive-solver"));
+        assert_eq!(Solution::trivial().solver_name, None);
+    }
+
+    #[test]
+    fn test_objective_value() {
+        let orders = vec![
+            Order::builder()
+                .account_id(Address::from_low_u64_be(1))
+                .sell_token(1)
+                .buy_token(0)
+                .sell_amount(10_000)
+                .buy_amount(5_000)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(2))
+                .sell_token(0)
+                .buy_token(1)
+                .sell_amount(10_000)
+                .buy_amount(5_000)
+                .build(),
+        ];
+        let executed_order = |account: u64, buy_amount: u128| ExecutedOrder {
+            account_id: Address::from_low_u64_be(account),
+            order_id: 0,
+            sell_amount: 10_000,
+            buy_amount,
+        };
+        let solution = Solution {
+            prices: map_from_slice(&[(0, FEE_TOKEN_PRICE), (1, FEE_TOKEN_PRICE)]),
+            executed_orders: vec![executed_order(1, 9_990), executed_order(2, 10_000)],
+            solver_name: None,
+        };
+
+        // Surplus of 4990 and 5000 for the orders and 10 burnt fees.
+        assert_eq!(solution.objective_value(&orders), U256::from(9_995));
+        assert_eq!(solution.objective_value(&[]), U256::zero());
+        assert_eq!(Solution::trivial().objective_value(&orders), U256::zero());
+    }
+
     #[test]
     fn test_max_token() {
         assert_eq!(generic_non_trivial_solution().max_token().unwrap(), 2);
         assert_eq!(Solution::trivial().max_token(), None);
     }
+
+    #[test]
+    fn test_trim() {
+        let mut solution = generic_non_trivial_solution();
+        solution.prices.insert(1, 0);
+        solution.executed_orders[1].sell_amount = 0;
+        solution.executed_orders[1].buy_amount = 0;
+
+        solution.trim();
+        assert_eq!(solution.prices, map_from_slice(&[(0, 42), (2, 42)]));
+        assert_eq!(
+            solution
+                .executed_orders
+                .iter()
+                .map(|executed_order| executed_order.order_id)
+                .collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let solution = generic_non_trivial_solution().with_solver_name("naive-solver");
+        let json = serde_json::to_value(&solution).unwrap();
+        assert_eq!(json["solverName"], "naive-solver");
+        assert_eq!(json["executedOrders"][1]["sellAmount"], "2");
+        assert_eq!(json["prices"]["2"], "42");
+        assert_eq!(serde_json::from_value::<Solution>(json).unwrap(), solution);
+
+        let trivial = Solution::trivial();
+        let json = serde_json::to_string(&trivial).unwrap();
+        assert_eq!(serde_json::from_str::<Solution>(&json).unwrap(), trivial);
+    }
+
+    #[test]
+    fn test_normalize_to_non_fee_token() {
+        let mut solution = Solution {
+            prices: hash_map! {
+                0 => 1_000_000_000_000_000_000,
+                1 => 4_000_000_000_000_000_000,
+                2 => 500_000_000_000_000_000,
+            },
+            ..generic_non_trivial_solution()
+        };
+        let executed_orders = solution.executed_orders.clone();
+        solution.normalize_to(1).unwrap();
+        assert_eq!(
+            solution.prices,
+            hash_map! {
+                0 => 250_000_000_000_000_000,
+                1 => 1_000_000_000_000_000_000,
+                2 => 125_000_000_000_000_000,
+            }
+        );
+        assert_eq!(solution.executed_orders, executed_orders);
+
+        // Ratios are preserved up to rounding, which is towards higher prices.
+        let mut solution = Solution {
+            prices: hash_map! { 0 => 3, 1 => 1 },
+            ..Solution::trivial()
+        };
+        solution.normalize_to(0).unwrap();
+        assert_eq!(
+            solution.prices,
+            hash_map! { 0 => 1_000_000_000_000_000_000, 1 => 333_333_333_333_333_334 }
+        );
+    }
+
+    #[test]
+    fn test_normalize_to_fails_without_modifying_prices() {
+        let prices = hash_map! { 0 => 1, 1 => u128::max_value() };
+        let mut solution = Solution {
+            prices: prices.clone(),
+            ..Solution::trivial()
+        };
+        assert!(solution.normalize_to(0).is_err());
+        assert!(solution.normalize_to(2).is_err());
+        assert_eq!(solution.prices, prices);
+
+        solution.prices.insert(2, 0);
+        assert!(solution.normalize_to(2).is_err());
+    }
+
+    #[test]
+    fn exports_executed_orders_of_stablex_e2e_auction_as_csv() {
+        const BASE_UNIT: u128 = 1_000_000_000_000_000_000;
+        let (_, orders) = crate::price_finding::naive_solver::tests::stablex_e2e_auction_data();
+        let executed_order = |order: &Order, sell_amount: u128, buy_amount: u128| ExecutedOrder {
+            account_id: order.account_id,
+            order_id: order.id,
+            sell_amount,
+            buy_amount,
+        };
+        let solution = Solution {
+            prices: map_from_slice(&[(0, FEE_TOKEN_PRICE), (1, 2 * FEE_TOKEN_PRICE)]),
+            executed_orders: vec![
+                executed_order(&orders[0], 1998 * BASE_UNIT, 999 * BASE_UNIT),
+                executed_order(&orders[1], 999 * BASE_UNIT, 1996 * BASE_UNIT),
+                // Orders that are not executed are omitted, even if they are
+                // not part of the orderbook.
+                ExecutedOrder {
+                    account_id: orders[1].account_id,
+                    order_id: 1,
+                    sell_amount: 0,
+                    buy_amount: 0,
+                },
+            ],
+            solver_name: None,
+        };
+
+        let mut csv = Vec::new();
+        write_executed_orders_csv(&mut csv, &solution, &orders).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap().lines().collect::<Vec<_>>(),
+            vec![
+                "account,order_id,sell_token,buy_token,sell_amount,buy_amount,\
+                 executed_sell_amount,executed_buy_amount",
+                "0x0000000000000000000000000000000000000000,0,0,1,\
+                 2000000000000000000000,999000000000000000000,\
+                 1998000000000000000000,999000000000000000000",
+                "0x0000000000000000000000000000000000000001,0,1,0,\
+                 999000000000000000000,1996000000000000000000,\
+                 999000000000000000000,1996000000000000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_export_fails_for_unknown_executed_orders() {
+        let solution = generic_non_trivial_solution();
+        assert!(write_executed_orders_csv(Vec::new(), &solution, &[]).is_err());
+    }
+
+    #[test]
+    fn computes_fill_ratio() {
+        let order = Order::builder().sell_amount(100).buy_amount(50).build();
+        let executed = |sell_amount: u128| ExecutedOrder {
+            account_id: order.account_id,
+            order_id: order.id,
+            sell_amount,
+            buy_amount: sell_amount / 2,
+        };
+
+        assert_eq!(fill_ratio(&order, &executed(100)), 1.0);
+        assert_eq!(fill_ratio(&order, &executed(50)), 0.5);
+        assert_eq!(fill_ratio(&order, &executed(0)), 0.0);
+
+        let zero_order = Order::builder().sell_amount(0).buy_amount(0).build();
+        assert_eq!(fill_ratio(&zero_order, &executed(0)), 0.0);
+        assert_eq!(fill_ratio(&zero_order, &executed(100)), 0.0);
+    }
 }
@@ -0,0 +1,102 @@
+//! An asynchronous variant of the orderbook reading trait for callers that
+//! live in an async context.
+
+use super::StableXOrderBookReading;
+use crate::models::{AccountState, Order};
+use anyhow::{anyhow, Result};
+use ethcontract::U256;
+use futures::{
+    channel::oneshot,
+    executor::ThreadPool,
+    future::{BoxFuture, FutureExt as _},
+};
+use lazy_static::lazy_static;
+use std::sync::Arc;
+
+/// An asynchronous variant of `StableXOrderBookReading` for callers that live
+/// in an async context and should not block while the orderbook is read.
+pub trait AsyncStableXOrderBookReading {
+    /// Returns the current state of the order book, see
+    /// `StableXOrderBookReading::get_auction_data`.
+    fn get_auction_data(
+        &self,
+        batch_id_to_solve: U256,
+    ) -> BoxFuture<'_, Result<(AccountState, Vec<Order>)>>;
+}
+
+lazy_static! {
+    /// The thread pool that blocking orderbook reads are moved onto when they
+    /// are read asynchronously.
+    static ref BLOCKING_READ_POOL: ThreadPool =
+        ThreadPool::new().expect("failed to create orderbook reading thread pool");
+}
+
+/// Reads the orderbook with a shared synchronous reader, like the paginated
+/// reader, on a shared thread pool so that the blocking read does not block
+/// the executor polling the returned future.
+impl<T> AsyncStableXOrderBookReading for Arc<T>
+where
+    T: StableXOrderBookReading + Send + Sync + ?Sized + 'static,
+{
+    fn get_auction_data(
+        &self,
+        batch_id_to_solve: U256,
+    ) -> BoxFuture<'_, Result<(AccountState, Vec<Order>)>> {
+        let reader = self.clone();
+        let (sender, receiver) = oneshot::channel();
+        BLOCKING_READ_POOL.spawn_ok(async move {
+            // NOTE: Sending only fails if the future was dropped, in which
+            //   case nobody is interested in the result anymore.
+            let _ = sender.send(StableXOrderBookReading::get_auction_data(
+                &*reader,
+                batch_id_to_solve,
+            ));
+        });
+        async move {
+            receiver
+                .await
+                .map_err(|_| anyhow!("orderbook reading task panicked"))?
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::MockStableXOrderBookReading;
+    use crate::util::FutureWaitExt as _;
+    use ethcontract::Address;
+
+    #[test]
+    fn async_read_matches_sync_read() {
+        let auction_data = (
+            AccountState(hash_map! { (Address::from_low_u64_be(1), 0) => 100 }),
+            vec![Order::for_token_pair(0, 1)],
+        );
+        let mut reader = MockStableXOrderBookReading::new();
+        reader.expect_get_auction_data().returning({
+            let auction_data = auction_data.clone();
+            move |_| Ok(auction_data.clone())
+        });
+        let reader = Arc::new(reader);
+
+        let sync_result = StableXOrderBookReading::get_auction_data(&*reader, U256::zero());
+        let async_result =
+            AsyncStableXOrderBookReading::get_auction_data(&reader, U256::zero()).wait();
+        assert_eq!(sync_result.unwrap(), auction_data);
+        assert_eq!(async_result.unwrap(), auction_data);
+    }
+
+    #[test]
+    fn async_read_propagates_errors() {
+        let mut reader = MockStableXOrderBookReading::new();
+        reader
+            .expect_get_auction_data()
+            .returning(|_| Err(anyhow!("error")));
+        let reader = Arc::new(reader);
+
+        let result = AsyncStableXOrderBookReading::get_auction_data(&reader, U256::zero()).wait();
+        assert!(result.is_err());
+    }
+}
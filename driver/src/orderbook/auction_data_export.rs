@@ -0,0 +1,220 @@
+//! This module implements exporting of auction data read by the driver into a
+//! stable JSON schema, so that offline solver tooling can work on the exact
+//! orderbook that the driver sees.
+
+use super::StableXOrderBookReading;
+use crate::models::{AccountState, Order};
+use crate::util::CheckedConvertU32;
+use anyhow::{anyhow, Context as _, Result};
+use ethcontract::{Address, U256};
+use serde::{Deserialize, Serialize};
+use serde_with::rust::display_fromstr;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// The version of the exported JSON schema. This needs to be bumped whenever
+/// the format of the exported data changes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The balance of a token for a user.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountBalance {
+    pub address: Address,
+    pub token: u16,
+    #[serde(with = "display_fromstr")]
+    pub balance: u128,
+}
+
+/// The exported auction data for a batch.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuctionDataExport {
+    pub version: u32,
+    pub batch_id: u32,
+    pub accounts: Vec<AccountBalance>,
+    pub orders: Vec<Order>,
+}
+
+impl AuctionDataExport {
+    /// Creates a new export for the auction data of the specified batch.
+    ///
+    /// Accounts are sorted by address and token so that exporting the same
+    /// auction data always produces the same output. Returns an error if the
+    /// batch id does not fit into 32 bits.
+    pub fn new(batch_id: U256, account_state: &AccountState, orders: &[Order]) -> Result<Self> {
+        let batch_id = batch_id
+            .as_u32_checked()
+            .ok_or_else(|| anyhow!("batch id {} does not fit into 32 bits", batch_id))?;
+        let mut accounts = account_state
+            .0
+            .iter()
+            .map(|(&(address, token), &balance)| AccountBalance {
+                address,
+                token,
+                balance,
+            })
+            .collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|account| (account.address, account.token));
+
+        Ok(AuctionDataExport {
+            version: SCHEMA_VERSION,
+            batch_id,
+            accounts,
+            orders: orders.to_vec(),
+        })
+    }
+}
+
+/// Writes the auction data for a batch as JSON to the specified writer.
+pub fn write_auction_data(
+    writer: impl Write,
+    batch_id: U256,
+    account_state: &AccountState,
+    orders: &[Order],
+) -> Result<()> {
+    let export = AuctionDataExport::new(batch_id, account_state, orders)?;
+    serde_json::to_writer(writer, &export)?;
+    Ok(())
+}
+
+/// An orderbook reader that exports all auction data read by the inner
+/// orderbook to a file per batch in the specified directory.
+pub struct ExportingOrderbookReader<'a> {
+    orderbook: &'a (dyn StableXOrderBookReading + Sync),
+    directory: PathBuf,
+}
+
+impl<'a> ExportingOrderbookReader<'a> {
+    pub fn new(orderbook: &'a (dyn StableXOrderBookReading + Sync), directory: PathBuf) -> Self {
+        Self {
+            orderbook,
+            directory,
+        }
+    }
+
+    fn export(&self, batch_id: U256, account_state: &AccountState, orders: &[Order]) -> Result<()> {
+        let path = self
+            .directory
+            .join(format!("auction_data_{}.json", batch_id));
+        let file = File::create(&path)
+            .with_context(|| format!("error creating file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        write_auction_data(&mut writer, batch_id, account_state, orders)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a> StableXOrderBookReading for ExportingOrderbookReader<'a> {
+    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
+        let (account_state, orders) = self.orderbook.get_auction_data(batch_id_to_solve)?;
+        if let Err(err) = self.export(batch_id_to_solve, &account_state, &orders) {
+            log::warn!("failed to export auction data: {:?}", err);
+        }
+        Ok((account_state, orders))
+    }
+
+    /// Historical auction data is not exported since it isn't the data of the
+    /// batch being solved.
+    fn get_auction_data_at_block(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: u64,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        self.orderbook
+            .get_auction_data_at_block(batch_id_to_solve, block_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::order::test_util::create_order_for_test;
+    use serde_json::json;
+
+    fn auction_data() -> (AccountState, Vec<Order>) {
+        let mut account_state = AccountState::default();
+        account_state.increase_balance(Address::from_low_u64_be(2), 1, 42);
+        account_state.increase_balance(Address::from_low_u64_be(1), 2, u128::max_value());
+        account_state.increase_balance(Address::from_low_u64_be(1), 1, 0);
+
+        let mut other_order = create_order_for_test();
+        other_order.id = 1;
+        other_order.buy_amount = u128::max_value();
+        (account_state, vec![create_order_for_test(), other_order])
+    }
+
+    #[test]
+    fn auction_data_round_trips() {
+        let (account_state, orders) = auction_data();
+
+        let mut bytes = Vec::new();
+        write_auction_data(&mut bytes, U256::from(42), &account_state, &orders).unwrap();
+        let export: AuctionDataExport = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(export.version, SCHEMA_VERSION);
+        assert_eq!(export.batch_id, 42);
+        assert_eq!(
+            export,
+            AuctionDataExport::new(U256::from(42), &account_state, &orders).unwrap()
+        );
+    }
+
+    #[test]
+    fn auction_data_schema() {
+        let mut account_state = AccountState::default();
+        account_state.increase_balance(Address::from_low_u64_be(1), 2, 3);
+        let export =
+            AuctionDataExport::new(U256::from(7), &account_state, &[create_order_for_test()])
+                .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&export).unwrap(),
+            json!({
+                "version": 2,
+                "batchId": 7,
+                "accounts": [{
+                    "address": "0x0000000000000000000000000000000000000001",
+                    "token": 2,
+                    "balance": "3",
+                }],
+                "orders": [{
+                    "id": 0,
+                    "accountId": "0x0000000000000000000000000000000000000001",
+                    "buyToken": 3,
+                    "sellToken": 2,
+                    "buyAmount": "5",
+                    "sellAmount": "4",
+                    "validFrom": 0,
+                    "validUntil": u32::max_value(),
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn accounts_are_sorted() {
+        let (account_state, orders) = auction_data();
+        let export = AuctionDataExport::new(U256::zero(), &account_state, &orders).unwrap();
+        assert_eq!(
+            export
+                .accounts
+                .iter()
+                .map(|account| (account.address, account.token))
+                .collect::<Vec<_>>(),
+            vec![
+                (Address::from_low_u64_be(1), 1),
+                (Address::from_low_u64_be(1), 2),
+                (Address::from_low_u64_be(2), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_batch_ids_that_do_not_fit_into_32_bits() {
+        let (account_state, orders) = auction_data();
+        let batch_id = U256::from(u32::max_value()) + 1;
+        assert!(AuctionDataExport::new(batch_id, &account_state, &orders).is_err());
+    }
+}
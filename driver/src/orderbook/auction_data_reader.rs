@@ -1,5 +1,5 @@
 use crate::contracts::stablex_auction_element::{
-    StableXAuctionElement, AUCTION_ELEMENT_WIDTH, INDEXED_AUCTION_ELEMENT_WIDTH,
+    DecodeError, StableXAuctionElement, AUCTION_ELEMENT_WIDTH, INDEXED_AUCTION_ELEMENT_WIDTH,
 };
 use crate::models::{AccountState, Order};
 use ethcontract::{Address, U256};
@@ -39,11 +39,13 @@ impl AuctionDataReader {
     /// A batch can come from `getEncodedUsersPaginated` or `getEncodedOrders`.
     /// In the latter case there is only one batch.
     ///
-    /// Panics if length of `packed_auction_bytes` is not a multiple of
-    /// `AUCTION_ELEMENT_WIDTH`.
-    pub fn apply_page(&mut self, packed_auction_bytes: &[u8]) {
-        let auction_elements = self.parse_auction_elements(packed_auction_bytes);
+    /// Returns an error without applying any of the data if an auction
+    /// element cannot be decoded, for example because the length of
+    /// `packed_auction_bytes` is not a multiple of `AUCTION_ELEMENT_WIDTH`.
+    pub fn apply_page(&mut self, packed_auction_bytes: &[u8]) -> Result<(), DecodeError> {
+        let auction_elements = self.parse_auction_elements(packed_auction_bytes)?;
         self.apply_auction_data(auction_elements);
+        Ok(())
     }
 
     fn apply_auction_data(&mut self, auction_elements: Vec<StableXAuctionElement>) {
@@ -85,27 +87,20 @@ impl AuctionDataReader {
     fn parse_auction_elements(
         &mut self,
         packed_auction_bytes: &[u8],
-    ) -> Vec<StableXAuctionElement> {
-        assert_eq!(
-            packed_auction_bytes.len() % AUCTION_ELEMENT_WIDTH,
-            0,
-            "Each auction should be packed in {} bytes",
-            AUCTION_ELEMENT_WIDTH
-        );
-
-        packed_auction_bytes
+    ) -> Result<Vec<StableXAuctionElement>, DecodeError> {
+        let mut auction_elements = packed_auction_bytes
             .chunks(AUCTION_ELEMENT_WIDTH)
-            .map(|chunk| {
-                let mut result = auction_element_from_slice(chunk);
-                let order_counter = self
-                    .user_order_counts
-                    .entry(result.order.account_id)
-                    .or_insert(0);
-                result.order.id = *order_counter as u16;
-                *order_counter += 1;
-                result
-            })
-            .collect()
+            .map(StableXAuctionElement::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        for auction_element in auction_elements.iter_mut() {
+            let order_counter = self
+                .user_order_counts
+                .entry(auction_element.order.account_id)
+                .or_insert(0);
+            auction_element.order.id = *order_counter as u16;
+            *order_counter += 1;
+        }
+        Ok(auction_elements)
     }
 }
 
@@ -121,7 +116,7 @@ pub struct PaginatedAuctionDataReader {
 
 /// Data for the next call to the smart contract's `getEncodedUsersPaginated`
 /// function (which should be named `getEncodedOrdersPaginated`).
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Pagination {
     /// The user of the last received order or Address::zero when no order has been
     /// received.
@@ -133,12 +128,35 @@ pub struct Pagination {
 impl PaginatedAuctionDataReader {
     /// Create a new PaginatedAuctionDataReader.
     pub fn new(index: U256, page_size: usize) -> PaginatedAuctionDataReader {
-        PaginatedAuctionDataReader {
-            reader: AuctionDataReader::new(index),
-            next_page: Some(Pagination {
+        Self::resume(
+            index,
+            page_size,
+            Pagination {
                 previous_page_user: Address::zero(),
                 previous_page_user_offset: 0,
-            }),
+            },
+        )
+    }
+
+    /// Create a new PaginatedAuctionDataReader that continues reading at the
+    /// specified cursor, as returned by `next_page` of a previous reader.
+    ///
+    /// The resumed reader only contains the auction data of the pages it
+    /// reads itself, so callers need to combine it with the data that was
+    /// read before the cursor.
+    pub fn resume(index: U256, page_size: usize, cursor: Pagination) -> PaginatedAuctionDataReader {
+        let mut reader = AuctionDataReader::new(index);
+        if cursor.previous_page_user_offset > 0 {
+            // NOTE: Order ids are the index of an order among the orders of its
+            // user, so ids of the orders of the cursor's user need to
+            // continue from its offset.
+            reader
+                .user_order_counts
+                .insert(cursor.previous_page_user, cursor.previous_page_user_offset);
+        }
+        PaginatedAuctionDataReader {
+            reader,
+            next_page: Some(cursor),
             page_size,
         }
     }
@@ -152,20 +170,23 @@ impl PaginatedAuctionDataReader {
     }
 
     /// Applies one batch of data to the underlying reader and keeps track of pagination info.
-    pub fn apply_page(&mut self, packed_auction_bytes: &[u8]) {
-        let number_of_orders = packed_auction_bytes.len() / AUCTION_ELEMENT_WIDTH;
-        if number_of_orders == 0 {
-            self.next_page = None;
-            return;
-        }
+    ///
+    /// Returns an error without applying any of the data if an auction
+    /// element cannot be decoded.
+    pub fn apply_page(&mut self, packed_auction_bytes: &[u8]) -> Result<(), DecodeError> {
+        let last_element = match packed_auction_bytes.chunks(AUCTION_ELEMENT_WIDTH).last() {
+            Some(last_element) => last_element,
+            None => {
+                self.next_page = None;
+                return Ok(());
+            }
+        };
+        let previous_page_user = StableXAuctionElement::decode(last_element)?
+            .order
+            .account_id;
+        self.reader.apply_page(packed_auction_bytes)?;
 
-        let previous_page_user = auction_element_from_slice(
-            &packed_auction_bytes
-                [packed_auction_bytes.len() - AUCTION_ELEMENT_WIDTH..packed_auction_bytes.len()],
-        )
-        .order
-        .account_id;
-        self.reader.apply_page(packed_auction_bytes);
+        let number_of_orders = packed_auction_bytes.len() / AUCTION_ELEMENT_WIDTH;
 
         self.next_page = if number_of_orders == self.page_size {
             let previous_page_user_offset = *self
@@ -181,6 +202,7 @@ impl PaginatedAuctionDataReader {
         } else {
             None
         };
+        Ok(())
     }
 }
 
@@ -202,40 +224,26 @@ impl IndexedAuctionDataReader {
     /// A batch can come from `getFinalizedOrderBook`, `getOpenOrderBook` or
     /// `getFilteredOrdersPaginated`.
     ///
-    /// Panics if length of `packed_auction_bytes` is not a multiple of
+    /// Returns an error without applying any of the data if an auction
+    /// element cannot be decoded, for example because the length of
+    /// `packed_auction_bytes` is not a multiple of
     /// `INDEXED_AUCTION_ELEMENT_WIDTH`.
-    pub fn apply_page(&mut self, packed_auction_bytes: &[u8]) {
-        let auction_elements = parse_indexed_auction_elements(packed_auction_bytes);
+    pub fn apply_page(&mut self, packed_auction_bytes: &[u8]) -> Result<(), DecodeError> {
+        let auction_elements = parse_indexed_auction_elements(packed_auction_bytes)?;
         self.0.apply_auction_data(auction_elements);
+        Ok(())
     }
 }
 
-fn parse_indexed_auction_elements(indexed_auction_bytes: &[u8]) -> Vec<StableXAuctionElement> {
-    assert_eq!(
-        indexed_auction_bytes.len() % INDEXED_AUCTION_ELEMENT_WIDTH,
-        0,
-        "Each auction should be packed in {} bytes",
-        INDEXED_AUCTION_ELEMENT_WIDTH
-    );
-
+fn parse_indexed_auction_elements(
+    indexed_auction_bytes: &[u8],
+) -> Result<Vec<StableXAuctionElement>, DecodeError> {
     indexed_auction_bytes
         .chunks(INDEXED_AUCTION_ELEMENT_WIDTH)
-        .map(auction_element_from_indexed_slice)
+        .map(StableXAuctionElement::decode_indexed)
         .collect()
 }
 
-fn auction_element_from_slice(chunk: &[u8]) -> StableXAuctionElement {
-    let mut chunk_array = [0u8; AUCTION_ELEMENT_WIDTH];
-    chunk_array.copy_from_slice(chunk);
-    StableXAuctionElement::from_bytes(&chunk_array)
-}
-
-fn auction_element_from_indexed_slice(chunk: &[u8]) -> StableXAuctionElement {
-    let mut chunk_array = [0u8; INDEXED_AUCTION_ELEMENT_WIDTH];
-    chunk_array.copy_from_slice(chunk);
-    StableXAuctionElement::from_indexed_bytes(&chunk_array)
-}
-
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -287,6 +295,8 @@ pub mod tests {
             buy_token: 258,
             sell_amount: 257,
             buy_amount: 257,
+            valid_from: 2,
+            valid_until: 261,
         };
         static ref ORDER_2: Order = Order {
             id: 1,
@@ -295,6 +305,8 @@ pub mod tests {
             buy_token: 257,
             sell_amount: 256,
             buy_amount: 256,
+            valid_from: 2,
+            valid_until: 261,
         };
         static ref ORDER_3: Order = Order {
             id: 0,
@@ -303,13 +315,15 @@ pub mod tests {
             buy_token: 257,
             sell_amount: 256,
             buy_amount: 256,
+            valid_from: 2,
+            valid_until: 261,
         };
     }
 
     #[test]
     fn auction_data_reader_empty() {
         let mut reader = AuctionDataReader::new(U256::from(3));
-        reader.apply_page(&[]);
+        reader.apply_page(&[]).unwrap();
         assert_eq!(reader.orders.len(), 0);
     }
 
@@ -319,7 +333,7 @@ pub mod tests {
         bytes.extend(ORDER_1_BYTES);
         bytes.extend(ORDER_2_BYTES);
         let mut reader = AuctionDataReader::new(U256::from(3));
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
 
         let mut account_state = AccountState::default();
         account_state.increase_balance(Address::from_low_u64_be(1), 257, 4);
@@ -336,21 +350,21 @@ pub mod tests {
         let mut bytes = Vec::new();
 
         bytes.extend(ORDER_1_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         account_state.increase_balance(Address::from_low_u64_be(1), 257, 4);
         assert_eq!(reader.account_state, account_state);
         assert_eq!(reader.orders, [ORDER_1.clone()]);
 
         bytes.clear();
         bytes.extend(ORDER_2_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         account_state.increase_balance(Address::from_low_u64_be(1), 258, 5);
         assert_eq!(reader.account_state, account_state);
         assert_eq!(reader.orders, [ORDER_1.clone(), ORDER_2.clone()]);
 
         bytes.clear();
         bytes.extend(ORDER_3_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         account_state.increase_balance(Address::from_low_u64_be(2), 257, 6);
         assert_eq!(reader.account_state, account_state);
     }
@@ -362,7 +376,7 @@ pub mod tests {
         let mut bytes = Vec::new();
 
         bytes.extend(ORDER_1_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         account_state.increase_balance(Address::from_low_u64_be(1), 257, 4);
         assert_eq!(reader.account_state, account_state);
         assert_eq!(reader.orders, [ORDER_1.clone()]);
@@ -370,7 +384,7 @@ pub mod tests {
         bytes.clear();
         bytes.extend(ORDER_2_BYTES);
         bytes.extend(ORDER_3_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         account_state.increase_balance(Address::from_low_u64_be(1), 258, 5);
         account_state.increase_balance(Address::from_low_u64_be(2), 257, 6);
         assert_eq!(reader.account_state, account_state);
@@ -383,7 +397,7 @@ pub mod tests {
         bytes.extend(ORDER_2_BYTES);
         let mut reader = AuctionDataReader::new(U256::from(1000));
         // the bytes contain two orders
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
 
         // We don't include balances for orders that were filtered out
         assert_eq!(reader.account_state, AccountState::default());
@@ -397,7 +411,7 @@ pub mod tests {
         bytes.extend(ORDER_1_BYTES);
         bytes.extend(ORDER_2_BYTES);
         let mut reader = PaginatedAuctionDataReader::new(U256::from(3), 2);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
 
         assert_eq!(
             reader.next_page,
@@ -414,7 +428,7 @@ pub mod tests {
         let mut bytes = Vec::new();
 
         bytes.extend(ORDER_1_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
 
         assert_eq!(
             reader.next_page,
@@ -426,7 +440,7 @@ pub mod tests {
 
         bytes.clear();
         bytes.extend(ORDER_2_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         assert_eq!(
             reader.next_page,
             Some(Pagination {
@@ -437,7 +451,7 @@ pub mod tests {
 
         bytes.clear();
         bytes.extend(ORDER_3_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         assert_eq!(
             reader.next_page,
             Some(Pagination {
@@ -446,7 +460,7 @@ pub mod tests {
             })
         );
 
-        reader.apply_page(&[]);
+        reader.apply_page(&[]).unwrap();
         assert_eq!(reader.next_page, None);
     }
 
@@ -457,7 +471,7 @@ pub mod tests {
 
         bytes.extend(ORDER_3_BYTES);
         bytes.extend(ORDER_2_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         assert_eq!(
             reader.next_page,
             Some(Pagination {
@@ -468,7 +482,67 @@ pub mod tests {
 
         bytes.clear();
         bytes.extend(ORDER_1_BYTES);
-        reader.apply_page(&bytes);
+        reader.apply_page(&bytes).unwrap();
         assert_eq!(reader.next_page, None);
     }
+
+    #[test]
+    fn paginated_auction_data_reader_rejects_truncated_page() {
+        let mut reader = PaginatedAuctionDataReader::new(U256::from(3), 2);
+        let mut bytes = Vec::new();
+        bytes.extend(ORDER_1_BYTES);
+        bytes.extend(&ORDER_2_BYTES[..AUCTION_ELEMENT_WIDTH - 1]);
+        assert_eq!(
+            reader.apply_page(&bytes),
+            Err(DecodeError::InvalidLength {
+                expected: AUCTION_ELEMENT_WIDTH,
+                actual: AUCTION_ELEMENT_WIDTH - 1
+            })
+        );
+
+        let (account_state, orders) = reader.get_auction_data();
+        assert_eq!(account_state, AccountState::default());
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn paginated_auction_data_reader_resumes_from_cursor() {
+        let pages = [ORDER_1_BYTES, ORDER_2_BYTES, ORDER_3_BYTES, &[]];
+
+        let mut full_reader = PaginatedAuctionDataReader::new(U256::from(3), 1);
+        for page in &pages {
+            full_reader.apply_page(page).unwrap();
+        }
+        assert_eq!(full_reader.next_page(), None);
+
+        let mut first_reader = PaginatedAuctionDataReader::new(U256::from(3), 1);
+        for page in &pages[..2] {
+            first_reader.apply_page(page).unwrap();
+        }
+        let cursor = first_reader.next_page().cloned().unwrap();
+        let mut resumed_reader = PaginatedAuctionDataReader::resume(U256::from(3), 1, cursor);
+        for page in &pages[2..] {
+            resumed_reader.apply_page(page).unwrap();
+        }
+        assert_eq!(resumed_reader.next_page(), None);
+
+        let (mut account_state, mut orders) = first_reader.get_auction_data();
+        let (resumed_account_state, resumed_orders) = resumed_reader.get_auction_data();
+        account_state.0.extend(resumed_account_state.0);
+        orders.extend(resumed_orders);
+        assert_eq!((account_state, orders), full_reader.get_auction_data());
+    }
+
+    #[test]
+    fn paginated_auction_data_reader_resumes_ids_of_cursor_user() {
+        let cursor = Pagination {
+            previous_page_user: ORDER_1.account_id,
+            previous_page_user_offset: 1,
+        };
+        let mut reader = PaginatedAuctionDataReader::resume(U256::from(3), 1, cursor);
+        reader.apply_page(ORDER_2_BYTES).unwrap();
+
+        let (_, orders) = reader.get_auction_data();
+        assert_eq!(orders, vec![ORDER_2.clone()]);
+    }
 }
@@ -3,8 +3,10 @@ use super::*;
 use crate::models::{AccountState, Order};
 use anyhow::Error;
 use ethcontract::Address;
+use log::warn;
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
+use std::iter;
 use std::str::FromStr;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -19,6 +21,49 @@ impl Default for TokenFilter {
     }
 }
 
+/// A direction of trading in a token pair, i.e. orders buying `buy` in
+/// exchange for `sell`. If `both` is set this is the token pair regardless of
+/// which of the tokens is bought and which one is sold.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+struct PairDirection {
+    buy: u16,
+    sell: u16,
+    #[serde(default)]
+    both: bool,
+}
+
+impl PairDirection {
+    fn matches(&self, order: &Order) -> bool {
+        (order.buy_token == self.buy && order.sell_token == self.sell)
+            || (self.both && order.buy_token == self.sell && order.sell_token == self.buy)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+enum PairDirectionFilter {
+    Whitelist(HashSet<PairDirection>),
+    Blacklist(HashSet<PairDirection>),
+}
+
+impl Default for PairDirectionFilter {
+    fn default() -> Self {
+        PairDirectionFilter::Blacklist(HashSet::new())
+    }
+}
+
+impl PairDirectionFilter {
+    fn allows(&self, order: &Order) -> bool {
+        match self {
+            PairDirectionFilter::Whitelist(directions) => {
+                directions.iter().any(|direction| direction.matches(order))
+            }
+            PairDirectionFilter::Blacklist(directions) => {
+                !directions.iter().any(|direction| direction.matches(order))
+            }
+        }
+    }
+}
+
 /// Data structure to specify what type of orders to filter
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
 pub struct OrderbookFilter {
@@ -29,13 +74,114 @@ pub struct OrderbookFilter {
     /// User addresses mapped to which of their orders to filter
     #[serde(default)]
     users: HashMap<Address, UserOrderFilter>,
+
+    /// The directions of token pairs that should be filtered. This allows for
+    /// example to allow selling a token while not allowing to buy it, or to
+    /// only allow orders on specific token pairs in both directions.
+    #[serde(default)]
+    pair_directions: PairDirectionFilter,
+
+    /// Sanity cap for token amounts, orders with a buy or sell amount and
+    /// balances exceeding this value are dropped.
+    #[serde(default)]
+    max_amount: Option<u128>,
+
+    /// Filters that orders and balances additionally need to pass.
+    #[serde(default)]
+    and: Vec<OrderbookFilter>,
+
+    /// Filters of which orders and balances need to pass at least one, if
+    /// any are specified.
+    #[serde(default)]
+    or: Vec<OrderbookFilter>,
 }
 
 impl OrderbookFilter {
-    pub fn whitelist(&self) -> Option<&HashSet<u16>> {
-        match &self.tokens {
-            TokenFilter::Whitelist(whitelist) => Some(whitelist),
+    /// Returns the tokens that orders kept by this filter can trade, or `None`
+    /// if orders on any token can be kept. This takes composed filters into
+    /// account, so it can be applied before any of the other conditions.
+    pub fn whitelist(&self) -> Option<HashSet<u16>> {
+        let whitelist = match &self.tokens {
+            TokenFilter::Whitelist(whitelist) => Some(whitelist.clone()),
             TokenFilter::Blacklist(_) => None,
+        };
+        // Orders only need to pass one of the `or` filters, so they can trade
+        // the tokens of any of them.
+        let or_whitelist = if self.or.is_empty() {
+            None
+        } else {
+            self.or
+                .iter()
+                .map(OrderbookFilter::whitelist)
+                .collect::<Option<Vec<_>>>()
+                .map(|whitelists| whitelists.into_iter().flatten().collect())
+        };
+        // Orders need to pass this filter and all `and` filters, so they can
+        // only trade tokens that all of them allow.
+        iter::once(whitelist)
+            .chain(self.and.iter().map(OrderbookFilter::whitelist))
+            .chain(iter::once(or_whitelist))
+            .flatten()
+            .fold(None, |result, whitelist| match result {
+                Some(result) => Some(&result & &whitelist),
+                None => Some(whitelist),
+            })
+    }
+
+    /// Composes two filters so that orders and balances are only kept if they
+    /// pass both of them.
+    pub fn and(mut self, other: OrderbookFilter) -> Self {
+        self.and.push(other);
+        self
+    }
+
+    /// Composes two filters so that orders and balances are kept if they pass
+    /// either of them.
+    pub fn or(self, other: OrderbookFilter) -> Self {
+        OrderbookFilter {
+            or: vec![self, other],
+            ..OrderbookFilter::default()
+        }
+    }
+
+    fn keeps_balance(&self, balance: u128) -> bool {
+        !self.exceeds_max_amount(balance)
+            && self.and.iter().all(|filter| filter.keeps_balance(balance))
+            && (self.or.is_empty() || self.or.iter().any(|filter| filter.keeps_balance(balance)))
+    }
+
+    fn keeps_order(&self, order: &Order) -> bool {
+        self.keeps_order_ignoring_composition(order)
+            && self.and.iter().all(|filter| filter.keeps_order(order))
+            && (self.or.is_empty() || self.or.iter().any(|filter| filter.keeps_order(order)))
+    }
+
+    fn keeps_order_ignoring_composition(&self, order: &Order) -> bool {
+        let token_filtered = match &self.tokens {
+            TokenFilter::Whitelist(token_list) => {
+                !token_list.contains(&order.buy_token) || !token_list.contains(&order.sell_token)
+            }
+            TokenFilter::Blacklist(token_list) => {
+                token_list.contains(&order.buy_token) || token_list.contains(&order.sell_token)
+            }
+        };
+        let user_filtered = match self.users.get(&order.account_id) {
+            Some(UserOrderFilter::All) => true,
+            Some(UserOrderFilter::OrderIds(ids)) => ids.contains(&order.id),
+            None => false,
+        };
+
+        !self.exceeds_max_amount(order.sell_amount)
+            && !self.exceeds_max_amount(order.buy_amount)
+            && !token_filtered
+            && self.pair_directions.allows(order)
+            && !user_filtered
+    }
+
+    fn exceeds_max_amount(&self, amount: u128) -> bool {
+        match self.max_amount {
+            Some(max_amount) => amount > max_amount,
+            None => false,
         }
     }
 }
@@ -66,37 +212,52 @@ impl<'a> FilteredOrderbookReader<'a> {
     ) -> Self {
         Self { orderbook, filter }
     }
+
+    /// Removes the balances and orders from auction data that the filter
+    /// doesn't keep.
+    fn filter_auction_data(
+        &self,
+        (mut state, mut orders): (AccountState, Vec<Order>),
+    ) -> (AccountState, Vec<Order>) {
+        state.0.retain(|(user, token), balance| {
+            let keep = self.filter.keeps_balance(*balance);
+            if !keep && self.filter.exceeds_max_amount(*balance) {
+                warn!(
+                    "dropping balance {} of token {} for user {:?} exceeding maximum amount",
+                    balance, token, user,
+                );
+            }
+            keep
+        });
+        orders.retain(|o| {
+            let keep = self.filter.keeps_order(o);
+            if !keep
+                && (self.filter.exceeds_max_amount(o.sell_amount)
+                    || self.filter.exceeds_max_amount(o.buy_amount))
+            {
+                warn!("dropping order exceeding maximum amount: {:?}", o);
+            }
+            keep
+        });
+        (state, orders)
+    }
 }
 
 impl<'a> StableXOrderBookReading for FilteredOrderbookReader<'a> {
     fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
-        let (state, orders) = self.orderbook.get_auction_data(batch_id_to_solve)?;
-        let token_filtered_orders: Vec<Order> = match &self.filter.tokens {
-            TokenFilter::Whitelist(token_list) => orders
-                .into_iter()
-                .filter(|o| token_list.contains(&o.buy_token) && token_list.contains(&o.sell_token))
-                .collect(),
-            TokenFilter::Blacklist(token_list) => orders
-                .into_iter()
-                .filter(|o| {
-                    !token_list.contains(&o.buy_token) && !token_list.contains(&o.sell_token)
-                })
-                .collect(),
-        };
-        let user_filtered_orders = token_filtered_orders
-            .into_iter()
-            .filter(|o| {
-                if let Some(user_filter) = self.filter.users.get(&o.account_id) {
-                    match user_filter {
-                        UserOrderFilter::All => false,
-                        UserOrderFilter::OrderIds(ids) => !ids.contains(&o.id),
-                    }
-                } else {
-                    true
-                }
-            })
-            .collect();
-        Ok((state, user_filtered_orders))
+        let auction_data = self.orderbook.get_auction_data(batch_id_to_solve)?;
+        Ok(self.filter_auction_data(auction_data))
+    }
+
+    fn get_auction_data_at_block(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: u64,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        let auction_data = self
+            .orderbook
+            .get_auction_data_at_block(batch_id_to_solve, block_number)?;
+        Ok(self.filter_auction_data(auction_data))
     }
 }
 
@@ -130,6 +291,9 @@ mod test {
             .iter()
             .cloned()
             .collect(),
+            pair_directions: PairDirectionFilter::default(),
+            max_amount: None,
+            ..OrderbookFilter::default()
         };
         assert_eq!(
             blacklist_filter,
@@ -145,6 +309,9 @@ mod test {
         let whitelist_filter = OrderbookFilter {
             tokens: TokenFilter::Whitelist([1, 2].iter().copied().collect()),
             users: HashMap::new(),
+            pair_directions: PairDirectionFilter::default(),
+            max_amount: None,
+            ..OrderbookFilter::default()
         };
         assert_eq!(
             whitelist_filter,
@@ -203,6 +370,9 @@ mod test {
             .iter()
             .cloned()
             .collect(),
+            pair_directions: PairDirectionFilter::default(),
+            max_amount: None,
+            ..OrderbookFilter::default()
         };
 
         let reader = FilteredOrderbookReader::new(&inner, filter);
@@ -232,6 +402,9 @@ mod test {
         let filter = OrderbookFilter {
             tokens: TokenFilter::Whitelist([2, 3].iter().copied().collect()),
             users: HashMap::new(),
+            pair_directions: PairDirectionFilter::default(),
+            max_amount: None,
+            ..OrderbookFilter::default()
         };
 
         let reader = FilteredOrderbookReader::new(&inner, filter);
@@ -239,4 +412,370 @@ mod test {
         let (_, filtered_orders) = reader.get_auction_data(U256::zero()).unwrap();
         assert_eq!(filtered_orders, vec![good_order]);
     }
+
+    #[test]
+    fn filters_orderbook_at_block() {
+        let mut bad_sell_token = create_order_for_test();
+        bad_sell_token.sell_token = 4;
+        let good_order = create_order_for_test();
+
+        let mut inner = MockStableXOrderBookReading::default();
+        inner
+            .expect_get_auction_data_at_block()
+            .withf(|batch_id, block_number| *batch_id == U256::from(10) && *block_number == 42)
+            .return_once({
+                let result = (
+                    AccountState::default(),
+                    vec![bad_sell_token, good_order.clone()],
+                );
+                move |_, _| Ok(result)
+            });
+
+        let filter = OrderbookFilter {
+            tokens: TokenFilter::Whitelist([2, 3].iter().copied().collect()),
+            ..OrderbookFilter::default()
+        };
+        let reader = FilteredOrderbookReader::new(&inner, filter);
+
+        let (_, filtered_orders) = reader
+            .get_auction_data_at_block(U256::from(10), 42)
+            .unwrap();
+        assert_eq!(filtered_orders, vec![good_order]);
+    }
+
+    #[test]
+    fn test_max_amount_filter_deserialization() {
+        let json = r#"{
+            "max_amount": 1000000000000000000000000000000
+        }"#;
+        let filter: OrderbookFilter = serde_json::from_str(json).expect("Failed to parse");
+        assert_eq!(
+            filter.max_amount,
+            Some(1_000_000_000_000_000_000_000_000_000_000)
+        );
+    }
+
+    #[test]
+    fn test_max_amount_orderbook_filter() {
+        let good_order = create_order_for_test();
+        let mut huge_sell_amount = create_order_for_test();
+        huge_sell_amount.id = 1;
+        huge_sell_amount.sell_amount = u128::max_value() - 1;
+        let mut huge_buy_amount = create_order_for_test();
+        huge_buy_amount.id = 2;
+        huge_buy_amount.buy_amount = u128::max_value() - 1;
+
+        let good_balance = (good_order.account_id, good_order.sell_token);
+        let huge_balance = (good_order.account_id, good_order.buy_token);
+        let mut inner = MockStableXOrderBookReading::default();
+        inner.expect_get_auction_data().return_once({
+            let result = (
+                AccountState(hash_map! {
+                    good_balance => 100,
+                    huge_balance => u128::max_value(),
+                }),
+                vec![huge_sell_amount, good_order.clone(), huge_buy_amount],
+            );
+            move |_| Ok(result)
+        });
+
+        let filter = OrderbookFilter {
+            max_amount: Some(1_000_000),
+            ..OrderbookFilter::default()
+        };
+        let reader = FilteredOrderbookReader::new(&inner, filter);
+
+        let (state, filtered_orders) = reader.get_auction_data(U256::zero()).unwrap();
+        assert_eq!(filtered_orders, vec![good_order]);
+        assert_eq!(state, AccountState(hash_map! { good_balance => 100 }));
+    }
+
+    #[test]
+    fn test_pair_direction_filter_deserialization() {
+        let json = r#"{
+            "pair_directions": { "Blacklist": [{ "buy": 1, "sell": 2 }] }
+        }"#;
+        let filter: OrderbookFilter = serde_json::from_str(json).expect("Failed to parse");
+        assert_eq!(
+            filter.pair_directions,
+            PairDirectionFilter::Blacklist(
+                [PairDirection {
+                    buy: 1,
+                    sell: 2,
+                    both: false,
+                }]
+                .iter()
+                .copied()
+                .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn test_pair_direction_orderbook_filter() {
+        let buy_restricted = Order {
+            id: 0,
+            ..Order::for_token_pair(1, 2)
+        };
+        let sell_restricted = Order {
+            id: 1,
+            ..Order::for_token_pair(2, 1)
+        };
+        let other_pair = Order {
+            id: 2,
+            ..Order::for_token_pair(1, 3)
+        };
+        let orders = vec![buy_restricted, sell_restricted.clone(), other_pair.clone()];
+
+        let filtered_orders = |pair_directions| {
+            let mut inner = MockStableXOrderBookReading::default();
+            inner.expect_get_auction_data().return_once({
+                let result = (AccountState::default(), orders.clone());
+                move |_| Ok(result)
+            });
+            let filter = OrderbookFilter {
+                pair_directions,
+                ..OrderbookFilter::default()
+            };
+            let reader = FilteredOrderbookReader::new(&inner, filter);
+            reader.get_auction_data(U256::zero()).unwrap().1
+        };
+
+        // Only allow selling token 1 for token 2 but not buying it.
+        let denied = [PairDirection {
+            buy: 1,
+            sell: 2,
+            both: false,
+        }]
+        .iter()
+        .copied()
+        .collect();
+        assert_eq!(
+            filtered_orders(PairDirectionFilter::Blacklist(denied)),
+            vec![sell_restricted.clone(), other_pair]
+        );
+
+        let allowed = [PairDirection {
+            buy: 2,
+            sell: 1,
+            both: false,
+        }]
+        .iter()
+        .copied()
+        .collect();
+        assert_eq!(
+            filtered_orders(PairDirectionFilter::Whitelist(allowed)),
+            vec![sell_restricted]
+        );
+    }
+
+    #[test]
+    fn test_pair_whitelist_filter_deserialization() {
+        let json = r#"{
+            "pair_directions": {
+                "Whitelist": [{ "buy": 1, "sell": 7, "both": true }, { "buy": 4, "sell": 1 }]
+            }
+        }"#;
+        let filter: OrderbookFilter = serde_json::from_str(json).expect("Failed to parse");
+        assert_eq!(
+            filter.pair_directions,
+            PairDirectionFilter::Whitelist(
+                [
+                    PairDirection {
+                        buy: 1,
+                        sell: 7,
+                        both: true,
+                    },
+                    PairDirection {
+                        buy: 4,
+                        sell: 1,
+                        both: false,
+                    },
+                ]
+                .iter()
+                .copied()
+                .collect()
+            )
+        );
+    }
+
+    #[test]
+    fn test_pair_whitelist_orderbook_filter() {
+        let eth_dai = Order {
+            id: 0,
+            ..Order::for_token_pair(1, 7)
+        };
+        let dai_eth = Order {
+            id: 1,
+            ..Order::for_token_pair(7, 1)
+        };
+        let usdc_eth = Order {
+            id: 2,
+            ..Order::for_token_pair(4, 1)
+        };
+        let dai_usdc = Order {
+            id: 3,
+            ..Order::for_token_pair(7, 4)
+        };
+        let eth_mkr = Order {
+            id: 4,
+            ..Order::for_token_pair(1, 8)
+        };
+
+        let mut inner = MockStableXOrderBookReading::default();
+        inner.expect_get_auction_data().return_once({
+            let result = (
+                AccountState::default(),
+                vec![
+                    eth_dai.clone(),
+                    dai_eth.clone(),
+                    usdc_eth.clone(),
+                    dai_usdc,
+                    eth_mkr,
+                ],
+            );
+            move |_| Ok(result)
+        });
+
+        let pair = |buy, sell| PairDirection {
+            buy,
+            sell,
+            both: true,
+        };
+        let filter = OrderbookFilter {
+            pair_directions: PairDirectionFilter::Whitelist(
+                [pair(1, 7), pair(1, 4)].iter().copied().collect(),
+            ),
+            ..OrderbookFilter::default()
+        };
+        let reader = FilteredOrderbookReader::new(&inner, filter);
+
+        let (_, filtered_orders) = reader.get_auction_data(U256::zero()).unwrap();
+        assert_eq!(filtered_orders, vec![eth_dai, dai_eth, usdc_eth]);
+    }
+
+    #[test]
+    fn test_composed_filter_deserialization() {
+        let json = r#"{
+            "tokens": { "Whitelist": [1, 2] },
+            "or": [
+                { "users": { "0x7b60655Ca240AC6c76dD29c13C45BEd969Ee6F0A": "All" } },
+                { "max_amount": 1000 }
+            ]
+        }"#;
+        let filter: OrderbookFilter = serde_json::from_str(json).expect("Failed to parse");
+        assert_eq!(filter.whitelist(), Some([1, 2].iter().copied().collect()));
+        assert_eq!(filter.or.len(), 2);
+        assert_eq!(filter.or[1].max_amount, Some(1000));
+    }
+
+    #[test]
+    fn test_composed_orderbook_filters() {
+        let user_1 = Address::from_low_u64_be(1);
+        let user_2 = Address::from_low_u64_be(2);
+        let small_whitelisted = Order {
+            id: 0,
+            account_id: user_1,
+            sell_amount: 100,
+            buy_amount: 100,
+            ..Order::for_token_pair(1, 2)
+        };
+        let large_whitelisted = Order {
+            id: 1,
+            account_id: user_2,
+            ..Order::for_token_pair(1, 2)
+        };
+        let small_other_token = Order {
+            id: 2,
+            account_id: user_2,
+            sell_amount: 100,
+            buy_amount: 100,
+            ..Order::for_token_pair(1, 3)
+        };
+        let large_other_token = Order {
+            id: 3,
+            account_id: user_2,
+            ..Order::for_token_pair(1, 3)
+        };
+        let orders = vec![
+            small_whitelisted.clone(),
+            large_whitelisted.clone(),
+            small_other_token.clone(),
+            large_other_token,
+        ];
+
+        let filtered_orders = |filter| {
+            let mut inner = MockStableXOrderBookReading::default();
+            inner.expect_get_auction_data().return_once({
+                let result = (AccountState::default(), orders.clone());
+                move |_| Ok(result)
+            });
+            let reader = FilteredOrderbookReader::new(&inner, filter);
+            reader.get_auction_data(U256::zero()).unwrap().1
+        };
+        let token_whitelist = OrderbookFilter {
+            tokens: TokenFilter::Whitelist([1, 2].iter().copied().collect()),
+            ..OrderbookFilter::default()
+        };
+        let small_amounts = OrderbookFilter {
+            max_amount: Some(1_000),
+            ..OrderbookFilter::default()
+        };
+        let not_user = |user: Address| OrderbookFilter {
+            users: hash_map! { user => UserOrderFilter::All },
+            ..OrderbookFilter::default()
+        };
+
+        assert_eq!(
+            filtered_orders(token_whitelist.clone().and(small_amounts.clone())),
+            vec![small_whitelisted.clone()]
+        );
+        assert_eq!(
+            filtered_orders(token_whitelist.or(small_amounts)),
+            vec![small_whitelisted, large_whitelisted, small_other_token]
+        );
+        assert_eq!(
+            filtered_orders(not_user(user_1).or(not_user(user_2))),
+            orders
+        );
+        assert_eq!(
+            filtered_orders(not_user(user_1).and(not_user(user_2))),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_whitelist_of_composed_filters() {
+        let token_whitelist = |tokens: &[u16]| OrderbookFilter {
+            tokens: TokenFilter::Whitelist(tokens.iter().copied().collect()),
+            ..OrderbookFilter::default()
+        };
+        let small_amounts = OrderbookFilter {
+            max_amount: Some(1_000),
+            ..OrderbookFilter::default()
+        };
+        let whitelist = |tokens: &[u16]| Some(tokens.iter().copied().collect::<HashSet<_>>());
+
+        assert_eq!(small_amounts.whitelist(), None);
+        assert_eq!(
+            token_whitelist(&[1, 2])
+                .and(token_whitelist(&[2, 3]))
+                .whitelist(),
+            whitelist(&[2])
+        );
+        assert_eq!(
+            token_whitelist(&[1, 2])
+                .and(small_amounts.clone())
+                .whitelist(),
+            whitelist(&[1, 2])
+        );
+        assert_eq!(
+            token_whitelist(&[1, 2])
+                .or(token_whitelist(&[3]))
+                .whitelist(),
+            whitelist(&[1, 2, 3])
+        );
+        // An `or` keeps orders on any token if one of its filters does.
+        assert_eq!(token_whitelist(&[1, 2]).or(small_amounts).whitelist(), None);
+    }
 }
@@ -1,25 +1,43 @@
+#[cfg(test)]
+mod async_reading;
+mod auction_data_export;
 mod auction_data_reader;
 mod filtered_orderbook;
 mod onchain_filtered_orderbook;
 mod paginated_orderbook;
+#[cfg(test)]
+mod pricegraph_orderbook;
 mod shadow_orderbook;
 mod streamed;
 
+#[cfg(test)]
+pub use self::async_reading::AsyncStableXOrderBookReading;
+#[cfg(test)]
+pub use self::auction_data_export::SCHEMA_VERSION as AUCTION_DATA_SCHEMA_VERSION;
+pub use self::auction_data_export::{AuctionDataExport, ExportingOrderbookReader};
 pub use self::filtered_orderbook::{FilteredOrderbookReader, OrderbookFilter};
 pub use self::onchain_filtered_orderbook::OnchainFilteredOrderBookReader;
 pub use self::paginated_orderbook::PaginatedStableXOrderBookReader;
 pub use self::shadow_orderbook::ShadowedOrderbookReader;
 pub use self::streamed::Orderbook as EventBasedOrderbook;
 
-use crate::contracts::{stablex_contract::StableXContractImpl, Web3};
+use crate::contracts::{
+    stablex_contract::{StableXContract, StableXContractImpl},
+    Web3,
+};
+use crate::metrics::ComponentMetrics;
 use crate::models::{AccountState, Order};
 
-use anyhow::{anyhow, Error, Result};
-use ethcontract::U256;
+use anyhow::{anyhow, ensure, Error, Result};
+use ethcontract::{Address, U256};
+use log::debug;
 #[cfg(test)]
 use mockall::automock;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 
 #[cfg_attr(test, automock)]
 pub trait StableXOrderBookReading {
@@ -29,6 +47,66 @@ pub trait StableXOrderBookReading {
     /// # Arguments
     /// * `batch_id_to_solve` - the index for which returned orders should be valid
     fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)>;
+
+    /// Returns the state of the order book like `get_auction_data`, but as it
+    /// was at the specified historical block, for example to analyze a past
+    /// batch after the fact.
+    ///
+    /// The default implementation fails, readers that can read the orderbook
+    /// at a given block should override it.
+    fn get_auction_data_at_block(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: u64,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        let _ = batch_id_to_solve;
+        Err(anyhow!(
+            "reading the orderbook at block {} is not supported by this reader",
+            block_number
+        ))
+    }
+
+    /// Returns the non-zero balances of a single user by token id in the same
+    /// batch as `get_auction_data`.
+    ///
+    /// The default implementation reads the whole auction data, readers that
+    /// can look up a user's balances directly should override it.
+    fn get_account_balances(
+        &self,
+        user: Address,
+        batch_id_to_solve: U256,
+    ) -> Result<HashMap<u16, u128>> {
+        let (account_state, _) = self.get_auction_data(batch_id_to_solve)?;
+        Ok(account_state
+            .0
+            .into_iter()
+            .filter(|((account, _), balance)| *account == user && *balance > 0)
+            .map(|((_, token), balance)| (token, balance))
+            .collect())
+    }
+}
+
+/// The error returned when reading the orderbook for a batch that cannot be
+/// solved yet because it is still collecting orders or has not started.
+#[derive(Debug, Error, Eq, PartialEq)]
+#[error("batch {requested} is in the future, the most recent batch that can be solved is {latest}")]
+pub struct FutureBatchError {
+    pub requested: U256,
+    pub latest: U256,
+}
+
+/// Fails with a `FutureBatchError` if `batch_id_to_solve` is after the batch
+/// preceding `current_batch_id`, the batch that is currently collecting orders.
+fn ensure_batch_is_solvable(batch_id_to_solve: U256, current_batch_id: u32) -> Result<()> {
+    let latest = U256::from(current_batch_id.saturating_sub(1));
+    if batch_id_to_solve > latest {
+        return Err(FutureBatchError {
+            requested: batch_id_to_solve,
+            latest,
+        }
+        .into());
+    }
+    Ok(())
 }
 
 /// The different kinds of orderbook readers.
@@ -44,31 +122,109 @@ pub enum OrderbookReaderKind {
 }
 
 impl OrderbookReaderKind {
-    /// Creates a new Orderbook reader based on the parameters.
+    /// Returns whether the reader can read the orderbook as of a block with a
+    /// minimum number of confirmations. The event based orderbook applies
+    /// events as soon as they are received instead, and reverts them if they
+    /// get reorged out.
+    pub fn supports_min_confirmations(&self) -> bool {
+        match self {
+            OrderbookReaderKind::Paginated | OrderbookReaderKind::OnchainFiltered => true,
+            OrderbookReaderKind::EventBased => false,
+        }
+    }
+
+    /// Returns whether the reader can read the orderbook as it was at a past
+    /// block. The event based orderbook only knows the current orderbook.
+    pub fn supports_reading_at_block(&self) -> bool {
+        match self {
+            OrderbookReaderKind::Paginated | OrderbookReaderKind::OnchainFiltered => true,
+            OrderbookReaderKind::EventBased => false,
+        }
+    }
+
+    /// Creates a new Orderbook reader based on the parameters. Orderbooks read
+    /// from the EVM are read as of `min_confirmations` blocks before the most
+    /// recent block, so that recent balance changes that may still be reorged
+    /// out are not trusted. Fails if the reader kind does not support minimum
+    /// confirmations but they are set. Paginated orderbook reads fail if there are more
+    /// than `max_orders` orders, log a warning if they take longer than
+    /// `slow_read_threshold` and are recorded in the specified component
+    /// metrics. The event based orderbook polls for new events in the
+    /// specified interval instead of using a filter if `event_poll_interval`
+    /// is set, applying only events of blocks with at least
+    /// `event_poll_confirmations` confirmations.
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
         &self,
         contract: Arc<StableXContractImpl>,
         auction_data_page_size: u16,
+        auction_data_page_timeout: Option<Duration>,
         orderbook_filter: &OrderbookFilter,
+        min_confirmations: u64,
+        max_orders: Option<usize>,
+        slow_read_threshold: Option<Duration>,
+        event_poll_interval: Option<Duration>,
+        event_poll_confirmations: u64,
         web3: Web3,
-    ) -> Box<dyn StableXOrderBookReading + Sync> {
-        match self {
-            OrderbookReaderKind::Paginated => Box::new(PaginatedStableXOrderBookReader::new(
-                contract,
-                auction_data_page_size,
-            )),
-            OrderbookReaderKind::OnchainFiltered => Box::new(OnchainFilteredOrderBookReader::new(
-                contract,
-                auction_data_page_size,
-                orderbook_filter,
-            )),
-            OrderbookReaderKind::EventBased => {
-                Box::new(EventBasedOrderbook::new(contract.as_ref(), web3))
+        metrics: &ComponentMetrics,
+    ) -> Result<Box<dyn StableXOrderBookReading + Sync>> {
+        ensure!(
+            min_confirmations == 0 || self.supports_min_confirmations(),
+            "the {:?} orderbook reader does not support minimum confirmations",
+            self
+        );
+        Ok(match self {
+            OrderbookReaderKind::Paginated => {
+                let mut reader = PaginatedStableXOrderBookReader::new(
+                    contract,
+                    auction_data_page_size,
+                    auction_data_page_timeout,
+                    metrics.clone(),
+                )
+                .with_min_confirmations(min_confirmations)
+                .with_progress_callback(|pages, orders| {
+                    debug!("Read {} orders in {} orderbook pages", orders, pages)
+                });
+                if let Some(max_orders) = max_orders {
+                    reader = reader.with_max_orders(max_orders);
+                }
+                if let Some(slow_read_threshold) = slow_read_threshold {
+                    reader = reader.with_slow_read_threshold(slow_read_threshold);
+                }
+                Box::new(reader)
             }
-        }
+            OrderbookReaderKind::OnchainFiltered => Box::new(
+                OnchainFilteredOrderBookReader::new(
+                    contract,
+                    auction_data_page_size,
+                    orderbook_filter,
+                )
+                .with_min_confirmations(min_confirmations),
+            ),
+            OrderbookReaderKind::EventBased => match event_poll_interval {
+                Some(poll_interval) => Box::new(EventBasedOrderbook::with_event_polling(
+                    contract,
+                    web3,
+                    poll_interval,
+                    event_poll_confirmations,
+                    Default::default(),
+                )),
+                None => Box::new(EventBasedOrderbook::new(contract, web3)),
+            },
+        })
     }
 }
 
+/// Returns the most recent block that has at least `min_confirmations`
+/// confirmations, or `None` if no confirmations are required.
+fn confirmed_block(contract: &dyn StableXContract, min_confirmations: u64) -> Result<Option<u64>> {
+    if min_confirmations == 0 {
+        return Ok(None);
+    }
+    let block_number = contract.get_block_number()?;
+    Ok(Some(block_number.saturating_sub(min_confirmations)))
+}
+
 impl FromStr for OrderbookReaderKind {
     type Err = Error;
     fn from_str(value: &str) -> Result<Self> {
@@ -80,3 +236,22 @@ impl FromStr for OrderbookReaderKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_based_orderbook_does_not_support_min_confirmations() {
+        assert!(OrderbookReaderKind::Paginated.supports_min_confirmations());
+        assert!(OrderbookReaderKind::OnchainFiltered.supports_min_confirmations());
+        assert!(!OrderbookReaderKind::EventBased.supports_min_confirmations());
+    }
+
+    #[test]
+    fn event_based_orderbook_does_not_support_reading_at_block() {
+        assert!(OrderbookReaderKind::Paginated.supports_reading_at_block());
+        assert!(OrderbookReaderKind::OnchainFiltered.supports_reading_at_block());
+        assert!(!OrderbookReaderKind::EventBased.supports_reading_at_block());
+    }
+}
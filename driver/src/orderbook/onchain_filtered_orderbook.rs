@@ -3,7 +3,7 @@ use crate::models::{AccountState, Order};
 
 use super::auction_data_reader::IndexedAuctionDataReader;
 use super::filtered_orderbook::OrderbookFilter;
-use super::StableXOrderBookReading;
+use super::{confirmed_block, ensure_batch_is_solvable, StableXOrderBookReading};
 
 use anyhow::Result;
 use ethcontract::{Address, BlockNumber, U256};
@@ -13,6 +13,7 @@ pub struct OnchainFilteredOrderBookReader {
     contract: Arc<dyn StableXContract + Send + Sync>,
     page_size: u16,
     filter: Vec<u16>,
+    min_confirmations: u64,
 }
 
 impl OnchainFilteredOrderBookReader {
@@ -26,17 +27,50 @@ impl OnchainFilteredOrderBookReader {
             page_size,
             filter: filter
                 .whitelist()
-                .map(|set| set.iter().cloned().collect())
+                .map(|set| set.into_iter().collect())
                 .unwrap_or_else(|| vec![]),
+            min_confirmations: 0,
         }
     }
+
+    /// Reads the orderbook as of a block with at least the specified number
+    /// of confirmations if the last block of the batch is not yet confirmed.
+    pub fn with_min_confirmations(mut self, min_confirmations: u64) -> Self {
+        self.min_confirmations = min_confirmations;
+        self
+    }
 }
 
-impl StableXOrderBookReading for OnchainFilteredOrderBookReader {
-    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
-        let last_block = self
-            .contract
-            .get_last_block_for_batch(batch_id_to_solve.as_u32())?;
+impl OnchainFilteredOrderBookReader {
+    /// Reads the orderbook at the specified block, or at the last block of the
+    /// batch or the most recent block with enough confirmations, whichever is
+    /// earlier, if it is not specified.
+    fn read_auction_data(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: Option<u64>,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        ensure_batch_is_solvable(
+            batch_id_to_solve,
+            self.contract.get_current_auction_index()?,
+        )?;
+        let block_number = match block_number {
+            Some(block_number) => Some(block_number),
+            None => {
+                let last_block = self
+                    .contract
+                    .get_last_block_for_batch(batch_id_to_solve.as_u32())?;
+                match (
+                    last_block,
+                    confirmed_block(&*self.contract, self.min_confirmations)?,
+                ) {
+                    (Some(last_block), Some(confirmed_block)) => {
+                        Some(last_block.min(confirmed_block))
+                    }
+                    (last_block, confirmed_block) => last_block.or(confirmed_block),
+                }
+            }
+        };
         let mut reader = IndexedAuctionDataReader::new(batch_id_to_solve);
         let mut auction_data = FilteredOrderPage {
             indexed_elements: vec![],
@@ -51,18 +85,33 @@ impl StableXOrderBookReading for OnchainFilteredOrderBookReader {
                 self.page_size,
                 auction_data.next_page_user,
                 auction_data.next_page_user_offset,
-                last_block.map(BlockNumber::from),
+                block_number.map(BlockNumber::from),
             )?;
-            reader.apply_page(&auction_data.indexed_elements);
+            reader.apply_page(&auction_data.indexed_elements)?;
         }
         Ok(reader.get_auction_data())
     }
 }
 
+impl StableXOrderBookReading for OnchainFilteredOrderBookReader {
+    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
+        self.read_auction_data(batch_id_to_solve, None)
+    }
+
+    fn get_auction_data_at_block(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: u64,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        self.read_auction_data(batch_id_to_solve, Some(block_number))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::contracts::stablex_contract::MockStableXContract;
+    use crate::orderbook::FutureBatchError;
     use mockall::Sequence;
 
     const FIRST_ORDER: &[u8] = &[
@@ -93,9 +142,19 @@ mod tests {
         0, 1, // order index
     ];
 
+    /// Creates a mock contract that is collecting orders for the batch after
+    /// batch 42, which is read by the tests.
+    fn mock_contract() -> MockStableXContract {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_current_auction_index()
+            .returning(|| Ok(43));
+        contract
+    }
+
     #[test]
     fn test_no_data() {
-        let mut contract = MockStableXContract::new();
+        let mut contract = mock_contract();
 
         contract
             .expect_get_last_block_for_batch()
@@ -125,7 +184,7 @@ mod tests {
 
     #[test]
     fn test_single_page() {
-        let mut contract = MockStableXContract::new();
+        let mut contract = mock_contract();
 
         contract
             .expect_get_last_block_for_batch()
@@ -157,6 +216,8 @@ mod tests {
             sell_token: 257,
             buy_amount: 257,
             sell_amount: 257,
+            valid_from: 2,
+            valid_until: 261,
         };
 
         assert_eq!(
@@ -167,7 +228,7 @@ mod tests {
 
     #[test]
     fn test_two_pages() {
-        let mut contract = MockStableXContract::new();
+        let mut contract = mock_contract();
         let mut seq = Sequence::new();
 
         contract
@@ -216,6 +277,8 @@ mod tests {
                 sell_token: 257,
                 buy_amount: 257,
                 sell_amount: 257,
+                valid_from: 2,
+                valid_until: 261,
             },
             Order {
                 id: 1,
@@ -224,6 +287,8 @@ mod tests {
                 sell_token: 258,
                 buy_amount: 256,
                 sell_amount: 256,
+                valid_from: 2,
+                valid_until: 261,
             },
         ];
 
@@ -232,4 +297,91 @@ mod tests {
             (state, orders)
         )
     }
+
+    #[test]
+    fn reads_orderbook_at_confirmed_block() {
+        let mut contract = mock_contract();
+
+        contract
+            .expect_get_last_block_for_batch()
+            .returning(|_| Ok(Some(42)));
+        contract.expect_get_block_number().returning(|| Ok(40));
+        contract
+            .expect_get_filtered_auction_data_paginated()
+            .withf(|_, _, _, _, _, block_number| {
+                matches!(block_number, Some(BlockNumber::Number(number)) if number.as_u64() == 35)
+            })
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(FilteredOrderPage {
+                    indexed_elements: vec![],
+                    has_next_page: false,
+                    next_page_user: Address::zero(),
+                    next_page_user_offset: 0,
+                })
+            });
+
+        let reader = OnchainFilteredOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            &OrderbookFilter::default(),
+        )
+        .with_min_confirmations(5);
+        reader.get_auction_data(U256::from(42)).unwrap();
+    }
+
+    #[test]
+    fn reads_orderbook_at_specified_block() {
+        let mut contract = mock_contract();
+
+        contract.expect_get_last_block_for_batch().times(0);
+        contract.expect_get_block_number().times(0);
+        contract
+            .expect_get_filtered_auction_data_paginated()
+            .withf(|_, _, _, _, _, block_number| {
+                matches!(block_number, Some(BlockNumber::Number(number)) if number.as_u64() == 30)
+            })
+            .times(1)
+            .returning(|_, _, _, _, _, _| {
+                Ok(FilteredOrderPage {
+                    indexed_elements: vec![],
+                    has_next_page: false,
+                    next_page_user: Address::zero(),
+                    next_page_user_offset: 0,
+                })
+            });
+
+        let reader = OnchainFilteredOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            &OrderbookFilter::default(),
+        )
+        .with_min_confirmations(5);
+        reader
+            .get_auction_data_at_block(U256::from(42), 30)
+            .unwrap();
+    }
+
+    #[test]
+    fn fails_for_future_batch() {
+        let mut contract = mock_contract();
+        contract.expect_get_last_block_for_batch().times(0);
+        contract
+            .expect_get_filtered_auction_data_paginated()
+            .times(0);
+
+        let reader = OnchainFilteredOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            &OrderbookFilter::default(),
+        );
+        let err = reader.get_auction_data(U256::from(100)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FutureBatchError>(),
+            Some(&FutureBatchError {
+                requested: U256::from(100),
+                latest: U256::from(42),
+            })
+        );
+    }
 }
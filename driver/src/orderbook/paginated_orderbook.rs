@@ -1,12 +1,31 @@
+use crate::contracts::stablex_auction_element::AUCTION_ELEMENT_WIDTH;
 use crate::contracts::stablex_contract::StableXContract;
+use crate::metrics::ComponentMetrics;
 use crate::models::{AccountState, Order};
+use crate::util::{Clock, SystemClock};
 
 use super::auction_data_reader::PaginatedAuctionDataReader;
-use super::StableXOrderBookReading;
-use anyhow::Result;
+use super::{confirmed_block, ensure_batch_is_solvable, StableXOrderBookReading};
+use anyhow::{ensure, Result};
 use ethcontract::{BlockNumber, U256};
+use log::warn;
 use std::convert::TryInto;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// A callback reporting the progress of a paginated orderbook read with the
+/// number of pages and the number of orders read so far.
+pub type ProgressCallback = Box<dyn Fn(usize, usize) + Send + Sync>;
+
+/// A callback reporting the warning message of a slow orderbook read.
+type SlowReadCallback = Box<dyn Fn(&str) + Send + Sync>;
+
+/// The number of pages and orders read so far by a paginated orderbook read.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct ReadProgress {
+    pages: usize,
+    orders: usize,
+}
 
 /// Implements the StableXOrderBookReading trait by using the underlying
 /// contract in a paginated way.
@@ -14,19 +33,128 @@ use std::sync::Arc;
 pub struct PaginatedStableXOrderBookReader {
     contract: Arc<dyn StableXContract + Send + Sync>,
     page_size: u16,
+    page_timeout: Option<Duration>,
+    min_confirmations: u64,
+    metrics: ComponentMetrics,
+    progress_callback: Option<ProgressCallback>,
+    max_orders: Option<usize>,
+    slow_read_threshold: Option<Duration>,
+    slow_read_callback: SlowReadCallback,
+    clock: Arc<dyn Clock>,
 }
 
 impl PaginatedStableXOrderBookReader {
-    pub fn new(contract: Arc<dyn StableXContract + Send + Sync>, page_size: u16) -> Self {
+    /// Creates a new paginated orderbook reader that records metrics about
+    /// reads with the specified component metrics. Reading a page uses the
+    /// specified timeout instead of the node connection's default if set.
+    pub fn new(
+        contract: Arc<dyn StableXContract + Send + Sync>,
+        page_size: u16,
+        page_timeout: Option<Duration>,
+        metrics: ComponentMetrics,
+    ) -> Self {
         Self {
             contract,
             page_size,
+            page_timeout,
+            min_confirmations: 0,
+            metrics,
+            progress_callback: None,
+            max_orders: None,
+            slow_read_threshold: None,
+            slow_read_callback: Box::new(|warning| warn!("{}", warning)),
+            clock: Arc::new(SystemClock),
         }
     }
-}
 
-impl StableXOrderBookReading for PaginatedStableXOrderBookReader {
-    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
+    /// Reads the orderbook as of the most recent block with at least the
+    /// specified number of confirmations instead of the pending block.
+    pub fn with_min_confirmations(mut self, min_confirmations: u64) -> Self {
+        self.min_confirmations = min_confirmations;
+        self
+    }
+
+    /// Stops reading and fails if the orderbook contains more than the
+    /// specified number of orders, so that a misbehaving node that keeps
+    /// returning pages can't make the reader run out of memory.
+    pub fn with_max_orders(mut self, max_orders: usize) -> Self {
+        self.max_orders = Some(max_orders);
+        self
+    }
+
+    /// Logs a warning with the number of pages and orders read if reading the
+    /// orderbook takes longer than the specified threshold, whether or not
+    /// the read succeeds.
+    pub fn with_slow_read_threshold(mut self, slow_read_threshold: Duration) -> Self {
+        self.slow_read_threshold = Some(slow_read_threshold);
+        self
+    }
+
+    /// Reports slow reads to the specified callback instead of logging them.
+    #[cfg(test)]
+    fn with_slow_read_callback(
+        mut self,
+        slow_read_callback: impl Fn(&str) + Send + Sync + 'static,
+    ) -> Self {
+        self.slow_read_callback = Box::new(slow_read_callback);
+        self
+    }
+
+    /// Measures the duration of reads with the specified clock instead of the
+    /// system clock.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Calls the specified callback after each page is read with the number of
+    /// pages and orders read so far, including orders that are not valid in
+    /// the batch being read. The callback is called from the reading loop, so
+    /// it should return quickly.
+    pub fn with_progress_callback(
+        mut self,
+        progress_callback: impl Fn(usize, usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress_callback = Some(Box::new(progress_callback));
+        self
+    }
+
+    /// Reads the orderbook at the specified block, or at the most recent block
+    /// with enough confirmations if it is not specified, and records metrics
+    /// about the read.
+    fn observed_read_auction_data(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: Option<u64>,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        let start = self.clock.now();
+        let mut progress = ReadProgress::default();
+        let result = self.read_auction_data(batch_id_to_solve, block_number, &mut progress);
+        let elapsed = self.clock.now().saturating_duration_since(start);
+        self.metrics.orderbook_read(elapsed, result.is_ok());
+        if let Some(warning) = self.slow_read_warning(elapsed, progress) {
+            (self.slow_read_callback)(&warning);
+        }
+        result
+    }
+
+    fn read_auction_data(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: Option<u64>,
+        progress: &mut ReadProgress,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        ensure_batch_is_solvable(
+            batch_id_to_solve,
+            self.contract.get_current_auction_index()?,
+        )?;
+        let block_number = match block_number {
+            Some(block_number) => Some(block_number),
+            None => confirmed_block(&*self.contract, self.min_confirmations)?,
+        }
+        .map(BlockNumber::from)
+        .unwrap_or(BlockNumber::Pending);
         let mut reader =
             PaginatedAuctionDataReader::new(batch_id_to_solve, self.page_size as usize);
         while let Some(page_info) = reader.next_page() {
@@ -37,10 +165,433 @@ impl StableXOrderBookReading for PaginatedStableXOrderBookReader {
                     .previous_page_user_offset
                     .try_into()
                     .expect("user cannot have more than u16::MAX orders"),
-                Some(BlockNumber::Pending),
+                Some(block_number),
+                self.page_timeout,
             )?;
-            reader.apply_page(page);
+            let page_orders = page.len() / AUCTION_ELEMENT_WIDTH;
+            if let Some(max_orders) = self.max_orders {
+                ensure!(
+                    progress.orders + page_orders <= max_orders,
+                    "orderbook exceeds the maximum of {} orders when reading page {}",
+                    max_orders,
+                    progress.pages + 1
+                );
+            }
+            reader.apply_page(page)?;
+            progress.pages += 1;
+            progress.orders += page_orders;
+            if let Some(progress_callback) = &self.progress_callback {
+                progress_callback(progress.pages, progress.orders);
+            }
         }
         Ok(reader.get_auction_data())
     }
+
+    /// Returns a warning message if a read took longer than the slow read
+    /// threshold.
+    fn slow_read_warning(&self, elapsed: Duration, progress: ReadProgress) -> Option<String> {
+        let threshold = self.slow_read_threshold?;
+        if elapsed <= threshold {
+            return None;
+        }
+        Some(format!(
+            "reading the orderbook took {:?}, exceeding {:?}, after {} pages with {} orders",
+            elapsed, threshold, progress.pages, progress.orders,
+        ))
+    }
+}
+
+impl StableXOrderBookReading for PaginatedStableXOrderBookReader {
+    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
+        self.observed_read_auction_data(batch_id_to_solve, None)
+    }
+
+    fn get_auction_data_at_block(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: u64,
+    ) -> Result<(AccountState, Vec<Order>)> {
+        self.observed_read_auction_data(batch_id_to_solve, Some(block_number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::stablex_auction_element::StableXAuctionElement;
+    use crate::contracts::stablex_contract::MockStableXContract;
+    use crate::metrics::component_metrics::test_util::metric_value;
+    use crate::orderbook::FutureBatchError;
+    use crate::util::test_util::MockClock;
+    use ethcontract::Address;
+    use mockall::predicate::{always, eq};
+    use prometheus::Registry;
+    use std::sync::Mutex;
+
+    /// Encodes an order selling token 1 for token 2 with the specified
+    /// validity the way the smart contract does.
+    fn encoded_order(user: u64, valid_from: u32, valid_until: u32) -> Vec<u8> {
+        StableXAuctionElement {
+            sell_token_balance: 100,
+            order: Order::builder()
+                .account_id(Address::from_low_u64_be(user))
+                .buy_token(2)
+                .sell_token(1)
+                .buy_amount(10)
+                .sell_amount(10)
+                .validity(valid_from, valid_until)
+                .build(),
+        }
+        .encode()
+    }
+
+    /// Creates a mock contract that is collecting orders for the batch after
+    /// batch 10, which is read by the tests.
+    fn mock_contract() -> MockStableXContract {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_current_auction_index()
+            .returning(|| Ok(11));
+        contract
+    }
+
+    #[test]
+    fn filters_orders_not_valid_in_batch() {
+        let mut contract = mock_contract();
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .returning(|_, _, _, _, _| {
+                Ok([
+                    encoded_order(1, 11, 20), // starts in the future
+                    encoded_order(2, 0, 9),   // already expired
+                    encoded_order(3, 10, 10), // only valid in the current batch
+                    encoded_order(4, 5, 15),  // currently valid
+                ]
+                .concat())
+            });
+
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            None,
+            ComponentMetrics::default(),
+        );
+        let (_, orders) = reader.get_auction_data(U256::from(10)).unwrap();
+        assert_eq!(
+            orders
+                .iter()
+                .map(|order| order.account_id)
+                .collect::<Vec<_>>(),
+            vec![Address::from_low_u64_be(3), Address::from_low_u64_be(4)]
+        );
+        assert!(orders.iter().all(|order| order.is_valid_at(10)));
+    }
+
+    #[test]
+    fn counts_orderbook_reads() {
+        let mut contract = mock_contract();
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .returning(|_, _, _, _, _| Ok(Vec::new()));
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .returning(|_, _, _, _, _| Err(anyhow::anyhow!("error")));
+        let registry = Arc::new(Registry::new());
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            None,
+            ComponentMetrics::new(&registry).unwrap(),
+        );
+
+        reader.get_auction_data(U256::from(10)).unwrap();
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_total"),
+            1
+        );
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_errors_total"),
+            0
+        );
+
+        assert!(reader.get_auction_data(U256::from(10)).is_err());
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_total"),
+            2
+        );
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_orderbook_read_errors_total"),
+            1
+        );
+    }
+
+    #[test]
+    fn passes_page_timeout_to_contract() {
+        let timeout = Duration::from_secs(60);
+        for &page_timeout in &[None, Some(timeout)] {
+            let mut contract = mock_contract();
+            contract
+                .expect_get_auction_data_paginated()
+                .with(always(), always(), always(), always(), eq(page_timeout))
+                .times(1)
+                .returning(|_, _, _, _, _| Ok(Vec::new()));
+
+            let reader = PaginatedStableXOrderBookReader::new(
+                Arc::new(contract),
+                10,
+                page_timeout,
+                ComponentMetrics::default(),
+            );
+            reader.get_auction_data(U256::from(10)).unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_progress_after_each_page() {
+        let mut contract = mock_contract();
+        let mut sequence = mockall::Sequence::new();
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _, _, _, _| {
+                Ok([encoded_order(1, 0, 20), encoded_order(2, 0, 20)].concat())
+            });
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _, _, _, _| {
+                Ok([encoded_order(3, 0, 20), encoded_order(4, 0, 20)].concat())
+            });
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _, _, _, _| Ok(encoded_order(5, 0, 20)));
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            2,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_progress_callback({
+            let progress = progress.clone();
+            move |pages, orders| progress.lock().unwrap().push((pages, orders))
+        });
+        let (_, orders) = reader.get_auction_data(U256::from(10)).unwrap();
+        assert_eq!(orders.len(), 5);
+        assert_eq!(*progress.lock().unwrap(), vec![(1, 2), (2, 4), (3, 5)]);
+    }
+
+    #[test]
+    fn stops_reading_at_first_page_exceeding_max_orders() {
+        let mut contract = mock_contract();
+        contract
+            .expect_get_auction_data_paginated()
+            .times(3)
+            // Always return a full page, so that there is another page.
+            .returning(|_, _, _, _, _| {
+                Ok([encoded_order(1, 0, 20), encoded_order(1, 0, 20)].concat())
+            });
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            2,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_max_orders(5)
+        .with_progress_callback({
+            let progress = progress.clone();
+            move |pages, orders| progress.lock().unwrap().push((pages, orders))
+        });
+        let err = reader.get_auction_data(U256::from(10)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "orderbook exceeds the maximum of 5 orders when reading page 3"
+        );
+        // The page that would exceed the maximum is not applied.
+        assert_eq!(*progress.lock().unwrap(), vec![(1, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn reads_orderbook_at_confirmed_block() {
+        let mut contract = mock_contract();
+        contract
+            .expect_get_block_number()
+            .times(1)
+            .returning(|| Ok(100));
+        contract
+            .expect_get_auction_data_paginated()
+            .withf(|_, _, _, block_number, _| {
+                matches!(block_number, Some(BlockNumber::Number(number)) if number.as_u64() == 95)
+            })
+            .times(1)
+            .returning(|_, _, _, _, _| Ok(Vec::new()));
+
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_min_confirmations(5);
+        reader.get_auction_data(U256::from(10)).unwrap();
+    }
+
+    #[test]
+    fn reads_orderbook_at_pending_block_without_confirmations() {
+        let mut contract = mock_contract();
+        contract.expect_get_block_number().times(0);
+        contract
+            .expect_get_auction_data_paginated()
+            .withf(|_, _, _, block_number, _| matches!(block_number, Some(BlockNumber::Pending)))
+            .times(1)
+            .returning(|_, _, _, _, _| Ok(Vec::new()));
+
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            None,
+            ComponentMetrics::default(),
+        );
+        reader.get_auction_data(U256::from(10)).unwrap();
+    }
+
+    #[test]
+    fn reads_orderbook_at_historical_block() {
+        let at_block_42 = |_: &u16,
+                           _: &Address,
+                           _: &u16,
+                           block_number: &Option<BlockNumber>,
+                           _: &Option<Duration>| {
+            matches!(block_number, Some(BlockNumber::Number(number)) if number.as_u64() == 42)
+        };
+        let mut contract = mock_contract();
+        contract.expect_get_block_number().times(0);
+        let mut sequence = mockall::Sequence::new();
+        contract
+            .expect_get_auction_data_paginated()
+            .withf(at_block_42)
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _, _, _, _| {
+                Ok([encoded_order(1, 0, 20), encoded_order(2, 0, 20)].concat())
+            });
+        contract
+            .expect_get_auction_data_paginated()
+            .withf(at_block_42)
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _, _, _, _| Ok(Vec::new()));
+
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            2,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_min_confirmations(5);
+        let (_, orders) = reader
+            .get_auction_data_at_block(U256::from(10), 42)
+            .unwrap();
+        assert_eq!(orders.len(), 2);
+    }
+
+    #[test]
+    fn fails_for_future_batch() {
+        let mut contract = mock_contract();
+        contract.expect_get_auction_data_paginated().times(0);
+
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            10,
+            None,
+            ComponentMetrics::default(),
+        );
+        let err = reader.get_auction_data(U256::from(11)).unwrap_err();
+        assert_eq!(
+            *err.downcast_ref::<FutureBatchError>().unwrap(),
+            FutureBatchError {
+                requested: U256::from(11),
+                latest: U256::from(10),
+            }
+        );
+    }
+
+    #[test]
+    fn warns_about_slow_reads() {
+        let clock = Arc::new(MockClock::new());
+        let mut contract = mock_contract();
+        let mut sequence = mockall::Sequence::new();
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning({
+                let clock = clock.clone();
+                move |_, _, _, _, _| {
+                    clock.advance(Duration::from_millis(10));
+                    Ok([encoded_order(1, 0, 20), encoded_order(2, 0, 20)].concat())
+                }
+            });
+        contract
+            .expect_get_auction_data_paginated()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning({
+                let clock = clock.clone();
+                move |_, _, _, _, _| {
+                    clock.advance(Duration::from_millis(10));
+                    Ok(encoded_order(3, 0, 20))
+                }
+            });
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let reader = PaginatedStableXOrderBookReader::new(
+            Arc::new(contract),
+            2,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_slow_read_threshold(Duration::from_millis(5))
+        .with_slow_read_callback({
+            let warnings = warnings.clone();
+            move |warning| warnings.lock().unwrap().push(warning.to_owned())
+        })
+        .with_clock(clock.clone());
+        reader.get_auction_data(U256::from(10)).unwrap();
+
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("reading the orderbook took 20ms"));
+        assert!(warnings[0].ends_with("after 2 pages with 3 orders"));
+
+        let progress = ReadProgress {
+            pages: 2,
+            orders: 3,
+        };
+        let elapsed = Duration::from_millis(20);
+        assert_eq!(
+            reader.slow_read_warning(Duration::from_millis(5), progress),
+            None
+        );
+        assert_eq!(
+            PaginatedStableXOrderBookReader::new(
+                Arc::new(MockStableXContract::new()),
+                2,
+                None,
+                ComponentMetrics::default()
+            )
+            .slow_read_warning(elapsed, progress),
+            None
+        );
+    }
 }
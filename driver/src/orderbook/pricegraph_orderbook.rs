@@ -0,0 +1,121 @@
+//! Module for building `pricegraph` orderbooks from the auction data read by
+//! the driver, so that it can be used for estimating prices on the live
+//! orderbook without encoding and decoding the orders first.
+
+use crate::models::{AccountState, Order};
+use pricegraph::{Element, Price, TokenPair, UserId, Validity};
+
+/// Builds a `pricegraph` orderbook from the account state and orders of an
+/// auction. Orders without any remaining sell amount are skipped, as they
+/// can't be filled and their price is undefined.
+pub fn build_pricegraph_orderbook(state: &AccountState, orders: &[Order]) -> pricegraph::Orderbook {
+    pricegraph::Orderbook::from_elements(
+        orders
+            .iter()
+            .filter(|order| order.sell_amount > 0)
+            .map(|order| to_element(state, order)),
+    )
+}
+
+/// Converts a driver order into a `pricegraph` auction element.
+fn to_element(state: &AccountState, order: &Order) -> Element {
+    Element {
+        user: UserId::from(order.account_id.to_fixed_bytes()),
+        balance: state
+            .read_balance(order.sell_token, order.account_id)
+            .into(),
+        pair: TokenPair {
+            buy: order.buy_token,
+            sell: order.sell_token,
+        },
+        valid: Validity {
+            from: order.valid_from,
+            to: order.valid_until,
+        },
+        // NOTE: Driver orders only contain the remaining amounts, which have
+        // the same ratio as the original price fraction.
+        price: Price {
+            numerator: order.buy_amount,
+            denominator: order.sell_amount,
+        },
+        remaining_sell_amount: order.sell_amount,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethcontract::Address;
+
+    #[test]
+    fn fills_market_order_on_driver_orderbook() {
+        let state = AccountState(hash_map! {
+            (Address::from_low_u64_be(1), 2) => 100_000_000,
+            (Address::from_low_u64_be(2), 2) => 100_000_000,
+        });
+        let orders = vec![
+            Order::builder()
+                .account_id(Address::from_low_u64_be(1))
+                .buy_token(1)
+                .sell_token(2)
+                .buy_amount(1_000_000)
+                .sell_amount(99_000_000)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(2))
+                .buy_token(1)
+                .sell_token(2)
+                .buy_amount(1_000_000)
+                .sell_amount(95_000_000)
+                .build(),
+        ];
+        let mut orderbook = build_pricegraph_orderbook(&state, &orders);
+
+        let fee_factor: f64 = 1.0 / 0.999;
+        let price = orderbook
+            .fill_market_order(TokenPair { buy: 2, sell: 1 }, 500_000.0)
+            .unwrap();
+        assert!((price - 99.0 / fee_factor.powi(2)).abs() < 1e-9);
+
+        let price = orderbook
+            .fill_market_order(TokenPair { buy: 2, sell: 1 }, 1_000_000.0)
+            .unwrap();
+        assert!((price - 95.0 / fee_factor.powi(2)).abs() < 1e-9);
+
+        assert_eq!(
+            orderbook.fill_market_order(TokenPair { buy: 1, sell: 2 }, 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn skips_orders_without_sell_amount() {
+        let state = AccountState(hash_map! {
+            (Address::from_low_u64_be(1), 2) => 100_000_000,
+            (Address::from_low_u64_be(2), 2) => 100_000_000,
+        });
+        let orders = vec![
+            Order::builder()
+                .account_id(Address::from_low_u64_be(1))
+                .buy_token(1)
+                .sell_token(2)
+                .buy_amount(0)
+                .sell_amount(0)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(2))
+                .buy_token(1)
+                .sell_token(2)
+                .buy_amount(1_000_000)
+                .sell_amount(99_000_000)
+                .build(),
+        ];
+        let mut orderbook = build_pricegraph_orderbook(&state, &orders);
+
+        let fee_factor: f64 = 1.0 / 0.999;
+        let price = orderbook
+            .fill_market_order(TokenPair { buy: 2, sell: 1 }, 500_000.0)
+            .unwrap();
+        assert!((price - 99.0 / fee_factor.powi(2)).abs() < 1e-9);
+    }
+}
@@ -12,6 +12,7 @@ use ethcontract::{Address, U256};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
 use std::thread::{self, JoinHandle};
 
 /// A type definition representing a complete orderbook.
@@ -22,7 +23,8 @@ type Orderbook = (AccountState, Vec<Order>);
 pub struct ShadowedOrderbookReader<'a> {
     primary: &'a (dyn StableXOrderBookReading + Sync),
     _shadow_thread: JoinHandle<()>,
-    shadow_channel: SyncSender<(u32, Orderbook)>,
+    shadow_channel: SyncSender<(U256, Orderbook)>,
+    sampler: Mutex<Sampler>,
 }
 
 impl<'a> ShadowedOrderbookReader<'a> {
@@ -43,22 +45,82 @@ impl<'a> ShadowedOrderbookReader<'a> {
             primary,
             _shadow_thread: shadow_thread,
             shadow_channel: shadow_channel_tx,
+            sampler: Mutex::new(Sampler::new(1.0)),
         }
     }
+
+    /// Only compares the specified fraction of reads with the shadow reader,
+    /// in order to bound the overhead of shadowing. Reads that are not
+    /// sampled just return the primary orderbook.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sampler = Mutex::new(Sampler::new(sample_rate));
+        self
+    }
 }
 
 impl<'a> StableXOrderBookReading for ShadowedOrderbookReader<'a> {
     fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<Orderbook> {
         let orderbook = self.primary.get_auction_data(batch_id_to_solve)?;
+        if !self
+            .sampler
+            .lock()
+            .expect("poisoned sampler mutex")
+            .sample()
+        {
+            return Ok(orderbook);
+        }
 
         // NOTE: Ignore errors here as they indicate that the shadow reader is
         //   already reading an orderbook.
         let _ = self
             .shadow_channel
-            .try_send((batch_id_to_solve.low_u32(), orderbook.clone()));
+            .try_send((batch_id_to_solve, orderbook.clone()));
 
         Ok(orderbook)
     }
+
+    fn get_auction_data_at_block(
+        &self,
+        batch_id_to_solve: U256,
+        block_number: u64,
+    ) -> Result<Orderbook> {
+        // NOTE: Historical reads are not compared with the shadow reader, as
+        //   it would read the current orderbook instead.
+        self.primary
+            .get_auction_data_at_block(batch_id_to_solve, block_number)
+    }
+}
+
+/// Decides which reads get compared with the shadow reader.
+///
+/// Instead of randomly sampling reads, the sample rate is accumulated with
+/// every read and a read is sampled whenever the accumulated rate reaches a
+/// whole read. This spreads sampled reads evenly and makes sampling
+/// deterministic.
+#[derive(Debug)]
+struct Sampler {
+    sample_rate: f64,
+    accumulated: f64,
+}
+
+impl Sampler {
+    fn new(sample_rate: f64) -> Self {
+        Sampler {
+            sample_rate: sample_rate.max(0.0).min(1.0),
+            accumulated: 0.0,
+        }
+    }
+
+    /// Returns whether the next read should be sampled.
+    fn sample(&mut self) -> bool {
+        self.accumulated += self.sample_rate;
+        if self.accumulated >= 1.0 {
+            self.accumulated -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 /// Background shadow thread that receives orders from the order channel,
@@ -69,10 +131,10 @@ impl<'a> StableXOrderBookReading for ShadowedOrderbookReader<'a> {
 /// thread should exit.
 fn background_shadow_reader(
     reader: &dyn StableXOrderBookReading,
-    channel: Receiver<(u32, Orderbook)>,
+    channel: Receiver<(U256, Orderbook)>,
 ) {
     while let Ok((batch_id, primary_orderbook)) = channel.recv() {
-        let shadow_orderbook = match reader.get_auction_data(batch_id.into()) {
+        let shadow_orderbook = match reader.get_auction_data(batch_id) {
             Ok(orderbook) => orderbook,
             Err(err) => {
                 log::error!(
@@ -275,6 +337,11 @@ impl From<&'_ Order> for OrderValues {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::orderbook::MockStableXOrderBookReading;
+    use mockall::predicate::eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
 
     #[test]
     fn account_state_diff() {
@@ -294,6 +361,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },
                     Order {
                         id: 1,
@@ -302,6 +370,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },
                     Order {
                         id: 0,
@@ -310,6 +379,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },
                 ],
             ),
@@ -327,6 +397,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },
                     Order {
                         id: 1,
@@ -335,6 +406,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 101,
+                        ..Default::default()
                     },
                     Order {
                         id: 1,
@@ -343,6 +415,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },
                 ],
             ),
@@ -400,6 +473,7 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },),
                     OrderChange::Removed(Order {
                         id: 1,
@@ -408,9 +482,84 @@ mod tests {
                         sell_token: 1,
                         buy_amount: 100,
                         sell_amount: 100,
+                        ..Default::default()
                     },),
                 ],
             )
         );
     }
+
+    #[test]
+    fn samples_fraction_of_reads() {
+        let samples = |sample_rate| {
+            let mut sampler = Sampler::new(sample_rate);
+            (0..8).map(|_| sampler.sample()).collect::<Vec<_>>()
+        };
+        assert_eq!(samples(0.0), vec![false; 8]);
+        assert_eq!(samples(1.0), vec![true; 8]);
+        assert_eq!(
+            samples(0.25),
+            vec![false, false, false, true, false, false, false, true]
+        );
+        assert_eq!(samples(2.0), vec![true; 8]);
+    }
+
+    /// A shadow reader that counts how often it is read.
+    struct CountingReader(Arc<AtomicUsize>);
+
+    impl StableXOrderBookReading for CountingReader {
+        fn get_auction_data(&self, _: U256) -> Result<Orderbook> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Orderbook::default())
+        }
+    }
+
+    #[test]
+    fn only_reads_shadow_on_sampled_reads() {
+        let mut primary = MockStableXOrderBookReading::default();
+        primary
+            .expect_get_auction_data()
+            .returning(|_| Ok(Orderbook::default()));
+
+        let shadow_reads = Arc::new(AtomicUsize::new(0));
+        let reader = ShadowedOrderbookReader::new(&primary, CountingReader(shadow_reads.clone()))
+            .with_sample_rate(0.0);
+        for _ in 0..10 {
+            reader.get_auction_data(U256::zero()).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(shadow_reads.load(Ordering::SeqCst), 0);
+
+        let shadow_reads = Arc::new(AtomicUsize::new(0));
+        let reader = ShadowedOrderbookReader::new(&primary, CountingReader(shadow_reads.clone()))
+            .with_sample_rate(1.0);
+        // NOTE: Reads are skipped while the shadow thread is busy or not yet
+        //   waiting for orderbooks, so retry until the shadow reader was used.
+        for _ in 0..100 {
+            reader.get_auction_data(U256::zero()).unwrap();
+            if shadow_reads.load(Ordering::SeqCst) > 0 {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("shadow reader was never used");
+    }
+
+    #[test]
+    fn reads_historical_orderbook_from_primary() {
+        let mut primary = MockStableXOrderBookReading::default();
+        primary.expect_get_auction_data().times(0);
+        primary
+            .expect_get_auction_data_at_block()
+            .with(eq(U256::from(10)), eq(42))
+            .times(1)
+            .returning(|_, _| Ok(Orderbook::default()));
+
+        let shadow_reads = Arc::new(AtomicUsize::new(0));
+        let reader = ShadowedOrderbookReader::new(&primary, CountingReader(shadow_reads.clone()));
+        reader
+            .get_auction_data_at_block(U256::from(10), 42)
+            .unwrap();
+        assert_eq!(shadow_reads.load(Ordering::SeqCst), 0);
+    }
 }
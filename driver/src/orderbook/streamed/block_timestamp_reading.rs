@@ -1,14 +1,23 @@
 use crate::contracts::Web3;
-use crate::transport::HttpTransport;
+use crate::transport::NodeTransport;
 use anyhow::{Context as _, Result};
 use ethcontract::web3::transports::Batch;
 use ethcontract::{
     web3::types::{Block, BlockId},
     H256,
 };
-use futures::{compat::Future01CompatExt as _, future::BoxFuture, FutureExt as _};
+use futures::{
+    compat::Future01CompatExt as _,
+    future::BoxFuture,
+    stream::{self, StreamExt as _, TryStreamExt as _},
+    FutureExt as _,
+};
 use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
+
+/// The default number of block timestamps to query in a single batch request.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+/// The default number of batch requests to have in flight at the same time.
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 10;
 
 /// Helper trait to make this functionality mockable for tests.
 pub trait BlockTimestampReading {
@@ -16,10 +25,8 @@ pub trait BlockTimestampReading {
 }
 
 pub trait BlockTimestampBatchReading {
-    fn block_timestamps(
-        &mut self,
-        block_hashes: HashSet<H256>,
-    ) -> BoxFuture<Result<Vec<(H256, u64)>>>;
+    /// Queries the timestamps of the specified blocks with a single request.
+    fn block_timestamps(&self, block_hashes: Vec<H256>) -> BoxFuture<Result<Vec<(H256, u64)>>>;
 }
 
 /// During normal operation this is implemented by Web3.
@@ -37,24 +44,13 @@ impl BlockTimestampReading for Web3 {
 }
 
 impl BlockTimestampBatchReading for Web3 {
-    fn block_timestamps(
-        &mut self,
-        block_hashes: HashSet<H256>,
-    ) -> BoxFuture<Result<Vec<(H256, u64)>>> {
+    fn block_timestamps(&self, block_hashes: Vec<H256>) -> BoxFuture<Result<Vec<(H256, u64)>>> {
         let batched_web3 = ethcontract::web3::Web3::new(Batch::new(self.transport().clone()));
-        async move {
-            let mut result = Vec::with_capacity(block_hashes.len());
-            for chunk in Vec::from_iter(block_hashes.into_iter()).chunks(1000) {
-                let partial_result = query_block_timestamps_batched(&batched_web3, chunk).await?;
-                result.extend(partial_result);
-            }
-            Ok(result)
-        }
-        .boxed()
+        async move { query_block_timestamps_batched(&batched_web3, &block_hashes).await }.boxed()
     }
 }
 
-type BatchedWeb3 = ethcontract::web3::Web3<Batch<HttpTransport>>;
+type BatchedWeb3 = ethcontract::web3::Web3<Batch<NodeTransport>>;
 async fn query_block_timestamps_batched(
     batched_web3: &BatchedWeb3,
     block_hashes: &[H256],
@@ -83,24 +79,46 @@ async fn query_block_timestamps_batched(
 pub struct CachedBlockTimestampReader<T> {
     inner: T,
     cache: HashMap<H256, u64>,
+    batch_size: usize,
+    max_concurrent_requests: usize,
 }
 
-impl<T: BlockTimestampBatchReading + Send> CachedBlockTimestampReader<T> {
+impl<T: BlockTimestampBatchReading + Send + Sync> CachedBlockTimestampReader<T> {
     pub fn new(inner: T) -> Self {
         Self {
             inner,
             cache: HashMap::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
         }
     }
 
+    /// Sets the maximum number of batch requests that are in flight at the
+    /// same time while preparing the cache.
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = max_concurrent_requests.max(1);
+        self
+    }
+
+    /// Fetches the timestamps of all blocks that are not yet cached, querying
+    /// them in batches of which several are in flight concurrently.
     pub fn prepare_cache(&mut self, block_hashes: HashSet<H256>) -> BoxFuture<Result<()>> {
         let missing_hashes = block_hashes
             .into_iter()
-            .filter(|hash| self.cache.contains_key(&hash))
-            .collect();
+            .filter(|hash| !self.cache.contains_key(&hash))
+            .collect::<Vec<_>>();
+        let batches = missing_hashes
+            .chunks(self.batch_size)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
         async move {
-            self.cache
-                .extend(self.inner.block_timestamps(missing_hashes).await?);
+            let inner = &self.inner;
+            let timestamps = stream::iter(batches)
+                .map(|batch| inner.block_timestamps(batch))
+                .buffer_unordered(self.max_concurrent_requests)
+                .try_collect::<Vec<_>>()
+                .await?;
+            self.cache.extend(timestamps.into_iter().flatten());
             Ok(())
         }
         .boxed()
@@ -121,3 +139,81 @@ impl<T: BlockTimestampReading + Send> BlockTimestampReading for CachedBlockTimes
         .boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::sync::Mutex;
+    use std::task::Poll;
+
+    /// A batch reader that tracks how many requests are in flight at once.
+    #[derive(Default)]
+    struct ConcurrencyTrackingReader {
+        // The number of requests currently in flight and the maximum number
+        // of requests that were in flight at the same time.
+        in_flight: Mutex<(usize, usize)>,
+    }
+
+    impl BlockTimestampBatchReading for ConcurrencyTrackingReader {
+        fn block_timestamps(&self, block_hashes: Vec<H256>) -> BoxFuture<Result<Vec<(H256, u64)>>> {
+            async move {
+                {
+                    let mut in_flight = self.in_flight.lock().unwrap();
+                    in_flight.0 += 1;
+                    in_flight.1 = in_flight.1.max(in_flight.0);
+                }
+                // Yield once so that other requests get started before this
+                // one completes.
+                let mut yielded = false;
+                future::poll_fn(|cx| {
+                    if yielded {
+                        Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                })
+                .await;
+                self.in_flight.lock().unwrap().0 -= 1;
+                Ok(block_hashes
+                    .into_iter()
+                    .map(|hash| (hash, hash.to_low_u64_be()))
+                    .collect())
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn prepare_cache_respects_concurrency_limit() {
+        let mut reader = CachedBlockTimestampReader::new(ConcurrencyTrackingReader::default())
+            .with_max_concurrent_requests(3);
+        reader.batch_size = 2;
+
+        let block_hashes = (0..20).map(H256::from_low_u64_be).collect::<HashSet<_>>();
+        futures::executor::block_on(reader.prepare_cache(block_hashes)).unwrap();
+
+        assert_eq!(reader.inner.in_flight.lock().unwrap().1, 3);
+        assert_eq!(
+            reader.cache,
+            (0..20)
+                .map(|i| (H256::from_low_u64_be(i), i))
+                .collect::<HashMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn prepare_cache_only_fetches_missing_timestamps() {
+        let mut reader = CachedBlockTimestampReader::new(ConcurrencyTrackingReader::default());
+        reader.cache.insert(H256::from_low_u64_be(1), 42);
+
+        let block_hashes = (0..3).map(H256::from_low_u64_be).collect::<HashSet<_>>();
+        futures::executor::block_on(reader.prepare_cache(block_hashes)).unwrap();
+
+        assert_eq!(reader.cache[&H256::from_low_u64_be(0)], 0);
+        assert_eq!(reader.cache[&H256::from_low_u64_be(1)], 42);
+        assert_eq!(reader.cache[&H256::from_low_u64_be(2)], 2);
+    }
+}
@@ -4,6 +4,7 @@ mod balance;
 mod block_timestamp_reading;
 mod order;
 mod orderbook;
+mod polling_event_source;
 mod state;
 mod updating_orderbook;
 
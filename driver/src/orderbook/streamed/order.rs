@@ -119,14 +119,15 @@ impl Order {
             self.price_denominator,
             self.get_remaining_amount(batch_id),
         );
-        ModelOrder {
-            id: order_id,
-            account_id: user_id,
-            buy_token: self.buy_token,
-            sell_token: self.sell_token,
-            buy_amount,
-            sell_amount,
-        }
+        ModelOrder::builder()
+            .id(order_id)
+            .account_id(user_id)
+            .buy_token(self.buy_token)
+            .sell_token(self.sell_token)
+            .buy_amount(buy_amount)
+            .sell_amount(sell_amount)
+            .validity(self.valid_from, self.valid_until)
+            .build()
     }
 
     fn apply_pending_used_amount(&mut self, batch_id: BatchId) {
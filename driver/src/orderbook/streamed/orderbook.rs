@@ -1,18 +1,22 @@
 use super::*;
 use crate::{
     contracts::stablex_contract::batch_exchange,
-    models::{AccountState, Order},
+    models::{order::OrderLifecycle, AccountState, Order},
     orderbook::StableXOrderBookReading,
 };
 use anyhow::Result;
 use ethcontract::{contract::EventData, H256, U256};
 use state::{Batch, State};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 // Ethereum events (logs) can be both created and removed. Removals happen if the chain reorganizes
 // and ends up not including block that was previously thought to be part of the chain.
 // However, the orderbook state (`State`) cannot remove events. To support this, we keep an ordered
 // list of all events based on which the state is built.
+// Removals are not always reported, for example when the event stream reconnects to a node that is
+// on a different branch of the chain. To handle this we also track the hash of the block for each
+// block number we have seen events from, and treat an event from a known block number but with a
+// different block hash as a reorg that invalidates all events from that block number onward.
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
 struct EventSortKey {
@@ -30,12 +34,41 @@ struct Value {
     batch_id: BatchId,
 }
 
+/// The metadata of an event that is needed to apply it to the orderbook.
+#[derive(Clone, Copy, Debug)]
+pub struct EventMeta {
+    pub block_number: u64,
+    pub log_index: usize,
+    pub block_hash: H256,
+    pub block_timestamp: u64,
+}
+
 #[derive(Debug, Default)]
 pub struct Orderbook {
     events: BTreeMap<EventSortKey, Value>,
+    block_hashes: BTreeMap<u64, H256>,
+    /// Index of all placed orders by user and order id, which is updated as order events are
+    /// handled so that single orders can be looked up without replaying every event.
+    orders: HashMap<(UserId, OrderId), order::Order>,
 }
 
 impl Orderbook {
+    /// Builds an orderbook from a list of previously observed events, for example from a dumped
+    /// event log, without needing a connection to a node.
+    pub fn replay(events: impl IntoIterator<Item = (batch_exchange::Event, EventMeta)>) -> Self {
+        let mut orderbook = Orderbook::default();
+        for (event, meta) in events {
+            orderbook.handle_event_data(
+                EventData::Added(event),
+                meta.block_number,
+                meta.log_index,
+                meta.block_hash,
+                meta.block_timestamp,
+            );
+        }
+        orderbook
+    }
+
     pub fn handle_event_data(
         &mut self,
         event_data: EventData<batch_exchange::Event>,
@@ -51,9 +84,100 @@ impl Orderbook {
             log_index,
         };
         match event_data {
-            EventData::Added(event) => self.events.insert(key, Value { event, batch_id }),
-            EventData::Removed(_event) => self.events.remove(&key),
-        };
+            EventData::Added(event) => {
+                self.handle_reorg(block_number, block_hash);
+                self.block_hashes.insert(block_number, block_hash);
+                // Events are usually added in order, in which case they can be applied to the
+                // order index directly. Otherwise the index needs to be rebuilt from all events.
+                let is_latest = self
+                    .events
+                    .keys()
+                    .next_back()
+                    .map(|latest| *latest < key)
+                    .unwrap_or(true);
+                if is_latest {
+                    update_order_index(&mut self.orders, &event, batch_id);
+                    self.events.insert(key, Value { event, batch_id });
+                } else {
+                    self.events.insert(key, Value { event, batch_id });
+                    self.rebuild_order_index();
+                }
+            }
+            EventData::Removed(_event) => {
+                if self.events.remove(&key).is_some() {
+                    self.rebuild_order_index();
+                }
+            }
+        }
+    }
+
+    /// Rolls back all events from `block_number` onward if we have previously applied events from
+    /// a different block with the same number, as that block is no longer part of the chain.
+    fn handle_reorg(&mut self, block_number: u64, block_hash: H256) {
+        if let Some(known_hash) = self
+            .block_hashes
+            .get(&block_number)
+            .copied()
+            .filter(|known_hash| *known_hash != block_hash)
+        {
+            let reverted_events = self.events.split_off(&EventSortKey {
+                block_number,
+                block_hash: H256::zero(),
+                log_index: 0,
+            });
+            self.block_hashes.split_off(&block_number);
+            self.rebuild_order_index();
+            log::warn!(
+                "reorg at block {}: block {:?} was replaced by {:?}, reverted {} events",
+                block_number,
+                known_hash,
+                block_hash,
+                reverted_events.len()
+            );
+        }
+    }
+
+    /// Returns the orders that are valid in the specified batch annotated with
+    /// where in their validity window the batch falls. Orders are sorted by
+    /// user and order id.
+    pub fn get_orders_with_lifecycle(
+        &self,
+        batch_id: BatchId,
+    ) -> Result<Vec<(Order, OrderLifecycle)>> {
+        let (_, orders) = self.get_auction_data(U256::from(batch_id))?;
+        let mut orders = orders
+            .into_iter()
+            .filter_map(|order| {
+                let lifecycle = order.lifecycle_at(batch_id)?;
+                Some((order, lifecycle))
+            })
+            .collect::<Vec<_>>();
+        orders.sort_unstable_by_key(|(order, _)| (order.account_id, order.id));
+        Ok(orders)
+    }
+
+    /// Returns a single order of a user that is valid in the batch being solved, or `None` if the
+    /// user has no such order. The order is looked up by user and order id instead of scanning all
+    /// orders. Its amounts are the ones it was placed with and don't account for partial fills.
+    pub fn get_order(
+        &self,
+        user: UserId,
+        order_id: OrderId,
+        batch_id_to_solve: U256,
+    ) -> Option<Order> {
+        // See `get_auction_data` for why the batch id is incremented.
+        let batch_id = batch_id_to_solve.low_u32() + 1;
+        self.orders
+            .get(&(user, order_id))
+            .filter(|order| order.is_valid_in_batch(batch_id - 1))
+            .map(|order| order.as_model_order(batch_id, user, order_id))
+    }
+
+    fn rebuild_order_index(&mut self) {
+        self.orders.clear();
+        for value in self.events.values() {
+            update_order_index(&mut self.orders, &value.event, value.batch_id);
+        }
     }
 
     fn create_state(&self) -> Result<State> {
@@ -65,6 +189,37 @@ impl Orderbook {
     }
 }
 
+/// Applies the changes of an order placement, cancellation or deletion to the order index the same
+/// way `State` applies them to its orders. Other events are ignored.
+fn update_order_index(
+    orders: &mut HashMap<(UserId, OrderId), order::Order>,
+    event: &batch_exchange::Event,
+    batch_id: BatchId,
+) {
+    match event {
+        batch_exchange::Event::OrderPlacement(event) => {
+            let order = order::Order::new(
+                event.buy_token,
+                event.sell_token,
+                event.valid_from,
+                event.valid_until,
+                event.price_numerator,
+                event.price_denominator,
+            );
+            orders.insert((event.owner, event.index), order);
+        }
+        batch_exchange::Event::OrderCancellation(event) => {
+            if let Some(order) = orders.get_mut(&(event.owner, event.id)) {
+                order.valid_until = batch_id.saturating_sub(1);
+            }
+        }
+        batch_exchange::Event::OrderDeletion(event) => {
+            orders.remove(&(event.owner, event.id));
+        }
+        _ => (),
+    }
+}
+
 fn filter_account_state(
     account_states: impl Iterator<Item = ((UserId, TokenId), U256)>,
     orders: &[Order],
@@ -95,11 +250,336 @@ impl StableXOrderBookReading for Orderbook {
         let account_state = filter_account_state(account_state, &orders);
         Ok((account_state, orders))
     }
+
+    fn get_account_balances(
+        &self,
+        user: UserId,
+        batch_id_to_solve: U256,
+    ) -> Result<HashMap<TokenId, u128>> {
+        let state = self.create_state()?;
+        // Unlike `get_auction_data` this includes balances of tokens the user has no orders for.
+        let (account_state, _) =
+            state.orderbook_for_batch(Batch::Future(batch_id_to_solve.low_u32() + 1))?;
+        Ok(account_state
+            .filter(|((user_id, _), balance)| *user_id == user && !balance.is_zero())
+            // TODO: change AccountState to use U256
+            .map(|((_, token_id), balance)| (token_id, balance.low_u128()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use super::*;
+    use batch_exchange::event_data::{Deposit, OrderPlacement, TokenListing};
+    use batch_exchange::Event;
+    use ethcontract::Address;
+
+    /// Returns the event listing the token with the specified id, whose address is the id.
+    pub fn token_listing(id: TokenId) -> Event {
+        Event::TokenListing(TokenListing {
+            token: Address::from_low_u64_be(id as u64),
+            id,
+        })
+    }
+
+    /// Returns events that list tokens 0 and 1, deposit 10 of token 1 for user 2 and place an
+    /// order of user 2 selling 10 of token 1 for 5 of token 0 that is valid until batch 10.
+    pub fn deposit_and_order_placement() -> Vec<Event> {
+        let user = Address::from_low_u64_be(2);
+        vec![
+            token_listing(0),
+            token_listing(1),
+            Event::Deposit(Deposit {
+                user,
+                token: Address::from_low_u64_be(1),
+                amount: 10.into(),
+                batch_id: 0,
+            }),
+            Event::OrderPlacement(OrderPlacement {
+                owner: user,
+                index: 0,
+                buy_token: 0,
+                sell_token: 1,
+                valid_from: 0,
+                valid_until: 10,
+                price_numerator: 5,
+                price_denominator: 10,
+            }),
+        ]
+    }
+
+    /// Builds an orderbook from events that were all emitted in block 0 in the specified order.
+    pub fn replay_in_order(events: impl IntoIterator<Item = Event>) -> Orderbook {
+        Orderbook::replay(events.into_iter().enumerate().map(|(i, event)| {
+            let meta = EventMeta {
+                block_number: 0,
+                log_index: i,
+                block_hash: H256::zero(),
+                block_timestamp: 0,
+            };
+            (event, meta)
+        }))
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::test_util::*;
     use super::*;
+    use batch_exchange::event_data::{
+        Deposit, OrderCancellation, OrderDeletion, OrderPlacement, TokenListing,
+    };
+    use batch_exchange::Event;
+    use ethcontract::Address;
+
+    #[test]
+    fn replay_deposit_and_order_placement() {
+        let orderbook = replay_in_order(deposit_and_order_placement());
+
+        let (account_state, orders) = orderbook.get_auction_data(U256::from(1)).unwrap();
+        assert_eq!(
+            account_state,
+            AccountState(hash_map! { (Address::from_low_u64_be(2), 1) => 10 })
+        );
+        assert_eq!(
+            orders,
+            vec![Order {
+                id: 0,
+                account_id: Address::from_low_u64_be(2),
+                buy_token: 0,
+                sell_token: 1,
+                buy_amount: 5,
+                sell_amount: 10,
+                valid_from: 0,
+                valid_until: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn account_balances_of_single_user() {
+        let user = Address::from_low_u64_be(2);
+        let other_user = Address::from_low_u64_be(3);
+        let deposit = |user, token: u64, amount: u64| {
+            Event::Deposit(Deposit {
+                user,
+                token: Address::from_low_u64_be(token),
+                amount: amount.into(),
+                batch_id: 0,
+            })
+        };
+        let events = vec![
+            token_listing(0),
+            token_listing(1),
+            token_listing(2),
+            deposit(user, 0, 10),
+            deposit(user, 1, 20),
+            deposit(user, 2, 0),
+            deposit(other_user, 0, 30),
+            Event::OrderPlacement(OrderPlacement {
+                owner: user,
+                index: 0,
+                buy_token: 0,
+                sell_token: 1,
+                valid_from: 0,
+                valid_until: 10,
+                price_numerator: 5,
+                price_denominator: 10,
+            }),
+        ];
+        let orderbook = replay_in_order(events);
+
+        assert_eq!(
+            orderbook.get_account_balances(user, U256::from(1)).unwrap(),
+            hash_map! { 0 => 10, 1 => 20 }
+        );
+        assert_eq!(
+            orderbook
+                .get_account_balances(other_user, U256::from(1))
+                .unwrap(),
+            hash_map! { 0 => 30 }
+        );
+        assert!(orderbook
+            .get_account_balances(Address::from_low_u64_be(4), U256::from(1))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn looks_up_single_order() {
+        let user = Address::from_low_u64_be(2);
+        let order_placement = |index, sell_token, valid_until| {
+            Event::OrderPlacement(OrderPlacement {
+                owner: user,
+                index,
+                buy_token: 0,
+                sell_token,
+                valid_from: 0,
+                valid_until,
+                price_numerator: 5,
+                price_denominator: 10,
+            })
+        };
+        let events = vec![
+            token_listing(0),
+            token_listing(1),
+            token_listing(2),
+            order_placement(0, 1, 10),
+            order_placement(1, 2, 10),
+            order_placement(2, 1, 0),
+        ];
+        let orderbook = replay_in_order(events);
+
+        let order = orderbook.get_order(user, 1, U256::from(1)).unwrap();
+        assert_eq!(order.id, 1);
+        assert_eq!(order.account_id, user);
+        assert_eq!(order.sell_token, 2);
+        let (_, orders) = orderbook.get_auction_data(U256::from(1)).unwrap();
+        assert!(orders.contains(&order));
+
+        // Order 2 is no longer valid in batch 1.
+        assert_eq!(orderbook.get_order(user, 2, U256::from(1)), None);
+        assert_eq!(orderbook.get_order(user, 3, U256::from(1)), None);
+        assert_eq!(
+            orderbook.get_order(Address::from_low_u64_be(3), 0, U256::from(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn orders_with_lifecycle() {
+        let order_placement = |index, valid_from, valid_until| {
+            Event::OrderPlacement(OrderPlacement {
+                owner: Address::from_low_u64_be(2),
+                index,
+                buy_token: 0,
+                sell_token: 1,
+                valid_from,
+                valid_until,
+                price_numerator: 1,
+                price_denominator: 1,
+            })
+        };
+        let events = vec![
+            token_listing(0),
+            token_listing(1),
+            order_placement(0, 0, 10),
+            order_placement(1, 5, 10),
+            order_placement(2, 0, 5),
+            order_placement(3, 5, 5),
+            order_placement(4, 6, 10),
+            order_placement(5, 0, 4),
+        ];
+        let orderbook = replay_in_order(events);
+
+        let lifecycles = orderbook
+            .get_orders_with_lifecycle(5)
+            .unwrap()
+            .into_iter()
+            .map(|(order, lifecycle)| (order.id, lifecycle))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lifecycles,
+            vec![
+                (0, OrderLifecycle::Continuing),
+                (1, OrderLifecycle::FreshlyValid),
+                (2, OrderLifecycle::Expiring),
+                (3, OrderLifecycle::FreshlyValid),
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_without_events_is_empty() {
+        let orderbook = Orderbook::replay(vec![]);
+        let (account_state, orders) = orderbook.get_auction_data(U256::zero()).unwrap();
+        assert_eq!(account_state, AccountState::default());
+        assert!(orders.is_empty());
+    }
+
+    #[test]
+    fn reverts_events_from_reorged_blocks() {
+        let deposit = |amount: u64| {
+            EventData::Added(Event::Deposit(Deposit {
+                user: Address::from_low_u64_be(2),
+                token: Address::from_low_u64_be(1),
+                amount: amount.into(),
+                batch_id: 0,
+            }))
+        };
+        let balance = |orderbook: &Orderbook| {
+            orderbook
+                .get_account_balances(Address::from_low_u64_be(2), U256::zero())
+                .unwrap()
+                .values()
+                .sum::<u128>()
+        };
+
+        let mut orderbook = Orderbook::default();
+        let token_listing = EventData::Added(Event::TokenListing(TokenListing {
+            token: Address::from_low_u64_be(1),
+            id: 1,
+        }));
+        orderbook.handle_event_data(token_listing, 1, 0, H256::from_low_u64_be(1), 0);
+        orderbook.handle_event_data(deposit(10), 1, 1, H256::from_low_u64_be(1), 0);
+        orderbook.handle_event_data(deposit(20), 2, 0, H256::from_low_u64_be(2), 0);
+        orderbook.handle_event_data(deposit(40), 3, 0, H256::from_low_u64_be(3), 0);
+        assert_eq!(balance(&orderbook), 70);
+
+        // Block 2 gets replaced by a different block, which invalidates the deposits in blocks 2
+        // and 3.
+        orderbook.handle_event_data(deposit(5), 2, 0, H256::from_low_u64_be(42), 0);
+        assert_eq!(orderbook.events.len(), 3);
+        assert_eq!(balance(&orderbook), 15);
+
+        // Events from the new block are applied normally.
+        orderbook.handle_event_data(deposit(1), 2, 1, H256::from_low_u64_be(42), 0);
+        assert_eq!(balance(&orderbook), 16);
+    }
+
+    #[test]
+    fn order_index_follows_order_events() {
+        let user = Address::from_low_u64_be(2);
+        let order_placement = |index| {
+            EventData::Added(Event::OrderPlacement(OrderPlacement {
+                owner: user,
+                index,
+                buy_token: 0,
+                sell_token: 1,
+                valid_from: 0,
+                valid_until: 10,
+                price_numerator: 5,
+                price_denominator: 10,
+            }))
+        };
+
+        let mut orderbook = Orderbook::default();
+        orderbook.handle_event_data(order_placement(0), 1, 0, H256::zero(), 0);
+        orderbook.handle_event_data(order_placement(1), 1, 1, H256::zero(), 0);
+        orderbook.handle_event_data(order_placement(2), 1, 2, H256::zero(), 0);
+
+        // Order 0 gets cancelled in batch 2 so it is no longer valid in batch 2.
+        let cancellation = EventData::Added(Event::OrderCancellation(OrderCancellation {
+            owner: user,
+            id: 0,
+        }));
+        orderbook.handle_event_data(cancellation, 2, 0, H256::zero(), 600);
+        assert!(orderbook.get_order(user, 0, U256::from(0)).is_some());
+        assert!(orderbook.get_order(user, 0, U256::from(1)).is_some());
+        assert_eq!(orderbook.get_order(user, 0, U256::from(2)), None);
+
+        // Deleted orders are removed from the index.
+        let deletion = EventData::Added(Event::OrderDeletion(OrderDeletion { owner: user, id: 0 }));
+        orderbook.handle_event_data(deletion, 3, 0, H256::zero(), 600);
+        assert_eq!(orderbook.get_order(user, 0, U256::from(0)), None);
+
+        // Removed placements are no longer in the index.
+        let removed = EventData::Removed(Event::OrderPlacement(OrderPlacement::default()));
+        orderbook.handle_event_data(removed, 1, 1, H256::zero(), 0);
+        assert_eq!(orderbook.get_order(user, 1, U256::from(0)), None);
+        assert!(orderbook.get_order(user, 2, U256::from(0)).is_some());
+    }
 
     #[test]
     fn test_filter_account_state() {
@@ -110,6 +590,7 @@ mod tests {
             sell_token: 1,
             buy_amount: 1,
             sell_amount: 1,
+            ..Default::default()
         }];
         let account_states = vec![
             ((Address::zero(), 0), 3.into()),
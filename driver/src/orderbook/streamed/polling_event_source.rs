@@ -0,0 +1,215 @@
+use crate::contracts::stablex_contract::{batch_exchange, StableXContract};
+use crate::util::FutureWaitExt as _;
+use anyhow::{anyhow, Result};
+use ethcontract::{contract::Event, errors::ExecutionError};
+use futures::{
+    channel::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
+    future::{BoxFuture, FutureExt as _},
+    stream::{BoxStream, StreamExt as _},
+};
+use std::sync::Arc;
+use std::{thread, time::Duration};
+
+/// An event source that repeatedly queries past events for the blocks that were confirmed since
+/// the previous query instead of relying on filters. This works with nodes that do not support
+/// filters or do not support them reliably.
+///
+/// Only blocks with at least the configured number of confirmations are queried, so events are
+/// only ever added and never removed. Reorgs deeper than the number of confirmations are not
+/// detected.
+///
+/// Failed queries are logged and retried on the next poll.
+pub struct PollingEventSource {
+    contract: Arc<dyn StableXContract + Send + Sync>,
+    poll_interval: Duration,
+    confirmations: u64,
+}
+
+impl PollingEventSource {
+    pub fn new(
+        contract: Arc<dyn StableXContract + Send + Sync>,
+        poll_interval: Duration,
+        confirmations: u64,
+    ) -> Self {
+        Self {
+            contract,
+            poll_interval,
+            confirmations,
+        }
+    }
+
+    /// Returns the past events up to the most recent confirmed block and a stream of the events
+    /// of all following blocks as they get confirmed. The stream starts right after the last
+    /// block of the past events, so that no events are missed or delivered twice. This can be
+    /// used in place of `StableXContract::past_events` and `StableXContract::stream_events`.
+    ///
+    /// Polling happens on a background thread that starts once the past events were fetched and
+    /// stops once the stream is dropped.
+    pub fn events(
+        self,
+    ) -> (
+        BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>>>,
+        BoxStream<'static, Result<Event<batch_exchange::Event>, ExecutionError>>,
+    ) {
+        let (next_block_sender, next_block_receiver) = oneshot::channel();
+        let past_events =
+            past_events(self.contract.clone(), self.confirmations, next_block_sender).boxed();
+
+        let (sender, receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            // NOTE: Receiving fails if fetching the past events failed or was cancelled, in which
+            //   case the orderbook is not updated anymore and there is nothing to poll for.
+            if let Ok(next_block) = next_block_receiver.wait() {
+                self.poll_forever(next_block, sender);
+            }
+        });
+        (past_events, receiver.boxed())
+    }
+
+    fn poll_forever(
+        &self,
+        mut next_block: u64,
+        sender: UnboundedSender<Result<Event<batch_exchange::Event>, ExecutionError>>,
+    ) {
+        while !sender.is_closed() {
+            match self.poll(next_block) {
+                Ok(Some((events, last_block))) => {
+                    for event in events {
+                        if sender.unbounded_send(Ok(event)).is_err() {
+                            return;
+                        }
+                    }
+                    next_block = last_block + 1;
+                }
+                Ok(None) => (),
+                Err(err) => log::warn!("failed to poll for new events: {:?}", err),
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// Queries the events from `from_block` up to and including the most recent confirmed block.
+    /// Returns the events and the last block that was queried, or `None` if there are no new
+    /// confirmed blocks.
+    fn poll(&self, from_block: u64) -> Result<Option<(Vec<Event<batch_exchange::Event>>, u64)>> {
+        let last_block = confirmed_block(&*self.contract, self.confirmations)?;
+        if from_block > last_block {
+            return Ok(None);
+        }
+
+        let events = self
+            .contract
+            .past_events_in_range(from_block, last_block)
+            .wait()?;
+        Ok(Some((events, last_block)))
+    }
+}
+
+/// Fetches the events up to and including the most recent confirmed block and sends the block
+/// following it to the poller.
+async fn past_events(
+    contract: Arc<dyn StableXContract + Send + Sync>,
+    confirmations: u64,
+    next_block: oneshot::Sender<u64>,
+) -> Result<Vec<Event<batch_exchange::Event>>> {
+    let last_block = confirmed_block(&*contract, confirmations)?;
+    let events = contract.past_events_until(last_block).await?;
+    // NOTE: Sending only fails if the polling thread already stopped.
+    let _ = next_block.send(last_block + 1);
+    Ok(events)
+}
+
+/// Returns the most recent block that has at least the specified number of confirmations.
+fn confirmed_block(contract: &dyn StableXContract, confirmations: u64) -> Result<u64> {
+    let latest_block = contract.get_block_number()?;
+    latest_block
+        .checked_sub(confirmations)
+        .ok_or_else(|| anyhow!("no block with {} confirmations yet", confirmations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::stablex_contract::MockStableXContract;
+    use crate::orderbook::streamed::orderbook::test_util::{
+        deposit_and_order_placement, replay_in_order,
+    };
+    use crate::orderbook::StableXOrderBookReading;
+    use ethcontract::{contract::EventData, Address, U256};
+    use futures::future;
+    use mockall::{predicate::eq, Sequence};
+
+    fn event(data: batch_exchange::Event) -> Event<batch_exchange::Event> {
+        Event {
+            data: EventData::Added(data),
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn streams_confirmed_events_after_past_events() {
+        let expected_events = deposit_and_order_placement();
+
+        let mut contract = MockStableXContract::new();
+        let mut sequence = Sequence::new();
+        contract
+            .expect_get_block_number()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| Ok(10));
+        contract
+            .expect_get_block_number()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| Err(anyhow::anyhow!("node unavailable")));
+        contract.expect_get_block_number().returning(|| Ok(12));
+        let past_events = expected_events[..3].iter().cloned().map(event).collect();
+        contract
+            .expect_past_events_until()
+            .with(eq(8))
+            .times(1)
+            .return_once(move |_| future::ready(Ok(past_events)).boxed());
+        let polled_events = expected_events[3..].iter().cloned().map(event).collect();
+        contract
+            .expect_past_events_in_range()
+            .with(eq(9), eq(10))
+            .times(1)
+            .return_once(move |_, _| future::ready(Ok(polled_events)).boxed());
+
+        let (past_events, stream) =
+            PollingEventSource::new(Arc::new(contract), Duration::from_millis(1), 2).events();
+        let past_events = past_events.wait().unwrap();
+        let polled_events = stream.take(1).collect::<Vec<_>>().wait();
+        let events = past_events
+            .into_iter()
+            .chain(polled_events.into_iter().map(Result::unwrap))
+            .map(|event| match event.data {
+                EventData::Added(data) => data,
+                EventData::Removed(_) => panic!("unexpected removed event"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(events, expected_events);
+
+        let (account_state, orders) = replay_in_order(events)
+            .get_auction_data(U256::from(1))
+            .unwrap();
+        assert_eq!(
+            account_state.read_balance(1, Address::from_low_u64_be(2)),
+            10
+        );
+        assert_eq!(orders.len(), 1);
+    }
+
+    #[test]
+    fn fails_to_fetch_past_events_without_confirmed_block() {
+        let mut contract = MockStableXContract::new();
+        contract.expect_get_block_number().returning(|| Ok(1));
+
+        let (past_events, _stream) =
+            PollingEventSource::new(Arc::new(contract), Duration::from_millis(1), 2).events();
+        assert!(past_events.wait().is_err());
+    }
+}
@@ -555,6 +555,8 @@ mod tests {
             sell_token: 1,
             buy_amount: 3,
             sell_amount: 4,
+            valid_from: 1,
+            valid_until: 2,
         }];
         assert_eq!(state.orders(2).collect::<Vec<_>>(), expected_orders);
         assert_eq!(state.orders(3).collect::<Vec<_>>(), expected_orders);
@@ -568,8 +570,14 @@ mod tests {
             .apply_event(&Event::OrderCancellation(event), 2)
             .unwrap();
 
+        // NOTE: Cancelling an order sets its `valid_until` to the batch before
+        //   the one it was cancelled in.
+        let cancelled_orders = vec![ModelOrder {
+            valid_until: 1,
+            ..expected_orders[0].clone()
+        }];
         assert_eq!(state.orders(1).next(), None);
-        assert_eq!(state.orders(2).collect::<Vec<_>>(), expected_orders);
+        assert_eq!(state.orders(2).collect::<Vec<_>>(), cancelled_orders);
         assert_eq!(state.orders(3).next(), None);
         assert_eq!(state.orders(4).next(), None);
 
@@ -5,49 +5,123 @@ use crate::{
         Web3,
     },
     models::{AccountState, Order},
-    orderbook::StableXOrderBookReading,
+    orderbook::{ensure_batch_is_solvable, StableXOrderBookReading},
 };
-use anyhow::{anyhow, bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Error, Result};
 use block_timestamp_reading::{BlockTimestampReading, CachedBlockTimestampReader};
-use ethcontract::{contract::Event, errors::ExecutionError, H256};
+use ethcontract::{
+    contract::{Event, EventData},
+    errors::ExecutionError,
+    H256,
+};
 use futures::{
     channel::oneshot,
-    future::FutureExt,
+    future::{BoxFuture, FutureExt, TryFutureExt as _},
     pin_mut, select_biased,
-    stream::{Stream, StreamExt as _},
+    stream::{BoxStream, Stream, StreamExt as _},
 };
 use orderbook::Orderbook;
-use std::collections::HashSet;
+use polling_event_source::PollingEventSource;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::future::Future;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+    Arc, Mutex, MutexGuard,
 };
 use std::{process, thread, time::Duration};
 
+/// How to handle a fatal failure of the background thread that updates the orderbook.
+pub enum FailurePolicy {
+    /// Exit the process. This forces a restart of the whole driver because otherwise we would be
+    /// stuck with an outdated orderbook forever.
+    Exit,
+    /// Panic in the background thread.
+    Panic,
+    /// Pass the error to a callback and let the host application decide how to handle it.
+    Callback(Box<dyn Fn(Error) + Send>),
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy::Exit
+    }
+}
+
+impl FailurePolicy {
+    fn handle(&self, err: Error) {
+        match self {
+            FailurePolicy::Exit => {
+                // Sleep for one second, so that we have time to flush the logs.
+                thread::sleep(Duration::from_secs(1));
+                process::exit(1);
+            }
+            FailurePolicy::Panic => panic!("event based orderbook failed: {:?}", err),
+            FailurePolicy::Callback(callback) => callback(err),
+        }
+    }
+}
+
 /// An event based orderbook that automatically updates itself with new events from the contract.
-#[derive(Debug)]
 pub struct UpdatingOrderbook {
+    // The contract to read the current batch from, so that batches in the future are rejected.
+    contract: Arc<dyn StableXContract + Send + Sync>,
     orderbook: Arc<Mutex<Orderbook>>,
     // Indicates whether the background thread has caught up with past events at which point the
     // orderbook is ready to be read.
     orderbook_ready: Arc<AtomicBool>,
     // When this struct is dropped this sender will be dropped which makes the updater thread stop.
-    _exit_tx: oneshot::Sender<()>,
+    exit_tx: oneshot::Sender<()>,
+    // The updater thread, so that shutting down can wait for it to finish.
+    updater: thread::JoinHandle<()>,
 }
 
 impl UpdatingOrderbook {
-    pub fn new(contract: &dyn StableXContract, web3: Web3) -> Self {
+    pub fn new(contract: Arc<dyn StableXContract + Send + Sync>, web3: Web3) -> Self {
+        Self::with_failure_policy(contract, web3, FailurePolicy::default())
+    }
+
+    /// Creates a new orderbook where a fatal failure of the background thread is handled with the
+    /// specified policy.
+    pub fn with_failure_policy(
+        contract: Arc<dyn StableXContract + Send + Sync>,
+        web3: Web3,
+        failure_policy: FailurePolicy,
+    ) -> Self {
+        // Create stream first to make sure we do not miss any events between it and past events.
+        let stream = contract.stream_events();
+        let past_events = contract.past_events().map_err(Error::from).boxed();
+        Self::from_events(contract, past_events, stream, web3, failure_policy)
+    }
+
+    /// Creates a new orderbook that receives new events by polling for them in the specified
+    /// interval instead of using a filter, for nodes that do not support filters reliably. Only
+    /// events of blocks with at least `confirmations` confirmations are applied.
+    pub fn with_event_polling(
+        contract: Arc<dyn StableXContract + Send + Sync>,
+        web3: Web3,
+        poll_interval: Duration,
+        confirmations: u64,
+        failure_policy: FailurePolicy,
+    ) -> Self {
+        let (past_events, stream) =
+            PollingEventSource::new(contract.clone(), poll_interval, confirmations).events();
+        Self::from_events(contract, past_events, stream, web3, failure_policy)
+    }
+
+    fn from_events(
+        contract: Arc<dyn StableXContract + Send + Sync>,
+        past_events: BoxFuture<'static, Result<Vec<Event<batch_exchange::Event>>>>,
+        stream: BoxStream<'static, Result<Event<batch_exchange::Event>, ExecutionError>>,
+        web3: Web3,
+        failure_policy: FailurePolicy,
+    ) -> Self {
         let orderbook = Arc::new(Mutex::new(Orderbook::default()));
         let orderbook_clone = orderbook.clone();
         let orderbook_ready = Arc::new(AtomicBool::new(false));
         let orderbook_ready_clone = orderbook_ready.clone();
         let (exit_tx, exit_rx) = oneshot::channel();
-        // Create stream first to make sure we do not miss any events between it and past events.
-        let stream = contract.stream_events();
-        let past_events = contract.past_events();
 
-        std::thread::spawn(move || {
+        let updater = thread::spawn(move || {
             let result = futures::executor::block_on(update_with_events_forever(
                 orderbook_clone,
                 orderbook_ready_clone,
@@ -59,33 +133,64 @@ impl UpdatingOrderbook {
             if let Err(err) = result {
                 log::error!("event based orderbook failed: {:?}", err);
                 // TODO: implement a retry mechanism
-                // For now we error the program so force a restart of the whole driver because
-                // without a retry we would be stuck with an outdated orderbook forever.
-                // Sleep for one second, so that we have time to flush the logs.
-                thread::sleep(Duration::from_secs(1));
-                process::exit(1);
+                failure_policy.handle(err);
             }
         });
 
         Self {
+            contract,
             orderbook,
             orderbook_ready,
-            _exit_tx: exit_tx,
+            exit_tx,
+            updater,
         }
     }
-}
 
-impl StableXOrderBookReading for UpdatingOrderbook {
-    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
+    /// Stops the background thread and waits for it to finish, which guarantees that the
+    /// orderbook is no longer being updated once this returns. Dropping the orderbook instead only
+    /// signals the thread to stop without waiting for it.
+    pub fn shutdown(self) -> Result<()> {
+        // NOTE: Sending fails if the thread already stopped on its own, in which case there is
+        //   nothing left to signal.
+        let _ = self.exit_tx.send(());
+        self.updater
+            .join()
+            .map_err(|_| anyhow!("orderbook updater thread panicked"))
+    }
+
+    /// Locks the orderbook for reading the specified batch, failing if the batch is in the future
+    /// or the orderbook is not yet ready.
+    fn orderbook_for_batch(&self, batch_id_to_solve: U256) -> Result<MutexGuard<Orderbook>> {
+        ensure_batch_is_solvable(
+            batch_id_to_solve,
+            self.contract.get_current_auction_index()?,
+        )?;
+        let orderbook = self
+            .orderbook
+            .lock()
+            .map_err(|err| anyhow!("poison error: {}", err))?;
         ensure!(
             self.orderbook_ready.load(Ordering::SeqCst),
             "orderbook not yet ready"
         );
-        self.orderbook
-            .lock()
-            .map_err(|err| anyhow!("poison error: {}", err))?
+        Ok(orderbook)
+    }
+}
+
+impl StableXOrderBookReading for UpdatingOrderbook {
+    fn get_auction_data(&self, batch_id_to_solve: U256) -> Result<(AccountState, Vec<Order>)> {
+        self.orderbook_for_batch(batch_id_to_solve)?
             .get_auction_data(batch_id_to_solve)
     }
+
+    fn get_account_balances(
+        &self,
+        user: UserId,
+        batch_id_to_solve: U256,
+    ) -> Result<HashMap<TokenId, u128>> {
+        self.orderbook_for_batch(batch_id_to_solve)?
+            .get_account_balances(user, batch_id_to_solve)
+    }
 }
 
 /// Update the orderbook with events from the stream forever or until exit_indicator is dropped.
@@ -97,7 +202,7 @@ async fn update_with_events_forever(
     orderbook_ready: Arc<AtomicBool>,
     mut block_timestamp_reader: CachedBlockTimestampReader<Web3>,
     exit_indicator: oneshot::Receiver<()>,
-    past_events: impl Future<Output = Result<Vec<Event<batch_exchange::Event>>, ExecutionError>>,
+    past_events: impl Future<Output = Result<Vec<Event<batch_exchange::Event>>>>,
     stream: impl Stream<Item = Result<Event<batch_exchange::Event>, ExecutionError>>,
 ) -> Result<()> {
     // `select!` requires the futures to be fused...
@@ -109,6 +214,8 @@ async fn update_with_events_forever(
     pin_mut!(past_events);
     pin_mut!(stream);
 
+    let mut recent_events = RecentEvents::default();
+
     log::info!("Starting event based orderbook updating.");
 
     loop {
@@ -120,7 +227,7 @@ async fn update_with_events_forever(
             event = stream.next() => {
                 log::info!("Received new event.");
                 let event = event.ok_or(anyhow!("stream ended"))??;
-                handle_event(&orderbook, &mut block_timestamp_reader, event).await?;
+                handle_event(&orderbook, &mut block_timestamp_reader, &mut recent_events, event).await?;
             },
             past_events = past_events => {
                 let past_events = past_events?;
@@ -131,7 +238,7 @@ async fn update_with_events_forever(
                 }).collect::<Result<HashSet<H256>>>()?;
                 block_timestamp_reader.prepare_cache(block_hashes).await?;
                 for event in past_events {
-                    handle_event(&orderbook, &mut block_timestamp_reader, event).await?;
+                    handle_event(&orderbook, &mut block_timestamp_reader, &mut recent_events, event).await?;
                 }
                 log::info!("Finished applying past events");
                 orderbook_ready.store(true, Ordering::SeqCst);
@@ -140,10 +247,52 @@ async fn update_with_events_forever(
     }
 }
 
-/// Apply a single event to the orderbook.
+/// The number of blocks after which events are considered final, so that they no longer need to
+/// be tracked for deduplication.
+const DEDUPLICATION_BLOCK_WINDOW: u64 = 100;
+
+/// Tracks the events from recent blocks that were applied to the orderbook, so that events which
+/// are delivered more than once, for example because of overlapping block ranges, are only
+/// applied once.
+#[derive(Debug, Default)]
+struct RecentEvents {
+    keys: BTreeSet<(u64, usize, H256)>,
+}
+
+impl RecentEvents {
+    /// Records an added event and returns whether it was not already recorded. Events from blocks
+    /// that are more than `DEDUPLICATION_BLOCK_WINDOW` blocks behind the most recent one are
+    /// pruned, and such events are considered to already have been applied.
+    fn insert(&mut self, block_number: u64, log_index: usize, block_hash: H256) -> bool {
+        if block_number < self.oldest_block() {
+            return false;
+        }
+        let inserted = self.keys.insert((block_number, log_index, block_hash));
+        let oldest_block = self.oldest_block();
+        self.keys = self.keys.split_off(&(oldest_block, 0, H256::zero()));
+        inserted
+    }
+
+    /// Returns the oldest block whose events are still tracked.
+    fn oldest_block(&self) -> u64 {
+        self.keys
+            .iter()
+            .next_back()
+            .map(|&(latest_block, _, _)| latest_block.saturating_sub(DEDUPLICATION_BLOCK_WINDOW))
+            .unwrap_or(0)
+    }
+
+    /// Forgets a removed event, so that it gets applied again if it is added back.
+    fn remove(&mut self, block_number: u64, log_index: usize, block_hash: H256) {
+        self.keys.remove(&(block_number, log_index, block_hash));
+    }
+}
+
+/// Apply a single event to the orderbook, unless it was already applied.
 async fn handle_event(
     orderbook: &Mutex<Orderbook>,
     block_timestamp_reader: &mut impl BlockTimestampReading,
+    recent_events: &mut RecentEvents,
     event: Event<batch_exchange::Event>,
 ) -> Result<()> {
     match event {
@@ -151,6 +300,21 @@ async fn handle_event(
             data,
             meta: Some(meta),
         } => {
+            match &data {
+                EventData::Added(_) => {
+                    if !recent_events.insert(meta.block_number, meta.log_index, meta.block_hash) {
+                        log::debug!(
+                            "Skipping duplicate event {} of block {}.",
+                            meta.log_index,
+                            meta.block_number
+                        );
+                        return Ok(());
+                    }
+                }
+                EventData::Removed(_) => {
+                    recent_events.remove(meta.block_number, meta.log_index, meta.block_hash)
+                }
+            }
             let block_timestamp = block_timestamp_reader
                 .block_timestamp(meta.block_hash)
                 .await?;
@@ -169,3 +333,203 @@ async fn handle_event(
         Event { meta: None, .. } => bail!("event without metadata"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::{stablex_contract::MockStableXContract, web3_provider};
+    use crate::http::HttpFactory;
+    use crate::orderbook::{AsyncStableXOrderBookReading, FutureBatchError};
+    use crate::transport::RetryPolicy;
+    use crate::util::FutureWaitExt as _;
+    use futures::{future, stream};
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    // NOTE: Reading the updating orderbook only locks the in-memory orderbook, so there is no need to
+    //   move the read onto a separate thread.
+    impl AsyncStableXOrderBookReading for UpdatingOrderbook {
+        fn get_auction_data(
+            &self,
+            batch_id_to_solve: U256,
+        ) -> BoxFuture<'_, Result<(AccountState, Vec<Order>)>> {
+            future::ready(StableXOrderBookReading::get_auction_data(
+                self,
+                batch_id_to_solve,
+            ))
+            .boxed()
+        }
+    }
+
+    /// Returns a contract whose event streams never produce events and that is
+    /// collecting orders for batch 11 along with a web3 instance for a node
+    /// that is not expected to be reached.
+    fn pending_contract_and_web3() -> (MockStableXContract, Web3) {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_stream_events()
+            .returning(|| stream::pending().boxed());
+        contract
+            .expect_past_events()
+            .returning(|| future::pending().boxed());
+        contract
+            .expect_get_current_auction_index()
+            .returning(|| Ok(11));
+        let web3 = web3_provider(
+            &HttpFactory::default(),
+            "http://localhost:8545",
+            Duration::from_secs(1),
+            None,
+            RetryPolicy::default(),
+        )
+        .unwrap();
+        (contract, web3)
+    }
+
+    #[test]
+    fn shutdown_stops_and_joins_updater_thread() {
+        let (contract, web3) = pending_contract_and_web3();
+
+        let orderbook =
+            UpdatingOrderbook::with_failure_policy(Arc::new(contract), web3, FailurePolicy::Panic);
+        assert!(orderbook.get_auction_data(U256::zero()).is_err());
+
+        let start = Instant::now();
+        orderbook.shutdown().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn fails_for_future_batch() {
+        let (contract, web3) = pending_contract_and_web3();
+
+        let orderbook =
+            UpdatingOrderbook::with_failure_policy(Arc::new(contract), web3, FailurePolicy::Panic);
+        let err = orderbook.get_auction_data(U256::from(11)).unwrap_err();
+        assert_eq!(
+            *err.downcast_ref::<FutureBatchError>().unwrap(),
+            FutureBatchError {
+                requested: U256::from(11),
+                latest: U256::from(10),
+            }
+        );
+
+        orderbook.shutdown().unwrap();
+    }
+
+    #[test]
+    fn async_read_matches_sync_read() {
+        let (contract, web3) = pending_contract_and_web3();
+
+        let orderbook =
+            UpdatingOrderbook::with_failure_policy(Arc::new(contract), web3, FailurePolicy::Panic);
+        let batch_id = U256::from(BatchId::max_value());
+        let sync_err = StableXOrderBookReading::get_auction_data(&orderbook, batch_id)
+            .unwrap_err()
+            .downcast::<FutureBatchError>()
+            .unwrap();
+        let async_err = AsyncStableXOrderBookReading::get_auction_data(&orderbook, batch_id)
+            .wait()
+            .unwrap_err()
+            .downcast::<FutureBatchError>()
+            .unwrap();
+        assert_eq!(sync_err.requested, async_err.requested);
+
+        orderbook.shutdown().unwrap();
+    }
+
+    #[derive(Default)]
+    struct CountingBlockTimestampReader {
+        calls: usize,
+    }
+
+    impl BlockTimestampReading for CountingBlockTimestampReader {
+        fn block_timestamp(&mut self, _: H256) -> BoxFuture<Result<u64>> {
+            self.calls += 1;
+            future::ready(Ok(0)).boxed()
+        }
+    }
+
+    #[test]
+    fn duplicate_events_are_applied_once() {
+        use crate::orderbook::streamed::orderbook::test_util::deposit_and_order_placement;
+        use ethcontract::{contract::EventMetadata, Address};
+
+        let user = Address::from_low_u64_be(2);
+        let events = deposit_and_order_placement()
+            .into_iter()
+            .enumerate()
+            .map(|(log_index, data)| Event {
+                data: EventData::Added(data),
+                meta: Some(EventMetadata {
+                    block_hash: H256::repeat_byte(1),
+                    block_number: 1,
+                    transaction_hash: H256::zero(),
+                    transaction_index: 0,
+                    log_index,
+                    transaction_log_index: None,
+                    log_type: None,
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        let orderbook = Mutex::new(Orderbook::default());
+        let mut block_timestamp_reader = CountingBlockTimestampReader::default();
+        let mut recent_events = RecentEvents::default();
+        for event in events.iter().chain(&events).cloned() {
+            futures::executor::block_on(handle_event(
+                &orderbook,
+                &mut block_timestamp_reader,
+                &mut recent_events,
+                event,
+            ))
+            .unwrap();
+        }
+
+        assert_eq!(block_timestamp_reader.calls, events.len());
+        let (account_state, orders) = orderbook
+            .lock()
+            .unwrap()
+            .get_auction_data(U256::from(1))
+            .unwrap();
+        assert_eq!(account_state.read_balance(1, user), 10);
+        assert_eq!(orders.len(), 1);
+    }
+
+    #[test]
+    fn recent_events_are_pruned() {
+        let mut recent_events = RecentEvents::default();
+        assert!(recent_events.insert(1, 0, H256::zero()));
+        assert!(!recent_events.insert(1, 0, H256::zero()));
+        assert!(recent_events.insert(1, 0, H256::repeat_byte(1)));
+
+        assert!(recent_events.insert(1 + DEDUPLICATION_BLOCK_WINDOW, 0, H256::zero()));
+        assert_eq!(recent_events.keys.len(), 3);
+        assert!(recent_events.insert(2 + DEDUPLICATION_BLOCK_WINDOW, 0, H256::zero()));
+        assert_eq!(recent_events.keys.len(), 2);
+
+        // Events older than the window are considered to already have been applied.
+        assert!(!recent_events.insert(1, 1, H256::zero()));
+        assert_eq!(recent_events.keys.len(), 2);
+
+        recent_events.remove(2 + DEDUPLICATION_BLOCK_WINDOW, 0, H256::zero());
+        assert!(recent_events.insert(2 + DEDUPLICATION_BLOCK_WINDOW, 0, H256::zero()));
+    }
+
+    #[test]
+    fn failure_policy_invokes_callback() {
+        let (sender, receiver) = mpsc::channel();
+        let policy = FailurePolicy::Callback(Box::new(move |err| {
+            sender.send(err.to_string()).unwrap();
+        }));
+
+        policy.handle(anyhow!("stream ended"));
+        assert_eq!(receiver.try_recv().unwrap(), "stream ended");
+    }
+
+    #[test]
+    #[should_panic]
+    fn failure_policy_panics() {
+        FailurePolicy::Panic.handle(anyhow!("stream ended"));
+    }
+}
@@ -0,0 +1,147 @@
+use super::average_price_source::get_prices_concurrently;
+use super::{PriceSource, Token};
+use crate::models::TokenId;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Combines two prices into their average, but only for tokens where both
+/// sources agree on the price within a tolerance. This guards against a single
+/// erroneous or compromised source.
+pub struct AgreementPriceSource<T0, T1> {
+    source_0: Mutex<T0>,
+    source_1: Mutex<T1>,
+    tolerance: f64,
+}
+
+impl<T0, T1> AgreementPriceSource<T0, T1> {
+    /// Creates a new price source where the prices of the two sources may
+    /// differ by at most `tolerance` relative to the lower price, e.g. 0.02
+    /// for 2%.
+    pub fn new(source_0: T0, source_1: T1, tolerance: f64) -> Self {
+        Self {
+            source_0: Mutex::new(source_0),
+            source_1: Mutex::new(source_1),
+            tolerance,
+        }
+    }
+}
+
+impl<T0: PriceSource + Send, T1: PriceSource + Send> PriceSource for AgreementPriceSource<T0, T1> {
+    fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>> {
+        match get_prices_concurrently(&self.source_0, &self.source_1, tokens) {
+            (Ok(p0), Ok(p1)) => Ok(agreeing_prices(p0, p1, self.tolerance)),
+            (Ok(_), Err(e)) | (Err(e), Ok(_)) => Err(anyhow!(
+                "one price source failed, so prices can't be checked for agreement: {}",
+                e
+            )),
+            (Err(e0), Err(e1)) => Err(anyhow!("both price sources failed: {}, {}", e0, e1)),
+        }
+    }
+}
+
+fn agreeing_prices(
+    prices_0: HashMap<TokenId, u128>,
+    prices_1: HashMap<TokenId, u128>,
+    tolerance: f64,
+) -> HashMap<TokenId, u128> {
+    prices_0
+        .into_iter()
+        .filter_map(|(token_id, price_0)| {
+            let price_1 = *prices_1.get(&token_id)?;
+            let difference = (price_0 as f64 - price_1 as f64).abs();
+            if difference > price_0.min(price_1) as f64 * tolerance {
+                log::warn!(
+                    "price sources disagree on the price of token {}: {} and {}",
+                    token_id.0,
+                    price_0,
+                    price_1
+                );
+                return None;
+            }
+            // NOTE: Compute the average without overflowing.
+            let average = price_0 / 2 + price_1 / 2 + (price_0 % 2 + price_1 % 2) / 2;
+            Some((token_id, average))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::price_source::MockPriceSource;
+    use super::*;
+
+    #[test]
+    fn only_prices_tokens_sources_agree_on() {
+        let tokens = vec![
+            Token::new(1, "AGREE", 18),
+            Token::new(2, "DISAGREE", 18),
+            Token::new(3, "ONE_SOURCE", 18),
+        ];
+
+        let mut source_0 = MockPriceSource::new();
+        source_0.expect_get_prices().returning(|_| {
+            Ok(hash_map! {
+                TokenId(1) => 100,
+                TokenId(2) => 100,
+                TokenId(3) => 100,
+            })
+        });
+        let mut source_1 = MockPriceSource::new();
+        source_1.expect_get_prices().returning(|_| {
+            Ok(hash_map! {
+                TokenId(1) => 102,
+                TokenId(2) => 110,
+            })
+        });
+
+        let source = AgreementPriceSource::new(source_0, source_1, 0.02);
+        assert_eq!(
+            source.get_prices(&tokens).unwrap(),
+            hash_map! { TokenId(1) => 101 }
+        );
+    }
+
+    #[test]
+    fn tolerance_is_relative_to_lower_price() {
+        let prices = |price| hash_map! { TokenId(0) => price };
+        assert_eq!(agreeing_prices(prices(100), prices(102), 0.02), prices(101));
+        assert_eq!(
+            agreeing_prices(prices(100), prices(103), 0.02),
+            hash_map! {}
+        );
+        assert_eq!(
+            agreeing_prices(prices(103), prices(100), 0.02),
+            hash_map! {}
+        );
+        assert_eq!(agreeing_prices(prices(0), prices(1), 0.02), hash_map! {});
+    }
+
+    #[test]
+    fn fails_if_a_source_fails() {
+        let mut source_0 = MockPriceSource::new();
+        source_0
+            .expect_get_prices()
+            .returning(|_| Ok(hash_map! { TokenId(1) => 100 }));
+        let mut source_1 = MockPriceSource::new();
+        source_1
+            .expect_get_prices()
+            .returning(|_| Err(anyhow!("error")));
+
+        let source = AgreementPriceSource::new(source_0, source_1, 0.02);
+        assert!(source.get_prices(&[Token::new(1, "A", 18)]).is_err());
+    }
+
+    #[test]
+    fn agreeing_prices_does_not_overflow() {
+        let max = u128::max_value();
+        assert_eq!(
+            agreeing_prices(
+                hash_map! { TokenId(0) => max },
+                hash_map! { TokenId(0) => max },
+                0.0,
+            ),
+            hash_map! { TokenId(0) => max }
+        );
+    }
+}
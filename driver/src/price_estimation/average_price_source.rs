@@ -22,13 +22,7 @@ impl<T0, T1> AveragePriceSource<T0, T1> {
 
 impl<T0: PriceSource + Send, T1: PriceSource + Send> PriceSource for AveragePriceSource<T0, T1> {
     fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>> {
-        let prices = thread::scope(|s| {
-            let handle_0 = s.spawn(|_| self.source_0.lock().unwrap().get_prices(tokens));
-            let handle_1 = s.spawn(|_| self.source_1.lock().unwrap().get_prices(tokens));
-            (handle_0.join().unwrap(), handle_1.join().unwrap())
-        })
-        .unwrap();
-        match prices {
+        match get_prices_concurrently(&self.source_0, &self.source_1, tokens) {
             (Ok(p0), Ok(p1)) => Ok(average_prices(p0, p1)),
             (Ok(p), Err(e)) | (Err(e), Ok(p)) => {
                 log::warn!("one price source failed: {}", e);
@@ -39,6 +33,27 @@ impl<T0: PriceSource + Send, T1: PriceSource + Send> PriceSource for AveragePric
     }
 }
 
+/// Fetches the prices of two price sources concurrently.
+pub(super) fn get_prices_concurrently<T0, T1>(
+    source_0: &Mutex<T0>,
+    source_1: &Mutex<T1>,
+    tokens: &[Token],
+) -> (
+    Result<HashMap<TokenId, u128>>,
+    Result<HashMap<TokenId, u128>>,
+)
+where
+    T0: PriceSource + Send,
+    T1: PriceSource + Send,
+{
+    thread::scope(|s| {
+        let handle_0 = s.spawn(|_| source_0.lock().unwrap().get_prices(tokens));
+        let handle_1 = s.spawn(|_| source_1.lock().unwrap().get_prices(tokens));
+        (handle_0.join().unwrap(), handle_1.join().unwrap())
+    })
+    .unwrap()
+}
+
 fn average_prices(
     prices_0: HashMap<TokenId, u128>,
     mut prices_1: HashMap<TokenId, u128>,
@@ -0,0 +1,151 @@
+use super::{PriceSource, Token};
+use crate::models::TokenId;
+use anyhow::{anyhow, ensure, Error, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// How prices outside of the configured bounds are handled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClampMode {
+    /// Prices outside of the bounds are removed from the result, as if the
+    /// price source was not able to find a price for the token.
+    Drop,
+    /// Prices outside of the bounds are clamped to the closest bound.
+    Clamp,
+}
+
+impl FromStr for ClampMode {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "drop" => Ok(ClampMode::Drop),
+            "clamp" => Ok(ClampMode::Clamp),
+            _ => Err(anyhow!("unknown price clamp mode '{}'", value)),
+        }
+    }
+}
+
+/// Protects against obviously bad data from external price feeds by
+/// restricting the prices of a price source to be within `[min, max]` (in
+/// OWL).
+pub struct ClampedPriceSource<S> {
+    source: S,
+    min: u128,
+    max: u128,
+    mode: ClampMode,
+}
+
+impl<S> ClampedPriceSource<S> {
+    /// Creates a new clamped price source, failing if the bounds are empty.
+    pub fn new(source: S, min: u128, max: u128, mode: ClampMode) -> Result<Self> {
+        ensure!(
+            min <= max,
+            "minimum price {} exceeds maximum price {}",
+            min,
+            max
+        );
+        Ok(Self {
+            source,
+            min,
+            max,
+            mode,
+        })
+    }
+}
+
+impl<S: PriceSource> PriceSource for ClampedPriceSource<S> {
+    fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>> {
+        let prices = self.source.get_prices(tokens)?;
+        Ok(prices
+            .into_iter()
+            .filter_map(|(token_id, price)| {
+                if self.min <= price && price <= self.max {
+                    return Some((token_id, price));
+                }
+                warn!(
+                    "price {} for token {} is outside of the bounds [{}, {}]",
+                    price, token_id.0, self.min, self.max,
+                );
+                match self.mode {
+                    ClampMode::Drop => None,
+                    ClampMode::Clamp => Some((token_id, price.max(self.min).min(self.max))),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::price_source::MockPriceSource;
+    use super::*;
+
+    fn source_with_bad_prices() -> MockPriceSource {
+        let mut source = MockPriceSource::new();
+        source.expect_get_prices().returning(|_| {
+            Ok(hash_map! {
+                TokenId(1) => 0,
+                TokenId(2) => 1_000_000_000_000_000_000,
+                TokenId(3) => u128::max_value() - 1,
+            })
+        });
+        source
+    }
+
+    #[test]
+    fn drops_prices_out_of_bounds() {
+        let source =
+            ClampedPriceSource::new(source_with_bad_prices(), 1, 10u128.pow(30), ClampMode::Drop)
+                .unwrap();
+        assert_eq!(
+            source.get_prices(&[]).unwrap(),
+            hash_map! {
+                TokenId(2) => 1_000_000_000_000_000_000,
+            }
+        );
+    }
+
+    #[test]
+    fn clamps_prices_out_of_bounds() {
+        let source = ClampedPriceSource::new(
+            source_with_bad_prices(),
+            1,
+            10u128.pow(30),
+            ClampMode::Clamp,
+        )
+        .unwrap();
+        assert_eq!(
+            source.get_prices(&[]).unwrap(),
+            hash_map! {
+                TokenId(1) => 1,
+                TokenId(2) => 1_000_000_000_000_000_000,
+                TokenId(3) => 10u128.pow(30),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_clamp_mode() {
+        assert_eq!("drop".parse::<ClampMode>().unwrap(), ClampMode::Drop);
+        assert_eq!("Clamp".parse::<ClampMode>().unwrap(), ClampMode::Clamp);
+        assert!("ignore".parse::<ClampMode>().is_err());
+    }
+
+    #[test]
+    fn propagates_source_errors() {
+        let mut source = MockPriceSource::new();
+        source
+            .expect_get_prices()
+            .returning(|_| Err(anyhow!("error")));
+        let source = ClampedPriceSource::new(source, 0, 1, ClampMode::Drop).unwrap();
+        assert!(source.get_prices(&[]).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_bounds() {
+        assert!(ClampedPriceSource::new(MockPriceSource::new(), 2, 1, ClampMode::Drop).is_err());
+        assert!(ClampedPriceSource::new(MockPriceSource::new(), 1, 1, ClampMode::Drop).is_ok());
+    }
+}
@@ -1,6 +1,7 @@
 //! This module contains fallback token data that should be used by the price
 //! estimator when prices are not available.
 
+use super::dispatching_price_source::PriceSourceKind;
 use super::Token;
 use crate::models::{TokenId, TokenInfo};
 use anyhow::{Context, Error, Result};
@@ -24,6 +25,10 @@ pub struct TokenBaseInfo {
     pub external_price: u128,
     #[serde(default)]
     pub should_estimate_price: bool,
+    /// The external price source that should be used to estimate the price of
+    /// this token instead of the default one.
+    #[serde(default)]
+    pub price_source: Option<PriceSourceKind>,
 }
 
 impl TokenBaseInfo {
@@ -40,6 +45,7 @@ impl TokenBaseInfo {
             decimals,
             external_price,
             should_estimate_price,
+            price_source: None,
         }
     }
 }
@@ -67,6 +73,11 @@ impl TokenData {
         self.0.get(&id.into())
     }
 
+    /// Returns an iterator over all the tokens in the token data map.
+    pub fn iter(&self) -> impl Iterator<Item = (TokenId, &TokenBaseInfo)> {
+        self.0.iter().map(|(&id, info)| (id, info))
+    }
+
     /// Returns true if the token data is empty and contains no token infos.
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
@@ -84,6 +95,14 @@ impl TokenData {
             })
             .collect()
     }
+
+    /// Returns the tokens for which a specific price source is configured.
+    pub fn price_source_preferences(&self) -> HashMap<TokenId, PriceSourceKind> {
+        self.0
+            .iter()
+            .filter_map(|(&id, info)| Some((id, info.price_source?)))
+            .collect()
+    }
 }
 
 impl From<HashMap<TokenId, TokenBaseInfo>> for TokenData {
@@ -129,4 +148,30 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn token_data_price_source_preferences() {
+        let json = r#"{
+          "T0001": {
+            "alias": "WETH",
+            "decimals": 18,
+            "externalPrice": 200000000000000000000,
+            "shouldEstimatePrice": true,
+            "priceSource": "kraken"
+          },
+          "T0004": {
+            "alias": "USDC",
+            "decimals": 6,
+            "externalPrice": 1000000000000000000000000000000,
+            "shouldEstimatePrice": true
+          }
+        }"#;
+
+        assert_eq!(
+            TokenData::from_str(json)
+                .unwrap()
+                .price_source_preferences(),
+            hash_map! { TokenId(1) => PriceSourceKind::Kraken }
+        );
+    }
 }
@@ -0,0 +1,172 @@
+use super::{PriceSource, Token};
+use crate::models::TokenId;
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The external price sources that can be configured per token.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceSourceKind {
+    Kraken,
+    Dexag,
+}
+
+/// A price source that fetches the price of every token from the source that
+/// is configured for it, so that each token gets its best-suited feed. Tokens
+/// without a configured source, or whose configured source is not available,
+/// are priced by the default source.
+pub struct DispatchingPriceSource {
+    default: Box<dyn PriceSource + Send>,
+    sources: HashMap<PriceSourceKind, Box<dyn PriceSource + Send>>,
+    preferences: HashMap<TokenId, PriceSourceKind>,
+}
+
+impl DispatchingPriceSource {
+    pub fn new(
+        default: Box<dyn PriceSource + Send>,
+        sources: HashMap<PriceSourceKind, Box<dyn PriceSource + Send>>,
+        preferences: HashMap<TokenId, PriceSourceKind>,
+    ) -> Self {
+        Self {
+            default,
+            sources,
+            preferences,
+        }
+    }
+
+    /// Groups the tokens by the source they should be priced with, where
+    /// `None` stands for the default source.
+    fn group_tokens(&self, tokens: &[Token]) -> HashMap<Option<PriceSourceKind>, Vec<Token>> {
+        let mut groups: HashMap<_, Vec<_>> = HashMap::new();
+        for token in tokens {
+            let kind = self
+                .preferences
+                .get(&token.id)
+                .copied()
+                .filter(|kind| self.sources.contains_key(kind));
+            groups.entry(kind).or_default().push(token.clone());
+        }
+        groups
+    }
+}
+
+impl PriceSource for DispatchingPriceSource {
+    fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>> {
+        let groups = self.group_tokens(tokens);
+        let num_groups = groups.len();
+
+        let mut prices = HashMap::new();
+        let mut errors = Vec::new();
+        for (kind, tokens) in groups {
+            let source = match kind {
+                Some(kind) => &self.sources[&kind],
+                None => &self.default,
+            };
+            match source.get_prices(&tokens) {
+                Ok(source_prices) => prices.extend(source_prices),
+                Err(err) => {
+                    warn!("price source {:?} failed: {}", kind, err);
+                    errors.push(err);
+                }
+            }
+        }
+
+        if num_groups > 0 && errors.len() == num_groups {
+            return Err(anyhow!("all price sources failed: {:?}", errors));
+        }
+        Ok(prices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::price_source::MockPriceSource;
+    use super::*;
+
+    fn source_for(expected_tokens: &'static [u16], price: u128) -> Box<dyn PriceSource + Send> {
+        let mut source = MockPriceSource::new();
+        source
+            .expect_get_prices()
+            .withf(move |tokens| {
+                let mut ids = tokens.iter().map(|token| token.id.0).collect::<Vec<_>>();
+                ids.sort_unstable();
+                ids == expected_tokens
+            })
+            .times(1)
+            .returning(move |tokens| Ok(tokens.iter().map(|token| (token.id, price)).collect()));
+        Box::new(source)
+    }
+
+    #[test]
+    fn routes_tokens_to_configured_sources() {
+        let source = DispatchingPriceSource::new(
+            source_for(&[3, 4], 0),
+            hash_map! {
+                PriceSourceKind::Kraken => source_for(&[1], 1),
+                PriceSourceKind::Dexag => source_for(&[2], 2),
+            },
+            hash_map! {
+                TokenId(1) => PriceSourceKind::Kraken,
+                TokenId(2) => PriceSourceKind::Dexag,
+            },
+        );
+
+        let tokens = (1..=4u16)
+            .map(|id| Token::new(id, "", 18))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            source.get_prices(&tokens).unwrap(),
+            hash_map! {
+                TokenId(1) => 1,
+                TokenId(2) => 2,
+                TokenId(3) => 0,
+                TokenId(4) => 0,
+            }
+        );
+    }
+
+    #[test]
+    fn uses_default_source_when_configured_source_is_missing() {
+        let source = DispatchingPriceSource::new(
+            source_for(&[1], 0),
+            HashMap::new(),
+            hash_map! { TokenId(1) => PriceSourceKind::Kraken },
+        );
+        assert_eq!(
+            source.get_prices(&[Token::new(1, "", 18)]).unwrap(),
+            hash_map! { TokenId(1) => 0 }
+        );
+    }
+
+    #[test]
+    fn fails_only_if_all_sources_fail() {
+        let failing_source = || -> Box<dyn PriceSource + Send> {
+            let mut source = MockPriceSource::new();
+            source
+                .expect_get_prices()
+                .returning(|_| Err(anyhow!("error")));
+            Box::new(source)
+        };
+        let tokens = [Token::new(1, "", 18), Token::new(2, "", 18)];
+        let preferences = hash_map! { TokenId(1) => PriceSourceKind::Kraken };
+
+        let source = DispatchingPriceSource::new(
+            failing_source(),
+            hash_map! { PriceSourceKind::Kraken => source_for(&[1], 1) },
+            preferences.clone(),
+        );
+        assert_eq!(
+            source.get_prices(&tokens).unwrap(),
+            hash_map! { TokenId(1) => 1 }
+        );
+
+        let source = DispatchingPriceSource::new(
+            failing_source(),
+            hash_map! { PriceSourceKind::Kraken => failing_source() },
+            preferences,
+        );
+        assert!(source.get_prices(&tokens).is_err());
+    }
+}
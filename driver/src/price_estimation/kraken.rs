@@ -2,26 +2,89 @@
 
 mod api;
 
-use self::api::{Asset, AssetPair, KrakenApi, KrakenHttpApi};
+use self::api::{Asset, AssetPair, Candle, KrakenApi, KrakenHttpApi};
 use super::{PriceSource, Token};
 use crate::http::HttpFactory;
+use crate::metrics::ComponentMetrics;
 use crate::models::TokenId;
+use crate::util::{Clock, SystemClock};
 use anyhow::{anyhow, Context, Result};
-use std::collections::HashMap;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long the asset and asset pair metadata is cached for. It rarely
+/// changes, but is also fetched again early when a token can't be found as it
+/// may have been listed since.
+const METADATA_TTL: Duration = Duration::from_secs(3600);
+
+/// The assets that tokens without a USD pair are priced through, in order of
+/// preference, by multiplying the token's price in the bridge asset with the
+/// bridge asset's USD price.
+const BRIDGE_ASSETS: &[&str] = &["XBT", "ETH", "EUR"];
+
+/// How the Kraken client determines the price of an asset pair.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KrakenPriceMode {
+    /// Use the average price of the last 24 hours from the ticker.
+    Ticker,
+    /// Use the volume weighted average price of the most recent candles, which
+    /// follows the market more closely during illiquid periods.
+    Vwap {
+        /// The interval in minutes each candle covers.
+        interval_minutes: u32,
+        /// The number of most recent candles to average over.
+        candles: usize,
+    },
+}
+
+impl Default for KrakenPriceMode {
+    fn default() -> Self {
+        KrakenPriceMode::Ticker
+    }
+}
 
 /// A client to the Kraken exchange.
 pub struct KrakenClient<Api> {
     /// A Kraken API implementation. This allows for mocked Kraken APIs to be
     /// used for testing.
     api: Api,
+    metrics: ComponentMetrics,
+    price_mode: KrakenPriceMode,
+    metadata: Mutex<Option<Metadata>>,
+    /// The committed candles of each asset pair along with when they were
+    /// fetched, so that they are fetched at most once per candle interval.
+    candles: Mutex<HashMap<String, (Instant, Vec<Candle>)>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// The cached Kraken asset and asset pair metadata.
+struct Metadata {
+    assets: HashMap<String, Asset>,
+    asset_pairs: HashMap<String, AssetPair>,
+    fetched_at: Instant,
+    /// The token symbols that had no asset when the metadata was fetched,
+    /// which don't cause it to be fetched again.
+    unknown_symbols: HashSet<String>,
+}
+
+impl Metadata {
+    /// Returns whether any of the tokens has no asset without already being
+    /// known to be missing.
+    fn has_new_unknown_symbols(&self, tokens: &[Token]) -> bool {
+        tokens.iter().map(|token| token.symbol()).any(|symbol| {
+            find_asset(symbol, &self.assets).is_none() && !self.unknown_symbols.contains(symbol)
+        })
+    }
 }
 
 impl KrakenClient<KrakenHttpApi> {
     /// Creates a new client instance using an HTTP API instance and the default
     /// Kraken API base URL.
-    pub fn new(http_factory: &HttpFactory) -> Result<Self> {
+    pub fn new(http_factory: &HttpFactory, metrics: ComponentMetrics) -> Result<Self> {
         let api = KrakenHttpApi::new(http_factory)?;
-        Ok(KrakenClient::with_api(api))
+        Ok(KrakenClient::with_api(api, metrics))
     }
 }
 
@@ -29,36 +92,152 @@ impl<Api> KrakenClient<Api>
 where
     Api: KrakenApi,
 {
-    /// Create a new client instance from an API.
-    pub fn with_api(api: Api) -> Self {
-        KrakenClient { api }
+    /// Create a new client instance from an API that records price requests
+    /// with the specified component metrics.
+    pub fn with_api(api: Api, metrics: ComponentMetrics) -> Self {
+        KrakenClient {
+            api,
+            metrics,
+            price_mode: KrakenPriceMode::default(),
+            metadata: Mutex::new(None),
+            candles: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
     }
 
-    /// Generates a mapping between Kraken asset pair identifiers and tokens
-    /// that are used when computing the price map.
-    fn get_token_asset_pairs<'a>(&self, tokens: &'a [Token]) -> Result<HashMap<String, &'a Token>> {
-        // TODO(nlordell): If these calls start taking too long, we can consider
-        //   caching this information somehow. The only thing that is
-        //   complicated is determining when the cache needs to be invalidated
-        //   as new assets get added to Kraken.
+    /// Uses the specified clock for expiring cached metadata and candles.
+    #[cfg(test)]
+    fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-        let assets = self.api.assets()?;
-        let asset_pairs = self.api.asset_pairs()?;
+    /// Sets how the client determines the prices of asset pairs.
+    pub fn with_price_mode(mut self, price_mode: KrakenPriceMode) -> Self {
+        self.price_mode = price_mode;
+        self
+    }
+
+    /// Generates a mapping between tokens and the Kraken asset pair
+    /// identifiers whose prices multiply to the token's USD price, that are
+    /// used when computing the price map. Tokens without a USD pair are routed
+    /// through the first bridge asset they have a pair with.
+    fn get_token_asset_pairs<'a>(
+        &self,
+        tokens: &'a [Token],
+    ) -> Result<Vec<(&'a Token, Vec<String>)>> {
+        let mut metadata = self.metadata.lock().expect("metadata mutex poisoned");
+        let stale = match &*metadata {
+            Some(cached) => {
+                let age = self
+                    .clock
+                    .now()
+                    .saturating_duration_since(cached.fetched_at);
+                // NOTE: Tokens that can't be found may have been listed since
+                //   the metadata was fetched.
+                age >= METADATA_TTL || cached.has_new_unknown_symbols(tokens)
+            }
+            None => true,
+        };
+        if stale {
+            *metadata = Some(self.fetch_metadata(tokens)?);
+        }
+        let Metadata {
+            assets,
+            asset_pairs,
+            ..
+        } = metadata.as_ref().expect("metadata was just fetched");
 
-        let usd =
-            find_asset("USD", &assets).ok_or_else(|| anyhow!("unable to locate USD asset"))?;
+        let usd = find_asset("USD", assets).ok_or_else(|| anyhow!("unable to locate USD asset"))?;
 
         let token_assets = tokens
             .iter()
             .flat_map(|token| {
-                let asset = find_asset(token.symbol(), &assets)?;
-                let pair = find_asset_pair(asset, usd, &asset_pairs)?;
-                Some((pair.to_owned(), token))
+                let asset = find_asset(token.symbol(), assets)?;
+                let route = find_route(asset, usd, assets, asset_pairs)?;
+                Some((token, route))
             })
             .collect();
 
         Ok(token_assets)
     }
+
+    /// Fetches the asset and asset pair metadata, remembering which of the
+    /// tokens' symbols have no asset.
+    fn fetch_metadata(&self, tokens: &[Token]) -> Result<Metadata> {
+        let assets = self.api.assets()?;
+        let asset_pairs = self.api.asset_pairs()?;
+        let unknown_symbols = tokens
+            .iter()
+            .map(|token| token.symbol())
+            .filter(|symbol| find_asset(symbol, &assets).is_none())
+            .map(str::to_owned)
+            .collect();
+        Ok(Metadata {
+            assets,
+            asset_pairs,
+            fetched_at: self.clock.now(),
+            unknown_symbols,
+        })
+    }
+
+    /// Retrieves the price of each asset pair as the average price of the last
+    /// 24 hours from the ticker.
+    fn get_ticker_prices(&self, asset_pairs: &[&str]) -> Result<HashMap<String, f64>> {
+        let ticker_infos = self.api.ticker(asset_pairs)?;
+        Ok(ticker_infos
+            .into_iter()
+            .map(|(pair, info)| (pair, info.p.last_24h()))
+            .collect())
+    }
+
+    /// Retrieves the price of each asset pair as the volume weighted average
+    /// price of its most recent committed candles, ignoring the candle of the
+    /// current interval as it is still changing. Asset pairs for which no
+    /// candles can be retrieved or that were not traded are skipped.
+    ///
+    /// Kraken only returns the candles of one asset pair per request, so the
+    /// candles are cached for one interval to stay within its rate limits, as
+    /// committed candles only change once a new interval starts.
+    fn get_vwap_prices(
+        &self,
+        asset_pairs: &[&str],
+        interval_minutes: u32,
+        candles: usize,
+    ) -> (HashMap<String, f64>, bool) {
+        let interval = Duration::from_secs(u64::from(interval_minutes) * 60);
+        let mut cache = self.candles.lock().expect("candles mutex poisoned");
+        let now = self.clock.now();
+        let mut failed = false;
+        let prices = asset_pairs
+            .iter()
+            .flat_map(|&pair| {
+                let is_fresh = |(fetched_at, _): &(Instant, Vec<Candle>)| {
+                    now.saturating_duration_since(*fetched_at) < interval
+                };
+                if !cache.get(pair).map(is_fresh).unwrap_or(false) {
+                    match self.api.ohlc(pair, interval_minutes) {
+                        Ok(mut ohlc) => {
+                            ohlc.pop();
+                            cache.insert(pair.to_owned(), (now, ohlc));
+                        }
+                        Err(err) => {
+                            failed = true;
+                            warn!(
+                                "failed to retrieve Kraken OHLC data for {}: {:?}",
+                                pair, err
+                            );
+                            return None;
+                        }
+                    }
+                }
+                let (_, committed) = &cache[pair];
+                let recent = &committed[committed.len().saturating_sub(candles)..];
+                Some((pair.to_owned(), volume_weighted_average_price(recent)?))
+            })
+            .collect();
+        (prices, failed)
+    }
 }
 
 impl<Api> PriceSource for KrakenClient<Api>
@@ -70,14 +249,38 @@ where
             .get_token_asset_pairs(tokens)
             .context("failed to generate asset pairs mapping for tokens")?;
 
-        let asset_pairs: Vec<_> = token_asset_pairs.keys().map(String::as_str).collect();
-        let ticker_infos = self.api.ticker(&asset_pairs)?;
+        let asset_pairs: Vec<_> = token_asset_pairs
+            .iter()
+            .flat_map(|(_, route)| route.iter().map(String::as_str))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let pair_prices = match self.price_mode {
+            KrakenPriceMode::Ticker => {
+                let pair_prices = self.get_ticker_prices(&asset_pairs);
+                self.metrics.kraken_price_request(pair_prices.is_ok());
+                pair_prices?
+            }
+            KrakenPriceMode::Vwap {
+                interval_minutes,
+                candles,
+            } => {
+                // NOTE: Errors are counted once per price request like the
+                //   requests themselves, even if several asset pairs failed.
+                let (pair_prices, failed) =
+                    self.get_vwap_prices(&asset_pairs, interval_minutes, candles);
+                self.metrics.kraken_price_request(!failed);
+                pair_prices
+            }
+        };
 
-        let prices = ticker_infos
+        let prices = token_asset_pairs
             .iter()
-            .flat_map(|(pair, info)| {
-                let token = token_asset_pairs.get(pair)?;
-                let price = token.get_owl_price(info.p.last_24h());
+            .flat_map(|(token, route)| {
+                let usd_price = route
+                    .iter()
+                    .try_fold(1.0, |price, pair| Some(price * pair_prices.get(pair)?))?;
+                let price = token.get_owl_price(usd_price);
 
                 Some((token.id, price))
             })
@@ -87,6 +290,20 @@ where
     }
 }
 
+/// Computes the volume weighted average price over the specified candles.
+/// Returns `None` if there was no volume.
+fn volume_weighted_average_price(candles: &[Candle]) -> Option<f64> {
+    let volume: f64 = candles.iter().map(|candle| candle.volume()).sum();
+    if volume <= 0.0 {
+        return None;
+    }
+    let weighted_prices: f64 = candles
+        .iter()
+        .map(|candle| candle.vwap() * candle.volume())
+        .sum();
+    Some(weighted_prices / volume)
+}
+
 /// Finds the Kraken asset identifier given a token symbol.
 fn find_asset<'a>(symbol: &'a str, assets: &'a HashMap<String, Asset>) -> Option<&'a str> {
     if assets.contains_key(symbol) {
@@ -98,6 +315,25 @@ fn find_asset<'a>(symbol: &'a str, assets: &'a HashMap<String, Asset>) -> Option
     }
 }
 
+/// Finds the asset pairs to price an asset in another one, either directly or
+/// through one of the bridge assets.
+fn find_route(
+    asset: &str,
+    to: &str,
+    assets: &HashMap<String, Asset>,
+    asset_pairs: &HashMap<String, AssetPair>,
+) -> Option<Vec<String>> {
+    if let Some(pair) = find_asset_pair(asset, to, asset_pairs) {
+        return Some(vec![pair.to_owned()]);
+    }
+    BRIDGE_ASSETS.iter().find_map(|symbol| {
+        let bridge = find_asset(symbol, assets)?;
+        let first = find_asset_pair(asset, bridge, asset_pairs)?;
+        let second = find_asset_pair(bridge, to, asset_pairs)?;
+        Some(vec![first.to_owned(), second.to_owned()])
+    })
+}
+
 /// Finds an asset pair from two Kraken asset identifiers.
 fn find_asset_pair<'a>(
     asset: &str,
@@ -118,8 +354,8 @@ fn find_asset_pair<'a>(
 mod tests {
     use super::api::{MockKrakenApi, TickerInfo};
     use super::*;
-    use std::collections::HashSet;
-    use std::time::Instant;
+    use crate::util::test_util::MockClock;
+    use std::time::SystemTime;
 
     #[test]
     fn get_token_prices() {
@@ -155,18 +391,254 @@ mod tests {
                 })
             });
 
-        let client = KrakenClient::with_api(api);
+        let client = KrakenClient::with_api(api, ComponentMetrics::default());
         let prices = client.get_prices(&tokens).unwrap();
 
         assert_eq!(
             prices,
             hash_map! {
-                TokenId(1) => (99.0 * 10f64.powi(18)) as u128,
-                TokenId(4) => (1.01 * 10f64.powi(30)) as u128,
+                TokenId(1) => 99_000_000_000_000_000_000,
+                TokenId(4) => 1_010_000_000_000_000_000_000_000_000_000,
             }
         );
     }
 
+    #[test]
+    fn get_token_prices_through_bridge_asset() {
+        let tokens = vec![Token::new(1, "ETH", 18), Token::new(7, "GNO", 18)];
+
+        let mut api = MockKrakenApi::new();
+        api.expect_assets().returning(|| {
+            Ok(hash_map! {
+                "GNO" => Asset::new("GNO"),
+                "XETH" => Asset::new("ETH"),
+                "XXBT" => Asset::new("XBT"),
+                "ZUSD" => Asset::new("USD"),
+            })
+        });
+        api.expect_asset_pairs().returning(|| {
+            Ok(hash_map! {
+                "GNOXBT" => AssetPair::new("GNO", "XXBT"),
+                "XETHZUSD" => AssetPair::new("XETH", "ZUSD"),
+                "XXBTZUSD" => AssetPair::new("XXBT", "ZUSD"),
+            })
+        });
+        api.expect_ticker()
+            .withf(|pairs| {
+                let unordered_pairs: HashSet<_> = pairs.iter().collect();
+                unordered_pairs == ["GNOXBT", "XETHZUSD", "XXBTZUSD"].iter().collect()
+            })
+            .returning(|_| {
+                Ok(hash_map! {
+                    "GNOXBT" => TickerInfo::new(0.002, 0.002),
+                    "XETHZUSD" => TickerInfo::new(100.0, 99.0),
+                    "XXBTZUSD" => TickerInfo::new(10_000.0, 10_000.0),
+                })
+            });
+
+        let client = KrakenClient::with_api(api, ComponentMetrics::default());
+        let prices = client.get_prices(&tokens).unwrap();
+
+        assert_eq!(
+            prices,
+            hash_map! {
+                TokenId(1) => 99_000_000_000_000_000_000,
+                TokenId(7) => 20_000_000_000_000_000_000,
+            }
+        );
+    }
+
+    fn metadata_api() -> MockKrakenApi {
+        let mut api = MockKrakenApi::new();
+        api.expect_ticker().returning(|_| {
+            Ok(hash_map! {
+                "XETHZUSD" => TickerInfo::new(100.0, 99.0),
+            })
+        });
+        api
+    }
+
+    #[test]
+    fn caches_metadata_until_it_expires() {
+        let tokens = vec![Token::new(1, "ETH", 18), Token::new(2, "UNLISTED", 18)];
+
+        let mut api = metadata_api();
+        api.expect_assets().times(2).returning(|| {
+            Ok(hash_map! {
+                "XETH" => Asset::new("ETH"),
+                "ZUSD" => Asset::new("USD"),
+            })
+        });
+        api.expect_asset_pairs().times(2).returning(|| {
+            Ok(hash_map! {
+                "XETHZUSD" => AssetPair::new("XETH", "ZUSD"),
+            })
+        });
+
+        let clock = Arc::new(MockClock::new());
+        let client =
+            KrakenClient::with_api(api, ComponentMetrics::default()).with_clock(clock.clone());
+        for _ in 0..3 {
+            let prices = client.get_prices(&tokens).unwrap();
+            assert_eq!(prices.keys().collect::<Vec<_>>(), vec![&TokenId(1)]);
+            clock.advance(METADATA_TTL / 4);
+        }
+        clock.advance(METADATA_TTL / 4);
+        client.get_prices(&tokens).unwrap();
+    }
+
+    #[test]
+    fn timestamps_prices_with_current_time() {
+        let tokens = vec![Token::new(1, "ETH", 18)];
+
+        let mut api = metadata_api();
+        api.expect_assets().returning(|| {
+            Ok(hash_map! {
+                "XETH" => Asset::new("ETH"),
+                "ZUSD" => Asset::new("USD"),
+            })
+        });
+        api.expect_asset_pairs().returning(|| {
+            Ok(hash_map! {
+                "XETHZUSD" => AssetPair::new("XETH", "ZUSD"),
+            })
+        });
+
+        let client = KrakenClient::with_api(api, ComponentMetrics::default());
+        let before = SystemTime::now();
+        let prices = client.get_prices_with_timestamp(&tokens).unwrap();
+        let after = SystemTime::now();
+
+        let priced_token = prices[&TokenId(1)];
+        assert_eq!(priced_token.price, 99_000_000_000_000_000_000);
+        assert!(before <= priced_token.as_of && priced_token.as_of <= after);
+    }
+
+    #[test]
+    fn fetches_metadata_for_new_tokens() {
+        let tokens = vec![Token::new(1, "ETH", 18), Token::new(2, "UNLISTED", 18)];
+
+        let mut api = metadata_api();
+        api.expect_assets().times(2).returning(|| {
+            Ok(hash_map! {
+                "XETH" => Asset::new("ETH"),
+                "ZUSD" => Asset::new("USD"),
+            })
+        });
+        api.expect_asset_pairs().times(2).returning(|| {
+            Ok(hash_map! {
+                "XETHZUSD" => AssetPair::new("XETH", "ZUSD"),
+            })
+        });
+
+        let client = KrakenClient::with_api(api, ComponentMetrics::default())
+            .with_clock(Arc::new(MockClock::new()));
+        client.get_prices(&tokens[..1]).unwrap();
+        // The unlisted token is new, so the metadata is fetched again in case
+        // it was listed in the meantime, but only once.
+        client.get_prices(&tokens).unwrap();
+        client.get_prices(&tokens).unwrap();
+    }
+
+    #[test]
+    fn get_token_prices_from_recent_candles() {
+        let tokens = vec![Token::new(1, "ETH", 18), Token::new(4, "USDC", 6)];
+
+        let mut api = MockKrakenApi::new();
+        api.expect_assets().returning(|| {
+            Ok(hash_map! {
+                "USDC" => Asset::new("USDC"),
+                "XETH" => Asset::new("ETH"),
+                "ZUSD" => Asset::new("USD"),
+            })
+        });
+        api.expect_asset_pairs().returning(|| {
+            Ok(hash_map! {
+                "USDCUSD" => AssetPair::new("USDC", "ZUSD"),
+                "XETHZUSD" => AssetPair::new("XETH", "ZUSD"),
+            })
+        });
+        api.expect_ticker().times(0);
+        api.expect_ohlc()
+            .withf(|pair, interval| pair == "XETHZUSD" && *interval == 60)
+            .returning(|_, _| {
+                Ok(vec![
+                    // Outside of the averaged candles.
+                    Candle::new(50.0, 1000.0),
+                    Candle::new(100.0, 1.0),
+                    Candle::new(110.0, 3.0),
+                    // The uncommitted candle of the current interval.
+                    Candle::new(200.0, 1000.0),
+                ])
+            });
+        api.expect_ohlc()
+            .withf(|pair, interval| pair == "USDCUSD" && *interval == 60)
+            .returning(|_, _| Ok(vec![Candle::new(1.0, 0.0), Candle::new(1.0, 1.0)]));
+
+        let client = KrakenClient::with_api(api, ComponentMetrics::default()).with_price_mode(
+            KrakenPriceMode::Vwap {
+                interval_minutes: 60,
+                candles: 2,
+            },
+        );
+        let prices = client.get_prices(&tokens).unwrap();
+
+        // USDC was not traded so there is no price for it.
+        assert_eq!(
+            prices,
+            hash_map! {
+                TokenId(1) => 107_500_000_000_000_000_000,
+            }
+        );
+    }
+
+    #[test]
+    fn caches_candles_for_one_interval() {
+        let tokens = vec![Token::new(1, "ETH", 18)];
+
+        let mut api = MockKrakenApi::new();
+        api.expect_assets().returning(|| {
+            Ok(hash_map! {
+                "XETH" => Asset::new("ETH"),
+                "ZUSD" => Asset::new("USD"),
+            })
+        });
+        api.expect_asset_pairs().returning(|| {
+            Ok(hash_map! {
+                "XETHZUSD" => AssetPair::new("XETH", "ZUSD"),
+            })
+        });
+        api.expect_ohlc()
+            .times(2)
+            .returning(|_, _| Ok(vec![Candle::new(100.0, 1.0), Candle::new(200.0, 1.0)]));
+
+        let clock = Arc::new(MockClock::new());
+        let client = KrakenClient::with_api(api, ComponentMetrics::default())
+            .with_price_mode(KrakenPriceMode::Vwap {
+                interval_minutes: 1,
+                candles: 1,
+            })
+            .with_clock(clock.clone());
+        client.get_prices(&tokens).unwrap();
+        clock.advance(Duration::from_secs(59));
+        client.get_prices(&tokens).unwrap();
+        clock.advance(Duration::from_secs(1));
+        client.get_prices(&tokens).unwrap();
+    }
+
+    #[test]
+    fn volume_weighted_average_price_weighs_by_volume() {
+        assert_eq!(
+            volume_weighted_average_price(&[Candle::new(1.0, 3.0), Candle::new(2.0, 1.0)]),
+            Some(1.25)
+        );
+        assert_eq!(
+            volume_weighted_average_price(&[Candle::new(1.0, 0.0)]),
+            None
+        );
+        assert_eq!(volume_weighted_average_price(&[]), None);
+    }
+
     #[test]
     #[ignore]
     fn online_kraken_prices() {
@@ -193,7 +665,8 @@ mod tests {
 
         let start_time = Instant::now();
         {
-            let client = KrakenClient::new(&HttpFactory::default()).unwrap();
+            let client =
+                KrakenClient::new(&HttpFactory::default(), ComponentMetrics::default()).unwrap();
             let prices = client.get_prices(&tokens).unwrap();
 
             println!("{:#?}", prices);
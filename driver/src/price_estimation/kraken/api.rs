@@ -17,6 +17,9 @@ pub trait KrakenApi {
     /// Retrieves ticker information (with recent prices) for the given asset
     /// pair identifiers.
     fn ticker<'a>(&'a self, pairs: &'a [&'a str]) -> Result<HashMap<String, TickerInfo>>;
+    /// Retrieves the most recent candles for the given asset pair identifier,
+    /// each covering the specified interval in minutes, oldest first.
+    fn ohlc<'a>(&'a self, pair: &'a str, interval_minutes: u32) -> Result<Vec<Candle>>;
 }
 
 /// An HTTP Kraken API Client.
@@ -76,6 +79,23 @@ impl KrakenApi for KrakenHttpApi {
             .context("failed to parse ticker JSON")?
             .into_result()
     }
+
+    fn ohlc(&self, pair: &str, interval_minutes: u32) -> Result<Vec<Candle>> {
+        let mut ohlc = self
+            .client
+            .get_json::<_, KrakenResult<OhlcResult>>(
+                format!(
+                    "{}/OHLC?pair={}&interval={}",
+                    self.base_url, pair, interval_minutes
+                ),
+                HttpLabel::Kraken,
+            )
+            .context("failed to parse OHLC JSON")?
+            .into_result()?;
+        ohlc.candles
+            .remove(pair)
+            .ok_or_else(|| anyhow!("missing OHLC data for asset pair {}", pair))
+    }
 }
 
 /// The result type that is returned by Kraken on API requests. This type is
@@ -164,6 +184,51 @@ impl TickerInfo {
     }
 }
 
+/// The OHLC data for an asset pair. This type is only used internally.
+#[derive(Clone, Debug, Deserialize)]
+struct OhlcResult {
+    /// The candles by asset pair identifier.
+    #[serde(flatten)]
+    candles: HashMap<String, Vec<Candle>>,
+    /// The ID to use for polling new candles, which is not needed for
+    /// retrieving price estimates.
+    #[allow(dead_code)]
+    last: u64,
+}
+
+/// A candle of OHLC data for an asset pair, with its time, open, high, low and
+/// close prices, volume weighted average price, volume and number of trades.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct Candle(
+    u64,
+    #[serde(with = "display_fromstr")] f64,
+    #[serde(with = "display_fromstr")] f64,
+    #[serde(with = "display_fromstr")] f64,
+    #[serde(with = "display_fromstr")] f64,
+    #[serde(with = "display_fromstr")] f64,
+    #[serde(with = "display_fromstr")] f64,
+    u64,
+);
+
+impl Candle {
+    /// Create a new candle from its volume weighted average price and volume.
+    #[cfg(test)]
+    pub fn new(vwap: f64, volume: f64) -> Candle {
+        Candle(0, vwap, vwap, vwap, vwap, vwap, volume, 1)
+    }
+
+    /// Retrieves the volume weighted average price of the candle's trades.
+    pub fn vwap(self) -> f64 {
+        self.5
+    }
+
+    /// Retrieves the traded volume in the base asset.
+    pub fn volume(self) -> f64 {
+        self.6
+    }
+}
+
 /// A price pair used in the ticker info, where the first field is today's price
 /// and the second field is from the last 24 hours.
 #[derive(Copy, Clone, Debug, Deserialize)]
@@ -237,6 +302,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_ohlc_json() {
+        // Sample retrieved from https://api.kraken.com/0/public/OHLC?pair=ETHUSD&interval=60
+        let value: OhlcResult = deserialize(
+            r#"{"error":[],"result":{"XETHZUSD":[[1594216800,"241.61","242.23","241.03","241.74","241.70","1082.98339186",339],[1594220400,"241.74","242.50","241.52","242.10","242.02","626.27452011",243]],"last":1594216800}}"#,
+        );
+        assert_eq!(value.last, 1_594_216_800);
+        assert_eq!(value.candles.len(), 1);
+        assert_eq!(
+            value.candles["XETHZUSD"]
+                .iter()
+                .map(|candle| (candle.vwap(), candle.volume()))
+                .collect::<Vec<_>>(),
+            vec![(241.70, 1_082.983_391_86), (242.02, 626.274_520_11)]
+        );
+    }
+
     #[test]
     #[ignore]
     fn online_kraken_api() {
@@ -260,5 +342,8 @@ mod tests {
 
         let ticker = api.ticker(&["GNOEUR"]).unwrap();
         println!("GNO/EUR ticker information: {:?}", ticker["GNOEUR"]);
+
+        let candles = api.ohlc("GNOEUR", 60).unwrap();
+        println!("GNO/EUR latest hourly candle: {:?}", candles.last());
     }
 }
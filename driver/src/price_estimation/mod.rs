@@ -1,27 +1,65 @@
 //! Module responsible for aggregating price estimates from various sources to
 //! give good price estimates to the solver for better results.
 
+mod agreement_price_source;
 mod average_price_source;
+mod clamped_price_source;
 pub mod data;
 mod dexag;
+mod dispatching_price_source;
 mod kraken;
 mod price_source;
+mod recording_price_source;
 mod threaded_price_source;
 
+pub use self::clamped_price_source::ClampMode;
 pub use self::data::TokenData;
 use self::dexag::DexagClient;
 use self::kraken::KrakenClient;
+pub use self::kraken::KrakenPriceMode;
+pub use self::recording_price_source::RecordingFormat;
 use crate::http::HttpFactory;
+use crate::metrics::ComponentMetrics;
 use crate::models::{Order, TokenId, TokenInfo};
-use anyhow::Result;
+use agreement_price_source::AgreementPriceSource;
+use anyhow::{ensure, Result};
 use average_price_source::AveragePriceSource;
+use clamped_price_source::ClampedPriceSource;
+use dispatching_price_source::{DispatchingPriceSource, PriceSourceKind};
 use log::warn;
 use price_source::{NoopPriceSource, PriceSource, Token};
+use recording_price_source::RecordingPriceSource;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::OpenOptions;
 use std::iter;
+use std::path::PathBuf;
 use std::time::Duration;
 use threaded_price_source::ThreadedPriceSource;
 
+/// Settings for the external price sources of a price oracle.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PriceSourceSettings {
+    /// How prices are determined from Kraken.
+    pub kraken_price_mode: KrakenPriceMode,
+    /// Only use prices for tokens without a preferred price source that Kraken
+    /// and DEX.AG agree on within this tolerance, instead of averaging them.
+    pub agreement_tolerance: Option<f64>,
+}
+
+impl PriceSourceSettings {
+    /// Returns an error if the settings are invalid.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(tolerance) = self.agreement_tolerance {
+            ensure!(
+                tolerance.is_finite() && tolerance >= 0.0,
+                "invalid price source agreement tolerance {}",
+                tolerance
+            );
+        }
+        Ok(())
+    }
+}
+
 /// A type alias for token information map that is passed to the solver.
 type Tokens = BTreeMap<TokenId, Option<TokenInfo>>;
 
@@ -41,24 +79,58 @@ pub struct PriceOracle {
 }
 
 impl PriceOracle {
-    /// Creates a new price oracle from a token whitelist data.
+    /// Creates a new price oracle from a token whitelist data. Estimated
+    /// prices outside of the `[min_price, max_price]` range are handled
+    /// according to the specified clamp mode. If a recording path is
+    /// specified, every price update gets appended to that file in the
+    /// specified format. Kraken price requests are recorded in the specified
+    /// component metrics.
     pub fn new(
         http_factory: &HttpFactory,
         tokens: TokenData,
         update_interval: Duration,
+        (min_price, max_price): (u128, u128),
+        clamp_mode: ClampMode,
+        settings: PriceSourceSettings,
+        recording: Option<(PathBuf, RecordingFormat)>,
+        metrics: &ComponentMetrics,
     ) -> Result<Self> {
+        settings.validate()?;
+        let kraken_price_mode = settings.kraken_price_mode;
         let source: Box<dyn PriceSource + Sync> = if tokens.is_empty() {
             Box::new(NoopPriceSource)
         } else {
-            let source = AveragePriceSource::new(
-                KrakenClient::new(http_factory)?,
-                DexagClient::new(http_factory)?,
-            );
-            let (source, _) = ThreadedPriceSource::new(
-                tokens.all_tokens_to_estimate_price(),
-                source,
-                update_interval,
+            let kraken = KrakenClient::new(http_factory, metrics.clone())?
+                .with_price_mode(kraken_price_mode);
+            let dexag = DexagClient::new(http_factory)?;
+            let default_source: Box<dyn PriceSource + Send> = match settings.agreement_tolerance {
+                Some(tolerance) => Box::new(AgreementPriceSource::new(kraken, dexag, tolerance)),
+                None => Box::new(AveragePriceSource::new(kraken, dexag)),
+            };
+            let source = DispatchingPriceSource::new(
+                default_source,
+                hash_map! {
+                    PriceSourceKind::Kraken => Box::new(
+                        KrakenClient::new(http_factory, metrics.clone())?
+                            .with_price_mode(kraken_price_mode),
+                    ) as _,
+                    PriceSourceKind::Dexag => Box::new(DexagClient::new(http_factory)?) as _,
+                },
+                tokens.price_source_preferences(),
             );
+            let source = ClampedPriceSource::new(source, min_price, max_price, clamp_mode)?;
+            let tokens_to_estimate = tokens.all_tokens_to_estimate_price();
+            let (source, _) = match recording {
+                Some((path, format)) => {
+                    let file = OpenOptions::new().create(true).append(true).open(path)?;
+                    ThreadedPriceSource::new(
+                        tokens_to_estimate,
+                        RecordingPriceSource::new(source, file, format),
+                        update_interval,
+                    )
+                }
+                None => ThreadedPriceSource::new(tokens_to_estimate, source, update_interval),
+            };
             Box::new(source)
         };
 
@@ -220,6 +292,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn price_source_settings_reject_invalid_agreement_tolerance() {
+        for &tolerance in &[-0.01, std::f64::NAN, std::f64::INFINITY] {
+            let settings = PriceSourceSettings {
+                agreement_tolerance: Some(tolerance),
+                ..Default::default()
+            };
+            assert!(settings.validate().is_err());
+        }
+        for &tolerance in &[None, Some(0.0), Some(0.05)] {
+            let settings = PriceSourceSettings {
+                agreement_tolerance: tolerance,
+                ..Default::default()
+            };
+            assert!(settings.validate().is_ok());
+        }
+    }
+
     #[test]
     fn price_oracle_always_includes_reference_token() {
         let oracle = PriceOracle::with_source(TokenData::default(), MockPriceSource::new());
@@ -283,16 +373,89 @@ mod tests {
     #[test]
     fn token_get_price() {
         for (token, usd_price, expected) in &[
-            (Token::new(4, "USDC", 6), 0.99, 0.99 * 10f64.powi(30)),
-            (Token::new(7, "DAI", 18), 1.01, 1.01 * 10f64.powi(18)),
-            (Token::new(42, "FAKE", 32), 1.0, 10f64.powi(4)),
-            (Token::new(99, "SCAM", 42), 10f64.powi(10), 10f64.powi(4)),
+            (
+                Token::new(4, "USDC", 6),
+                0.99,
+                990_000_000_000_000_000_000_000_000_000,
+            ),
+            (Token::new(7, "DAI", 18), 1.01, 1_010_000_000_000_000_000),
+            (Token::new(42, "FAKE", 32), 1.0, 10_000),
+            (Token::new(99, "SCAM", 42), 10f64.powi(10), 10_000),
         ] {
             let owl_price = token.get_owl_price(*usd_price);
-            assert_eq!(owl_price, *expected as u128);
+            assert_eq!(owl_price, *expected);
         }
     }
 
+    #[test]
+    fn token_get_price_scales_by_decimals() {
+        for (decimals, usd_price, expected) in &[
+            (0, 0.000_001, 1_000_000_000_000_000_000_000_000_000_000),
+            (0, 0.5, 500_000_000_000_000_000_000_000_000_000_000_000),
+            (0, 1.0, 1_000_000_000_000_000_000_000_000_000_000_000_000),
+            (2, 0.01, 100_000_000_000_000_000_000_000_000_000_000),
+            (2, 1.0, 10_000_000_000_000_000_000_000_000_000_000_000),
+            (2, 42.5, 425_000_000_000_000_000_000_000_000_000_000_000),
+            (6, 0.99, 990_000_000_000_000_000_000_000_000_000),
+            (6, 1.0, 1_000_000_000_000_000_000_000_000_000_000),
+            (6, 250.0, 250_000_000_000_000_000_000_000_000_000_000),
+            (18, 0.000_000_001, 1_000_000_000),
+            (18, 1.0, 1_000_000_000_000_000_000),
+            (18, 7000.0, 7_000_000_000_000_000_000_000),
+        ] {
+            let token = Token::new(1, "TKN", *decimals);
+            assert_eq!(
+                token.get_owl_price(*usd_price),
+                *expected,
+                "unexpected OWL price for {} USD with {} decimals",
+                usd_price,
+                decimals,
+            );
+        }
+    }
+
+    #[test]
+    fn token_get_price_keeps_precision_for_few_decimals() {
+        // Prices are per token atom, so one GUSD atom (2 decimals) is worth
+        // 10^16 DAI atoms (18 decimals) at the same USD value. Whole tokens are
+        // worth exactly the same amount of OWL, so no precision is lost.
+        let gusd = Token::new(1, "GUSD", 2);
+        let dai = Token::new(7, "DAI", 18);
+        for &usd_price in &[0.000_001, 0.01, 0.999_999, 1.0, 1.234_567, 1000.0] {
+            let gusd_price = gusd.get_owl_price(usd_price);
+            let dai_price = dai.get_owl_price(usd_price);
+            assert_ne!(gusd_price, 0);
+            assert_eq!(
+                gusd_price,
+                dai_price * 10u128.pow(16),
+                "unexpected OWL prices for {} USD",
+                usd_price,
+            );
+        }
+    }
+
+    #[test]
+    fn token_get_price_rounds_to_nearest_for_many_decimals() {
+        // 10^10 USD is 10^28 OWL atoms, which is not exact as an `f64`.
+        assert_eq!(Token::new(1, "TKN", 42).get_owl_price(1e10), 10_000);
+        // Half an atom of the token rounds up, less than half rounds down.
+        assert_eq!(Token::new(1, "TKN", 32).get_owl_price(5e-5), 1);
+        assert_eq!(Token::new(1, "TKN", 32).get_owl_price(4e-5), 0);
+        // The largest number of decimals for which the divisor is computed.
+        assert_eq!(Token::new(1, "TKN", 56).get_owl_price(1e20), 1);
+        assert_eq!(Token::new(1, "TKN", 57).get_owl_price(1e20), 0);
+    }
+
+    #[test]
+    fn token_get_price_saturates() {
+        let token = Token::new(1, "TKN", 0);
+        assert_eq!(token.get_owl_price(1000.0), u128::max_value());
+        assert_eq!(token.get_owl_price(f64::INFINITY), u128::max_value());
+        assert_eq!(token.get_owl_price(-1.0), 0);
+        assert_eq!(token.get_owl_price(f64::NAN), 0);
+        assert_eq!(Token::new(1, "TKN", 255).get_owl_price(1.0), 0);
+    }
+
     #[test]
     fn token_get_price_without_rounding_error() {
         assert_eq!(
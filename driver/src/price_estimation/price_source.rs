@@ -1,7 +1,10 @@
 use crate::models::{TokenId, TokenInfo};
+use crate::util::CheckedConvertU128 as _;
 use anyhow::Result;
+use ethcontract::U256;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
+use std::time::SystemTime;
 
 /// A token reprensentation.
 #[cfg_attr(test, derive(Eq, PartialEq))]
@@ -36,9 +39,29 @@ impl Token {
     /// Converts the prices from USD into the unit expected by the contract.
     /// This price is relative to the OWL token which is considered pegged at
     /// exactly 1 USD with 18 decimals.
+    ///
+    /// The USD price is first converted to an amount of OWL atoms (i.e. a
+    /// fixed point value with 18 decimals) rounding to the nearest atom, which
+    /// is the only step performed with floating point arithmetic. The scaling
+    /// by the token's decimals is done with exact integer arithmetic, rounding
+    /// to the nearest integer for tokens with more than 18 decimals. Negative
+    /// and NaN prices result in a price of 0, and prices that do not fit in a
+    /// `u128` saturate to `u128::max_value()`.
     pub fn get_owl_price(&self, usd_price: f64) -> u128 {
-        let pow = 36 - (self.info.decimals as i32);
-        (usd_price * 10f64.powi(pow)) as _
+        let owl_atoms = U256::from((usd_price * 1e18).round() as u128);
+        let decimals = self.info.decimals as usize;
+        let price = if decimals <= 18 {
+            owl_atoms * U256::exp10(18 - decimals)
+        } else if decimals - 18 <= 38 {
+            let divisor = U256::exp10(decimals - 18);
+            (owl_atoms + divisor / 2) / divisor
+        } else {
+            // NOTE: Any `u128` amount of OWL atoms is less than half of 10^39,
+            //   so the price rounds to 0 for these tokens. This also avoids
+            //   overflowing `U256` when computing the divisor.
+            U256::zero()
+        };
+        price.as_u128_checked().unwrap_or_else(u128::max_value)
     }
 
     /// Creates a new token from its parameters.
@@ -55,6 +78,15 @@ impl Token {
     }
 }
 
+/// A price estimate for a token along with the time it was retrieved from its
+/// source, so that stale estimates can be told apart from fresh ones.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PricedToken {
+    /// The price relative to the OWL token, see `PriceSource::get_prices`.
+    pub price: u128,
+    pub as_of: SystemTime,
+}
+
 /// An abstraction around a type that retrieves price estimate from a source
 /// such as an exchange.
 #[cfg_attr(test, mockall::automock)]
@@ -64,6 +96,18 @@ pub trait PriceSource {
     /// sparce price array as being unable to find a price is not considered an
     /// error.
     fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>>;
+
+    /// Retrieve prices like `get_prices` along with the time they were
+    /// retrieved. Sources that serve previously retrieved prices return the
+    /// time they were originally retrieved, all others the current time.
+    fn get_prices_with_timestamp(&self, tokens: &[Token]) -> Result<HashMap<TokenId, PricedToken>> {
+        let as_of = SystemTime::now();
+        Ok(self
+            .get_prices(tokens)?
+            .into_iter()
+            .map(|(token_id, price)| (token_id, PricedToken { price, as_of }))
+            .collect())
+    }
 }
 
 /// A no-op price source that always succeeds and finds no prices.
@@ -0,0 +1,188 @@
+use super::{PriceSource, Token};
+use crate::models::TokenId;
+use anyhow::{anyhow, Error, Result};
+use chrono::Utc;
+use log::warn;
+use serde::Serialize;
+use serde_with::rust::display_fromstr;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// The format in which recorded prices are written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordingFormat {
+    /// One `timestamp,token_id,price` row per token and fetch.
+    Csv,
+    /// One JSON object containing the timestamp and all prices per fetch.
+    Jsonl,
+}
+
+impl FromStr for RecordingFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "csv" => Ok(RecordingFormat::Csv),
+            "jsonl" => Ok(RecordingFormat::Jsonl),
+            _ => Err(anyhow!("unknown price recording format '{}'", value)),
+        }
+    }
+}
+
+/// A single price fetch as written in the JSONL format.
+#[derive(Serialize)]
+struct JsonRecord {
+    timestamp: i64,
+    prices: BTreeMap<u16, JsonPrice>,
+}
+
+/// A price written as a decimal string, as JSON numbers can't represent all
+/// `u128` values exactly.
+#[derive(Serialize)]
+struct JsonPrice(#[serde(with = "display_fromstr")] u128);
+
+/// Records every successful price fetch of a price source together with the
+/// time it was fetched, building a price time series that can be used for
+/// backtesting.
+pub struct RecordingPriceSource<S, W> {
+    source: S,
+    writer: Mutex<W>,
+    format: RecordingFormat,
+}
+
+impl<S, W> RecordingPriceSource<S, W> {
+    pub fn new(source: S, writer: W, format: RecordingFormat) -> Self {
+        Self {
+            source,
+            writer: Mutex::new(writer),
+            format,
+        }
+    }
+}
+
+impl<S, W: Write> RecordingPriceSource<S, W> {
+    fn record(&self, timestamp: i64, prices: &HashMap<TokenId, u128>) -> Result<()> {
+        let prices = prices
+            .iter()
+            .map(|(token_id, price)| (token_id.0, *price))
+            .collect::<BTreeMap<_, _>>();
+        let mut record = String::new();
+        match self.format {
+            RecordingFormat::Csv => {
+                for (token_id, price) in prices {
+                    record.push_str(&format!("{},{},{}\n", timestamp, token_id, price));
+                }
+            }
+            RecordingFormat::Jsonl => {
+                let line = serde_json::to_string(&JsonRecord {
+                    timestamp,
+                    prices: prices
+                        .into_iter()
+                        .map(|(token_id, price)| (token_id, JsonPrice(price)))
+                        .collect(),
+                })?;
+                record.push_str(&format!("{}\n", line));
+            }
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(record.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<S: PriceSource, W: Write> PriceSource for RecordingPriceSource<S, W> {
+    fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>> {
+        let prices = self.source.get_prices(tokens)?;
+        if let Err(err) = self.record(Utc::now().timestamp(), &prices) {
+            warn!("failed to record token prices: {:?}", err);
+        }
+        Ok(prices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::price_source::MockPriceSource;
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn source_with_prices() -> MockPriceSource {
+        let mut source = MockPriceSource::new();
+        source.expect_get_prices().returning(|_| {
+            Ok(hash_map! {
+                TokenId(1) => 1_000_000_000_000_000_000,
+            })
+        });
+        source
+    }
+
+    fn recorded_lines(source: RecordingPriceSource<MockPriceSource, Vec<u8>>) -> Vec<String> {
+        let output = source.writer.into_inner().unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn records_csv_row_per_fetch() {
+        let source =
+            RecordingPriceSource::new(source_with_prices(), Vec::new(), RecordingFormat::Csv);
+        source.get_prices(&[]).unwrap();
+        source.get_prices(&[]).unwrap();
+
+        let lines = recorded_lines(source);
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let fields = line.split(',').collect::<Vec<_>>();
+            assert_eq!(fields.len(), 3);
+            assert!(fields[0].parse::<i64>().is_ok());
+            assert_eq!(fields[1..], ["1", "1000000000000000000"]);
+        }
+    }
+
+    #[test]
+    fn records_json_line_per_fetch() {
+        let source =
+            RecordingPriceSource::new(source_with_prices(), Vec::new(), RecordingFormat::Jsonl);
+        source.get_prices(&[]).unwrap();
+        source.get_prices(&[]).unwrap();
+
+        let lines = recorded_lines(source);
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let record: Value = serde_json::from_str(&line).unwrap();
+            assert!(record["timestamp"].is_i64());
+            assert_eq!(record["prices"], json!({ "1": "1000000000000000000" }));
+        }
+    }
+
+    #[test]
+    fn does_not_record_failed_fetches() {
+        let mut source = MockPriceSource::new();
+        source
+            .expect_get_prices()
+            .returning(|_| Err(anyhow!("error")));
+        let source = RecordingPriceSource::new(source, Vec::new(), RecordingFormat::Csv);
+        assert!(source.get_prices(&[]).is_err());
+        assert!(recorded_lines(source).is_empty());
+    }
+
+    #[test]
+    fn parses_recording_format() {
+        assert_eq!(
+            "csv".parse::<RecordingFormat>().unwrap(),
+            RecordingFormat::Csv
+        );
+        assert_eq!(
+            "JSONL".parse::<RecordingFormat>().unwrap(),
+            RecordingFormat::Jsonl
+        );
+        assert!("xml".parse::<RecordingFormat>().is_err());
+    }
+}
@@ -1,4 +1,7 @@
-use super::{price_source::PriceSource, Token};
+use super::{
+    price_source::{PriceSource, PricedToken},
+    Token,
+};
 use crate::models::TokenId;
 use anyhow::Result;
 use std::collections::HashMap;
@@ -9,14 +12,15 @@ use std::sync::{
     Arc, Mutex,
 };
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 /// Implements `PriceSource` in a non blocking way by updating prices in a
-/// thread and reusing previous results.
+/// thread and reusing previous results. Prices are timestamped with the time
+/// they were updated.
 pub struct ThreadedPriceSource {
     // Shared between this struct and the thread. The background thread writes
     // the prices, the thread calling `get_prices` reads them.
-    price_map: Arc<Mutex<HashMap<TokenId, u128>>>,
+    price_map: Arc<Mutex<HashMap<TokenId, PricedToken>>>,
     // Allows the thread to notice when the owning struct is dropped.
     // Mutex is not needed because we don't access the sender at all but we need
     // this struct to be sync and this makes the compiler happy.
@@ -57,10 +61,15 @@ impl ThreadedPriceSource {
                     Ok(()) | Err(RecvTimeoutError::Timeout) => {
                         // Make sure we don't hold the mutex while this blocking call happens.
                         match price_source.get_prices(&tokens) {
-                            Ok(prices) => price_map
-                                .lock()
-                                .expect("mutex should never be poisoned")
-                                .extend(prices),
+                            Ok(prices) => {
+                                let as_of = SystemTime::now();
+                                price_map
+                                    .lock()
+                                    .expect("mutex should never be poisoned")
+                                    .extend(prices.into_iter().map(|(token_id, price)| {
+                                        (token_id, PricedToken { price, as_of })
+                                    }))
+                            }
                             Err(err) => log::warn!("price_source::get_prices failed: {}", err),
                         }
                     }
@@ -89,6 +98,14 @@ impl ThreadedPriceSource {
 
 impl PriceSource for ThreadedPriceSource {
     fn get_prices(&self, tokens: &[Token]) -> Result<HashMap<TokenId, u128>> {
+        Ok(self
+            .get_prices_with_timestamp(tokens)?
+            .into_iter()
+            .map(|(token_id, priced_token)| (token_id, priced_token.price))
+            .collect())
+    }
+
+    fn get_prices_with_timestamp(&self, tokens: &[Token]) -> Result<HashMap<TokenId, PricedToken>> {
         let price_map = self
             .price_map
             .lock()
@@ -104,6 +121,7 @@ impl PriceSource for ThreadedPriceSource {
 mod tests {
     use super::super::price_source::MockPriceSource;
     use super::*;
+    use anyhow::anyhow;
     use std::sync::atomic;
     use std::time::Instant;
 
@@ -194,4 +212,89 @@ mod tests {
         );
         join(tps, handle);
     }
+
+    #[test]
+    fn returns_time_prices_were_updated() {
+        let mut price_source = MockPriceSource::new();
+        price_source
+            .expect_get_prices()
+            .returning(|_| Ok(hash_map! {TOKENS[0].id => 1}));
+
+        let before_update = SystemTime::now();
+        let (tps, handle) =
+            ThreadedPriceSource::new(TOKENS.to_vec(), price_source, Duration::from_secs(3600));
+        // Wait for the initial update, which does not repeat within the test.
+        tps.test_receiver
+            .lock()
+            .unwrap()
+            .recv_timeout(THREAD_TIMEOUT)
+            .unwrap();
+        let after_update = SystemTime::now();
+
+        let prices = tps.get_prices_with_timestamp(&TOKENS[..]).unwrap();
+        let as_of = prices[&TOKENS[0].id].as_of;
+        assert!(before_update <= as_of && as_of <= after_update);
+
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(tps.get_prices_with_timestamp(&TOKENS[..]).unwrap(), prices);
+        assert_eq!(
+            tps.get_prices(&TOKENS[..]).unwrap(),
+            hash_map! {TOKENS[0].id => 1}
+        );
+        join(tps, handle);
+    }
+
+    #[test]
+    fn serves_prices_while_update_is_in_progress() {
+        let (unblock, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let calls = atomic::AtomicUsize::new(0);
+        let mut price_source = MockPriceSource::new();
+        price_source.expect_get_prices().returning(move |_| {
+            if calls.fetch_add(1, ORDERING) > 0 {
+                // Block all updates after the first one until the test is done.
+                let _ = blocked.lock().unwrap().recv();
+            }
+            Ok(hash_map! {TOKENS[0].id => 1})
+        });
+
+        let (tps, handle) =
+            ThreadedPriceSource::new(TOKENS.to_vec(), price_source, Duration::from_millis(1));
+        // Wait for the initial update, the following one blocks.
+        tps.test_receiver
+            .lock()
+            .unwrap()
+            .recv_timeout(THREAD_TIMEOUT)
+            .unwrap();
+        assert_eq!(
+            tps.get_prices(&TOKENS[..]).unwrap(),
+            hash_map! {TOKENS[0].id => 1}
+        );
+
+        drop(unblock);
+        join(tps, handle);
+    }
+
+    #[test]
+    fn keeps_previous_prices_when_update_fails() {
+        let calls = atomic::AtomicUsize::new(0);
+        let mut price_source = MockPriceSource::new();
+        price_source.expect_get_prices().returning(move |_| {
+            if calls.fetch_add(1, ORDERING) == 0 {
+                Ok(hash_map! {TOKENS[0].id => 1})
+            } else {
+                Err(anyhow!("error"))
+            }
+        });
+
+        let (tps, handle) =
+            ThreadedPriceSource::new(TOKENS.to_vec(), price_source, Duration::from_millis(1));
+        wait_for_thread_to_loop(&tps);
+        wait_for_thread_to_loop(&tps);
+        assert_eq!(
+            tps.get_prices(&TOKENS[..]).unwrap(),
+            hash_map! {TOKENS[0].id => 1}
+        );
+        join(tps, handle);
+    }
 }
@@ -0,0 +1,212 @@
+use crate::models::{AccountState, Order, Solution};
+use crate::orderbook::AuctionDataExport;
+use crate::price_finding::optimization_price_finder::deserialize_result;
+use crate::price_finding::price_finder_interface::{PriceFinding, SolverType};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use ethcontract::U256;
+use log::{debug, warn};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often to check whether the solver process has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Implements PriceFinding by running an external solver binary.
+///
+/// The auction data is written to the solver's stdin as JSON in the auction
+/// data export format and the solver is expected to write its solution to
+/// stdout in the same format as the optimization solvers. The time limit is
+/// passed to the solver as `--time-limit=<seconds>` and enforced by killing
+/// the solver if it overruns. The trivial solution is used whenever the
+/// solver fails.
+pub struct ExternalSolver {
+    command: PathBuf,
+    args: Vec<String>,
+}
+
+impl ExternalSolver {
+    /// Creates a new external solver that runs the specified command with the
+    /// specified arguments, followed by the time limit argument.
+    pub fn new(command: impl Into<PathBuf>, args: Vec<String>) -> Self {
+        ExternalSolver {
+            command: command.into(),
+            args,
+        }
+    }
+
+    fn run(
+        &self,
+        orders: &[Order],
+        state: &AccountState,
+        time_limit: Duration,
+    ) -> Result<Solution> {
+        // We are solving the batch before the current one
+        let batch_id = U256::from(Utc::now().timestamp() / 300 - 1);
+        let input = serde_json::to_vec(&AuctionDataExport::new(batch_id, state, orders)?)?;
+
+        let mut command = Command::new(&self.command);
+        command
+            .args(&self.args)
+            .arg(format!("--time-limit={}", time_limit.as_secs_f64()))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        debug!("Using external solver command `{:?}`", command);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("failed to start external solver {:?}", self.command))?;
+
+        // NOTE: Write the input and read the output on separate threads so
+        //   that the solver can never block on a full pipe while we are
+        //   waiting for it to exit.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        thread::spawn(move || stdin.write_all(&input));
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let output = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        wait_with_timeout(&mut child, time_limit)?;
+        let output = output
+            .join()
+            .map_err(|_| anyhow!("thread reading solver output panicked"))?
+            .context("error reading external solver output")?;
+        deserialize_result(output).context("error deserializing external solver output")
+    }
+}
+
+/// Waits for the child process to exit successfully, killing it if it does
+/// not exit within the time limit.
+fn wait_with_timeout(child: &mut Child, time_limit: Duration) -> Result<()> {
+    let deadline = Instant::now() + time_limit;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                return Err(anyhow!("external solver failed with {}", status));
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(anyhow!(
+                "external solver did not finish within {:?}",
+                time_limit
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+impl PriceFinding for ExternalSolver {
+    fn find_prices(
+        &self,
+        orders: &[Order],
+        state: &AccountState,
+        time_limit: Duration,
+    ) -> Result<Solution> {
+        let solution = match self.run(orders, state, time_limit) {
+            Ok(solution) => solution,
+            Err(err) => {
+                warn!("external solver failed, using trivial solution: {:?}", err);
+                Solution::trivial()
+            }
+        };
+        Ok(solution.with_solver_name(SolverType::ExternalSolver.name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ExecutedOrder;
+    use crate::orderbook::AUCTION_DATA_SCHEMA_VERSION;
+    use ethcontract::Address;
+
+    /// Creates an external solver that runs the specified shell script.
+    fn solver_with_script(script: &str) -> ExternalSolver {
+        ExternalSolver::new("sh", vec!["-c".to_owned(), script.to_owned()])
+    }
+
+    #[test]
+    fn reads_solution_from_stdout() {
+        let solver = solver_with_script(
+            r#"cat > /dev/null; echo '{
+                "prices": { "T0000": "1000000000000000000", "T0001": "2000000000000000000" },
+                "orders": [{
+                    "accountID": "0x0000000000000000000000000000000000000001",
+                    "orderID": 0,
+                    "execSellAmount": "10",
+                    "execBuyAmount": "20"
+                }]
+            }'"#,
+        );
+        let solution = solver
+            .find_prices(&[], &AccountState::default(), Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            solution,
+            Solution {
+                prices: hash_map! {
+                    0 => 1_000_000_000_000_000_000,
+                    1 => 2_000_000_000_000_000_000,
+                },
+                executed_orders: vec![ExecutedOrder {
+                    account_id: Address::from_low_u64_be(1),
+                    order_id: 0,
+                    sell_amount: 10,
+                    buy_amount: 20,
+                }],
+                solver_name: Some("external-solver".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn passes_auction_data_and_time_limit() {
+        // The script echoes back a valid solution only if it received the
+        // export of the auction data and the time limit.
+        let solver = solver_with_script(&format!(
+            r#"grep -q '"version":{}' && [ "$0" = "--time-limit=5" ] \
+                && echo '{{ "prices": {{}}, "orders": [] }}'"#,
+            AUCTION_DATA_SCHEMA_VERSION,
+        ));
+        let state = AccountState::default();
+        assert!(solver.run(&[], &state, Duration::from_secs(5)).is_ok());
+        assert!(solver.run(&[], &state, Duration::from_secs(6)).is_err());
+
+        let failing_solver = solver_with_script("cat > /dev/null; exit 1");
+        assert!(failing_solver
+            .run(&[], &state, Duration::from_secs(5))
+            .is_err());
+    }
+
+    #[test]
+    fn falls_back_to_trivial_solution_on_malformed_output() {
+        let solver = solver_with_script("cat > /dev/null; echo 'not a solution'");
+        let solution = solver
+            .find_prices(&[], &AccountState::default(), Duration::from_secs(10))
+            .unwrap();
+        assert!(!solution.is_non_trivial());
+        assert!(solver
+            .run(&[], &AccountState::default(), Duration::from_secs(10))
+            .is_err());
+    }
+
+    #[test]
+    fn kills_solver_that_overruns_time_limit() {
+        let solver = solver_with_script("exec sleep 10");
+        let start = Instant::now();
+        let solution = solver
+            .find_prices(&[], &AccountState::default(), Duration::from_millis(100))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!solution.is_non_trivial());
+    }
+}
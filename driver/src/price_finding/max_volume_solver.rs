@@ -0,0 +1,168 @@
+use crate::metrics::ComponentMetrics;
+use crate::models::{AccountState, Order, Solution};
+use crate::price_finding::naive_solver::{create_solution, find_matches};
+use crate::price_finding::price_finder_interface::{Fee, PriceFinding, SolverType};
+
+use anyhow::Result;
+use ethcontract::U256;
+use std::time::{Duration, Instant};
+
+const BASE_PRICE: u128 = 1_000_000_000_000_000_000;
+
+/// Implements PriceFinding by settling a single pair of orders like the naive
+/// solver, but instead of using the first pair of orders that can be matched,
+/// it evaluates all of them and uses the one that trades the most volume.
+/// If there are several such pairs then the first one is used.
+pub struct MaxVolumeSolver {
+    fee: Option<Fee>,
+    metrics: ComponentMetrics,
+}
+
+impl MaxVolumeSolver {
+    /// Creates a new max volume solver that records the duration of finding
+    /// prices with the specified component metrics.
+    pub fn new(fee: Option<Fee>, metrics: ComponentMetrics) -> Self {
+        MaxVolumeSolver { fee, metrics }
+    }
+}
+
+impl PriceFinding for MaxVolumeSolver {
+    fn find_prices(&self, orders: &[Order], state: &AccountState, _: Duration) -> Result<Solution> {
+        let start = Instant::now();
+        let mut best: Option<(Solution, U256)> = None;
+        for order_match in find_matches(orders, state, &self.fee) {
+            let solution = create_solution(&order_match, &self.fee);
+            if !solution.is_non_trivial() {
+                continue;
+            }
+            let volume = traded_volume(&solution, orders);
+            if best
+                .as_ref()
+                .map(|(_, best_volume)| volume > *best_volume)
+                .unwrap_or(true)
+            {
+                best = Some((solution, volume));
+            }
+        }
+        let solution = best
+            .map(|(solution, _)| solution)
+            .unwrap_or_else(Solution::trivial);
+        self.metrics.price_finding(start.elapsed());
+        Ok(solution.with_solver_name(SolverType::MaxVolumeSolver.name()))
+    }
+}
+
+/// Returns the total value of the executed sell amounts of a solution. When
+/// there is a fee the prices are normalized so that this is in units of the
+/// fee token.
+fn traded_volume(solution: &Solution, orders: &[Order]) -> U256 {
+    solution
+        .executed_orders
+        .iter()
+        .filter_map(|executed_order| {
+            let order = orders.iter().find(|order| {
+                order.account_id == executed_order.account_id && order.id == executed_order.order_id
+            })?;
+            let price = solution.prices.get(&order.sell_token).copied().unwrap_or(0);
+            Some(U256::from(executed_order.sell_amount) * U256::from(price))
+        })
+        .fold(U256::zero(), |total, volume| total + volume)
+        / U256::from(BASE_PRICE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_finding::naive_solver::tests::check_solution;
+    use crate::price_finding::NaiveSolver;
+    use ethcontract::Address;
+
+    const BASE_UNIT: u128 = 1_000_000_000_000_000_000;
+
+    fn matched_accounts(solution: &Solution) -> Vec<Address> {
+        solution
+            .executed_orders
+            .iter()
+            .map(|executed_order| executed_order.account_id)
+            .collect()
+    }
+
+    #[test]
+    fn settles_pair_with_highest_volume() {
+        let orders = vec![
+            Order::builder()
+                .account_id(Address::from_low_u64_be(1))
+                .sell_token(0)
+                .buy_token(1)
+                .sell_amount(10 * BASE_UNIT)
+                .buy_amount(10 * BASE_UNIT / 2)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(2))
+                .sell_token(1)
+                .buy_token(0)
+                .sell_amount(10 * BASE_UNIT)
+                .buy_amount(10 * BASE_UNIT / 2)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(3))
+                .sell_token(0)
+                .buy_token(1)
+                .sell_amount(1000 * BASE_UNIT)
+                .buy_amount(1000 * BASE_UNIT / 2)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(4))
+                .sell_token(1)
+                .buy_token(0)
+                .sell_amount(1000 * BASE_UNIT)
+                .buy_amount(1000 * BASE_UNIT / 2)
+                .build(),
+        ];
+        let state = AccountState::with_balance_for(&orders);
+        let fee = Some(Fee::default());
+
+        let naive_solution = NaiveSolver::new(fee.clone(), ComponentMetrics::default())
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert_eq!(
+            matched_accounts(&naive_solution),
+            vec![Address::from_low_u64_be(1), Address::from_low_u64_be(2)]
+        );
+
+        let solution = MaxVolumeSolver::new(fee.clone(), ComponentMetrics::default())
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert_eq!(
+            matched_accounts(&solution),
+            vec![Address::from_low_u64_be(3), Address::from_low_u64_be(4)]
+        );
+        assert_eq!(solution.solver_name.as_deref(), Some("max-volume-solver"));
+        check_solution(&orders, solution, &fee).unwrap();
+    }
+
+    #[test]
+    fn returns_trivial_solution_without_matches() {
+        let orders = vec![
+            Order::builder()
+                .account_id(Address::from_low_u64_be(1))
+                .sell_token(0)
+                .buy_token(1)
+                .sell_amount(10 * BASE_UNIT)
+                .buy_amount(10 * BASE_UNIT / 2)
+                .build(),
+            Order::builder()
+                .account_id(Address::from_low_u64_be(2))
+                .sell_token(0)
+                .buy_token(1)
+                .sell_amount(10 * BASE_UNIT)
+                .buy_amount(10 * BASE_UNIT / 2)
+                .build(),
+        ];
+        let state = AccountState::with_balance_for(&orders);
+        let solution = MaxVolumeSolver::new(Some(Fee::default()), ComponentMetrics::default())
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert!(!solution.is_non_trivial());
+    }
+}
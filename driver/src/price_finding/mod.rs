@@ -1,29 +1,122 @@
+pub mod external_solver;
+pub mod max_volume_solver;
 pub mod naive_solver;
 pub mod optimization_price_finder;
 pub mod price_finder_interface;
+pub mod racing_price_finder;
 
+use crate::metrics::ComponentMetrics;
 use crate::price_estimation::PriceEstimating;
+pub use crate::price_finding::external_solver::ExternalSolver;
+pub use crate::price_finding::max_volume_solver::MaxVolumeSolver;
 pub use crate::price_finding::naive_solver::NaiveSolver;
-pub use crate::price_finding::optimization_price_finder::OptimisationPriceFinder;
+pub use crate::price_finding::optimization_price_finder::{
+    OptimisationPriceFinder, UnpricedTokenPolicy,
+};
 pub use crate::price_finding::price_finder_interface::{Fee, PriceFinding, SolverType};
+pub use crate::price_finding::racing_price_finder::RacingPriceFinder;
+use anyhow::{anyhow, Result};
+use ethcontract::Address;
 use log::info;
+use std::collections::HashSet;
+use std::path::PathBuf;
 
+/// The options used to create a price finder.
+pub struct PriceFinderConfig {
+    pub fee: Option<Fee>,
+    pub solver_type: SolverType,
+    pub min_avg_fee_per_order: u128,
+    pub log_rejected_matches: bool,
+    pub fee_token_bridging: bool,
+    pub allowed_accounts: Option<HashSet<Address>>,
+    pub unpriced_token_policy: UnpricedTokenPolicy,
+    pub external_solver_command: Option<PathBuf>,
+    /// Whether to race the specified solver against a naive solver created
+    /// with the same options and use the better solution.
+    pub race_naive_solver: bool,
+}
+
+/// Creates the price finder specified by the config. Returns an error if the
+/// config is missing options required by the specified solver type.
 pub fn create_price_finder(
-    fee: Option<Fee>,
-    solver_type: SolverType,
+    config: PriceFinderConfig,
     price_oracle: impl PriceEstimating + Sync + 'static,
-    min_avg_fee_per_order: u128,
-) -> Box<dyn PriceFinding + Sync> {
-    if solver_type == SolverType::NaiveSolver {
+    metrics: ComponentMetrics,
+) -> Result<Box<dyn PriceFinding + Sync>> {
+    let PriceFinderConfig {
+        fee,
+        solver_type,
+        min_avg_fee_per_order,
+        log_rejected_matches,
+        fee_token_bridging,
+        allowed_accounts,
+        unpriced_token_policy,
+        external_solver_command,
+        race_naive_solver,
+    } = config;
+    let naive_solver = || {
+        NaiveSolver::new(fee.clone(), metrics.clone())
+            .with_rejected_match_logging(log_rejected_matches)
+            .with_fee_token_bridging(fee_token_bridging)
+            .with_allowed_accounts(allowed_accounts.clone())
+    };
+    let price_finder: Box<dyn PriceFinding + Sync> = if solver_type == SolverType::NaiveSolver {
         info!("Using naive price finder");
-        Box::new(NaiveSolver::new(fee))
+        Box::new(naive_solver())
+    } else if solver_type == SolverType::MaxVolumeSolver {
+        info!("Using max volume price finder");
+        Box::new(MaxVolumeSolver::new(fee.clone(), metrics.clone()))
+    } else if solver_type == SolverType::ExternalSolver {
+        let command = external_solver_command
+            .ok_or_else(|| anyhow!("the external solver requires an external solver command"))?;
+        info!("Using external solver {:?}", command);
+        Box::new(ExternalSolver::new(command, Vec::new()))
     } else {
         info!("Using {:?} optimization price finder", solver_type);
-        Box::new(OptimisationPriceFinder::new(
-            fee,
-            solver_type,
-            price_oracle,
-            min_avg_fee_per_order,
-        ))
+        Box::new(
+            OptimisationPriceFinder::new(
+                fee.clone(),
+                solver_type,
+                price_oracle,
+                min_avg_fee_per_order,
+                metrics.clone(),
+            )
+            .with_unpriced_token_policy(unpriced_token_policy),
+        )
+    };
+    if race_naive_solver && solver_type != SolverType::NaiveSolver {
+        info!("Racing {:?} against naive solver", solver_type);
+        return Ok(Box::new(RacingPriceFinder::new(vec![
+            price_finder,
+            Box::new(naive_solver()),
+        ])));
+    }
+    Ok(price_finder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price_estimation::MockPriceEstimating;
+
+    #[test]
+    fn external_solver_requires_command() {
+        let config = PriceFinderConfig {
+            fee: None,
+            solver_type: SolverType::ExternalSolver,
+            min_avg_fee_per_order: 0,
+            log_rejected_matches: false,
+            fee_token_bridging: false,
+            allowed_accounts: None,
+            unpriced_token_policy: UnpricedTokenPolicy::Ignore,
+            external_solver_command: None,
+            race_naive_solver: false,
+        };
+        let result = create_price_finder(
+            config,
+            MockPriceEstimating::new(),
+            ComponentMetrics::default(),
+        );
+        assert!(result.is_err());
     }
 }
@@ -1,12 +1,17 @@
+use crate::metrics::ComponentMetrics;
 use crate::models::{AccountState, ExecutedOrder, Order, Solution};
-use crate::price_finding::price_finder_interface::{Fee, PriceFinding};
+use crate::price_finding::price_finder_interface::{Fee, PriceFinding, SolverType};
 use crate::util::{CeiledDiv, CheckedConvertU128};
 
-use std::collections::HashMap;
-use std::time::Duration;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use ethcontract::U256;
+use anyhow::{anyhow, Error, Result};
+use ethcontract::{Address, U256};
+use log::{debug, trace};
 
 const BASE_UNIT: u128 = 1_000_000_000_000_000_000u128;
 const BASE_PRICE: u128 = BASE_UNIT;
@@ -17,20 +22,148 @@ pub enum OrderPairType {
     BothFullyFilled,
 }
 
+/// The reason why two orders could not be matched.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum MatchRejection {
+    /// One of the accounts does not have enough funds of its sell token.
+    InsufficientFunds,
+    /// There is a fee and neither of the traded tokens is the fee token.
+    FeeTokenNotTraded,
+    /// The orders do not buy the token that the other order sells.
+    TokensNotOpposite,
+    /// There is no price that satisfies both orders.
+    NoPriceOverlap,
+}
+
+impl fmt::Display for MatchRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            MatchRejection::InsufficientFunds => "insufficient seller funds",
+            MatchRejection::FeeTokenNotTraded => "fee token not traded",
+            MatchRejection::TokensNotOpposite => "tokens not opposite",
+            MatchRejection::NoPriceOverlap => "no price overlap",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Why the naive solver could not match any pair of orders, which results in
+/// the trivial solution.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TrivialSolutionReason {
+    /// The number of orders that were ignored because they were not placed
+    /// by one of the allowed accounts.
+    filtered_orders: usize,
+    /// The number of pairs of orders that were rejected for each reason. This
+    /// is empty if there were fewer than two orders to match.
+    rejected_pairs: BTreeMap<MatchRejection, usize>,
+    /// Whether a matching pair of orders was found but no valid solution
+    /// could be created for it, for example because the fee could not be
+    /// applied to the executed amounts.
+    unsettled_match: bool,
+}
+
+impl TrivialSolutionReason {
+    /// Returns the number of pairs of orders that were rejected because of
+    /// the specified reason.
+    #[cfg(test)]
+    pub fn rejected_pairs(&self, reason: MatchRejection) -> usize {
+        self.rejected_pairs.get(&reason).copied().unwrap_or(0)
+    }
+
+    fn record_rejection(&mut self, reason: MatchRejection) {
+        *self.rejected_pairs.entry(reason).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for TrivialSolutionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.filtered_orders > 0 {
+            write!(
+                f,
+                "ignored {} orders of accounts that are not allowed, ",
+                self.filtered_orders
+            )?;
+        }
+        if self.unsettled_match {
+            return f.write_str("found a match that could not be settled");
+        }
+        if self.rejected_pairs.is_empty() {
+            return f.write_str("not enough orders to match");
+        }
+        f.write_str("rejected pairs of orders: ")?;
+        for (i, (reason, count)) in self.rejected_pairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} {}", count, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether an order can be matched with a counterparty.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchOutcome {
+    /// The orders can be matched.
+    Matched,
+    /// The orders can be matched but belong to the same account, so the
+    /// account would only trade with itself.
+    SelfTrade,
+    /// The orders can't be matched for the specified reason.
+    Rejected(MatchRejection),
+}
+
+impl fmt::Display for MatchOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchOutcome::Matched => f.write_str("matched"),
+            MatchOutcome::SelfTrade => f.write_str("self-trade"),
+            MatchOutcome::Rejected(reason) => write!(f, "rejected: {}", reason),
+        }
+    }
+}
+
+/// Identifies an order by the account that placed it and its id, specified
+/// as `<account>:<order id>`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OrderReference {
+    pub account_id: Address,
+    pub id: u16,
+}
+
+impl FromStr for OrderReference {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut parts = value.splitn(2, ':');
+        let account_id = parts.next().unwrap_or_default().trim_start_matches("0x");
+        let id = parts
+            .next()
+            .ok_or_else(|| anyhow!("missing order id in '{}'", value))?;
+        Ok(OrderReference {
+            account_id: account_id
+                .parse()
+                .map_err(|_| anyhow!("invalid account '{}'", account_id))?,
+            id: id.parse()?,
+        })
+    }
+}
+
 trait Matchable {
-    /// Returns whether the orders can be matched. For this the tokens need
-    /// to match, there must be a price that satisfies both orders and if there
-    /// is a fee then one of the tokens must be the fee token.
-    fn attracts(&self, other: &Order, fee: &Option<Fee>) -> bool;
     /// Returns whether the account to which the order belongs has at least
     /// as many funds of the sell token as the order's sell amount.
     fn sufficient_seller_funds(&self, state: &AccountState) -> bool;
+    /// Returns how the orders can be matched or the reason why they can't. For
+    /// this both accounts need sufficient funds, the tokens need to match,
+    /// there must be a price that satisfies both orders and if there is a fee
+    /// then one of the tokens must be the fee token.
     fn match_compare(
         &self,
         other: &Order,
         state: &AccountState,
         fee: &Option<Fee>,
-    ) -> Option<OrderPairType>;
+    ) -> Result<OrderPairType, MatchRejection>;
     /// Returns whether this order's sell token is the other order's buy token
     /// and vice versa.
     fn opposite_tokens(&self, other: &Order) -> bool;
@@ -41,16 +174,6 @@ trait Matchable {
 }
 
 impl Matchable for Order {
-    fn attracts(&self, other: &Order, fee: &Option<Fee>) -> bool {
-        // We can only match orders that touch the fee token
-        if fee.is_some()
-            && ![self.sell_token, self.buy_token].contains(&fee.as_ref().unwrap().token)
-        {
-            return false;
-        }
-        self.opposite_tokens(other) && self.have_price_overlap(other)
-    }
-
     fn sufficient_seller_funds(&self, state: &AccountState) -> bool {
         state.read_balance(self.sell_token, self.account_id) >= self.sell_amount
     }
@@ -60,24 +183,29 @@ impl Matchable for Order {
         other: &Order,
         state: &AccountState,
         fee: &Option<Fee>,
-    ) -> Option<OrderPairType> {
-        if !self.sufficient_seller_funds(&state)
-            || !other.sufficient_seller_funds(&state)
-            || !self.attracts(other, fee)
-            || !fee
-                .as_ref()
-                .map(|fee| self.trades_fee_token(fee))
-                .unwrap_or(true)
-        {
-            return None;
+    ) -> Result<OrderPairType, MatchRejection> {
+        if !self.sufficient_seller_funds(&state) || !other.sufficient_seller_funds(&state) {
+            return Err(MatchRejection::InsufficientFunds);
+        }
+        // We can only match orders that touch the fee token
+        if let Some(fee) = fee {
+            if !self.trades_fee_token(fee) {
+                return Err(MatchRejection::FeeTokenNotTraded);
+            }
+        }
+        if !self.opposite_tokens(other) {
+            return Err(MatchRejection::TokensNotOpposite);
+        }
+        if !self.have_price_overlap(other) {
+            return Err(MatchRejection::NoPriceOverlap);
         }
 
         if self.buy_amount <= other.sell_amount && self.sell_amount <= other.buy_amount {
-            Some(OrderPairType::LhsFullyFilled)
+            Ok(OrderPairType::LhsFullyFilled)
         } else if self.buy_amount >= other.sell_amount && self.sell_amount >= other.buy_amount {
-            Some(OrderPairType::RhsFullyFilled)
+            Ok(OrderPairType::RhsFullyFilled)
         } else {
-            Some(OrderPairType::BothFullyFilled)
+            Ok(OrderPairType::BothFullyFilled)
         }
     }
 
@@ -86,33 +214,142 @@ impl Matchable for Order {
     }
 
     fn have_price_overlap(&self, other: &Order) -> bool {
-        self.sell_amount > 0
-            && other.sell_amount > 0
-            && U256::from(self.buy_amount) * U256::from(other.buy_amount)
-                <= U256::from(other.sell_amount) * U256::from(self.sell_amount)
+        if self.sell_amount == 0 || other.sell_amount == 0 {
+            return false;
+        }
+        // NOTE: The product of two `u128` always fits in a `U256`, but use
+        //   checked arithmetic anyway so that an overflow can never panic and
+        //   is treated as no overlap instead.
+        let buy_product = U256::from(self.buy_amount).checked_mul(U256::from(other.buy_amount));
+        let sell_product = U256::from(other.sell_amount).checked_mul(U256::from(self.sell_amount));
+        match (buy_product, sell_product) {
+            (Some(buy_product), Some(sell_product)) => buy_product <= sell_product,
+            _ => false,
+        }
     }
 
     fn trades_fee_token(&self, fee: &Fee) -> bool {
-        self.buy_token == fee.token || self.sell_token == fee.token
+        self.buy_token == fee.token() || self.sell_token == fee.token()
     }
 }
 
 /// Implements PriceFinding in a simplistic way.
 ///
 /// Tries to find a match of two orders that trade the fee token and uses this
-/// as the only trade in the solution.
+/// as the only trade in the solution. Orders are considered by account and
+/// order id so that the same orderbook always results in the same match.
+/// Optionally, if no such match exists, it tries to find a ring of three
+/// orders that trades a pair of non-fee tokens through the fee token.
 /// If no such match can be found then the trivial solution is returned.
+///
+/// Optionally, only orders of a set of allowed accounts are matched.
 pub struct NaiveSolver {
     fee: Option<Fee>,
+    /// Whether to log every rejected pair of orders along with the reason it
+    /// was rejected. This is disabled by default as the number of rejected
+    /// pairs grows quadratically with the number of orders.
+    log_rejected_matches: bool,
+    metrics: ComponentMetrics,
+    /// Whether to settle orders on pairs that don't include the fee token by
+    /// bridging them to the fee token with two other orders.
+    fee_token_bridging: bool,
+    /// The accounts whose orders are matched, or `None` to match orders of
+    /// any account.
+    allowed_accounts: Option<HashSet<Address>>,
 }
 
 impl NaiveSolver {
-    pub fn new(fee: Option<Fee>) -> Self {
-        NaiveSolver { fee }
+    /// Creates a new naive solver that records the duration of finding prices
+    /// with the specified component metrics.
+    pub fn new(fee: Option<Fee>, metrics: ComponentMetrics) -> Self {
+        NaiveSolver {
+            fee,
+            log_rejected_matches: false,
+            metrics,
+            fee_token_bridging: false,
+            allowed_accounts: None,
+        }
+    }
+
+    /// Enables or disables trace logging of rejected order pairs.
+    pub fn with_rejected_match_logging(self, log_rejected_matches: bool) -> Self {
+        NaiveSolver {
+            log_rejected_matches,
+            ..self
+        }
+    }
+
+    /// Enables or disables matching orders on non-fee token pairs through
+    /// the fee token.
+    pub fn with_fee_token_bridging(self, fee_token_bridging: bool) -> Self {
+        NaiveSolver {
+            fee_token_bridging,
+            ..self
+        }
+    }
+
+    /// Restricts the solver to only match orders placed by the specified
+    /// accounts, or allows orders of any account if `None`.
+    pub fn with_allowed_accounts(self, allowed_accounts: Option<HashSet<Address>>) -> Self {
+        NaiveSolver {
+            allowed_accounts,
+            ..self
+        }
+    }
+
+    /// Finds prices like `find_prices` and additionally returns the reason why
+    /// no match was found when the solution is trivial.
+    pub fn find_prices_with_reason(
+        &self,
+        orders: &[Order],
+        state: &AccountState,
+    ) -> Result<(Solution, Option<TrivialSolutionReason>)> {
+        let allowed_orders = self.allowed_orders(orders);
+        let mut reason = TrivialSolutionReason {
+            filtered_orders: orders.len() - allowed_orders.len(),
+            ..Default::default()
+        };
+        let orders = allowed_orders;
+        let solution = if let Some(first_match) = find_first_match(
+            &orders,
+            state,
+            &self.fee,
+            self.log_rejected_matches,
+            &mut reason,
+        ) {
+            let solution = create_solution(&first_match, &self.fee);
+            reason.unsettled_match = !solution.is_non_trivial();
+            solution
+        } else if let (Some(fee), true) = (&self.fee, self.fee_token_bridging) {
+            find_first_bridged_match(&orders, state, fee).unwrap_or_else(Solution::trivial)
+        } else {
+            Solution::trivial()
+        };
+        let reason = if solution.is_non_trivial() {
+            None
+        } else {
+            Some(reason)
+        };
+        Ok((
+            solution.with_solver_name(SolverType::NaiveSolver.name()),
+            reason,
+        ))
+    }
+
+    /// Returns the orders that are allowed to be matched.
+    fn allowed_orders<'a>(&self, orders: &'a [Order]) -> Cow<'a, [Order]> {
+        match &self.allowed_accounts {
+            Some(accounts) => orders
+                .iter()
+                .filter(|order| accounts.contains(&order.account_id))
+                .cloned()
+                .collect(),
+            None => Cow::Borrowed(orders),
+        }
     }
 }
 
-struct Match {
+pub(super) struct Match {
     order_pair_type: OrderPairType,
     orders: OrderPair,
 }
@@ -123,37 +360,268 @@ type ExecutedOrderPair = [ExecutedOrder; 2];
 
 impl PriceFinding for NaiveSolver {
     fn find_prices(&self, orders: &[Order], state: &AccountState, _: Duration) -> Result<Solution> {
-        let solution = if let Some(first_match) = find_first_match(orders, state, &self.fee) {
-            let (executed_orders, prices) = create_executed_orders(&first_match, &self.fee);
-            if let Some(ref fee) = self.fee {
-                create_solution_with_fee(&first_match.orders, fee, executed_orders, prices)
-            } else {
-                Solution {
-                    prices,
-                    executed_orders: executed_orders.to_vec(),
-                }
-            }
-        } else {
-            Solution::trivial()
-        };
+        let start = Instant::now();
+        let (solution, reason) = self.find_prices_with_reason(orders, state)?;
+        if let Some(reason) = reason {
+            debug!("naive solver found no match: {}", reason);
+        }
+        self.metrics.price_finding(start.elapsed());
         Ok(solution)
     }
 }
 
-fn find_first_match(orders: &[Order], state: &AccountState, fee: &Option<Fee>) -> Option<Match> {
-    for (i, x) in orders.iter().enumerate() {
-        for y in orders.iter().skip(i + 1) {
-            if let Some(order_pair_type) = x.match_compare(&y, &state, fee) {
+fn find_first_match(
+    orders: &[Order],
+    state: &AccountState,
+    fee: &Option<Fee>,
+    log_rejected_matches: bool,
+    reason: &mut TrivialSolutionReason,
+) -> Option<Match> {
+    for (x, y) in candidate_pairs(sorted_orders(orders)) {
+        match x.match_compare(y, &state, fee) {
+            Ok(order_pair_type) => {
                 return Some(Match {
                     order_pair_type,
                     orders: [x.clone(), y.clone()],
-                });
+                })
+            }
+            Err(rejection) => {
+                if log_rejected_matches {
+                    trace!(
+                        "rejected match of order {} of {:?} with order {} of {:?}: {}",
+                        x.id,
+                        x.account_id,
+                        y.id,
+                        y.account_id,
+                        rejection,
+                    );
+                }
+                reason.record_rejection(rejection);
+            }
+        }
+    }
+    None
+}
+
+/// Returns all pairs of orders that can be matched, in the order in which
+/// `find_first_match` considers them.
+pub(super) fn find_matches<'a>(
+    orders: &'a [Order],
+    state: &'a AccountState,
+    fee: &'a Option<Fee>,
+) -> impl Iterator<Item = Match> + 'a {
+    candidate_pairs(sorted_orders(orders)).filter_map(move |(x, y)| {
+        let order_pair_type = x.match_compare(y, state, fee).ok()?;
+        Some(Match {
+            order_pair_type,
+            orders: [x.clone(), y.clone()],
+        })
+    })
+}
+
+/// Returns the orders sorted by account and id, which is the order in which
+/// they are considered for matching, so that the solution doesn't depend on
+/// the order in which the orderbook was read.
+fn sorted_orders(orders: &[Order]) -> Vec<&Order> {
+    let mut sorted = orders.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|order| (order.account_id, order.id));
+    sorted
+}
+
+/// Returns all pairs of the sorted orders in the order in which they are
+/// considered for matching. The orders of each pair are in sorted order as
+/// well.
+fn candidate_pairs(sorted_orders: Vec<&Order>) -> impl Iterator<Item = (&Order, &Order)> + '_ {
+    let len = sorted_orders.len();
+    (0..len)
+        .flat_map(move |i| (i + 1..len).map(move |j| (i, j)))
+        .map(move |(i, j)| (sorted_orders[i], sorted_orders[j]))
+}
+
+/// Creates the solution settling a single match, with prices normalized to
+/// the fee token if there is a fee.
+pub(super) fn create_solution(order_match: &Match, fee: &Option<Fee>) -> Solution {
+    let (executed_orders, prices) = create_executed_orders(order_match, fee);
+    if let Some(fee) = fee {
+        create_solution_with_fee(&order_match.orders, fee, executed_orders, prices)
+    } else {
+        Solution {
+            prices,
+            executed_orders: executed_orders.to_vec(),
+            solver_name: None,
+        }
+    }
+}
+
+/// Finds the first ring of three orders `x`, `y` and `z` where `x` trades a
+/// pair of non-fee tokens and `y` and `z` bridge its buy and sell token to the
+/// fee token respectively, so that `x` sells `a` for `b`, `y` sells `b` for
+/// the fee token and `z` sells the fee token for `a`. Returns the solution
+/// settling the first such ring that can be executed.
+fn find_first_bridged_match(orders: &[Order], state: &AccountState, fee: &Fee) -> Option<Solution> {
+    let orders = sorted_orders(orders);
+    let funded = |order: &&Order| order.sufficient_seller_funds(state);
+    for x in orders.iter().copied().filter(funded) {
+        if x.trades_fee_token(fee) || x.sell_token == x.buy_token {
+            continue;
+        }
+        let bridges_buy_token =
+            |y: &&Order| y.sell_token == x.buy_token && y.buy_token == fee.token();
+        let bridges_sell_token =
+            |z: &&Order| z.sell_token == fee.token() && z.buy_token == x.sell_token;
+        for y in orders
+            .iter()
+            .copied()
+            .filter(funded)
+            .filter(bridges_buy_token)
+        {
+            for z in orders
+                .iter()
+                .copied()
+                .filter(funded)
+                .filter(bridges_sell_token)
+            {
+                if let Some(solution) = create_bridged_solution([x, y, z], fee) {
+                    return Some(solution);
+                }
             }
         }
     }
     None
 }
 
+/// Creates a solution for a ring of orders as found by
+/// `find_first_bridged_match`, or `None` if the limit prices of the orders
+/// don't leave enough room for the fees of all three trades.
+///
+/// The prices are chosen such that the bridging orders `y` and `z` trade at
+/// their limit price (including the fee) and all volumes are derived from the
+/// volume of `b` traded, which makes the executed amounts of the non-fee
+/// tokens balance exactly. Rounding errors are moved to the fee token.
+fn create_bridged_solution([x, y, z]: [&Order; 3], fee: &Fee) -> Option<Solution> {
+    if [x, y, z].iter().any(|order| order.buy_amount == 0) {
+        return None;
+    }
+    // NOTE: Order amounts are arbitrary values, so all arithmetic is checked
+    //   and an overflow or a division by zero means the ring can't be settled.
+    fn product(factors: &[U256]) -> Option<U256> {
+        factors
+            .iter()
+            .try_fold(U256::one(), |product, factor| product.checked_mul(*factor))
+    }
+    let u256 = U256::from;
+    let fee_denominator = u256(fee.denominator());
+    let fee_denominator_minus_one = fee_denominator.checked_sub(U256::one())?;
+
+    // z sells the fee token for `a` and y sells `b` for the fee token, round
+    // the prices in their favour so that their limit prices are respected.
+    let price_a = product(&[
+        u256(BASE_PRICE),
+        u256(z.sell_amount),
+        fee_denominator_minus_one,
+    ])?
+    .checked_div(product(&[fee_denominator, u256(z.buy_amount)])?)?
+    .as_u128_checked()?;
+    let price_b_divisor = product(&[fee_denominator_minus_one, u256(y.sell_amount)])?;
+    let price_b = product(&[u256(BASE_PRICE), u256(y.buy_amount), fee_denominator])?
+        .checked_add(price_b_divisor.checked_sub(U256::one())?)?
+        .checked_div(price_b_divisor)?
+        .as_u128_checked()?;
+    if price_a == 0
+        || price_b == 0
+        || product(&[u256(x.buy_amount), u256(price_b), fee_denominator])?
+            > product(&[
+                u256(x.sell_amount),
+                u256(price_a),
+                fee_denominator_minus_one,
+            ])?
+    {
+        return None;
+    }
+
+    // The executed amount of `b` is limited by the sell amounts of all three
+    // orders, as the other volumes are derived from it.
+    let volume_b = [
+        u256(y.sell_amount),
+        product(&[
+            u256(x.sell_amount),
+            fee_denominator_minus_one,
+            u256(price_a),
+        ])?
+        .checked_div(product(&[fee_denominator, u256(price_b)])?)?,
+        product(&[
+            u256(z.sell_amount),
+            fee_denominator_minus_one,
+            fee_denominator_minus_one,
+            u256(BASE_PRICE),
+        ])?
+        .checked_div(product(&[fee_denominator, fee_denominator, u256(price_b)])?)?,
+    ]
+    .iter()
+    .min()
+    .copied()?
+    .as_u128();
+    let y_buy_amount = executed_buy_amount(fee, volume_b, BASE_PRICE, price_b)?;
+    let volume_a = executed_sell_amount(fee, volume_b, price_b, price_a)?;
+    let z_sell_amount = executed_sell_amount(fee, volume_a, price_a, BASE_PRICE)?;
+    if volume_a == 0 || y_buy_amount == 0 {
+        return None;
+    }
+
+    let executed_order = |order: &Order, sell_amount, buy_amount| ExecutedOrder {
+        account_id: order.account_id,
+        order_id: order.id,
+        sell_amount,
+        buy_amount,
+    };
+    Some(Solution {
+        prices: hash_map! {
+            fee.token() => BASE_PRICE,
+            x.sell_token => price_a,
+            x.buy_token => price_b,
+        },
+        executed_orders: vec![
+            executed_order(x, volume_a, volume_b),
+            executed_order(y, volume_b, y_buy_amount),
+            executed_order(z, z_sell_amount, volume_a),
+        ],
+        solver_name: None,
+    })
+}
+
+/// Explains why the referenced order is or isn't matched with each of the
+/// other orders of a batch, re-running the matching of the naive solver for
+/// just that order.
+pub fn explain_order_matches(
+    order: OrderReference,
+    orders: &[Order],
+    state: &AccountState,
+    fee: &Option<Fee>,
+) -> Result<Vec<(Order, MatchOutcome)>> {
+    let order = orders
+        .iter()
+        .find(|o| o.account_id == order.account_id && o.id == order.id)
+        .ok_or_else(|| {
+            anyhow!(
+                "order {} of {:?} is not part of the batch",
+                order.id,
+                order.account_id
+            )
+        })?;
+    Ok(orders
+        .iter()
+        .filter(|other| !std::ptr::eq(*other, order))
+        .map(|other| {
+            let outcome = match order.match_compare(other, state, fee) {
+                Ok(_) if order.account_id == other.account_id => MatchOutcome::SelfTrade,
+                Ok(_) => MatchOutcome::Matched,
+                Err(reason) => MatchOutcome::Rejected(reason),
+            };
+            (other.clone(), outcome)
+        })
+        .collect())
+}
+
 fn create_executed_orders(first_match: &Match, fee: &Option<Fee>) -> (ExecutedOrderPair, PriceMap) {
     fn create_executed_order(order: &Order, sell_amount: u128, buy_amount: u128) -> ExecutedOrder {
         ExecutedOrder {
@@ -200,29 +668,32 @@ fn create_executed_orders(first_match: &Match, fee: &Option<Fee>) -> (ExecutedOr
 fn create_solution_with_fee(
     orders: &OrderPair,
     fee: &Fee,
-    mut executed_orders: ExecutedOrderPair,
-    mut prices: PriceMap,
+    executed_orders: ExecutedOrderPair,
+    prices: PriceMap,
 ) -> Solution {
+    let mut solution = Solution {
+        prices,
+        executed_orders: executed_orders.to_vec(),
+        solver_name: None,
+    };
     // normalize prices so fee token price is BASE_PRICE
-    let pre_normalized_fee_price = prices.get(&fee.token).copied().unwrap_or(0);
-    if pre_normalized_fee_price == 0 {
+    if solution.normalize_to(fee.token()).is_err() {
         return Solution::trivial();
     }
-    for price in prices.values_mut() {
-        *price = match normalize_price(*price, pre_normalized_fee_price) {
-            Some(price) => price,
-            None => return Solution::trivial(),
-        };
-    }
 
     // apply fee to volumes account for rounding errors, moving them to
     // the fee token
+    let prices = &solution.prices;
     for (i, order) in orders.iter().enumerate() {
-        let executed_order = &mut executed_orders[i];
-        if order.sell_token == fee.token {
+        let executed_order = &mut solution.executed_orders[i];
+        if order.sell_token == fee.token() {
             let price_buy = prices[&order.buy_token];
-            executed_order.sell_amount =
+            let exec_sell_amt =
                 executed_sell_amount(fee, executed_order.buy_amount, price_buy, BASE_PRICE);
+            executed_order.sell_amount = match exec_sell_amt {
+                Some(exec_sell_amt) => exec_sell_amt,
+                None => return Solution::trivial(),
+            };
         } else {
             let price_sell = prices[&order.sell_token];
             executed_order.buy_amount = match executed_buy_amount(
@@ -237,10 +708,7 @@ fn create_solution_with_fee(
         }
     }
 
-    Solution {
-        prices,
-        executed_orders: executed_orders.to_vec(),
-    }
+    solution
 }
 
 fn order_with_buffer_for_fee(order: &Order, fee: &Option<Fee>) -> Order {
@@ -250,12 +718,18 @@ fn order_with_buffer_for_fee(order: &Order, fee: &Option<Fee>) -> Order {
             // In order to make space for a fee in the existing limit, we need to
             // a) receive more stuff (while giving away the same)
             // b) give away less stuff (while receiving the same)
-            let fee_denominator = (1.0 / fee.ratio) as u128;
-            if fee.token == order.buy_token {
-                order.buy_amount =
-                    (order.buy_amount * fee_denominator).ceiled_div(fee_denominator - 1)
-            } else if fee.token == order.sell_token {
-                order.sell_amount = order.sell_amount * (fee_denominator - 1) / fee_denominator
+            // upcast to u256 to avoid overflows, an order whose buy amount
+            // doesn't fit anymore is capped to the maximum buy amount
+            let fee_denominator = U256::from(fee.denominator());
+            if fee.token() == order.buy_token {
+                order.buy_amount = (U256::from(order.buy_amount) * fee_denominator)
+                    .ceiled_div(fee_denominator - 1)
+                    .as_u128_checked()
+                    .unwrap_or_else(u128::max_value)
+            } else if fee.token() == order.sell_token {
+                order.sell_amount = (U256::from(order.sell_amount) * (fee_denominator - 1)
+                    / fee_denominator)
+                    .low_u128()
             }
             order
         }
@@ -263,47 +737,54 @@ fn order_with_buffer_for_fee(order: &Order, fee: &Option<Fee>) -> Order {
     }
 }
 
-/// Normalizes a price base on the pre-normalized fee price.
-fn normalize_price(price: u128, pre_normalized_fee_price: u128) -> Option<u128> {
-    // upcast to u256 to avoid overflows
-    (U256::from(price) * U256::from(BASE_PRICE))
-        .ceiled_div(U256::from(pre_normalized_fee_price))
-        .as_u128_checked()
-}
-
 /// Calculate the executed sell amount from the fee, executed buy amount, and
-/// the buy and sell prices of the traded tokens.
-fn executed_sell_amount(fee: &Fee, exec_buy_amt: u128, buy_price: u128, sell_price: u128) -> u128 {
-    let fee_denominator = (1.0 / fee.ratio) as u128;
-    ((((U256::from(exec_buy_amt) * U256::from(buy_price)) / U256::from(fee_denominator - 1))
-        * U256::from(fee_denominator))
-        / U256::from(sell_price))
-    .as_u128()
+/// the buy and sell prices of the traded tokens. This function returns `None`
+/// if the executed sell amount can't be represented, i.e. if it overflows or
+/// the sell price is zero.
+fn executed_sell_amount(
+    fee: &Fee,
+    exec_buy_amt: u128,
+    buy_price: u128,
+    sell_price: u128,
+) -> Option<u128> {
+    let fee_denominator = U256::from(fee.denominator());
+    (U256::from(exec_buy_amt) * U256::from(buy_price))
+        .checked_div(fee_denominator.checked_sub(U256::one())?)?
+        .checked_mul(fee_denominator)?
+        .checked_div(U256::from(sell_price))?
+        .as_u128_checked()
 }
 
 /// Calculate the executed buy amount from the fee, executed sell amount, and
 /// the buy and sell prices of the traded tokens. This function returns a `None`
 /// if no value can be found such that `executed_sell_amount(result, pb, ps) ==
-/// xs`. This function acts as an inverse to `executed_sell_amount`.
+/// xs`. This function acts as an inverse to `executed_sell_amount`. It also
+/// returns `None` if the executed buy amount overflows or the buy price is
+/// zero.
 fn executed_buy_amount(
     fee: &Fee,
     exec_sell_amt: u128,
     buy_price: u128,
     sell_price: u128,
 ) -> Option<u128> {
-    let fee_denominator = (1.0 / fee.ratio) as u128;
-    let exec_buy_amt = (((U256::from(exec_sell_amt) * U256::from(sell_price))
-        / U256::from(fee_denominator))
-        * U256::from(fee_denominator - 1))
-    .ceiled_div(U256::from(buy_price))
-    .as_u128();
+    let fee_denominator = U256::from(fee.denominator());
+    if buy_price == 0 || fee_denominator.is_zero() {
+        return None;
+    }
+    // NOTE: This can't overflow as the product of two `u128` is at most
+    //   `U256::MAX - 2^129 + 2`, and neither the factor `(d - 1) / d` nor the
+    //   rounding up can push it above `U256::MAX`.
+    let exec_buy_amt = ((U256::from(exec_sell_amt) * U256::from(sell_price)) / fee_denominator
+        * (fee_denominator - 1))
+        .ceiled_div(U256::from(buy_price))
+        .as_u128_checked()?;
 
     // we need to account for rounding errors here, since this function is
     // essentially an inverse of `executed_sell_amount`; when the buy price is
     // higher than the sell price, there are executed sell amounts that cannot
     // be satisfied, check the executed buy amount correctly "round trips" to
     // the specified executed sell amount and return `None` if it doesn't
-    if exec_sell_amt == executed_sell_amount(fee, exec_buy_amt, buy_price, sell_price) {
+    if Some(exec_sell_amt) == executed_sell_amount(fee, exec_buy_amt, buy_price, sell_price) {
         Some(exec_buy_amt)
     } else {
         None
@@ -313,10 +794,12 @@ fn executed_buy_amount(
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::logging::test_util::capture_logs;
     use crate::models::order::test_util::order_to_executed_order;
     use crate::models::AccountState;
 
     use ethcontract::{Address, U256};
+    use log::Level;
     use std::collections::HashMap;
 
     #[test]
@@ -324,20 +807,23 @@ pub mod tests {
         let orders = order_pair_first_fully_matching_second();
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
 
+        assert_eq!(res.executed_orders.len(), 2);
         assert_eq!(
-            vec![
-                order_to_executed_order(&orders[0], 52 * BASE_UNIT, 4 * BASE_UNIT),
-                order_to_executed_order(&orders[1], 4 * BASE_UNIT, 52 * BASE_UNIT),
-            ],
-            res.executed_orders
+            executed_order_of(&res, &orders[0]),
+            &order_to_executed_order(&orders[0], 52 * BASE_UNIT, 4 * BASE_UNIT),
+        );
+        assert_eq!(
+            executed_order_of(&res, &orders[1]),
+            &order_to_executed_order(&orders[1], 4 * BASE_UNIT, 52 * BASE_UNIT),
         );
         assert_eq!(4 * BASE_UNIT, res.prices[&0]);
         assert_eq!(52 * BASE_UNIT, res.prices[&1]);
+        assert_eq!(res.solver_name.as_deref(), Some("naive-solver"));
 
         check_solution(&orders, res, &None).unwrap();
     }
@@ -346,12 +832,9 @@ pub mod tests {
     fn test_type_left_fully_matched_with_fee() {
         let orders = order_pair_first_fully_matching_second();
         let state = AccountState::with_balance_for(&orders);
-        let fee = Some(Fee {
-            token: 0,
-            ratio: 0.001,
-        });
+        let fee = Some(Fee::new(0, 0.001).unwrap());
 
-        let solver = NaiveSolver::new(fee.clone());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -364,7 +847,7 @@ pub mod tests {
         orders.reverse();
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -387,12 +870,9 @@ pub mod tests {
         let mut orders = order_pair_first_fully_matching_second();
         orders.reverse();
         let state = AccountState::with_balance_for(&orders);
-        let fee = Some(Fee {
-            token: 0,
-            ratio: 0.001,
-        });
+        let fee = Some(Fee::new(0, 0.001).unwrap());
 
-        let solver = NaiveSolver::new(fee.clone());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -404,7 +884,7 @@ pub mod tests {
         let orders = order_pair_both_fully_matched();
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -426,72 +906,75 @@ pub mod tests {
     fn test_type_both_fully_matched_with_fee() {
         let orders = order_pair_both_fully_matched();
         let state = AccountState::with_balance_for(&orders);
-        let fee = Some(Fee {
-            token: 2,
-            ratio: 0.001,
-        });
-        let solver = NaiveSolver::new(fee.clone());
+        let fee = Some(Fee::new(2, 0.001).unwrap());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
         check_solution(&orders, res, &fee).unwrap();
     }
 
+    #[test]
+    fn test_integer_fee_denominator_matches_ratio() {
+        let orders = order_pair_first_fully_matching_second();
+        let state = AccountState::with_balance_for(&orders);
+        for &denominator in &[1000, 500, 400, 250, 200, 100] {
+            let ratio_fee = Some(Fee::new(0, 1.0 / denominator as f64).unwrap());
+            let denominator_fee = Some(Fee::from_denominator(0, denominator).unwrap());
+            assert_eq!(ratio_fee.as_ref().unwrap().denominator(), denominator);
+
+            let ratio_solution = NaiveSolver::new(ratio_fee, ComponentMetrics::default())
+                .find_prices(&orders, &state, Duration::default())
+                .unwrap();
+            let denominator_solution =
+                NaiveSolver::new(denominator_fee.clone(), ComponentMetrics::default())
+                    .find_prices(&orders, &state, Duration::default())
+                    .unwrap();
+            assert!(denominator_solution.is_non_trivial());
+            assert_eq!(ratio_solution, denominator_solution);
+            check_solution(&orders, denominator_solution, &denominator_fee).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_integer_fee_denominator_avoids_truncation() {
+        // 1 / (1 / 99) is slightly less than 99 in floating point arithmetic.
+        assert_eq!(Fee::new(0, 1.0 / 99.0).unwrap().denominator(), 98);
+        assert_eq!(Fee::from_denominator(0, 99).unwrap().denominator(), 99);
+
+        let orders = order_pair_first_fully_matching_second();
+        let state = AccountState::with_balance_for(&orders);
+        let fee = Some(Fee::from_denominator(0, 99).unwrap());
+        let solution = NaiveSolver::new(fee.clone(), ComponentMetrics::default())
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert!(solution.is_non_trivial());
+
+        // The executed sell amount of the order selling the fee token is
+        // computed with the exact denominator and not the truncated one.
+        assert_eq!(orders[0].sell_token, 0);
+        let executed_order = executed_order_of(&solution, &orders[0]);
+        let buy_price = solution.prices[&1];
+        assert_eq!(
+            executed_order.sell_amount,
+            executed_order.buy_amount * buy_price / 98 * 99 / BASE_PRICE
+        );
+        check_solution(&orders, solution, &fee).unwrap();
+    }
+
     #[test]
     fn test_retreth_example() {
         let orders = vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 3,
-                buy_token: 2,
-                sell_amount: 12,
-                buy_amount: 12,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 2,
-                buy_token: 3,
-                sell_amount: 20,
-                buy_amount: 22,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(2),
-                sell_token: 3,
-                buy_token: 1,
-                sell_amount: 10,
-                buy_amount: 150,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(3),
-                sell_token: 2,
-                buy_token: 1,
-                sell_amount: 15,
-                buy_amount: 180,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(4),
-                sell_token: 1,
-                buy_token: 2,
-                sell_amount: 52,
-                buy_amount: 4,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(5),
-                sell_token: 1,
-                buy_token: 3,
-                sell_amount: 280,
-                buy_amount: 20,
-            },
+            order(0, 3, 2, 12, 12),
+            order(1, 2, 3, 20, 22),
+            order(2, 3, 1, 10, 150),
+            order(3, 2, 1, 15, 180),
+            order(4, 1, 2, 52, 4),
+            order(5, 1, 3, 280, 20),
         ];
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -513,26 +996,9 @@ pub mod tests {
     fn test_insufficient_balance() {
         const NUM_TOKENS: u16 = 10;
         let state = AccountState::new(vec![0; (NUM_TOKENS * 2) as usize], NUM_TOKENS);
-        let orders = vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 1,
-                buy_token: 2,
-                sell_amount: 52,
-                buy_amount: 4,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 2,
-                buy_token: 1,
-                sell_amount: 15,
-                buy_amount: 180,
-            },
-        ];
+        let orders = vec![order(1, 1, 2, 52, 4), order(0, 2, 1, 15, 180)];
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -541,27 +1007,10 @@ pub mod tests {
 
     #[test]
     fn test_no_matches() {
-        let orders = vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 1,
-                buy_token: 2,
-                sell_amount: 52,
-                buy_amount: 4,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 2,
-                buy_token: 1,
-                sell_amount: 10,
-                buy_amount: 180,
-            },
-        ];
+        let orders = vec![order(1, 1, 2, 52, 4), order(0, 2, 1, 10, 180)];
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -571,30 +1020,16 @@ pub mod tests {
     #[test]
     fn test_stablex_contract_example() {
         let orders = vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 20000,
-                buy_amount: 9990,
-            },
+            order(0, 0, 1, 20000, 9990),
             Order {
                 id: 1,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 9990,
-                buy_amount: 19960,
+                ..order(0, 1, 0, 9990, 19960)
             },
         ];
         let state = AccountState::with_balance_for(&orders);
 
-        let fee = Some(Fee {
-            token: 0,
-            ratio: 0.001,
-        });
-        let solver = NaiveSolver::new(fee.clone());
+        let fee = Some(Fee::new(0, 0.001).unwrap());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -612,8 +1047,9 @@ pub mod tests {
         check_solution(&orders, res, &fee).unwrap();
     }
 
-    #[test]
-    fn stablex_e2e_auction() {
+    /// Returns the account state and orders of the auction that is settled in
+    /// the end-to-end tests of the exchange.
+    pub fn stablex_e2e_auction_data() -> (AccountState, Vec<Order>) {
         let users = [Address::from_low_u64_be(0), Address::from_low_u64_be(1)];
         let state = {
             let mut state = AccountState::default();
@@ -622,29 +1058,18 @@ pub mod tests {
             state
         };
         let orders = vec![
-            Order {
-                id: 0,
-                account_id: users[0],
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 2000 * BASE_UNIT,
-                buy_amount: 999 * BASE_UNIT,
-            },
-            Order {
-                id: 0,
-                account_id: users[1],
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 999 * BASE_UNIT,
-                buy_amount: 1996 * BASE_UNIT,
-            },
+            order(0, 0, 1, 2000 * BASE_UNIT, 999 * BASE_UNIT),
+            order(1, 1, 0, 999 * BASE_UNIT, 1996 * BASE_UNIT),
         ];
+        (state, orders)
+    }
 
-        let fee = Some(Fee {
-            token: 0,
-            ratio: 0.001,
-        });
-        let solver = NaiveSolver::new(fee.clone());
+    #[test]
+    fn stablex_e2e_auction() {
+        let (state, orders) = stablex_e2e_auction_data();
+
+        let fee = Some(Fee::new(0, 0.001).unwrap());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -655,72 +1080,168 @@ pub mod tests {
 
     #[test]
     fn test_does_not_trade_non_fee_tokens() {
+        let orders = vec![order(0, 0, 1, 20000, 9990), order(0, 1, 0, 9990, 19960)];
+        let state = AccountState::with_balance_for(&orders);
+
+        let fee = Some(Fee::new(2, 0.001).unwrap());
+        let solver = NaiveSolver::new(fee, ComponentMetrics::default());
+        let res = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert!(!res.is_non_trivial());
+    }
+
+    #[test]
+    fn test_bridges_non_fee_token_pair_through_fee_token() {
         let orders = vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 20000,
-                buy_amount: 9990,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 9990,
-                buy_amount: 19960,
-            },
+            order(1, 1, 2, 10 * BASE_UNIT, 9 * BASE_UNIT),
+            order(2, 2, 0, 10 * BASE_UNIT, 11 * BASE_UNIT),
+            order(3, 0, 1, 10 * BASE_UNIT, 9 * BASE_UNIT),
         ];
         let state = AccountState::with_balance_for(&orders);
+        let fee = Some(Fee::new(0, 0.001).unwrap());
 
-        let fee = Some(Fee {
-            token: 2,
-            ratio: 0.001,
-        });
-        let solver = NaiveSolver::new(fee);
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
+        let res = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert!(!res.is_non_trivial());
+
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default())
+            .with_fee_token_bridging(true);
+        let res = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert!(res.is_non_trivial());
+        assert_eq!(res.executed_orders.len(), 3);
+        check_solution(&orders, res, &fee).unwrap();
+
+        // The ring doesn't leave enough room for the fees.
+        let orders = vec![
+            order(1, 1, 2, 10 * BASE_UNIT, 10 * BASE_UNIT),
+            order(2, 2, 0, 10 * BASE_UNIT, 10 * BASE_UNIT),
+            order(3, 0, 1, 10 * BASE_UNIT, 10 * BASE_UNIT),
+        ];
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
         assert!(!res.is_non_trivial());
     }
 
+    #[test]
+    fn test_bridged_match_does_not_depend_on_order_of_orders() {
+        let mut orders = vec![
+            order(4, 1, 2, 10 * BASE_UNIT, 9 * BASE_UNIT),
+            order(2, 2, 0, 10 * BASE_UNIT, 11 * BASE_UNIT),
+            order(3, 0, 1, 10 * BASE_UNIT, 9 * BASE_UNIT),
+            order(1, 1, 2, 10 * BASE_UNIT, 9 * BASE_UNIT),
+        ];
+        let state = AccountState::with_balance_for(&orders);
+        let solver = NaiveSolver::new(Some(Fee::default()), ComponentMetrics::default())
+            .with_fee_token_bridging(true);
+
+        let expected_accounts = [1, 2, 3]
+            .iter()
+            .map(|&account| Address::from_low_u64_be(account))
+            .collect::<HashSet<_>>();
+        for shuffle in 0..2 * orders.len() {
+            if shuffle == orders.len() {
+                orders.reverse();
+            }
+            orders.rotate_left(1);
+
+            let solution = solver
+                .find_prices(&orders, &state, Duration::default())
+                .unwrap();
+            let matched_accounts = solution
+                .executed_orders
+                .iter()
+                .map(|order| order.account_id)
+                .collect::<HashSet<_>>();
+            assert_eq!(matched_accounts, expected_accounts);
+        }
+    }
+
+    #[test]
+    fn test_bridged_match_with_extreme_amounts_does_not_panic() {
+        let fee = Fee::default();
+        let max = u128::max_value();
+        for &(x, y, z) in &[
+            ((max, 1), (0, 1), (max, 1)),
+            ((max, 1), (max, 1), (max, 1)),
+            ((1, max), (1, max), (1, max)),
+        ] {
+            let orders = [
+                order(1, 1, 2, x.0, x.1),
+                order(2, 2, 0, y.0, y.1),
+                order(3, 0, 1, z.0, z.1),
+            ];
+            create_bridged_solution([&orders[0], &orders[1], &orders[2]], &fee);
+        }
+    }
+
+    #[test]
+    fn test_price_overlap_with_max_amounts() {
+        let max = u128::max_value();
+
+        let x = order(0, 0, 1, max, max);
+        assert!(x.have_price_overlap(&order(0, 1, 0, max, max)));
+        assert!(x.have_price_overlap(&order(0, 1, 0, max, max - 1)));
+        assert!(!x.have_price_overlap(&order(0, 1, 0, max - 1, max)));
+        assert!(!x.have_price_overlap(&order(0, 1, 0, 1, max)));
+        assert!(x.have_price_overlap(&order(0, 1, 0, max, 1)));
+        assert!(!x.have_price_overlap(&order(0, 1, 0, 0, 0)));
+    }
+
+    #[test]
+    fn test_executed_amounts_handle_overflow() {
+        let fee = Fee::default();
+        let max = u128::max_value();
+
+        assert_eq!(executed_sell_amount(&fee, max, max, 1), None);
+        assert_eq!(
+            executed_sell_amount(&fee, max, BASE_PRICE, BASE_PRICE / 2),
+            None
+        );
+        assert_eq!(executed_sell_amount(&fee, 1, BASE_PRICE, 0), None);
+        assert_eq!(executed_sell_amount(&fee, 999, 1, 1), Some(1000));
+
+        assert_eq!(executed_buy_amount(&fee, max, 1, max), None);
+        assert_eq!(executed_buy_amount(&fee, 1, 0, BASE_PRICE), None);
+        assert_eq!(executed_buy_amount(&fee, 1000, 1, 1), Some(999));
+    }
+
+    #[test]
+    fn test_extreme_orders_do_not_panic() {
+        let max = u128::max_value();
+        let orders = vec![order(0, 0, 1, max, 1), order(1, 1, 0, max, max)];
+        let state = AccountState::with_balance_for(&orders);
+        for fee in &[None, Some(Fee::default())] {
+            let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
+            assert!(solver
+                .find_prices(&orders, &state, Duration::default())
+                .is_ok());
+        }
+    }
+
     #[test]
     fn test_empty_orders() {
         let orders: Vec<Order> = vec![];
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
-        assert_eq!(res, Solution::trivial());
+        assert_eq!(res, Solution::trivial().with_solver_name("naive-solver"));
     }
 
     #[test]
     fn test_empty_sell_volume() {
-        let orders = vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 0,
-                buy_amount: 0,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 0,
-                buy_amount: 0,
-            },
-        ];
+        let orders = vec![order(0, 0, 1, 0, 0), order(0, 1, 0, 0, 0)];
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(None);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -729,39 +1250,21 @@ pub mod tests {
 
     #[test]
     fn test_match_and_unmatchable_order() {
-        let fee = Some(Fee {
-            token: 0,
-            ratio: 0.5,
-        });
+        let fee = Some(Fee::new(0, 0.5).unwrap());
         let orders = [
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 20 * BASE_UNIT,
-                buy_amount: 10 * BASE_UNIT,
-            },
+            order(0, 0, 1, 20 * BASE_UNIT, 10 * BASE_UNIT),
             Order {
                 id: 1,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 10 * BASE_UNIT,
-                buy_amount: 5 * BASE_UNIT,
+                ..order(1, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT)
             },
             Order {
                 id: 2,
-                account_id: Address::from_low_u64_be(2),
-                sell_token: 0,
-                buy_token: 2,
-                sell_amount: BASE_UNIT,
-                buy_amount: BASE_UNIT,
+                ..order(2, 0, 2, BASE_UNIT, BASE_UNIT)
             },
         ];
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(fee.clone());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
@@ -770,96 +1273,415 @@ pub mod tests {
 
     #[test]
     fn test_multiple_matches() {
-        let fee = Some(Fee {
-            token: 0,
-            ratio: 0.5,
-        });
+        let fee = Some(Fee::new(0, 0.5).unwrap());
         let orders = [
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 20 * BASE_UNIT,
-                buy_amount: 10 * BASE_UNIT,
-            },
+            order(0, 0, 1, 20 * BASE_UNIT, 10 * BASE_UNIT),
             Order {
                 id: 1,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 10 * BASE_UNIT,
-                buy_amount: 5 * BASE_UNIT,
+                ..order(1, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT)
             },
             Order {
                 id: 2,
-                account_id: Address::from_low_u64_be(2),
-                sell_token: 0,
-                buy_token: 2,
-                sell_amount: 20 * BASE_UNIT,
-                buy_amount: 10 * BASE_UNIT,
+                ..order(2, 0, 2, 20 * BASE_UNIT, 10 * BASE_UNIT)
             },
             Order {
                 id: 3,
-                account_id: Address::from_low_u64_be(3),
-                sell_token: 2,
-                buy_token: 0,
-                sell_amount: 10 * BASE_UNIT,
-                buy_amount: 5 * BASE_UNIT,
+                ..order(3, 2, 0, 10 * BASE_UNIT, 5 * BASE_UNIT)
             },
         ];
         let state = AccountState::with_balance_for(&orders);
 
-        let solver = NaiveSolver::new(fee.clone());
+        let solver = NaiveSolver::new(fee.clone(), ComponentMetrics::default());
         let res = solver
             .find_prices(&orders, &state, Duration::default())
             .unwrap();
         check_solution(&orders, res, &fee).unwrap();
     }
 
+    #[test]
+    fn test_match_rejection_reasons() {
+        let fee = Some(Fee::new(0, 0.001).unwrap());
+
+        let orders = [order(0, 0, 1, 20, 10), order(0, 1, 0, 10, 20)];
+        let state = AccountState::new(vec![0; 2], 2);
+        assert_eq!(
+            orders[0].match_compare(&orders[1], &state, &fee).err(),
+            Some(MatchRejection::InsufficientFunds)
+        );
+
+        let orders = [order(0, 1, 2, 20, 10), order(0, 2, 1, 10, 20)];
+        let state = AccountState::with_balance_for(&orders);
+        assert_eq!(
+            orders[0].match_compare(&orders[1], &state, &fee).err(),
+            Some(MatchRejection::FeeTokenNotTraded)
+        );
+
+        let orders = [order(0, 0, 1, 20, 10), order(0, 0, 2, 10, 20)];
+        let state = AccountState::with_balance_for(&orders);
+        assert_eq!(
+            orders[0].match_compare(&orders[1], &state, &fee).err(),
+            Some(MatchRejection::TokensNotOpposite)
+        );
+
+        let orders = [order(0, 0, 1, 10, 20), order(0, 1, 0, 10, 20)];
+        let state = AccountState::with_balance_for(&orders);
+        assert_eq!(
+            orders[0].match_compare(&orders[1], &state, &fee).err(),
+            Some(MatchRejection::NoPriceOverlap)
+        );
+    }
+
+    #[test]
+    fn test_rejected_matches_are_logged_with_reason() {
+        let fee = Some(Fee::new(0, 0.001).unwrap());
+        let solver =
+            NaiveSolver::new(fee, ComponentMetrics::default()).with_rejected_match_logging(true);
+
+        for (orders, funded, reason) in vec![
+            (
+                [order(1, 0, 1, 20, 10), order(2, 1, 0, 10, 20)],
+                false,
+                MatchRejection::InsufficientFunds,
+            ),
+            (
+                [order(1, 1, 2, 20, 10), order(2, 2, 1, 10, 20)],
+                true,
+                MatchRejection::FeeTokenNotTraded,
+            ),
+            (
+                [order(1, 0, 1, 20, 10), order(2, 0, 2, 10, 20)],
+                true,
+                MatchRejection::TokensNotOpposite,
+            ),
+            (
+                [order(1, 0, 1, 10, 20), order(2, 1, 0, 10, 20)],
+                true,
+                MatchRejection::NoPriceOverlap,
+            ),
+        ] {
+            let state = if funded {
+                AccountState::with_balance_for(&orders)
+            } else {
+                AccountState::default()
+            };
+            let logs = capture_logs(Level::Trace, || {
+                let solution = solver
+                    .find_prices(&orders, &state, Duration::default())
+                    .unwrap();
+                assert!(!solution.is_non_trivial());
+            });
+            assert_eq!(
+                logs,
+                vec![format!(
+                    "rejected match of order 0 of {:?} with order 0 of {:?}: {}",
+                    orders[0].account_id, orders[1].account_id, reason,
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejected_matches_are_not_logged_by_default() {
+        let orders = [order(1, 0, 1, 10, 20), order(2, 1, 0, 10, 20)];
+        let state = AccountState::with_balance_for(&orders);
+
+        let logs = capture_logs(Level::Trace, || {
+            NaiveSolver::new(None, ComponentMetrics::default())
+                .find_prices(&orders, &state, Duration::default())
+                .unwrap();
+        });
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn test_rejected_match_logging_does_not_change_solution() {
+        let orders = order_pair_first_fully_matching_second();
+        let state = AccountState::with_balance_for(&orders);
+
+        let solver =
+            NaiveSolver::new(None, ComponentMetrics::default()).with_rejected_match_logging(true);
+        let res = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        check_solution(&orders, res, &None).unwrap();
+    }
+
+    #[test]
+    fn first_match_does_not_depend_on_order_of_orders() {
+        let mut orders = vec![
+            order(4, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(2, 0, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(3, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(1, 0, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+        ];
+        let state = AccountState::with_balance_for(&orders);
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
+
+        let expected_accounts = vec![Address::from_low_u64_be(1), Address::from_low_u64_be(3)];
+        for shuffle in 0..2 * orders.len() {
+            if shuffle == orders.len() {
+                orders.reverse();
+            }
+            orders.rotate_left(1);
+
+            let solution = solver
+                .find_prices(&orders, &state, Duration::default())
+                .unwrap();
+            let matched_accounts = solution
+                .executed_orders
+                .iter()
+                .map(|order| order.account_id)
+                .collect::<Vec<_>>();
+            assert_eq!(matched_accounts, expected_accounts);
+        }
+    }
+
+    #[test]
+    fn only_matches_orders_of_allowed_accounts() {
+        let orders = vec![
+            order(1, 0, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(2, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(3, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(4, 0, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+        ];
+        let state = AccountState::with_balance_for(&orders);
+        let accounts = |accounts: &[u64]| {
+            accounts
+                .iter()
+                .map(|account| Address::from_low_u64_be(*account))
+                .collect::<HashSet<_>>()
+        };
+        let matched_accounts = |solution: Solution| {
+            solution
+                .executed_orders
+                .iter()
+                .map(|order| order.account_id)
+                .collect::<HashSet<_>>()
+        };
+
+        let solver = NaiveSolver::new(None, ComponentMetrics::default());
+        let solution = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert_eq!(matched_accounts(solution), accounts(&[1, 2]));
+
+        let solver = NaiveSolver::new(None, ComponentMetrics::default())
+            .with_allowed_accounts(Some(accounts(&[3, 4, 5])));
+        let solution = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert_eq!(matched_accounts(solution), accounts(&[3, 4]));
+
+        let solver = NaiveSolver::new(None, ComponentMetrics::default())
+            .with_allowed_accounts(Some(accounts(&[1, 4])));
+        let solution = solver
+            .find_prices(&orders, &state, Duration::default())
+            .unwrap();
+        assert!(solution.executed_orders.is_empty());
+    }
+
+    #[test]
+    fn returns_reason_for_trivial_solution() {
+        let solver = NaiveSolver::new(Some(Fee::default()), ComponentMetrics::default());
+        let find_reason = |orders: &[Order], state: &AccountState| {
+            let (solution, reason) = solver.find_prices_with_reason(orders, state).unwrap();
+            assert!(!solution.is_non_trivial());
+            reason.unwrap()
+        };
+
+        let reason = find_reason(&[], &AccountState::default());
+        assert_eq!(reason, TrivialSolutionReason::default());
+
+        let orders = [
+            order(0, 0, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(1, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),
+        ];
+        let reason = find_reason(&orders, &AccountState::default());
+        assert_eq!(reason.rejected_pairs(MatchRejection::InsufficientFunds), 1);
+
+        let orders = [
+            order(0, 0, 1, 10 * BASE_UNIT, 20 * BASE_UNIT),
+            order(1, 1, 0, 10 * BASE_UNIT, 20 * BASE_UNIT),
+        ];
+        let reason = find_reason(&orders, &AccountState::with_balance_for(&orders));
+        assert_eq!(reason.rejected_pairs(MatchRejection::NoPriceOverlap), 1);
+
+        let orders = [
+            order(0, 1, 2, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(1, 2, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+        ];
+        let reason = find_reason(&orders, &AccountState::with_balance_for(&orders));
+        assert_eq!(reason.rejected_pairs(MatchRejection::FeeTokenNotTraded), 1);
+
+        let orders = [
+            order(0, 1, 2, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(1, 0, 1, 10 * BASE_UNIT, 5 * BASE_UNIT),
+            order(2, 0, 2, 10 * BASE_UNIT, 5 * BASE_UNIT),
+        ];
+        let reason = find_reason(&orders, &AccountState::with_balance_for(&orders));
+        assert_eq!(reason.rejected_pairs(MatchRejection::FeeTokenNotTraded), 2);
+        assert_eq!(reason.rejected_pairs(MatchRejection::TokensNotOpposite), 1);
+        assert_eq!(reason.rejected_pairs(MatchRejection::NoPriceOverlap), 0);
+    }
+
+    #[test]
+    fn trivial_solution_reason_includes_filtered_orders_and_unsettled_matches() {
+        let orders = order_pair_first_fully_matching_second();
+        let state = AccountState::with_balance_for(&orders);
+
+        let solver = NaiveSolver::new(None, ComponentMetrics::default())
+            .with_allowed_accounts(Some(HashSet::new()));
+        let (solution, reason) = solver.find_prices_with_reason(&orders, &state).unwrap();
+        assert!(!solution.is_non_trivial());
+        let reason = reason.unwrap();
+        assert_eq!(reason.filtered_orders, 2);
+        assert_eq!(
+            reason.to_string(),
+            "ignored 2 orders of accounts that are not allowed, not enough orders to match"
+        );
+
+        // NOTE: The orders match, but the price of token 1 overflows when
+        //   normalizing the prices to the fee token.
+        let orders = [
+            order(0, 1, 0, 1, 10u128.pow(30)),
+            order(1, 0, 1, 2 * 10u128.pow(30), 1),
+        ];
+        let state = AccountState::with_balance_for(&orders);
+        let solver = NaiveSolver::new(Some(Fee::default()), ComponentMetrics::default());
+        let (solution, reason) = solver.find_prices_with_reason(&orders, &state).unwrap();
+        assert!(!solution.is_non_trivial());
+        let reason = reason.unwrap();
+        assert!(reason.unsettled_match);
+        assert_eq!(
+            reason.to_string(),
+            "found a match that could not be settled"
+        );
+    }
+
+    #[test]
+    fn matched_solution_has_no_trivial_solution_reason() {
+        let orders = order_pair_first_fully_matching_second();
+        let state = AccountState::with_balance_for(&orders);
+
+        let (solution, reason) = NaiveSolver::new(None, ComponentMetrics::default())
+            .find_prices_with_reason(&orders, &state)
+            .unwrap();
+        assert!(solution.is_non_trivial());
+        assert_eq!(reason, None);
+    }
+
+    #[test]
+    fn explains_unmatchable_order() {
+        let orders = vec![
+            order(1, 0, 1, 10 * BASE_UNIT, 10 * BASE_UNIT), // the order to explain
+            order(2, 1, 0, 10 * BASE_UNIT, 20 * BASE_UNIT), // asks for a too high price
+            order(6, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),  // seller has no balance
+            order(3, 2, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),  // sells a different token
+            Order {
+                id: 1,
+                ..order(1, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT)
+            }, // placed by the same account
+            order(4, 1, 0, 10 * BASE_UNIT, 5 * BASE_UNIT),  // matches
+            order(5, 2, 3, 10 * BASE_UNIT, 5 * BASE_UNIT),  // does not trade the fee token
+        ];
+        let funded_orders = orders
+            .iter()
+            .filter(|order| order.account_id != Address::from_low_u64_be(6))
+            .cloned()
+            .collect::<Vec<_>>();
+        let state = AccountState::with_balance_for(&funded_orders);
+        let fee = Some(Fee::default());
+
+        let order_ref = "0x0000000000000000000000000000000000000001:0"
+            .parse::<OrderReference>()
+            .unwrap();
+        let explanation = explain_order_matches(order_ref, &orders, &state, &fee).unwrap();
+        assert_eq!(
+            explanation
+                .iter()
+                .map(|(_, outcome)| *outcome)
+                .collect::<Vec<_>>(),
+            vec![
+                MatchOutcome::Rejected(MatchRejection::NoPriceOverlap),
+                MatchOutcome::Rejected(MatchRejection::InsufficientFunds),
+                MatchOutcome::Rejected(MatchRejection::TokensNotOpposite),
+                MatchOutcome::SelfTrade,
+                MatchOutcome::Matched,
+                MatchOutcome::Rejected(MatchRejection::TokensNotOpposite),
+            ]
+        );
+        assert_eq!(explanation[5].0, orders[6]);
+
+        let fee_token_ref = OrderReference {
+            account_id: Address::from_low_u64_be(5),
+            id: 0,
+        };
+        let explanation = explain_order_matches(fee_token_ref, &orders, &state, &fee).unwrap();
+        assert_eq!(
+            explanation[0],
+            (
+                orders[0].clone(),
+                MatchOutcome::Rejected(MatchRejection::FeeTokenNotTraded)
+            )
+        );
+
+        let unknown_ref = OrderReference {
+            account_id: Address::from_low_u64_be(1),
+            id: 42,
+        };
+        assert!(explain_order_matches(unknown_ref, &orders, &state, &fee).is_err());
+        assert!("0x01".parse::<OrderReference>().is_err());
+    }
+
+    /// Returns an order of the account with the specified low address bits
+    /// and an id of 0 that is always valid.
+    fn order(
+        account: u64,
+        sell_token: u16,
+        buy_token: u16,
+        sell_amount: u128,
+        buy_amount: u128,
+    ) -> Order {
+        Order::builder()
+            .account_id(Address::from_low_u64_be(account))
+            .sell_token(sell_token)
+            .buy_token(buy_token)
+            .sell_amount(sell_amount)
+            .buy_amount(buy_amount)
+            .build()
+    }
+
+    /// Returns the executed order of the solution for the specified order.
+    /// Executed orders are sorted by account, so they are looked up instead of
+    /// relying on their position in the solution.
+    fn executed_order_of<'a>(solution: &'a Solution, order: &Order) -> &'a ExecutedOrder {
+        solution
+            .executed_orders
+            .iter()
+            .find(|executed_order| {
+                executed_order.account_id == order.account_id && executed_order.order_id == order.id
+            })
+            .expect("order was not executed")
+    }
+
     fn order_pair_first_fully_matching_second() -> Vec<Order> {
         vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 0,
-                buy_token: 1,
-                sell_amount: 52 * BASE_UNIT,
-                buy_amount: 4 * BASE_UNIT,
-            },
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(0),
-                sell_token: 1,
-                buy_token: 0,
-                sell_amount: 15 * BASE_UNIT,
-                buy_amount: 180 * BASE_UNIT,
-            },
+            order(1, 0, 1, 52 * BASE_UNIT, 4 * BASE_UNIT),
+            order(0, 1, 0, 15 * BASE_UNIT, 180 * BASE_UNIT),
         ]
     }
 
     fn order_pair_both_fully_matched() -> Vec<Order> {
         vec![
-            Order {
-                id: 0,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 2,
-                buy_token: 1,
-                sell_amount: 10 * BASE_UNIT,
-                buy_amount: 10 * BASE_UNIT,
-            },
+            order(1, 2, 1, 10 * BASE_UNIT, 10 * BASE_UNIT),
             Order {
                 id: 1,
-                account_id: Address::from_low_u64_be(1),
-                sell_token: 1,
-                buy_token: 2,
-                sell_amount: 16 * BASE_UNIT,
-                buy_amount: 8 * BASE_UNIT,
+                ..order(1, 1, 2, 16 * BASE_UNIT, 8 * BASE_UNIT)
             },
         ]
     }
 
-    fn check_solution(
+    pub fn check_solution(
         orders: &[Order],
         solution: Solution,
         fee: &Option<Fee>,
@@ -869,7 +1691,7 @@ pub mod tests {
             return Ok(());
         }
 
-        if let Some(fee_token) = fee.as_ref().map(|fee| fee.token) {
+        if let Some(fee_token) = fee.as_ref().map(|fee| fee.token()) {
             if solution.price(fee_token).unwrap_or_default() != BASE_PRICE {
                 return Err(format!(
                     "price of fee token does not match the base price: {} != {}",
@@ -894,7 +1716,7 @@ pub mod tests {
                 .unwrap_or(0);
             let exec_sell_amount = if sell_token_price > 0 {
                 if let Some(fee) = fee {
-                    let fee_denominator = (1.0 / fee.ratio) as u128;
+                    let fee_denominator = fee.denominator();
                     // We compute:
                     // sell_amount_wo_fee = buy_amount * buy_token_price / sell_token_price
                     // sell_amount_w_fee = sell_amount_wo_fee * fee_denominator / (fee_denominator - 1)
@@ -933,7 +1755,7 @@ pub mod tests {
 
         for token_id in solution.prices.keys() {
             let balance = token_conservation.entry(*token_id).or_insert(0);
-            if *balance != 0 && (fee.is_none() || *token_id != fee.as_ref().unwrap().token) {
+            if *balance != 0 && (fee.is_none() || *token_id != fee.as_ref().unwrap().token()) {
                 return Err(format!(
                     "Token balance of token {} not 0 (was {})",
                     token_id, balance
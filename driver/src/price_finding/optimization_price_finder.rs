@@ -1,16 +1,18 @@
+use crate::metrics::ComponentMetrics;
 use crate::models::{self, TokenId, TokenInfo};
 use crate::price_estimation::PriceEstimating;
 use crate::price_finding::price_finder_interface::{Fee, PriceFinding, SolverType};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use chrono::Utc;
-use log::error;
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
 use serde_with::rust::display_fromstr;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::{create_dir_all, File};
 use std::io::{BufReader, BufWriter, Read, Write};
-use std::time::Duration;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 /// A number wrapper type that correctly serializes large u128`s to strings to
 /// avoid precision loss.
@@ -80,6 +82,7 @@ mod solver_output {
             Solution {
                 prices,
                 executed_orders,
+                solver_name: None,
             }
         }
     }
@@ -109,8 +112,8 @@ mod solver_input {
     impl From<&'_ price_finding::Fee> for Fee {
         fn from(fee: &price_finding::Fee) -> Self {
             Fee {
-                token: TokenId(fee.token),
-                ratio: fee.ratio,
+                token: TokenId(fee.token()),
+                ratio: fee.ratio(),
             }
         }
     }
@@ -174,12 +177,46 @@ trait Io {
     fn read_output(&self, result_folder: &str) -> std::io::Result<String>;
 }
 
+/// How to handle orders on tokens for which no price information is
+/// available, as the solver cannot validate the prices of such markets.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnpricedTokenPolicy {
+    /// Solve the batch including the unpriced tokens.
+    Ignore,
+    /// Do not solve the batch at all.
+    SkipBatch,
+    /// Only solve the markets where all tokens are priced, removing orders on
+    /// unpriced tokens from the batch.
+    PricedMarketsOnly,
+}
+
+impl Default for UnpricedTokenPolicy {
+    fn default() -> Self {
+        UnpricedTokenPolicy::Ignore
+    }
+}
+
+impl FromStr for UnpricedTokenPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "ignore" => Ok(UnpricedTokenPolicy::Ignore),
+            "skip-batch" => Ok(UnpricedTokenPolicy::SkipBatch),
+            "priced-markets-only" => Ok(UnpricedTokenPolicy::PricedMarketsOnly),
+            _ => Err(anyhow!("unknown unpriced token policy '{}'", value)),
+        }
+    }
+}
+
 pub struct OptimisationPriceFinder {
     io_methods: Box<dyn Io + Sync>,
     fee: Option<Fee>,
     solver_type: SolverType,
     price_oracle: Box<dyn PriceEstimating + Sync>,
     min_avg_fee_per_order: u128,
+    unpriced_token_policy: UnpricedTokenPolicy,
+    metrics: ComponentMetrics,
 }
 
 impl OptimisationPriceFinder {
@@ -188,6 +225,7 @@ impl OptimisationPriceFinder {
         solver_type: SolverType,
         price_oracle: impl PriceEstimating + Sync + 'static,
         min_avg_fee_per_order: u128,
+        metrics: ComponentMetrics,
     ) -> Self {
         OptimisationPriceFinder {
             io_methods: Box::new(DefaultIo),
@@ -195,8 +233,15 @@ impl OptimisationPriceFinder {
             solver_type,
             price_oracle: Box::new(price_oracle),
             min_avg_fee_per_order,
+            unpriced_token_policy: UnpricedTokenPolicy::default(),
+            metrics,
         }
     }
+
+    pub fn with_unpriced_token_policy(mut self, policy: UnpricedTokenPolicy) -> Self {
+        self.unpriced_token_policy = policy;
+        self
+    }
 }
 
 fn serialize_balances(
@@ -216,7 +261,7 @@ fn serialize_balances(
     accounts
 }
 
-fn deserialize_result(result: String) -> Result<models::Solution> {
+pub fn deserialize_result(result: String) -> Result<models::Solution> {
     let output: solver_output::Output = serde_json::from_str(&result)?;
     Ok(output.to_solution())
 }
@@ -228,8 +273,54 @@ impl PriceFinding for OptimisationPriceFinder {
         state: &models::AccountState,
         time_limit: Duration,
     ) -> Result<models::Solution> {
+        let start = Instant::now();
+        let result = self.solve(orders, state, time_limit);
+        self.metrics.price_finding(start.elapsed());
+        result
+    }
+}
+
+impl OptimisationPriceFinder {
+    /// Finds prices by running the optimization solver on the orders.
+    fn solve(
+        &self,
+        orders: &[models::Order],
+        state: &models::AccountState,
+        time_limit: Duration,
+    ) -> Result<models::Solution> {
+        let mut tokens = self.price_oracle.get_token_prices(&orders);
+        let unpriced_tokens = tokens
+            .iter()
+            .filter(|(_, info)| info.is_none())
+            .map(|(token_id, _)| token_id.0)
+            .collect::<HashSet<_>>();
+
+        let mut orders = orders.to_vec();
+        if !unpriced_tokens.is_empty() {
+            warn!("orderbook contains unpriced tokens {:?}", unpriced_tokens);
+            match self.unpriced_token_policy {
+                UnpricedTokenPolicy::Ignore => (),
+                UnpricedTokenPolicy::SkipBatch => {
+                    warn!("skipping batch because of unpriced tokens");
+                    return Ok(
+                        models::Solution::trivial().with_solver_name(self.solver_type.name())
+                    );
+                }
+                UnpricedTokenPolicy::PricedMarketsOnly => {
+                    orders.retain(|order| {
+                        !unpriced_tokens.contains(&order.buy_token)
+                            && !unpriced_tokens.contains(&order.sell_token)
+                    });
+                    tokens = tokens
+                        .into_iter()
+                        .filter(|(token_id, _)| !unpriced_tokens.contains(&token_id.0))
+                        .collect();
+                }
+            }
+        }
+
         let input = solver_input::Input {
-            tokens: self.price_oracle.get_token_prices(&orders),
+            tokens,
             ref_token: TokenId(0),
             accounts: serialize_balances(&state, &orders),
             orders: orders.iter().map(From::from).collect(),
@@ -276,7 +367,7 @@ impl PriceFinding for OptimisationPriceFinder {
             .read_output(&result_folder)
             .with_context(|| format!("error reading solver output from {}", &result_folder))?;
         let solution = deserialize_result(result).context("error deserializing solver output")?;
-        Ok(solution)
+        Ok(solution.with_solver_name(self.solver_type.name()))
     }
 }
 
@@ -325,12 +416,16 @@ impl Io for DefaultIo {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::metrics::component_metrics::test_util::metric_value;
+    use crate::models::order::test_util::create_order_for_test;
     use crate::models::AccountState;
     use crate::price_estimation::MockPriceEstimating;
     use crate::util::test_util::map_from_slice;
     use ethcontract::Address;
+    use prometheus::Registry;
     use serde_json::json;
     use std::collections::BTreeMap;
+    use std::sync::Arc;
 
     #[test]
     fn token_id_serialization() {
@@ -398,6 +493,7 @@ pub mod tests {
                     buy_amount: 95_042_777_139_162_480_000,
                 },
             ],
+            solver_name: None,
         };
 
         let solution = deserialize_result(json.to_string()).expect("Should not fail to parse");
@@ -542,6 +638,7 @@ pub mod tests {
                 buy_token: 2,
                 sell_amount: 100,
                 buy_amount: 200,
+                ..Default::default()
             },
             models::Order {
                 id: 0,
@@ -550,6 +647,7 @@ pub mod tests {
                 buy_token: 1,
                 sell_amount: 200,
                 buy_amount: 100,
+                ..Default::default()
             },
         ];
         let result = serialize_balances(&state, &orders);
@@ -569,10 +667,7 @@ pub mod tests {
 
     #[test]
     fn test_serialize_input_with_fee() {
-        let fee = Fee {
-            token: 0,
-            ratio: 0.001,
-        };
+        let fee = Fee::new(0, 0.001).unwrap();
 
         let mut price_oracle = MockPriceEstimating::new();
         price_oracle
@@ -611,6 +706,8 @@ pub mod tests {
             min_avg_fee_per_order: 0,
             solver_type: SolverType::StandardSolver,
             price_oracle: Box::new(price_oracle),
+            unpriced_token_policy: UnpricedTokenPolicy::Ignore,
+            metrics: ComponentMetrics::default(),
         };
         let orders = vec![];
         assert!(solver
@@ -622,6 +719,186 @@ pub mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_solution_is_attributed_to_solver() {
+        let mut price_oracle = MockPriceEstimating::new();
+        price_oracle
+            .expect_get_token_prices()
+            .returning(|_| BTreeMap::new());
+
+        let mut io_methods = MockIo::new();
+        io_methods.expect_write_input().returning(|_, _| Ok(()));
+        io_methods
+            .expect_run_solver()
+            .returning(|_, _, _, _, _| Ok(()));
+        io_methods.expect_read_output().returning(|_| {
+            Ok(json!({
+                "prices": { "T0000": "1" },
+                "orders": [],
+            })
+            .to_string())
+        });
+        let solver = OptimisationPriceFinder {
+            io_methods: Box::new(io_methods),
+            fee: None,
+            min_avg_fee_per_order: 0,
+            solver_type: SolverType::OpenSolver,
+            price_oracle: Box::new(price_oracle),
+            unpriced_token_policy: UnpricedTokenPolicy::Ignore,
+            metrics: ComponentMetrics::default(),
+        };
+
+        let orders = vec![];
+        let solution = solver
+            .find_prices(
+                &orders,
+                &AccountState::with_balance_for(&orders),
+                Duration::from_secs(180),
+            )
+            .unwrap();
+        assert_eq!(solution.solver_name.as_deref(), Some("open-solver"));
+    }
+
+    #[test]
+    fn records_price_finding_duration() {
+        let mut price_oracle = MockPriceEstimating::new();
+        price_oracle
+            .expect_get_token_prices()
+            .returning(|_| BTreeMap::new());
+
+        let mut io_methods = MockIo::new();
+        io_methods.expect_write_input().returning(|_, _| Ok(()));
+        io_methods
+            .expect_run_solver()
+            .returning(|_, _, _, _, _| Err(anyhow!("error")));
+        let registry = Arc::new(Registry::new());
+        let solver = OptimisationPriceFinder {
+            io_methods: Box::new(io_methods),
+            fee: None,
+            min_avg_fee_per_order: 0,
+            solver_type: SolverType::OpenSolver,
+            price_oracle: Box::new(price_oracle),
+            unpriced_token_policy: UnpricedTokenPolicy::Ignore,
+            metrics: ComponentMetrics::new(&registry).unwrap(),
+        };
+
+        let orders = vec![];
+        let result = solver.find_prices(
+            &orders,
+            &AccountState::with_balance_for(&orders),
+            Duration::from_secs(180),
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            metric_value(&registry, "dfusion_service_price_finding_duration_seconds"),
+            1
+        );
+    }
+
+    fn solver_with_unpriced_token(
+        io_methods: MockIo,
+        unpriced_token_policy: UnpricedTokenPolicy,
+    ) -> OptimisationPriceFinder {
+        let mut price_oracle = MockPriceEstimating::new();
+        price_oracle.expect_get_token_prices().returning(|_| {
+            btree_map! {
+                TokenId(0) => Some(TokenInfo::new("OWL", 18, 1_000_000_000_000_000_000)),
+                TokenId(1) => Some(TokenInfo::new("WETH", 18, 1_000_000_000_000_000_000)),
+                TokenId(2) => None,
+            }
+        });
+        OptimisationPriceFinder {
+            io_methods: Box::new(io_methods),
+            fee: None,
+            min_avg_fee_per_order: 0,
+            solver_type: SolverType::StandardSolver,
+            price_oracle: Box::new(price_oracle),
+            unpriced_token_policy,
+            metrics: ComponentMetrics::default(),
+        }
+    }
+
+    fn orders_with_unpriced_market() -> Vec<models::Order> {
+        let priced_order = models::Order {
+            buy_token: 0,
+            sell_token: 1,
+            ..create_order_for_test()
+        };
+        let unpriced_order = models::Order {
+            id: 1,
+            buy_token: 1,
+            sell_token: 2,
+            ..create_order_for_test()
+        };
+        vec![priced_order, unpriced_order]
+    }
+
+    #[test]
+    fn test_unpriced_token_policy_skip_batch() {
+        let mut io_methods = MockIo::new();
+        io_methods.expect_write_input().times(0);
+        io_methods.expect_run_solver().times(0);
+        let solver = solver_with_unpriced_token(io_methods, UnpricedTokenPolicy::SkipBatch);
+
+        let orders = orders_with_unpriced_market();
+        let solution = solver
+            .find_prices(
+                &orders,
+                &AccountState::with_balance_for(&orders),
+                Duration::from_secs(180),
+            )
+            .unwrap();
+        assert!(!solution.is_non_trivial());
+    }
+
+    #[test]
+    fn test_unpriced_token_policy_priced_markets_only() {
+        let mut io_methods = MockIo::new();
+        io_methods
+            .expect_write_input()
+            .times(1)
+            .withf(|_, content: &str| {
+                let json: serde_json::value::Value = serde_json::from_str(content).unwrap();
+                json["orders"].as_array().unwrap().len() == 1
+                    && json["orders"][0]["sellToken"] == "T0001"
+                    && json["tokens"].get("T0002").is_none()
+            })
+            .returning(|_, _| Ok(()));
+        io_methods
+            .expect_run_solver()
+            .times(1)
+            .returning(|_, _, _, _, _| Ok(()));
+        io_methods
+            .expect_read_output()
+            .times(1)
+            .returning(|_| Ok(json!({ "prices": {}, "orders": [] }).to_string()));
+        let solver = solver_with_unpriced_token(io_methods, UnpricedTokenPolicy::PricedMarketsOnly);
+
+        let orders = orders_with_unpriced_market();
+        assert!(solver
+            .find_prices(
+                &orders,
+                &AccountState::with_balance_for(&orders),
+                Duration::from_secs(180),
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_parse_unpriced_token_policy() {
+        assert_eq!(
+            "skip-batch".parse::<UnpricedTokenPolicy>().unwrap(),
+            UnpricedTokenPolicy::SkipBatch
+        );
+        assert_eq!(
+            "Priced-Markets-Only"
+                .parse::<UnpricedTokenPolicy>()
+                .unwrap(),
+            UnpricedTokenPolicy::PricedMarketsOnly
+        );
+        assert!("drop".parse::<UnpricedTokenPolicy>().is_err());
+    }
+
     #[test]
     fn test_balance_serialization() {
         let mut accounts = BTreeMap::new();
@@ -656,6 +933,7 @@ pub mod tests {
                 buy_token: 2,
                 sell_amount: 100,
                 buy_amount: 200,
+                ..Default::default()
             },
             models::Order {
                 id: 0,
@@ -664,6 +942,7 @@ pub mod tests {
                 buy_token: 1,
                 sell_amount: 200,
                 buy_amount: 100,
+                ..Default::default()
             },
         ]
         .to_vec();
@@ -1,5 +1,5 @@
 use crate::models;
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, ensure, Error, Result};
 use log::debug;
 #[cfg(test)]
 use mockall::automock;
@@ -10,17 +10,73 @@ use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Fee {
-    pub token: u16,
+    token: u16,
     /// Value between [0, 1] mapping from 0% -> 100%
-    pub ratio: f64,
+    ratio: f64,
+    /// The exact integer fee denominator, i.e. the fee is `1 / denominator`,
+    /// if the fee was specified as such.
+    denominator: Option<u128>,
+}
+
+impl Fee {
+    /// Creates a fee from a ratio, which must be in the interval (0, 0.5].
+    /// The integer denominator used by the naive solver is derived from the
+    /// ratio and truncated, so this should only be used for ratios that are
+    /// exact reciprocals of integers.
+    pub fn new(token: u16, ratio: f64) -> Result<Self> {
+        ensure!(
+            ratio.is_finite() && ratio > 0.0 && ratio < 1.0,
+            "invalid fee ratio {}, must be between 0 and 1",
+            ratio
+        );
+        ensure!(
+            (1.0 / ratio) as u128 >= 2,
+            "invalid fee ratio {}, must be at most 0.5",
+            ratio
+        );
+        Ok(Fee {
+            token,
+            ratio,
+            denominator: None,
+        })
+    }
+
+    /// Creates a fee of `1 / denominator` from its integer denominator, so
+    /// that integer computations on the fee are exact. The denominator must be
+    /// at least 2.
+    pub fn from_denominator(token: u16, denominator: u128) -> Result<Self> {
+        ensure!(
+            denominator >= 2,
+            "invalid fee denominator {}, must be at least 2",
+            denominator
+        );
+        Ok(Fee {
+            denominator: Some(denominator),
+            ..Fee::new(token, 1.0 / denominator as f64)?
+        })
+    }
+
+    /// Returns the id of the token in which the fee is paid.
+    pub fn token(&self) -> u16 {
+        self.token
+    }
+
+    /// Returns the fee as a ratio in the interval (0, 0.5].
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Returns the integer fee denominator, such that the fee is
+    /// `1 / denominator`.
+    pub fn denominator(&self) -> u128 {
+        self.denominator
+            .unwrap_or_else(|| (1.0 / self.ratio) as u128)
+    }
 }
 
 impl Default for Fee {
     fn default() -> Self {
-        Fee {
-            token: 0,
-            ratio: 0.001,
-        }
+        Fee::from_denominator(0, 1000).expect("default fee is valid")
     }
 }
 
@@ -30,6 +86,8 @@ pub enum SolverType {
     StandardSolver,
     FallbackSolver,
     OpenSolver,
+    ExternalSolver,
+    MaxVolumeSolver,
 }
 
 impl FromStr for SolverType {
@@ -41,12 +99,26 @@ impl FromStr for SolverType {
             "fallback-solver" => Ok(SolverType::FallbackSolver),
             "naive-solver" => Ok(SolverType::NaiveSolver),
             "open-solver" => Ok(SolverType::OpenSolver),
+            "external-solver" => Ok(SolverType::ExternalSolver),
+            "max-volume-solver" => Ok(SolverType::MaxVolumeSolver),
             _ => Err(anyhow!("solver type does not exit")),
         }
     }
 }
 
 impl SolverType {
+    /// Returns the name of the solver, as it is specified on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            SolverType::StandardSolver => "standard-solver",
+            SolverType::FallbackSolver => "fallback-solver",
+            SolverType::NaiveSolver => "naive-solver",
+            SolverType::OpenSolver => "open-solver",
+            SolverType::ExternalSolver => "external-solver",
+            SolverType::MaxVolumeSolver => "max-volume-solver",
+        }
+    }
+
     pub fn execute(
         self,
         result_folder: &str,
@@ -64,8 +136,8 @@ impl SolverType {
             SolverType::FallbackSolver => {
                 execute_private_solver(result_folder, input_file, time_limit, min_avg_fee_per_order)
             }
-            SolverType::NaiveSolver => {
-                panic!("fn execute should not be called by the naive solver")
+            SolverType::NaiveSolver | SolverType::ExternalSolver | SolverType::MaxVolumeSolver => {
+                Err(anyhow!("the {} is not an optimization solver", self.name()))
             }
         }
     }
@@ -124,3 +196,52 @@ pub trait PriceFinding {
         time_limit: Duration,
     ) -> Result<models::Solution, Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_rejects_invalid_ratios() {
+        for &ratio in &[
+            0.0,
+            -0.001,
+            0.6,
+            1.0,
+            1.5,
+            std::f64::NAN,
+            std::f64::INFINITY,
+        ] {
+            assert!(Fee::new(0, ratio).is_err(), "accepted ratio {}", ratio);
+        }
+        assert!(Fee::from_denominator(0, 0).is_err());
+        assert!(Fee::from_denominator(0, 1).is_err());
+    }
+
+    #[test]
+    fn fee_accepts_valid_ratios() {
+        let fee = Fee::new(1, 0.001).unwrap();
+        assert_eq!(fee.token(), 1);
+        assert_eq!(fee.denominator(), 1000);
+
+        let fee = Fee::new(0, 0.5).unwrap();
+        assert_eq!(fee.denominator(), 2);
+
+        let fee = Fee::from_denominator(0, 2).unwrap();
+        assert_eq!(fee.denominator(), 2);
+        assert_eq!(Fee::default().denominator(), 1000);
+    }
+
+    #[test]
+    fn only_optimization_solvers_are_executed() {
+        for &solver_type in &[
+            SolverType::NaiveSolver,
+            SolverType::ExternalSolver,
+            SolverType::MaxVolumeSolver,
+        ] {
+            assert!(solver_type
+                .execute("results/", "input.json", "10".to_owned(), 0)
+                .is_err());
+        }
+    }
+}
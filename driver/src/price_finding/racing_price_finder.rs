@@ -0,0 +1,190 @@
+use crate::models::{AccountState, Order, Solution};
+use crate::price_finding::price_finder_interface::PriceFinding;
+
+use anyhow::{anyhow, Result};
+use crossbeam_utils::thread;
+use log::{info, warn};
+use std::time::Duration;
+
+/// Implements PriceFinding by running several price finders concurrently with
+/// the same time limit and using the solution with the highest objective
+/// value. This allows running a fast solver alongside a slower one that may
+/// find a better solution within the time limit.
+///
+/// Price finders that fail or panic are ignored. If there is a tie, the
+/// solution of the price finder that was specified first is used.
+pub struct RacingPriceFinder {
+    price_finders: Vec<Box<dyn PriceFinding + Sync>>,
+}
+
+impl RacingPriceFinder {
+    pub fn new(price_finders: Vec<Box<dyn PriceFinding + Sync>>) -> Self {
+        RacingPriceFinder { price_finders }
+    }
+}
+
+impl PriceFinding for RacingPriceFinder {
+    fn find_prices(
+        &self,
+        orders: &[Order],
+        state: &AccountState,
+        time_limit: Duration,
+    ) -> Result<Solution> {
+        let results = thread::scope(|s| {
+            let handles = self
+                .price_finders
+                .iter()
+                .map(|price_finder| {
+                    s.spawn(move |_| price_finder.find_prices(orders, state, time_limit))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("price finder panicked")))
+                })
+                .collect::<Vec<_>>()
+        })
+        .map_err(|_| anyhow!("price finder thread panicked"))?;
+
+        let mut best: Option<(Solution, _)> = None;
+        for result in results {
+            let solution = match result {
+                Ok(solution) => solution,
+                Err(err) => {
+                    warn!("racing price finder failed: {:?}", err);
+                    continue;
+                }
+            };
+            let objective_value = solution.objective_value(orders);
+            info!(
+                "{} found solution with objective value {}",
+                solution.solver_name.as_deref().unwrap_or("unknown solver"),
+                objective_value,
+            );
+            if best
+                .as_ref()
+                .map(|(_, best_objective_value)| objective_value > *best_objective_value)
+                .unwrap_or(true)
+            {
+                best = Some((solution, objective_value));
+            }
+        }
+
+        best.map(|(solution, _)| solution)
+            .ok_or_else(|| anyhow!("all racing price finders failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ExecutedOrder;
+    use crate::price_finding::price_finder_interface::MockPriceFinding;
+    use ethcontract::Address;
+
+    fn orders() -> Vec<Order> {
+        vec![
+            Order {
+                id: 0,
+                account_id: Address::from_low_u64_be(1),
+                sell_token: 1,
+                buy_token: 0,
+                sell_amount: 10_000,
+                buy_amount: 5_000,
+                ..Default::default()
+            },
+            Order {
+                id: 0,
+                account_id: Address::from_low_u64_be(2),
+                sell_token: 0,
+                buy_token: 1,
+                sell_amount: 10_000,
+                buy_amount: 5_000,
+                ..Default::default()
+            },
+        ]
+    }
+
+    /// A solution matching the two orders, where the first order receives the
+    /// specified amount.
+    fn solution(buy_amount: u128, solver_name: &str) -> Solution {
+        let executed_order = |account: u64, buy_amount: u128| ExecutedOrder {
+            account_id: Address::from_low_u64_be(account),
+            order_id: 0,
+            sell_amount: 10_000,
+            buy_amount,
+        };
+        Solution {
+            prices: hash_map! {
+                0 => 1_000_000_000_000_000_000,
+                1 => 1_000_000_000_000_000_000,
+            },
+            executed_orders: vec![executed_order(1, buy_amount), executed_order(2, 10_000)],
+            solver_name: None,
+        }
+        .with_solver_name(solver_name)
+    }
+
+    fn price_finder(result: Result<Solution>) -> Box<dyn PriceFinding + Sync> {
+        let mut price_finder = MockPriceFinding::new();
+        price_finder
+            .expect_find_prices()
+            .times(1)
+            .return_once(move |_, _, _| result);
+        Box::new(price_finder)
+    }
+
+    #[test]
+    fn uses_solution_with_highest_objective_value() {
+        let racing_price_finder = RacingPriceFinder::new(vec![
+            price_finder(Ok(solution(9_000, "worse"))),
+            price_finder(Ok(solution(9_990, "better"))),
+            price_finder(Ok(Solution::trivial())),
+        ]);
+        let solution = racing_price_finder
+            .find_prices(&orders(), &AccountState::default(), Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(solution.solver_name.as_deref(), Some("better"));
+    }
+
+    #[test]
+    fn ignores_failing_and_panicking_price_finders() {
+        struct PanickingPriceFinder;
+        impl PriceFinding for PanickingPriceFinder {
+            fn find_prices(&self, _: &[Order], _: &AccountState, _: Duration) -> Result<Solution> {
+                panic!("solver crashed")
+            }
+        }
+
+        let racing_price_finder = RacingPriceFinder::new(vec![
+            price_finder(Err(anyhow!("solver failed"))),
+            Box::new(PanickingPriceFinder),
+            price_finder(Ok(solution(9_000, "working"))),
+        ]);
+        let solution = racing_price_finder
+            .find_prices(&orders(), &AccountState::default(), Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(solution.solver_name.as_deref(), Some("working"));
+    }
+
+    #[test]
+    fn returns_trivial_solution_if_all_solutions_are_trivial() {
+        let racing_price_finder = RacingPriceFinder::new(vec![
+            price_finder(Ok(Solution::trivial())),
+            price_finder(Ok(Solution::trivial())),
+        ]);
+        let solution = racing_price_finder
+            .find_prices(&orders(), &AccountState::default(), Duration::from_secs(1))
+            .unwrap();
+        assert!(!solution.is_non_trivial());
+
+        let racing_price_finder =
+            RacingPriceFinder::new(vec![price_finder(Err(anyhow!("solver failed")))]);
+        assert!(racing_price_finder
+            .find_prices(&orders(), &AccountState::default(), Duration::from_secs(1))
+            .is_err());
+    }
+}
@@ -1,9 +1,13 @@
 #![allow(clippy::ptr_arg)] // required for automock
 
-use crate::contracts::stablex_contract::StableXContract;
+mod nonce_manager;
+
+use crate::contracts::stablex_contract::{DryRunResult, StableXContract};
 use crate::models::Solution;
 
+use crate::gas_price_strategy::GasPriceStrategy;
 use crate::gas_station::GasPriceEstimating;
+use crate::metrics::ComponentMetrics;
 use anyhow::{Error, Result};
 use ethcontract::errors::{ExecutionError, MethodError};
 use ethcontract::web3::types::TransactionReceipt;
@@ -11,6 +15,7 @@ use ethcontract::U256;
 use log::info;
 #[cfg(test)]
 use mockall::automock;
+use nonce_manager::NonceManager;
 use std::thread;
 use std::time::Duration;
 use thiserror::Error;
@@ -23,6 +28,39 @@ const POLL_TIMEOUT: Duration = Duration::from_secs(5);
 #[cfg(test)]
 const POLL_TIMEOUT: Duration = Duration::from_secs(0);
 
+/// The amounts of gas a `submitSolution` call is assumed to use, for
+/// estimating the cost of submitting a solution without an extra node
+/// roundtrip.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SolutionGasModel {
+    /// The fixed amount of gas, independent of the size of the solution.
+    pub base: u64,
+    /// The amount of gas for every order traded by the solution.
+    pub per_executed_order: u64,
+    /// The amount of gas for every token price submitted with the solution.
+    pub per_price: u64,
+}
+
+impl Default for SolutionGasModel {
+    fn default() -> Self {
+        SolutionGasModel {
+            base: 150_000,
+            per_executed_order: 70_000,
+            per_price: 15_000,
+        }
+    }
+}
+
+impl SolutionGasModel {
+    /// Returns a rough estimate of the gas that submitting the solution
+    /// consumes.
+    pub fn estimate_solution_gas(&self, solution: &Solution) -> u64 {
+        self.base
+            + self.per_executed_order * solution.executed_orders.len() as u64
+            + self.per_price * solution.prices.len() as u64
+    }
+}
+
 #[cfg_attr(test, automock)]
 pub trait StableXSolutionSubmitting {
     /// Return the objective value for the given solution in the given
@@ -94,18 +132,104 @@ impl From<Error> for SolutionSubmissionError {
 pub struct StableXSolutionSubmitter<'a> {
     contract: &'a (dyn StableXContract + Sync),
     gas_price_estimating: &'a (dyn GasPriceEstimating + Sync),
+    gas_price_strategy: Option<&'a (dyn GasPriceStrategy + Sync)>,
+    gas_budget: Option<u64>,
+    gas_model: SolutionGasModel,
+    dry_run: bool,
+    nonce_manager: NonceManager<'a>,
+    metrics: ComponentMetrics,
 }
 
 impl<'a> StableXSolutionSubmitter<'a> {
+    /// Creates a new solution submitter that records successful submissions
+    /// with the specified component metrics. Solutions whose estimated gas
+    /// exceeds the specified gas budget are not submitted.
+    ///
+    /// If a gas price strategy is specified, it is applied to the node's
+    /// current gas price in order to determine the submission gas price.
+    /// Otherwise the gas station estimate is used.
     pub fn new(
         contract: &'a (dyn StableXContract + Sync),
         gas_price_estimating: &'a (dyn GasPriceEstimating + Sync),
+        gas_price_strategy: Option<&'a (dyn GasPriceStrategy + Sync)>,
+        gas_budget: Option<u64>,
+        metrics: ComponentMetrics,
     ) -> Self {
         Self {
             contract,
             gas_price_estimating,
+            gas_price_strategy,
+            gas_budget,
+            gas_model: SolutionGasModel::default(),
+            dry_run: false,
+            nonce_manager: NonceManager::new(contract),
+            metrics,
+        }
+    }
+
+    /// Uses the specified gas model for checking solutions against the gas
+    /// budget.
+    pub fn with_gas_model(self, gas_model: SolutionGasModel) -> Self {
+        StableXSolutionSubmitter { gas_model, ..self }
+    }
+
+    /// Only simulates solution submissions with an `eth_call` instead of
+    /// sending transactions. Solutions the contract would reject are reported
+    /// as benign errors with the revert reason.
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        StableXSolutionSubmitter { dry_run, ..self }
+    }
+
+    fn submit_solution_dry_run(
+        &self,
+        batch_index: U256,
+        solution: Solution,
+        claimed_objective_value: U256,
+    ) -> Result<(), SolutionSubmissionError> {
+        match self.contract.submit_solution_dry_run(
+            batch_index,
+            solution,
+            claimed_objective_value,
+        )? {
+            DryRunResult::Accepted => {
+                info!(
+                    "Dry run: solution for batch {} would be accepted",
+                    batch_index
+                );
+                Ok(())
+            }
+            DryRunResult::Reverted(reason) => Err(SolutionSubmissionError::Benign(format!(
+                "dry run: solution would revert: {}",
+                reason.as_deref().unwrap_or("no reason"),
+            ))),
         }
     }
+
+    /// Checks the solution against the gas budget. Solutions over budget have
+    /// their untraded orders and zero prices trimmed and are rejected if they
+    /// still exceed the budget after that.
+    fn check_gas_budget(
+        &self,
+        mut solution: Solution,
+    ) -> Result<Solution, SolutionSubmissionError> {
+        let gas_budget = match self.gas_budget {
+            Some(gas_budget) => gas_budget,
+            None => return Ok(solution),
+        };
+        if self.gas_model.estimate_solution_gas(&solution) <= gas_budget {
+            return Ok(solution);
+        }
+
+        solution.trim();
+        let estimated_gas = self.gas_model.estimate_solution_gas(&solution);
+        if estimated_gas > gas_budget {
+            return Err(SolutionSubmissionError::Benign(format!(
+                "estimated solution gas {} exceeds gas budget {}",
+                estimated_gas, gas_budget
+            )));
+        }
+        Ok(solution)
+    }
 }
 
 impl<'a> StableXSolutionSubmitting for StableXSolutionSubmitter<'a> {
@@ -114,6 +238,8 @@ impl<'a> StableXSolutionSubmitting for StableXSolutionSubmitter<'a> {
         batch_index: U256,
         solution: Solution,
     ) -> Result<U256, SolutionSubmissionError> {
+        let solution = self.check_gas_budget(solution)?;
+
         // NOTE: Compare with `>=` as the exchange's current batch index is the
         //   one accepting orders and does not yet accept solutions.
         while batch_index.as_u32() >= self.contract.get_current_auction_index()? {
@@ -132,15 +258,28 @@ impl<'a> StableXSolutionSubmitting for StableXSolutionSubmitter<'a> {
         solution: Solution,
         claimed_objective_value: U256,
     ) -> Result<(), SolutionSubmissionError> {
-        retry_with_gas_price_increase(
+        let solution = self.check_gas_budget(solution)?;
+        if self.dry_run {
+            return self.submit_solution_dry_run(batch_index, solution, claimed_objective_value);
+        }
+
+        let nonce = self.nonce_manager.next_nonce()?;
+        submit_solution_with_escalation(
             self.contract,
             batch_index,
             solution.clone(),
             claimed_objective_value,
+            nonce,
             self.gas_price_estimating,
+            self.gas_price_strategy
+                .map(|strategy| strategy as &dyn GasPriceStrategy),
             60_000_000_000u64.into(),
         )
         .map_err(|err| {
+            // NOTE: The nonce may or may not have been used by a failed
+            //   submission, so make sure to get the correct one from the node
+            //   for the next submission.
+            self.nonce_manager.resync();
             extract_transaction_receipt(&err)
                 .and_then(|tx| {
                     let block_number = tx.block_number?;
@@ -155,16 +294,24 @@ impl<'a> StableXSolutionSubmitting for StableXSolutionSubmitter<'a> {
                 })
                 .unwrap_or_else(|| SolutionSubmissionError::Unexpected(err.into()))
         })
-        .map(|_| ())
+        .map(|_| self.metrics.solution_submitted())
     }
 }
 
-fn retry_with_gas_price_increase(
+/// Submits a solution and, whenever the transaction is not confirmed within a
+/// few blocks, resubmits it at an increased gas price until the gas cap is
+/// reached.
+///
+/// All submissions use the same nonce, so that every resubmission replaces
+/// the previous transaction and at most one of them can be mined.
+fn submit_solution_with_escalation(
     contract: &dyn StableXContract,
     batch_index: U256,
     solution: Solution,
     claimed_objective_value: U256,
+    nonce: U256,
     gas_price_estimating: &dyn GasPriceEstimating,
+    gas_price_strategy: Option<&dyn GasPriceStrategy>,
     gas_cap: U256,
 ) -> Result<(), MethodError> {
     const INCREASE_FACTOR: u32 = 2;
@@ -176,15 +323,24 @@ fn retry_with_gas_price_increase(
     let mut result;
     // the following block emulates a do-while loop
     while {
-        gas_price_estimate = match gas_price_estimating.estimate_gas_price() {
-            Ok(gas_estimate) => gas_estimate.fast,
-            Err(ref err) => {
-                log::warn!(
-                    "failed to get gas price from gnosis safe gas station: {}",
-                    err
-                );
-                gas_price_estimate
-            }
+        gas_price_estimate = match gas_price_strategy {
+            Some(strategy) => match contract.get_gas_price() {
+                Ok(base_gas_price) => strategy.gas_price(base_gas_price),
+                Err(ref err) => {
+                    log::warn!("failed to get gas price from node: {}", err);
+                    gas_price_estimate
+                }
+            },
+            None => match gas_price_estimating.estimate_gas_price() {
+                Ok(gas_estimate) => gas_estimate.fast,
+                Err(ref err) => {
+                    log::warn!(
+                        "failed to get gas price from gnosis safe gas station: {}",
+                        err
+                    );
+                    gas_price_estimate
+                }
+            },
         };
         // Never exceed the gas cap.
         let gas_price = std::cmp::min(gas_price_estimate * gas_price_factor, gas_cap);
@@ -194,6 +350,7 @@ fn retry_with_gas_price_increase(
             batch_index,
             solution.clone(),
             claimed_objective_value,
+            nonce,
             gas_price,
             if gas_price == gas_cap {
                 None
@@ -215,8 +372,8 @@ fn retry_with_gas_price_increase(
         // Increase gas
         gas_price_factor *= INCREASE_FACTOR;
         info!(
-            "retrying solution submission with increased gas price factor of {}",
-            gas_price_factor,
+            "resubmitting solution with nonce {} and increased gas price factor of {}",
+            nonce, gas_price_factor,
         );
     }
 
@@ -234,7 +391,10 @@ fn extract_transaction_receipt(err: &MethodError) -> Option<&TransactionReceipt>
 mod tests {
     use super::*;
     use crate::contracts::stablex_contract::MockStableXContract;
+    use crate::gas_price_strategy::MultipliedGasPrice;
     use crate::gas_station::{GasPrice, MockGasPriceEstimating};
+    use crate::models::ExecutedOrder;
+    use ethcontract::Address;
 
     use anyhow::anyhow;
     use ethcontract::web3::types::H2048;
@@ -260,21 +420,129 @@ mod tests {
 
         let gas_station = MockGasPriceEstimating::new();
 
-        let submitter = StableXSolutionSubmitter::new(&contract, &gas_station);
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            None,
+            ComponentMetrics::default(),
+        );
         let result = submitter.get_solution_objective_value(U256::zero(), Solution::trivial());
 
         contract.checkpoint();
         assert_eq!(result.unwrap(), U256::from(42));
     }
 
+    fn executed_orders(count: usize) -> Vec<ExecutedOrder> {
+        vec![
+            ExecutedOrder {
+                account_id: Address::zero(),
+                order_id: 0,
+                sell_amount: 1,
+                buy_amount: 1,
+            };
+            count
+        ]
+    }
+
+    #[test]
+    fn estimates_gas_based_on_solution_size() {
+        let gas_model = SolutionGasModel {
+            base: 100,
+            per_executed_order: 10,
+            per_price: 1,
+        };
+        let solution = Solution {
+            prices: hash_map! { 0 => 1, 1 => 2 },
+            executed_orders: executed_orders(3),
+            solver_name: None,
+        };
+        assert_eq!(gas_model.estimate_solution_gas(&Solution::trivial()), 100);
+        assert_eq!(gas_model.estimate_solution_gas(&solution), 132);
+    }
+
+    #[test]
+    fn trims_large_solution_exceeding_gas_budget() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_current_auction_index()
+            .returning(|| Ok(1));
+        contract
+            .expect_get_solution_objective_value()
+            .withf(|_, solution, _| {
+                solution.executed_orders.len() == 2 && solution.prices.len() == 2
+            })
+            .return_once(|_, _, _| Ok(U256::from(42)));
+        let gas_station = MockGasPriceEstimating::new();
+
+        let mut executed_orders = executed_orders(100);
+        for executed_order in &mut executed_orders[2..] {
+            executed_order.sell_amount = 0;
+            executed_order.buy_amount = 0;
+        }
+        let solution = Solution {
+            prices: (0..10)
+                .map(|token| (token, if token < 2 { 1 } else { 0 }))
+                .collect(),
+            executed_orders,
+            solver_name: None,
+        };
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            Some(1_000_000),
+            ComponentMetrics::default(),
+        );
+        let result = submitter.get_solution_objective_value(U256::zero(), solution);
+
+        assert_eq!(result.unwrap(), U256::from(42));
+    }
+
+    #[test]
+    fn skips_solution_exceeding_gas_budget() {
+        let mut contract = MockStableXContract::new();
+        contract.expect_get_current_auction_index().times(0);
+        contract.expect_get_solution_objective_value().times(0);
+        let gas_station = MockGasPriceEstimating::new();
+
+        let solution = Solution {
+            prices: (0..10).map(|token| (token, 1)).collect(),
+            executed_orders: executed_orders(100),
+            solver_name: None,
+        };
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            Some(1_000_000),
+            ComponentMetrics::default(),
+        );
+        let result = submitter.get_solution_objective_value(U256::zero(), solution);
+
+        match result.expect_err("Should have errored") {
+            SolutionSubmissionError::Benign(_) => (),
+            SolutionSubmissionError::Unexpected(err) => {
+                panic!("Expecting benign failure, but got {}", err)
+            }
+        };
+    }
+
     #[test]
     fn test_retry_with_gas_price_increase_once() {
         let mut contract = MockStableXContract::new();
         contract
             .expect_submit_solution()
             .times(1)
-            .with(always(), always(), always(), eq(U256::from(5)), eq(Some(2)))
-            .return_once(|_, _, _, _, _| {
+            .with(
+                always(),
+                always(),
+                always(),
+                always(),
+                eq(U256::from(5)),
+                eq(Some(2)),
+            )
+            .return_once(|_, _, _, _, _, _| {
                 Err(MethodError::from_parts(
                     "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
                         .to_owned(),
@@ -283,8 +551,15 @@ mod tests {
             });
         contract
             .expect_submit_solution()
-            .with(always(), always(), always(), eq(U256::from(9)), eq(None))
-            .return_once(|_, _, _, _, _| Ok(()));
+            .with(
+                always(),
+                always(),
+                always(),
+                always(),
+                eq(U256::from(9)),
+                eq(None),
+            )
+            .return_once(|_, _, _, _, _, _| Ok(()));
 
         let mut gas_station = MockGasPriceEstimating::new();
         gas_station.expect_estimate_gas_price().returning(|| {
@@ -294,12 +569,14 @@ mod tests {
             })
         });
 
-        retry_with_gas_price_increase(
+        submit_solution_with_escalation(
             &contract,
             1.into(),
             Solution::trivial(),
             1.into(),
+            0.into(),
             &gas_station,
+            None,
             9.into(),
         )
         .unwrap();
@@ -311,8 +588,15 @@ mod tests {
         contract
             .expect_submit_solution()
             .times(1)
-            .with(always(), always(), always(), eq(U256::from(5)), eq(Some(2)))
-            .return_once(|_, _, _, _, _| {
+            .with(
+                always(),
+                always(),
+                always(),
+                always(),
+                eq(U256::from(5)),
+                eq(Some(2)),
+            )
+            .return_once(|_, _, _, _, _, _| {
                 Err(MethodError::from_parts(
                     "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
                         .to_owned(),
@@ -326,10 +610,11 @@ mod tests {
                 always(),
                 always(),
                 always(),
+                always(),
                 eq(U256::from(12)),
                 eq(Some(2)),
             )
-            .return_once(|_, _, _, _, _| {
+            .return_once(|_, _, _, _, _, _| {
                 Err(MethodError::from_parts(
                     "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
                         .to_owned(),
@@ -338,8 +623,15 @@ mod tests {
             });
         contract
             .expect_submit_solution()
-            .with(always(), always(), always(), eq(U256::from(15)), eq(None))
-            .return_once(|_, _, _, _, _| {
+            .with(
+                always(),
+                always(),
+                always(),
+                always(),
+                eq(U256::from(15)),
+                eq(None),
+            )
+            .return_once(|_, _, _, _, _, _| {
                 Err(MethodError::from_parts(
                     "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
                         .to_owned(),
@@ -364,17 +656,112 @@ mod tests {
             })
         });
 
-        assert!(retry_with_gas_price_increase(
+        assert!(submit_solution_with_escalation(
             &contract,
             1.into(),
             Solution::trivial(),
             1.into(),
+            0.into(),
             &gas_station,
+            None,
             15.into(),
         )
         .is_err())
     }
 
+    #[test]
+    fn test_escalation_reuses_nonce() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_transaction_count()
+            .times(1)
+            .return_once(|| Ok(U256::from(42)));
+        contract
+            .expect_submit_solution()
+            .times(1)
+            .with(
+                always(),
+                always(),
+                always(),
+                eq(U256::from(42)),
+                eq(U256::from(5)),
+                always(),
+            )
+            .return_once(|_, _, _, _, _, _| {
+                Err(MethodError::from_parts(
+                    "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
+                        .to_owned(),
+                    ExecutionError::ConfirmTimeout,
+                ))
+            });
+        contract
+            .expect_submit_solution()
+            .times(1)
+            .with(
+                always(),
+                always(),
+                always(),
+                eq(U256::from(42)),
+                eq(U256::from(10)),
+                always(),
+            )
+            .return_once(|_, _, _, _, _, _| Ok(()));
+
+        let mut gas_station = MockGasPriceEstimating::new();
+        gas_station.expect_estimate_gas_price().returning(|| {
+            Ok(GasPrice {
+                fast: 5.into(),
+                ..Default::default()
+            })
+        });
+
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            None,
+            ComponentMetrics::default(),
+        );
+        submitter
+            .submit_solution(U256::one(), Solution::trivial(), U256::one())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_retry_with_gas_price_strategy() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_gas_price()
+            .returning(|| Ok(U256::from(10)));
+        contract
+            .expect_submit_solution()
+            .times(1)
+            .with(
+                always(),
+                always(),
+                always(),
+                always(),
+                eq(U256::from(15)),
+                eq(Some(2)),
+            )
+            .return_once(|_, _, _, _, _, _| Ok(()));
+
+        let mut gas_station = MockGasPriceEstimating::new();
+        gas_station.expect_estimate_gas_price().times(0);
+
+        submit_solution_with_escalation(
+            &contract,
+            1.into(),
+            Solution::trivial(),
+            1.into(),
+            0.into(),
+            &gas_station,
+            Some(&MultipliedGasPrice::new(1.5)),
+            100.into(),
+        )
+        .unwrap();
+    }
+
     #[test]
     fn test_benign_verification_failure() {
         let mut contract = MockStableXContract::new();
@@ -393,7 +780,13 @@ mod tests {
             });
         let gas_station = MockGasPriceEstimating::new();
 
-        let submitter = StableXSolutionSubmitter::new(&contract, &gas_station);
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            None,
+            ComponentMetrics::default(),
+        );
         let result = submitter.get_solution_objective_value(U256::zero(), Solution::trivial());
 
         match result.expect_err("Should have errored") {
@@ -404,6 +797,57 @@ mod tests {
         };
     }
 
+    #[test]
+    fn dry_run_simulates_instead_of_submitting() {
+        let mut contract = MockStableXContract::new();
+        contract.expect_get_transaction_count().times(0);
+        contract.expect_submit_solution().times(0);
+        contract
+            .expect_submit_solution_dry_run()
+            .with(eq(U256::from(42)), always(), eq(U256::from(1337)))
+            .return_once(|_, _, _| Ok(DryRunResult::Accepted));
+        let gas_station = MockGasPriceEstimating::new();
+
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_dry_run(true);
+        submitter
+            .submit_solution(42.into(), Solution::trivial(), 1337.into())
+            .unwrap();
+    }
+
+    #[test]
+    fn dry_run_reports_revert_reason_as_benign() {
+        let mut contract = MockStableXContract::new();
+        contract.expect_submit_solution().times(0);
+        contract
+            .expect_submit_solution_dry_run()
+            .return_once(|_, _, _| Ok(DryRunResult::Reverted(Some("bad solution".to_owned()))));
+        let gas_station = MockGasPriceEstimating::new();
+
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            None,
+            ComponentMetrics::default(),
+        )
+        .with_dry_run(true);
+        let result = submitter.submit_solution(U256::zero(), Solution::trivial(), U256::zero());
+
+        match result.expect_err("Should have errored") {
+            SolutionSubmissionError::Benign(reason) => assert!(reason.contains("bad solution")),
+            SolutionSubmissionError::Unexpected(err) => {
+                panic!("Expecting benign failure, but got {}", err)
+            }
+        }
+    }
+
     #[test]
     fn test_benign_solution_submission_failure() {
         let mut contract = MockStableXContract::new();
@@ -423,10 +867,13 @@ mod tests {
             logs_bloom: H2048::zero(),
         };
 
+        contract
+            .expect_get_transaction_count()
+            .return_once(|| Ok(U256::zero()));
         // Submit Solution returns failed tx
         contract
             .expect_submit_solution()
-            .return_once(move |_, _, _, _, _| {
+            .return_once(move |_, _, _, _, _, _| {
                 Err(MethodError::from_parts(
                     "submitSolution(uint32,uint256,address[],uint16[],uint128[],uint128[],uint16[])"
                         .to_owned(),
@@ -452,7 +899,13 @@ mod tests {
             })
         });
 
-        let submitter = StableXSolutionSubmitter::new(&contract, &gas_station);
+        let submitter = StableXSolutionSubmitter::new(
+            &contract,
+            &gas_station,
+            None,
+            None,
+            ComponentMetrics::default(),
+        );
         let result = submitter.submit_solution(U256::zero(), Solution::trivial(), U256::zero());
 
         match result.expect_err("Should have errored") {
@@ -0,0 +1,105 @@
+use crate::contracts::stablex_contract::StableXContract;
+use anyhow::Result;
+use ethcontract::U256;
+use log::info;
+use std::sync::Mutex;
+
+/// Hands out monotonically increasing nonces for the solver account.
+///
+/// The next nonce is tracked in memory so that transactions submitted in
+/// quick succession never race for the same nonce. It is seeded from the
+/// node's transaction count for the account and can be resynced from the
+/// chain whenever a submission fails.
+pub struct NonceManager<'a> {
+    contract: &'a (dyn StableXContract + Sync),
+    next_nonce: Mutex<Option<U256>>,
+}
+
+impl<'a> NonceManager<'a> {
+    pub fn new(contract: &'a (dyn StableXContract + Sync)) -> Self {
+        NonceManager {
+            contract,
+            next_nonce: Mutex::new(None),
+        }
+    }
+
+    /// Returns the nonce to use for the next transaction, fetching it from
+    /// the node if it is not yet known.
+    pub fn next_nonce(&self) -> Result<U256> {
+        let mut next_nonce = self.next_nonce.lock().unwrap();
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.contract.get_transaction_count()?,
+        };
+        *next_nonce = Some(nonce + 1);
+        Ok(nonce)
+    }
+
+    /// Forgets the tracked nonce so that the next nonce is fetched from the
+    /// node again.
+    pub fn resync(&self) {
+        if self.next_nonce.lock().unwrap().take().is_some() {
+            info!("resyncing solver account nonce from the node");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::stablex_contract::MockStableXContract;
+
+    #[test]
+    fn increments_nonce() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_transaction_count()
+            .times(1)
+            .returning(|| Ok(U256::from(7)));
+
+        let nonce_manager = NonceManager::new(&contract);
+        for expected in 7..10 {
+            assert_eq!(nonce_manager.next_nonce().unwrap(), U256::from(expected));
+        }
+    }
+
+    #[test]
+    fn resyncs_nonce_from_chain() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_transaction_count()
+            .times(1)
+            .return_once(|| Ok(U256::from(7)));
+        // Simulate transactions sent from the solver account by someone else,
+        // leaving a gap between the tracked and the actual nonce.
+        contract
+            .expect_get_transaction_count()
+            .times(1)
+            .return_once(|| Ok(U256::from(12)));
+
+        let nonce_manager = NonceManager::new(&contract);
+        assert_eq!(nonce_manager.next_nonce().unwrap(), U256::from(7));
+        assert_eq!(nonce_manager.next_nonce().unwrap(), U256::from(8));
+
+        nonce_manager.resync();
+        assert_eq!(nonce_manager.next_nonce().unwrap(), U256::from(12));
+        assert_eq!(nonce_manager.next_nonce().unwrap(), U256::from(13));
+    }
+
+    #[test]
+    fn does_not_track_nonce_on_error() {
+        let mut contract = MockStableXContract::new();
+        contract
+            .expect_get_transaction_count()
+            .times(1)
+            .return_once(|| Err(anyhow::anyhow!("error")));
+        contract
+            .expect_get_transaction_count()
+            .times(1)
+            .return_once(|| Ok(U256::from(3)));
+
+        let nonce_manager = NonceManager::new(&contract);
+        assert!(nonce_manager.next_nonce().is_err());
+        assert_eq!(nonce_manager.next_nonce().unwrap(), U256::from(3));
+    }
+}
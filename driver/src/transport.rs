@@ -1,37 +1,129 @@
-use crate::http::{HttpClient, HttpFactory, HttpLabel};
-use anyhow::Error;
-use ethcontract::jsonrpc::types::{Call, Output, Request};
+use crate::http::{HttpClient, HttpFactory, HttpLabel, HttpStatusError};
+use crate::util::{Clock, SystemClock};
+use anyhow::{ensure, Error};
+use ethcontract::jsonrpc::types::{Call, MethodCall, Output, Request};
+use ethcontract::web3::futures::Future as Future01;
 use ethcontract::web3::helpers;
-use ethcontract::web3::{BatchTransport, Error as Web3Error, RequestId, Transport};
-use futures::compat::Compat;
+use ethcontract::web3::transports::{EventLoopHandle, WebSocket};
+use ethcontract::web3::types::{Filter, Log};
+use ethcontract::web3::{BatchTransport, Error as Web3Error, RequestId, Transport, Web3};
+use futures::compat::{Compat, Stream01CompatExt as _};
 use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use futures::stream::{BoxStream, StreamExt as _};
+use futures_timer::Delay;
 use isahc::config::{Configurable, VersionNegotiation};
-use log::{debug, info, warn};
+use log::{debug, info, log, log_enabled, warn, Level};
 use serde::Deserialize;
 use serde_json::Value;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::HashSet;
 use std::fmt::{self, Debug, Formatter};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 /// An HTTP transport implementation with timeout and logging.
 #[derive(Clone)]
-pub struct HttpTransport(Arc<HttpTransportInner>);
+pub struct HttpTransport {
+    inner: Arc<HttpTransportInner>,
+    /// Overrides the default timeout of the transport for all requests.
+    timeout: Option<Duration>,
+    retry_policy: Arc<RetryPolicy>,
+}
+
+/// Which JSON RPC requests are retried after transient failures and how.
+///
+/// Requests sending transactions are never retried, even if their method is
+/// in the allowlist, as they are not idempotent. Requests that timed out are
+/// not retried either, as every retry would take up to the full timeout again.
+#[derive(Clone, Debug, Default)]
+pub struct RetryPolicy {
+    /// The idempotent JSON RPC methods that are safe to retry.
+    pub methods: HashSet<String>,
+    pub max_retries: usize,
+    /// The delay before the first retry, which doubles with every retry.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Returns whether a request is allowed to be retried, which is only the
+    /// case if all of its calls are.
+    fn allows(&self, request: &Request) -> bool {
+        is_idempotent(request)
+            && request_calls(request).iter().all(|call| match call {
+                Call::MethodCall(MethodCall { method, .. }) => self.methods.contains(method),
+                _ => false,
+            })
+    }
+
+    /// Returns the delay before the specified retry, starting with 0.
+    fn backoff(&self, retry: usize) -> Duration {
+        let factor = 1u32.checked_shl(retry as u32).unwrap_or(u32::max_value());
+        cmp::min(
+            self.initial_backoff
+                .checked_mul(factor)
+                .unwrap_or(self.max_backoff),
+            self.max_backoff,
+        )
+    }
+}
+
+/// Returns whether an HTTP request failed quickly for a reason that may go
+/// away when sending it again.
+fn is_transient_error(err: &Error) -> bool {
+    if let Some(err) = err.downcast_ref::<HttpStatusError>() {
+        return err.status.is_server_error();
+    }
+    match err.downcast_ref::<isahc::Error>() {
+        Some(isahc::Error::ConnectFailed)
+        | Some(isahc::Error::CouldntResolveHost)
+        | Some(isahc::Error::Io(_)) => true,
+        _ => false,
+    }
+}
 
 struct HttpTransportInner {
-    url: String,
+    endpoints: Endpoints,
     client: HttpClient,
     id: AtomicUsize,
+    rpc_log_max_body_length: Option<usize>,
 }
 
 impl HttpTransport {
     /// Creates a new HTTP transport with settings.
+    ///
+    /// If a maximum body length is specified, all JSON RPC requests and their
+    /// responses are logged at the trace level, truncated to that length.
     pub fn new(
         http_factory: &HttpFactory,
         url: impl Into<String>,
         timeout: Duration,
+        rpc_log_max_body_length: Option<usize>,
     ) -> Result<HttpTransport, Error> {
+        HttpTransport::with_failover(
+            http_factory,
+            vec![url.into()],
+            Duration::default(),
+            timeout,
+            rpc_log_max_body_length,
+        )
+    }
+
+    /// Creates a new HTTP transport that sends requests to the first of the
+    /// specified URLs and fails over to the next one when it can't be
+    /// connected to. Requests are sent to the first URL again once the
+    /// cooldown has passed after failing over.
+    pub fn with_failover(
+        http_factory: &HttpFactory,
+        urls: Vec<String>,
+        failover_cooldown: Duration,
+        timeout: Duration,
+        rpc_log_max_body_length: Option<usize>,
+    ) -> Result<HttpTransport, Error> {
+        let endpoints = Endpoints::new(urls, failover_cooldown, Arc::new(SystemClock))?;
         let client = http_factory.with_config(|builder| {
             builder
                 .timeout(timeout)
@@ -40,34 +132,218 @@ impl HttpTransport {
                 .version_negotiation(VersionNegotiation::http11())
         })?;
 
-        Ok(HttpTransport(Arc::new(HttpTransportInner {
-            url: url.into(),
-            client,
-            id: AtomicUsize::default(),
-        })))
+        Ok(HttpTransport {
+            inner: Arc::new(HttpTransportInner {
+                endpoints,
+                client,
+                id: AtomicUsize::default(),
+                rpc_log_max_body_length,
+            }),
+            timeout: None,
+            retry_policy: Arc::new(RetryPolicy::default()),
+        })
+    }
+
+    /// Returns a transport sharing the connection of this one, that uses the
+    /// specified timeout for its requests instead of the default.
+    pub fn with_timeout(&self, timeout: Duration) -> HttpTransport {
+        HttpTransport {
+            inner: self.inner.clone(),
+            timeout: Some(timeout),
+            retry_policy: self.retry_policy.clone(),
+        }
+    }
+
+    /// Returns a transport sharing the connection of this one, that retries
+    /// requests after transient failures according to the specified policy.
+    pub fn with_retry_policy(&self, retry_policy: RetryPolicy) -> HttpTransport {
+        HttpTransport {
+            inner: self.inner.clone(),
+            timeout: self.timeout,
+            retry_policy: Arc::new(retry_policy),
+        }
+    }
+}
+
+/// The URLs of the nodes an HTTP transport sends requests to.
+struct Endpoints {
+    urls: Vec<String>,
+    failover_cooldown: Duration,
+    clock: Arc<dyn Clock>,
+    /// The index of the URL requests are currently sent to and when the
+    /// transport failed over to it.
+    active: Mutex<(usize, Instant)>,
+}
+
+impl Endpoints {
+    fn new(
+        urls: Vec<String>,
+        failover_cooldown: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, Error> {
+        ensure!(!urls.is_empty(), "no node URLs specified");
+        let now = clock.now();
+        Ok(Endpoints {
+            urls,
+            failover_cooldown,
+            clock,
+            active: Mutex::new((0, now)),
+        })
+    }
+
+    /// Returns the index of the URL to send the next request to, switching
+    /// back to the primary URL if the cooldown has passed.
+    fn active(&self) -> usize {
+        let mut active = self.active.lock().expect("endpoints mutex poisoned");
+        let (index, since) = *active;
+        let cooled_down =
+            self.clock.now().saturating_duration_since(since) >= self.failover_cooldown;
+        if index != 0 && cooled_down {
+            *active = (0, self.clock.now());
+        }
+        active.0
+    }
+
+    /// Fails over to the URL following the specified one unless a concurrent
+    /// request already did. Returns the index of the URL to retry with.
+    fn failed(&self, index: usize) -> usize {
+        let next = (index + 1) % self.urls.len();
+        let mut active = self.active.lock().expect("endpoints mutex poisoned");
+        if active.0 == index {
+            *active = (next, self.clock.now());
+        }
+        next
+    }
+}
+
+/// Returns the calls of a single or batch JSON RPC request.
+fn request_calls(request: &Request) -> &[Call] {
+    match request {
+        Request::Single(call) => std::slice::from_ref(call),
+        Request::Batch(calls) => calls.as_slice(),
+    }
+}
+
+/// Returns whether sending a request more than once has the same effect as
+/// sending it once, which is the case unless it sends a transaction.
+fn is_idempotent(request: &Request) -> bool {
+    request_calls(request).iter().all(|call| match call {
+        Call::MethodCall(MethodCall { method, .. }) => !method.starts_with("eth_send"),
+        _ => false,
+    })
+}
+
+/// Returns whether an HTTP request failed because the node could not be
+/// connected to, in which case it is safe to send it to another node.
+fn is_connection_error(err: &Error) -> bool {
+    match err.downcast_ref::<isahc::Error>() {
+        Some(isahc::Error::ConnectFailed) | Some(isahc::Error::CouldntResolveHost) => true,
+        _ => false,
+    }
+}
+
+/// Returns whether an HTTP request timed out. The node may or may not have
+/// received and processed the request in that case.
+///
+/// Note that `isahc` reports connect timeouts and request timeouts as the
+/// same error.
+fn is_timeout(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<isahc::Error>(),
+        Some(isahc::Error::Timeout)
+    )
+}
+
+/// Truncates a body to at most `max_length` bytes for logging.
+fn truncate_body(body: &str, max_length: usize) -> Cow<str> {
+    if body.len() <= max_length {
+        return Cow::Borrowed(body);
+    }
+    let mut end = max_length;
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!(
+        "{}... ({} bytes truncated)",
+        &body[..end],
+        body.len() - end
+    ))
+}
+
+/// Formats the log message of a JSON RPC request or response body.
+fn rpc_log_message(id: RequestId, kind: &str, body: &str, max_length: usize) -> String {
+    format!(
+        "[id:{}] rpc {}: '{}'",
+        id,
+        kind,
+        truncate_body(body.trim(), max_length)
+    )
+}
+
+/// Logs the message built by `message` at the specified level. The message is
+/// only built if logging is enabled for the level.
+fn log_lazy(level: Level, message: impl FnOnce() -> String) {
+    if log_enabled!(level) {
+        log!(level, "{}", message());
     }
 }
 
 type RpcResult = Result<Value, Web3Error>;
 
 impl HttpTransportInner {
-    /// Execute an HTTP JSON RPC request.
-    async fn execute_rpc(self: Arc<Self>, id: RequestId, request: Request) -> RpcResult {
+    /// Logs a JSON RPC request or response body if RPC logging is enabled.
+    fn log_rpc(&self, id: RequestId, kind: &str, body: &str) {
+        if let Some(max_length) = self.rpc_log_max_body_length {
+            log_lazy(Level::Trace, || rpc_log_message(id, kind, body, max_length));
+        }
+    }
+
+    /// Execute an HTTP JSON RPC request, retrying it after transient failures
+    /// if the retry policy allows it.
+    async fn execute_rpc(
+        self: Arc<Self>,
+        id: RequestId,
+        request: Request,
+        timeout: Option<Duration>,
+        retry_policy: Arc<RetryPolicy>,
+    ) -> RpcResult {
         let label: HttpLabel = (&request).into();
+        let idempotent = is_idempotent(&request);
+        let max_retries = if retry_policy.allows(&request) {
+            retry_policy.max_retries
+        } else {
+            0
+        };
 
         let request = serde_json::to_string(&request)?;
         debug!("[id:{}] sending request: '{}'", id, &request);
+        self.log_rpc(id, "request", &request);
 
-        let content = self
-            .client
-            .post_raw_json_async(&self.url, request, label)
-            .await
-            .map_err(|err| {
-                warn!("[id:{}] returned an error: '{}'", id, err.to_string());
-                Web3Error::Transport(err.to_string())
-            })?;
+        let mut retry = 0;
+        let content = loop {
+            match self
+                .post_rpc(id, &request, label, timeout, idempotent)
+                .await
+            {
+                Ok(content) => break content,
+                Err(err) if retry < max_retries && is_transient_error(&err) => {
+                    let backoff = retry_policy.backoff(retry);
+                    warn!(
+                        "[id:{}] retrying in {:?} after transient error: '{}'",
+                        id, backoff, err
+                    );
+                    Delay::new(backoff).await;
+                    retry += 1;
+                }
+                Err(err) => {
+                    warn!("[id:{}] returned an error: '{}'", id, err.to_string());
+                    return Err(Web3Error::Transport(err.to_string()));
+                }
+            }
+        };
 
         debug!("[id:{}] received response: '{}'", id, content.trim());
+        self.log_rpc(id, "response", &content);
         let mut json = Value::from_str(&content)?;
         if let Some(map) = json.as_object_mut() {
             // NOTE: Ganache sometimes returns errors inlined with responses,
@@ -82,8 +358,51 @@ impl HttpTransportInner {
         Ok(json)
     }
 
-    async fn execute_single_rpc(self: Arc<Self>, id: RequestId, call: Call) -> RpcResult {
-        let json = self.execute_rpc(id, Request::Single(call)).await?;
+    /// Posts a serialized JSON RPC request to the active node, failing over to
+    /// the following nodes if it can't be connected to. Idempotent requests
+    /// also fail over when the node doesn't respond in time, other requests
+    /// are never sent twice as the node may have processed them.
+    async fn post_rpc(
+        &self,
+        id: RequestId,
+        request: &str,
+        label: HttpLabel,
+        timeout: Option<Duration>,
+        idempotent: bool,
+    ) -> Result<String, Error> {
+        let mut index = self.endpoints.active();
+        let mut attempts = 1;
+        loop {
+            let url = &self.endpoints.urls[index];
+            match self
+                .client
+                .post_raw_json_async(url, request, label, timeout)
+                .await
+            {
+                Ok(content) => return Ok(content),
+                Err(err)
+                    if (is_connection_error(&err) || (idempotent && is_timeout(&err)))
+                        && attempts < self.endpoints.urls.len() =>
+                {
+                    warn!("[id:{}] failed to connect to {}: '{}'", id, url, err);
+                    index = self.endpoints.failed(index);
+                    attempts += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn execute_single_rpc(
+        self: Arc<Self>,
+        id: RequestId,
+        call: Call,
+        timeout: Option<Duration>,
+        retry_policy: Arc<RetryPolicy>,
+    ) -> RpcResult {
+        let json = self
+            .execute_rpc(id, Request::Single(call), timeout, retry_policy)
+            .await?;
         let output = Output::deserialize(json)?;
         let result = helpers::to_result_from_output(output)?;
         Ok(result)
@@ -93,8 +412,12 @@ impl HttpTransportInner {
         self: Arc<Self>,
         id: RequestId,
         request: Vec<Call>,
+        timeout: Option<Duration>,
+        retry_policy: Arc<RetryPolicy>,
     ) -> Result<Vec<RpcResult>, Web3Error> {
-        let result = self.execute_rpc(id, Request::Batch(request)).await?;
+        let result = self
+            .execute_rpc(id, Request::Batch(request), timeout, retry_policy)
+            .await?;
         let sub_results = result.as_array().ok_or_else(|| {
             warn!(
                 "[id:{}] Batch request did not return a list of responses: '{}'",
@@ -116,7 +439,9 @@ impl HttpTransportInner {
 
 impl Debug for HttpTransport {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        f.debug_tuple("HttpTransport").field(&self.0.url).finish()
+        f.debug_tuple("HttpTransport")
+            .field(&self.inner.endpoints.urls)
+            .finish()
     }
 }
 
@@ -124,16 +449,16 @@ impl Transport for HttpTransport {
     type Out = Compat<BoxFuture<'static, RpcResult>>;
 
     fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
-        let id = self.0.id.fetch_add(1, Ordering::SeqCst);
+        let id = self.inner.id.fetch_add(1, Ordering::SeqCst);
         let request = helpers::build_request(id, method, params);
 
         (id, request)
     }
 
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
-        self.0
+        self.inner
             .clone()
-            .execute_single_rpc(id, request)
+            .execute_single_rpc(id, request, self.timeout, self.retry_policy.clone())
             .boxed()
             .compat()
     }
@@ -146,12 +471,541 @@ impl BatchTransport for HttpTransport {
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
-        let id = self.0.id.fetch_add(1, Ordering::SeqCst);
+        let id = self.inner.id.fetch_add(1, Ordering::SeqCst);
         let requests = requests.into_iter().map(|r| r.1).collect();
-        self.0
+        self.inner
             .clone()
-            .execute_batch_rpc(id, requests)
+            .execute_batch_rpc(id, requests, self.timeout, self.retry_policy.clone())
             .boxed()
             .compat()
     }
 }
+
+/// A WebSocket transport that keeps the event loop driving the connection
+/// alive for as long as the transport is in use.
+#[derive(Clone)]
+pub struct WebSocketTransport {
+    url: String,
+    inner: WebSocket,
+    _event_loop: Arc<EventLoopHandle>,
+}
+
+impl WebSocketTransport {
+    /// Creates a new WebSocket transport connected to the specified node.
+    pub fn new(url: impl Into<String>) -> Result<WebSocketTransport, Error> {
+        let url = url.into();
+        let (event_loop, inner) = WebSocket::new(&url)?;
+        Ok(WebSocketTransport {
+            url,
+            inner,
+            _event_loop: Arc::new(event_loop),
+        })
+    }
+}
+
+impl WebSocketTransport {
+    /// Subscribes to the logs matching the specified filter with
+    /// `eth_subscribe`, so that new logs are pushed by the node instead of
+    /// being polled for. Unsubscribes once the returned stream is dropped.
+    pub fn subscribe_logs(&self, filter: Filter) -> BoxStream<'static, Result<Log, Web3Error>> {
+        Web3::new(self.inner.clone())
+            .eth_subscribe()
+            .subscribe_logs(filter)
+            .flatten_stream()
+            .compat()
+            .boxed()
+    }
+}
+
+impl Debug for WebSocketTransport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("WebSocketTransport")
+            .field(&self.url)
+            .finish()
+    }
+}
+
+type BoxFuture01<T> = Box<dyn Future01<Item = T, Error = Web3Error> + Send>;
+
+/// A transport that connects to a node either over HTTP or over WebSockets
+/// depending on the node URL.
+#[derive(Clone, Debug)]
+pub enum NodeTransport {
+    Http(HttpTransport),
+    WebSocket(WebSocketTransport),
+}
+
+impl NodeTransport {
+    /// Creates a new transport for the specified node URL. URLs with a `ws` or
+    /// `wss` scheme use a WebSocket connection, all other URLs use HTTP.
+    ///
+    /// Note that the timeout and RPC logging only apply to HTTP connections.
+    pub fn new(
+        http_factory: &HttpFactory,
+        url: &str,
+        timeout: Duration,
+        rpc_log_max_body_length: Option<usize>,
+    ) -> Result<NodeTransport, Error> {
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            Ok(NodeTransport::WebSocket(WebSocketTransport::new(url)?))
+        } else {
+            Ok(NodeTransport::Http(HttpTransport::new(
+                http_factory,
+                url,
+                timeout,
+                rpc_log_max_body_length,
+            )?))
+        }
+    }
+}
+
+impl NodeTransport {
+    /// Returns a transport sharing the connection of this one, that uses the
+    /// specified timeout for its requests instead of the default.
+    ///
+    /// Note that WebSocket connections do not support timeouts and are
+    /// returned unchanged.
+    pub fn with_timeout(&self, timeout: Duration) -> NodeTransport {
+        match self {
+            NodeTransport::Http(transport) => NodeTransport::Http(transport.with_timeout(timeout)),
+            NodeTransport::WebSocket(_) => self.clone(),
+        }
+    }
+
+    /// Returns a transport sharing the connection of this one, that retries
+    /// requests after transient failures according to the specified policy.
+    ///
+    /// Note that WebSocket connections are returned unchanged.
+    pub fn with_retry_policy(&self, retry_policy: RetryPolicy) -> NodeTransport {
+        match self {
+            NodeTransport::Http(transport) => {
+                NodeTransport::Http(transport.with_retry_policy(retry_policy))
+            }
+            NodeTransport::WebSocket(_) => self.clone(),
+        }
+    }
+}
+
+impl Transport for NodeTransport {
+    type Out = BoxFuture01<Value>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        match self {
+            NodeTransport::Http(transport) => transport.prepare(method, params),
+            NodeTransport::WebSocket(transport) => transport.inner.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        match self {
+            NodeTransport::Http(transport) => Box::new(transport.send(id, request)),
+            NodeTransport::WebSocket(transport) => Box::new(transport.inner.send(id, request)),
+        }
+    }
+}
+
+impl BatchTransport for NodeTransport {
+    type Batch = BoxFuture01<Vec<RpcResult>>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        match self {
+            NodeTransport::Http(transport) => Box::new(transport.send_batch(requests)),
+            NodeTransport::WebSocket(transport) => Box::new(transport.inner.send_batch(requests)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test_util {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Starts a single use HTTP server that responds to one request with the
+    /// specified body and returns its URL.
+    pub fn mock_node(response: &str) -> String {
+        mock_node_with_statuses(&[(200, response)])
+    }
+
+    /// Starts an HTTP server that responds to each of the specified number
+    /// of requests with the corresponding status and body and returns its URL.
+    pub fn mock_node_with_statuses(responses: &[(u16, &str)]) -> String {
+        let responses = responses
+            .iter()
+            .map(|(status, body)| (*status, body.to_string()))
+            .collect::<Vec<_>>();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (status, response) in responses {
+                respond(&listener, status, &response);
+            }
+        });
+        url
+    }
+
+    /// Accepts a connection and responds to its request.
+    fn respond(listener: &TcpListener, status: u16, response: &str) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim().to_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(length) = line.strip_prefix("content-length:") {
+                content_length = length.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            concat!(
+                "HTTP/1.1 {} Mock\r\n",
+                "Content-Type: application/json\r\n",
+                "Content-Length: {}\r\n",
+                "Connection: close\r\n",
+                "\r\n",
+                "{}",
+            ),
+            status,
+            response.len(),
+            response,
+        )
+        .unwrap();
+    }
+
+    /// Returns the URL of a node that refuses connections.
+    pub fn unreachable_node() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    /// Starts an HTTP server that accepts a connection but never responds and
+    /// returns its URL.
+    pub fn silent_node() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            thread::park();
+        });
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::{mock_node, mock_node_with_statuses, silent_node, unreachable_node};
+    use super::*;
+    use crate::logging::test_util::capture_logs;
+
+    use crate::util::test_util::MockClock;
+    use ethcontract::web3::futures::Future as _;
+    use std::time::Instant;
+
+    #[test]
+    fn timeout_override() {
+        let transport =
+            HttpTransport::new(&HttpFactory::default(), "", Duration::from_secs(10), None).unwrap();
+        assert_eq!(transport.timeout, None);
+
+        let overridden = transport.with_timeout(Duration::from_secs(60));
+        assert_eq!(overridden.timeout, Some(Duration::from_secs(60)));
+        assert!(Arc::ptr_eq(&transport.inner, &overridden.inner));
+    }
+
+    /// Asserts that a request failed with a timeout error.
+    fn assert_timed_out(result: RpcResult) {
+        match result {
+            Err(Web3Error::Transport(message)) => {
+                assert_eq!(message, isahc::Error::Timeout.to_string())
+            }
+            result => panic!("request did not time out: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn timeout_override_applies_to_requests() {
+        let transport = HttpTransport::new(
+            &HttpFactory::default(),
+            silent_node(),
+            Duration::from_secs(60),
+            None,
+        )
+        .unwrap()
+        .with_timeout(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let result = transport.execute("eth_blockNumber", vec![]).wait();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_timed_out(result);
+    }
+
+    #[test]
+    fn truncates_long_bodies() {
+        assert_eq!(truncate_body("short", 10), "short");
+        assert_eq!(
+            truncate_body("0123456789", 4),
+            "0123... (6 bytes truncated)"
+        );
+        // Never splits a multi-byte character.
+        assert_eq!(truncate_body("aé", 2), "a... (2 bytes truncated)");
+    }
+
+    #[test]
+    fn formats_rpc_log_messages() {
+        assert_eq!(
+            rpc_log_message(1, "request", " {\"method\":\"eth_blockNumber\"}\n", 64),
+            r#"[id:1] rpc request: '{"method":"eth_blockNumber"}'"#
+        );
+        assert_eq!(
+            rpc_log_message(2, "response", "0123456789", 4),
+            "[id:2] rpc response: '0123... (6 bytes truncated)'"
+        );
+    }
+
+    #[test]
+    fn logs_rpc_request_and_response() {
+        let url = mock_node(r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#);
+        let transport = HttpTransport::new(
+            &HttpFactory::default(),
+            url,
+            Duration::from_secs(10),
+            Some(64),
+        )
+        .unwrap();
+
+        let logs = capture_logs(Level::Trace, || {
+            let result = transport
+                .execute("eth_blockNumber", vec![Value::from("logging_test")])
+                .wait()
+                .unwrap();
+            assert_eq!(result, Value::from("0x2a"));
+        });
+
+        let rpc_logs = logs
+            .iter()
+            .filter(|log| log.contains("] rpc "))
+            .collect::<Vec<_>>();
+        assert_eq!(rpc_logs.len(), 2);
+        assert!(rpc_logs[0].starts_with("[id:0] rpc request: '"));
+        assert!(rpc_logs[0].contains("eth_blockNumber"));
+        assert!(rpc_logs[0].contains("bytes truncated"));
+        assert_eq!(
+            rpc_logs[1],
+            r#"[id:0] rpc response: '{"jsonrpc":"2.0","id":0,"result":"0x2a"}'"#
+        );
+    }
+
+    #[test]
+    fn does_not_format_rpc_logs_when_trace_is_disabled() {
+        let url = mock_node(r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#);
+        let transport = HttpTransport::new(
+            &HttpFactory::default(),
+            url,
+            Duration::from_secs(10),
+            Some(64),
+        )
+        .unwrap();
+
+        let logs = capture_logs(Level::Info, || {
+            log_lazy(Level::Trace, || panic!("disabled log message was built"));
+            transport.execute("eth_blockNumber", vec![]).wait().unwrap();
+        });
+        assert!(logs.is_empty(), "{:?}", logs);
+    }
+
+    #[test]
+    fn request_timeout_applies_to_slow_nodes() {
+        let factory = HttpFactory::default().with_connect_timeout(Duration::from_secs(5));
+        let transport =
+            HttpTransport::new(&factory, silent_node(), Duration::from_millis(100), None).unwrap();
+
+        let start = Instant::now();
+        let result = transport.execute("eth_blockNumber", vec![]).wait();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_timed_out(result);
+    }
+
+    #[test]
+    fn fails_over_to_next_node() {
+        let transport = HttpTransport::with_failover(
+            &HttpFactory::default(),
+            vec![
+                unreachable_node(),
+                mock_node(r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#),
+            ],
+            Duration::from_secs(60),
+            Duration::from_secs(10),
+            None,
+        )
+        .unwrap();
+
+        let result = transport.execute("eth_blockNumber", vec![]).wait();
+        assert_eq!(result.unwrap(), Value::from("0x2a"));
+        assert_eq!(transport.inner.endpoints.active(), 1);
+    }
+
+    #[test]
+    fn fails_over_to_next_node_on_timeout() {
+        let transport = HttpTransport::with_failover(
+            &HttpFactory::default(),
+            vec![
+                silent_node(),
+                mock_node(r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#),
+            ],
+            Duration::from_secs(60),
+            Duration::from_millis(100),
+            None,
+        )
+        .unwrap();
+
+        let result = transport.execute("eth_blockNumber", vec![]).wait();
+        assert_eq!(result.unwrap(), Value::from("0x2a"));
+        assert_eq!(transport.inner.endpoints.active(), 1);
+    }
+
+    #[test]
+    fn does_not_fail_over_transactions_on_timeout() {
+        let transport = HttpTransport::with_failover(
+            &HttpFactory::default(),
+            vec![
+                silent_node(),
+                mock_node(r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#),
+            ],
+            Duration::from_secs(60),
+            Duration::from_millis(100),
+            None,
+        )
+        .unwrap();
+
+        let result = transport
+            .execute("eth_sendRawTransaction", vec![Value::from("0x00")])
+            .wait();
+        assert!(result.is_err());
+        assert_eq!(transport.inner.endpoints.active(), 0);
+    }
+
+    #[test]
+    fn distinguishes_connection_errors_from_timeouts() {
+        assert!(is_connection_error(&isahc::Error::ConnectFailed.into()));
+        assert!(!is_connection_error(&isahc::Error::Timeout.into()));
+        assert!(!is_connection_error(&isahc::Error::TooManyRedirects.into()));
+        assert!(is_timeout(&isahc::Error::Timeout.into()));
+        assert!(!is_timeout(&isahc::Error::ConnectFailed.into()));
+    }
+
+    #[test]
+    fn returns_to_primary_node_after_cooldown() {
+        let clock = Arc::new(MockClock::new());
+        let endpoints = Endpoints::new(
+            vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+            Duration::from_secs(60),
+            clock.clone(),
+        )
+        .unwrap();
+        assert_eq!(endpoints.active(), 0);
+
+        assert_eq!(endpoints.failed(0), 1);
+        assert_eq!(endpoints.failed(1), 2);
+        // A request that was still using the first node fails after the
+        // transport already failed over.
+        assert_eq!(endpoints.failed(0), 1);
+        assert_eq!(endpoints.active(), 2);
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(endpoints.active(), 2);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(endpoints.active(), 0);
+    }
+
+    #[test]
+    fn requires_a_node_url() {
+        assert!(Endpoints::new(vec![], Duration::default(), Arc::new(SystemClock)).is_err());
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            methods: ["eth_call", "eth_sendRawTransaction"]
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn retries_idempotent_requests() {
+        let url = mock_node_with_statuses(&[
+            (503, "unavailable"),
+            (200, r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#),
+        ]);
+        let transport =
+            HttpTransport::new(&HttpFactory::default(), url, Duration::from_secs(10), None)
+                .unwrap()
+                .with_retry_policy(retry_policy());
+
+        let result = transport.execute("eth_call", vec![]).wait();
+        assert_eq!(result.unwrap(), Value::from("0x2a"));
+    }
+
+    #[test]
+    fn never_retries_sending_transactions() {
+        let url = mock_node_with_statuses(&[
+            (503, "unavailable"),
+            (200, r#"{"jsonrpc":"2.0","id":0,"result":"0x2a"}"#),
+        ]);
+        let transport =
+            HttpTransport::new(&HttpFactory::default(), url, Duration::from_secs(10), None)
+                .unwrap()
+                .with_retry_policy(retry_policy());
+
+        let result = transport.execute("eth_sendRawTransaction", vec![]).wait();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn does_not_retry_timeouts() {
+        let transport = HttpTransport::new(
+            &HttpFactory::default(),
+            silent_node(),
+            Duration::from_millis(100),
+            None,
+        )
+        .unwrap()
+        .with_retry_policy(retry_policy());
+
+        let mut result = None;
+        let logs = capture_logs(Level::Warn, || {
+            result = Some(transport.execute("eth_call", vec![]).wait());
+        });
+        assert_timed_out(result.unwrap());
+        assert!(
+            !logs.iter().any(|log| log.contains("retrying")),
+            "{:?}",
+            logs
+        );
+    }
+
+    #[test]
+    fn retry_backoff_is_exponential_and_bounded() {
+        let policy = retry_policy();
+        assert_eq!(policy.backoff(0), Duration::from_millis(1));
+        assert_eq!(policy.backoff(1), Duration::from_millis(2));
+        assert_eq!(policy.backoff(3), Duration::from_millis(8));
+        assert_eq!(policy.backoff(4), Duration::from_millis(10));
+        assert_eq!(policy.backoff(100), Duration::from_millis(10));
+    }
+}
@@ -1,5 +1,6 @@
 use ethcontract::U256;
 use std::future::Future;
+use std::time::Instant;
 
 pub trait CeiledDiv {
     fn ceiled_div(&self, divisor: Self) -> Self;
@@ -33,6 +34,20 @@ impl CheckedConvertU128 for U256 {
     }
 }
 
+pub trait CheckedConvertU32 {
+    fn as_u32_checked(&self) -> Option<u32>;
+}
+
+impl CheckedConvertU32 for U256 {
+    fn as_u32_checked(&self) -> Option<u32> {
+        if *self <= U256::from(u32::max_value()) {
+            Some(self.low_u32())
+        } else {
+            None
+        }
+    }
+}
+
 pub trait FutureWaitExt: Future {
     fn wait(self) -> Self::Output;
 }
@@ -46,14 +61,56 @@ where
     }
 }
 
+/// A source of the current time, so that components measuring time can be
+/// tested without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The clock of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
 #[cfg(test)]
 pub mod test_util {
+    use super::Clock;
     use std::collections::HashMap;
     use std::hash::Hash;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
 
     pub fn map_from_slice<T: Copy + Eq + Hash, U: Copy>(arr: &[(T, U)]) -> HashMap<T, U> {
         arr.iter().copied().collect()
     }
+
+    /// A clock that stands still until it is advanced manually.
+    pub struct MockClock {
+        now: Mutex<Instant>,
+    }
+
+    impl MockClock {
+        pub fn new() -> Self {
+            Self {
+                now: Mutex::new(Instant::now()),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -61,6 +118,7 @@ pub mod tests {
     use super::test_util::*;
     use super::*;
     use std::collections::HashMap;
+    use std::time::Duration;
 
     #[test]
     fn test_checked_u256_to_u128() {
@@ -76,6 +134,19 @@ pub mod tests {
         assert_eq!(None, U256::max_value().as_u128_checked(),);
     }
 
+    #[test]
+    fn test_checked_u256_to_u32() {
+        assert_eq!(Some(42u32), U256::from(42).as_u32_checked());
+        assert_eq!(
+            Some(u32::max_value()),
+            U256::from(u32::max_value()).as_u32_checked(),
+        );
+        assert_eq!(
+            None,
+            (U256::from(u32::max_value()) + U256::one()).as_u32_checked(),
+        );
+    }
+
     #[test]
     fn test_ceiled_div_u128() {
         assert_eq!(0u128.ceiled_div(10), 0);
@@ -114,6 +185,15 @@ pub mod tests {
         U256::max_value().ceiled_div(U256::from(1));
     }
 
+    #[test]
+    fn mock_clock_only_advances_manually() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(clock.now() - start, Duration::from_secs(60));
+    }
+
     #[test]
     fn test_map_from_slice() {
         let mut expected = HashMap::new();
@@ -67,11 +67,60 @@ pub fn fill_market_order(c: &mut Criterion) {
     group.finish();
 }
 
+pub fn snapshot_and_restore(c: &mut Criterion) {
+    let dai_weth = TokenPair { buy: 7, sell: 1 };
+    let volume = 10.0 * 10.0f64.powi(18);
+
+    let mut group = c.benchmark_group("Orderbook::fill_market_order(rollback)");
+    group.bench_function("clone", |b| {
+        let orderbook = read_default_orderbook();
+        b.iter(|| {
+            let mut orderbook = orderbook.clone();
+            orderbook.fill_market_order(black_box(dai_weth), volume)
+        })
+    });
+    group.bench_function("snapshot", |b| {
+        let mut orderbook = read_default_orderbook();
+        let snapshot = orderbook.snapshot();
+        b.iter(|| {
+            let price = orderbook.fill_market_order(black_box(dai_weth), volume);
+            orderbook.restore(&snapshot);
+            price
+        })
+    });
+    group.finish();
+}
+
+pub fn fill_transitive_market_order(c: &mut Criterion) {
+    // NOTE: The default orderbook has orders selling token 7 for token 15 but
+    // none selling token 15 for token 7, so there is no direct counterparty
+    // for this market order and every fill has to route through at least one
+    // intermediate token.
+    let transitive_pair = TokenPair { buy: 15, sell: 7 };
+    let eth = 10.0f64.powi(18);
+    let volumes = &[0.1 * eth, eth, 10.0 * eth, 100.0 * eth, 1000.0 * eth];
+
+    let mut group = c.benchmark_group("Orderbook::fill_market_order(transitive)");
+    for volume in volumes {
+        group.bench_with_input(BenchmarkId::from_parameter(volume), volume, |b, &volume| {
+            let orderbook = read_default_orderbook();
+            b.iter_batched(
+                || orderbook.clone(),
+                |mut orderbook| orderbook.fill_market_order(black_box(transitive_pair), volume),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     read,
     is_overlapping,
     reduce_overlapping_orders,
     fill_market_order,
+    snapshot_and_restore,
+    fill_transitive_market_order,
 );
 criterion_main!(benches);
@@ -25,6 +25,27 @@ pub struct TokenPair {
     pub sell: TokenId,
 }
 
+impl TokenPair {
+    /// Returns the token pair with the buy and sell tokens swapped.
+    pub fn inverse(self) -> TokenPair {
+        TokenPair {
+            buy: self.sell,
+            sell: self.buy,
+        }
+    }
+
+    /// Returns the canonical ordering of the token pair, where the buy token
+    /// is never greater than the sell token, along with whether or not the
+    /// pair was inverted. This maps both sides of a market to the same pair.
+    pub fn normalized(self) -> (TokenPair, bool) {
+        if self.buy > self.sell {
+            (self.inverse(), true)
+        } else {
+            (self, false)
+        }
+    }
+}
+
 /// A struct representing the validity of an order.
 #[derive(Debug, PartialEq)]
 pub struct Validity {
@@ -121,6 +142,23 @@ pub struct InvalidLength(usize);
 mod tests {
     use super::*;
 
+    #[test]
+    fn token_pair_inverse_round_trips() {
+        let pair = TokenPair { buy: 1, sell: 2 };
+        assert_eq!(pair.inverse(), TokenPair { buy: 2, sell: 1 });
+        assert_eq!(pair.inverse().inverse(), pair);
+    }
+
+    #[test]
+    fn token_pair_normalization() {
+        let pair = TokenPair { buy: 1, sell: 2 };
+        assert_eq!(pair.normalized(), (pair, false));
+        assert_eq!(pair.inverse().normalized(), (pair, true));
+
+        let same = TokenPair { buy: 3, sell: 3 };
+        assert_eq!(same.normalized(), (same, false));
+    }
+
     #[test]
     #[allow(clippy::unreadable_literal)]
     fn read_all_elements() {
@@ -7,5 +7,6 @@ mod orderbook;
 #[path = "../data/mod.rs"]
 mod data;
 
-pub use encoding::{TokenId, TokenPair};
-pub use orderbook::Orderbook;
+pub use encoding::{Element, Price, TokenId, TokenPair, UserId, Validity};
+pub use num::TokenDecimals;
+pub use orderbook::{FillSummary, Orderbook, OrderbookSnapshot};
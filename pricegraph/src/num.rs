@@ -14,6 +14,34 @@ pub fn u256_to_f64(u: U256) -> f64 {
     (u.low_u128() as f64) * factor
 }
 
+/// The number of decimals of a token, used for converting between amounts in
+/// token units (e.g. `1.5` WETH) and amounts in atoms, the smallest
+/// denomination of a token that is used by the orderbook.
+///
+/// Conversions are done with `f64` arithmetic which has a 53-bit mantissa, so
+/// scaled amounts are only exact up to about 15 significant decimal digits.
+/// For tokens with 18 decimals this means that amounts above around 0.009
+/// tokens are no longer exact to the atom, which is well below the precision
+/// that is relevant for estimating prices.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TokenDecimals(pub u8);
+
+impl TokenDecimals {
+    /// Converts an amount in token units into atoms.
+    pub fn to_atoms(self, amount: f64) -> f64 {
+        amount * self.scale()
+    }
+
+    /// Converts an amount in atoms into token units.
+    pub fn from_atoms(self, atoms: f64) -> f64 {
+        atoms / self.scale()
+    }
+
+    fn scale(self) -> f64 {
+        10.0f64.powi(self.0 as i32)
+    }
+}
+
 /// Calculates the minimum of two floats. Note that we cannot use the standard
 /// library `std::cmp::min` here since `f64` does not implement `Ord`. This be
 /// because there is no real ordering for `NaN`s and `NaN < 0 == false` and
@@ -31,3 +59,17 @@ pub fn min(a: f64, b: f64) -> f64 {
         _ => b,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_between_units_and_atoms() {
+        assert_eq!(TokenDecimals(6).to_atoms(1.5), 1_500_000.0);
+        assert_eq!(TokenDecimals(6).from_atoms(1_500_000.0), 1.5);
+        assert_eq!(TokenDecimals(18).to_atoms(2.0), 2e18);
+        assert_eq!(TokenDecimals(18).from_atoms(2e18), 2.0);
+        assert_eq!(TokenDecimals(0).to_atoms(42.0), 42.0);
+    }
+}
@@ -10,13 +10,14 @@ mod user;
 
 use self::order::{Order, OrderCollector, OrderMap, FEE_FACTOR};
 use self::user::{User, UserMap};
-use crate::encoding::{Element, InvalidLength, TokenId, TokenPair};
+use crate::encoding::{Element, InvalidLength, TokenId, TokenPair, UserId};
 use crate::graph::bellman_ford::{self, NegativeCycle};
 use crate::graph::path;
 use crate::graph::subgraph::{ControlFlow, Subgraphs};
-use crate::num;
+use crate::num::{self, TokenDecimals};
 use petgraph::graph::{DiGraph, EdgeIndex, NodeIndex};
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::f64;
 use thiserror::Error;
 
@@ -33,6 +34,12 @@ pub struct Orderbook {
     /// A projection of the order book onto a graph of lowest priced orders
     /// between tokens.
     projection: DiGraph<TokenId, f64>,
+    /// The changes to orders and user balances since the oldest unreleased
+    /// snapshot was taken, which are undone when restoring a snapshot.
+    journal: Vec<Change>,
+    /// The number of snapshots that were taken and not yet released. Changes
+    /// are only recorded while there are unreleased snapshots.
+    snapshots: usize,
 }
 
 impl Orderbook {
@@ -44,7 +51,7 @@ impl Orderbook {
     }
 
     /// Creates an orderbook from an iterator over decoded auction elements.
-    fn from_elements(elements: impl IntoIterator<Item = Element>) -> Self {
+    pub fn from_elements(elements: impl IntoIterator<Item = Element>) -> Self {
         let mut max_token = 0;
         let mut orders = OrderCollector::default();
         let mut users = UserMap::default();
@@ -70,24 +77,125 @@ impl Orderbook {
             // be.
             debug_assert_eq!(token_node, node_index(token_id));
         }
-        projection.extend_with_edges(orders.all_pairs().map({
-            |(pair, orders)| {
-                let cheapest_order = orders
-                    .last()
-                    .expect("unexpected token pair in orders map without any orders");
-                (
-                    node_index(pair.buy),
-                    node_index(pair.sell),
-                    cheapest_order.weight(),
-                )
-            }
-        }));
+        add_projection_edges(&mut projection, &orders);
 
         Orderbook {
             orders,
             users,
             projection,
+            journal: Vec::new(),
+            snapshots: 0,
+        }
+    }
+
+    /// Returns a copy of the orderbook with an additional hypothetical order.
+    ///
+    /// This allows estimating the effect placing an order would have, for
+    /// example on `fill_market_order` or `is_overlapping`, without modifying
+    /// the original orderbook.
+    pub fn with_hypothetical_order(&self, element: Element) -> Self {
+        let mut orderbook = self.clone();
+        // NOTE: Snapshots of this orderbook cannot be restored on the copy, so
+        //   it starts out without a journal.
+        orderbook.journal.clear();
+        orderbook.snapshots = 0;
+        orderbook.insert_order(element);
+        orderbook
+    }
+
+    /// Takes a snapshot of the orders and user balances, the parts of the
+    /// orderbook that are modified when filling orders.
+    ///
+    /// This allows simulating fills, for example with `fill_market_order`,
+    /// and then rolling them back with `restore` instead of filling a clone of
+    /// the complete orderbook. Taking a snapshot is cheap, after it is taken
+    /// the orderbook records the previous order amounts and user balances
+    /// whenever they change, so that they can be put back.
+    pub fn snapshot(&mut self) -> OrderbookSnapshot {
+        self.snapshots += 1;
+        OrderbookSnapshot(self.journal.len())
+    }
+
+    /// Restores the orders and user balances from a snapshot, undoing all
+    /// fills since it was taken. The projection graph is recomputed from the
+    /// restored orders.
+    ///
+    /// Note that the snapshot is not consumed, so that the same snapshot can
+    /// be restored after each of multiple simulated fills.
+    pub fn restore(&mut self, snapshot: &OrderbookSnapshot) {
+        let Self {
+            orders,
+            users,
+            projection,
+            journal,
+            ..
+        } = self;
+
+        while journal.len() > snapshot.0 {
+            match journal.pop().expect("journal unexpectedly empty") {
+                Change::OrderAmount { pair, amount } => {
+                    orders
+                        .best_order_for_pair_mut(pair)
+                        .expect("missing order for journaled amount")
+                        .amount = amount;
+                }
+                Change::Balance {
+                    user,
+                    token,
+                    balance,
+                } => users
+                    .get_mut(&user)
+                    .expect("missing user for journaled balance")
+                    .set_balance(token, balance),
+                Change::RemovedOrder(order) => orders.push_pair_order(order),
+            }
+        }
+
+        projection.clear_edges();
+        add_projection_edges(projection, orders);
+    }
+
+    /// Releases a snapshot that is no longer needed, keeping all changes since
+    /// it was taken. Changes are kept in the journal while older snapshots
+    /// that can still undo them remain, and the journal is truncated once the
+    /// last snapshot is released so that it doesn't grow with snapshots that
+    /// are taken one after the other.
+    pub fn release(&mut self, _: OrderbookSnapshot) {
+        self.snapshots = self.snapshots.saturating_sub(1);
+        if self.snapshots == 0 {
+            self.journal.clear();
+        }
+    }
+
+    /// Records a change to the orderbook if there are unreleased snapshots.
+    fn record_change(&mut self, change: Change) {
+        if self.snapshots > 0 {
+            self.journal.push(change);
+        }
+    }
+
+    /// Inserts a single order into the orderbook, updating the projection
+    /// graph accordingly.
+    fn insert_order(&mut self, element: Element) {
+        let pair = element.pair;
+        let order_id = self
+            .users
+            .entry(element.user)
+            .or_default()
+            .include_order(&element);
+        self.orders.insert_order(Order::new(element, order_id));
+
+        let max_token = cmp::max(pair.buy, pair.sell) as usize;
+        while self.projection.node_count() <= max_token {
+            let token_id = self.projection.node_count() as TokenId;
+            let token_node = self.projection.add_node(token_id);
+            debug_assert_eq!(token_node, node_index(token_id));
+        }
+        if self.get_pair_edge(pair).is_none() {
+            self.projection
+                .add_edge(node_index(pair.buy), node_index(pair.sell), f64::INFINITY);
         }
+        self.update_projection_graph_edge(pair);
     }
 
     /// Returns the number of orders in the orderbook.
@@ -128,10 +236,99 @@ impl Orderbook {
             .unwrap_or(false)
     }
 
-    /// Reduces the orderbook by matching all overlapping ring trades.
-    pub fn reduce_overlapping_orders(&mut self) {
+    /// Returns the token pairs along the overlapping ring trades that make the
+    /// orderbook overlap, or an empty vector if it does not overlap. For ring
+    /// trades between two tokens these are the pairs where the best bid
+    /// crosses the best ask.
+    ///
+    /// Note that at most one ring trade is reported for each disconnected
+    /// subgraph, so reducing the overlapping ring trades may reveal others.
+    pub fn overlapping_pairs(&self) -> Vec<TokenPair> {
+        // NOTE: Like `is_overlapping`, negative cycles are detected from each
+        // disconnected subgraph and only reported if they are connected to the
+        // fee token.
+
+        let mut pairs = Vec::new();
+        Subgraphs::new(self.projection.node_indices().skip(1)).for_each(|token| {
+            match bellman_ford::search(&self.projection, token) {
+                Ok((_, predecessor)) => predecessor,
+                Err(NegativeCycle(predecessor, node)) => {
+                    if predecessor[0].is_some() {
+                        // The negative cycle is connected to the fee token.
+                        let mut cycle = path::find_cycle(&predecessor, node)
+                            .expect("negative cycle not found after being detected");
+                        cycle.push(cycle[0]);
+                        pairs.extend(pairs_on_path(&cycle));
+                    }
+                    predecessor
+                }
+            }
+        });
+
+        pairs
+    }
+
+    /// Reduces the orderbook by matching all overlapping ring trades,
+    /// returning a summary of the orders that were filled in the process.
+    pub fn reduce_overlapping_orders(&mut self) -> FillSummary {
+        let mut summary = FillSummary::default();
         Subgraphs::new(self.projection.node_indices())
-            .for_each(|token| self.reduced_shortest_paths(token));
+            .for_each(|token| self.reduced_shortest_paths(token, &mut summary));
+        summary
+    }
+
+    /// Estimates the uniform clearing prices that would settle the overlapping
+    /// orders of the orderbook. Prices are denominated in the fee token, i.e.
+    /// the fee token has a price of `1.0` and every other price is the value
+    /// of one atom of a token in atoms of the fee token.
+    ///
+    /// This is done by reducing a copy of the orderbook and then computing the
+    /// shortest distances from the fee token in a graph with an edge for each
+    /// remaining token pair, so that remaining orders do not overlap at the
+    /// estimated prices, and a reversed edge with negated weight for each
+    /// order that was matched during the reduction, so that the limit prices
+    /// of matched orders are respected. Prices are only returned for tokens
+    /// that are reachable from the fee token in this graph.
+    ///
+    /// Note that the reduction does not necessarily find an optimal matching,
+    /// so there might not be a price vector that respects the limit prices of
+    /// all matched orders. In that case, prices are computed from the
+    /// remaining orders only.
+    pub fn clearing_prices(&self) -> HashMap<TokenId, f64> {
+        if self.projection.node_count() == 0 {
+            return HashMap::new();
+        }
+
+        let mut reduced = self.clone();
+        let summary = reduced.reduce_overlapping_orders();
+        reduced.update_projection_graph();
+
+        let mut constraints = reduced.projection.clone();
+        for (pair, orders) in self.orders.all_pairs() {
+            for order in orders
+                .iter()
+                .filter(|order| summary.filled_orders.contains(&(order.user, order.index)))
+            {
+                constraints.add_edge(node_index(pair.sell), node_index(pair.buy), -order.weight());
+            }
+        }
+
+        let fee_token = node_index(0);
+        let distances = match bellman_ford::search(&constraints, fee_token) {
+            Ok((distances, _)) => distances,
+            Err(_) => {
+                bellman_ford::search(&reduced.projection, fee_token)
+                    .expect("negative cycle in reduced orderbook")
+                    .0
+            }
+        };
+
+        distances
+            .into_iter()
+            .enumerate()
+            .filter(|(_, distance)| distance.is_finite())
+            .map(|(node, distance)| (node as TokenId, distance.exp2()))
+            .collect()
     }
 
     /// Fill a market order in the current orderbook graph returning the maximum
@@ -152,7 +349,9 @@ impl Orderbook {
         self.update_projection_graph();
 
         let (sell, buy) = (node_index(pair.sell), node_index(pair.buy));
-        let predecessors = self.reduced_shortest_paths(sell);
+        // NOTE: Orders filled by the market order are currently not reported.
+        let mut summary = FillSummary::default();
+        let predecessors = self.reduced_shortest_paths(sell, &mut summary);
         let mut path = path::find_path(&predecessors, sell, buy)?;
 
         // NOTE: The transient price of a path is the price of the sell token
@@ -181,12 +380,13 @@ impl Orderbook {
         let mut remaining_volume = volume;
         let mut last_transient_price: f64;
         while {
-            let (capacity, transient_price) = self.fill_path(&path).unwrap_or_else(|| {
-                panic!(
-                    "failed to fill detected shortest path {}",
-                    format_path(&path),
-                )
-            });
+            let (capacity, transient_price) =
+                self.fill_path(&path, &mut summary).unwrap_or_else(|| {
+                    panic!(
+                        "failed to fill detected shortest path {}",
+                        format_path(&path),
+                    )
+                });
             remaining_volume -= capacity;
             last_transient_price = transient_price;
 
@@ -200,6 +400,48 @@ impl Orderbook {
         Some(invert_price(last_transient_price))
     }
 
+    /// Fills multiple market orders in sequence, returning the price for each
+    /// order as returned by `fill_market_order`.
+    ///
+    /// Orders are filled against the same orderbook one after the other, so
+    /// later orders only see the liquidity that was not already consumed by
+    /// earlier ones. This allows estimating the price impact of trading a
+    /// basket of tokens. Note that orders that cannot be filled may still
+    /// partially consume the orderbook before failing. Orders for tokens that
+    /// are not part of the orderbook cannot be filled.
+    pub fn fill_market_orders(&mut self, orders: &[(TokenPair, f64)]) -> Vec<Option<f64>> {
+        orders
+            .iter()
+            .map(|&(pair, volume)| {
+                if !self.has_token(pair.sell) || !self.has_token(pair.buy) {
+                    return None;
+                }
+                self.fill_market_order(pair, volume)
+            })
+            .collect()
+    }
+
+    /// Returns whether the token is a node of the projection graph.
+    fn has_token(&self, token: TokenId) -> bool {
+        (token as usize) < self.projection.node_count()
+    }
+
+    /// Fill a market order for an amount specified in units of the sell token
+    /// instead of atoms, returning the price as returned by
+    /// `fill_market_order`.
+    ///
+    /// The amount is scaled with the specified sell token decimals, see
+    /// `TokenDecimals` for the precision of the conversion. Note that the
+    /// returned price is still an exchange rate between atoms.
+    pub fn fill_market_order_with_decimals(
+        &mut self,
+        pair: TokenPair,
+        amount: f64,
+        sell_decimals: TokenDecimals,
+    ) -> Option<f64> {
+        self.fill_market_order(pair, sell_decimals.to_atoms(amount))
+    }
+
     /// Calculates the shortest paths from the start token to all other tokens
     /// using Bellman-Ford path finding algorithm after removing all filled
     /// orders and negative cycles. Returns a vector of predecessors for each,
@@ -207,7 +449,11 @@ impl Orderbook {
     ///
     /// Note that while this method does update the graph, it is idempotent and
     /// multiple calls with the same starting token will yield the same paths.
-    fn reduced_shortest_paths(&mut self, start: NodeIndex) -> Vec<Option<NodeIndex>> {
+    fn reduced_shortest_paths(
+        &mut self,
+        start: NodeIndex,
+        summary: &mut FillSummary,
+    ) -> Vec<Option<NodeIndex>> {
         loop {
             match bellman_ford::search(&self.projection, start) {
                 Ok((_, predecessors)) => return predecessors,
@@ -219,7 +465,7 @@ impl Orderbook {
                         cycle
                     };
 
-                    self.fill_path(&path).unwrap_or_else(|| {
+                    self.fill_path(&path, summary).unwrap_or_else(|| {
                         panic!(
                             "failed to fill path along detected negative cycle {}",
                             format_path(&path),
@@ -267,7 +513,9 @@ impl Orderbook {
             .best_order_for_pair(pair)
             .map(|order| order.get_effective_amount(&self.users) <= 0.0)
         {
-            self.orders.remove_pair_order(pair);
+            if let Some(order) = self.orders.remove_pair_order(pair) {
+                self.record_change(Change::RemovedOrder(order));
+            }
         }
 
         let edge = self.get_pair_edge(pair).unwrap_or_else(|| {
@@ -294,13 +542,14 @@ impl Orderbook {
     /// the remaining order amounts and user balances along the way, returning
     /// the amount of flow that left the first node in the path and the final
     /// transient price (i.e. the final price of the last node as a result of
-    /// trading along the path) or `None` if the path was invalid.
+    /// trading along the path) or `None` if the path was invalid. The filled
+    /// orders are recorded in `summary`.
     ///
     /// Note that currently, user buy token balances are not incremented as a
     /// result of filling orders along a path.
-    fn fill_path(&mut self, path: &[NodeIndex]) -> Option<(f64, f64)> {
+    fn fill_path(&mut self, path: &[NodeIndex], summary: &mut FillSummary) -> Option<(f64, f64)> {
         let (capacity, price) = self.find_path_capacity_and_price(path)?;
-        self.fill_path_with_capacity(path, capacity)
+        self.fill_path_with_capacity(path, capacity, summary)
             .unwrap_or_else(|_| {
                 panic!(
                     "failed to fill with capacity along detected path {}",
@@ -338,6 +587,7 @@ impl Orderbook {
         &mut self,
         path: &[NodeIndex],
         capacity: f64,
+        summary: &mut FillSummary,
     ) -> Result<(), IncompletePathError> {
         let mut transient_price = 1.0;
         for pair in pairs_on_path(path) {
@@ -350,13 +600,26 @@ impl Orderbook {
             // NOTE: `capacity` here is a buy amount, so we need to divide by
             // the price to get the sell amount being filled.
             let fill_amount = capacity / transient_price;
+            summary.record_fill(order, fill_amount);
 
+            let amount_change = Change::OrderAmount {
+                pair,
+                amount: order.amount,
+            };
+            let balance_change = Change::Balance {
+                user: order.user,
+                token: order.pair.sell,
+                balance: user.balance_of(order.pair.sell),
+            };
             order.amount -= fill_amount;
             let new_balance = user.deduct_from_balance(order.pair.sell, fill_amount);
+            let order_filled = order.amount <= 0.0;
+            self.record_change(amount_change);
+            self.record_change(balance_change);
 
             if new_balance.is_none() {
                 self.update_projection_graph_node(pair.sell);
-            } else if order.amount <= 0.0 {
+            } else if order_filled {
                 self.update_projection_graph_edge(pair);
             }
         }
@@ -382,6 +645,86 @@ impl Orderbook {
     }
 }
 
+/// A snapshot of the orders and user balances of an orderbook that can be
+/// restored with `Orderbook::restore`. It refers to a position in the
+/// orderbook's journal of changes, so it can only be restored on the
+/// orderbook it was taken from and until it is released with
+/// `Orderbook::release`.
+#[derive(Debug)]
+pub struct OrderbookSnapshot(usize);
+
+/// A change to an orderbook caused by filling orders, recording the previous
+/// state so that it can be undone.
+#[derive(Clone, Debug)]
+enum Change {
+    /// The amount of the cheapest order of a token pair changed.
+    OrderAmount { pair: TokenPair, amount: f64 },
+    /// The balance of a user changed.
+    Balance {
+        user: UserId,
+        token: TokenId,
+        balance: f64,
+    },
+    /// The cheapest order of a token pair was removed.
+    RemovedOrder(Order),
+}
+
+/// A summary of the orders that were filled when matching orders in the
+/// orderbook.
+#[derive(Clone, Debug, Default)]
+pub struct FillSummary {
+    /// The orders that were filled or partially filled, identified by their
+    /// user and order index.
+    filled_orders: HashSet<(UserId, usize)>,
+    /// The total filled sell amount for each token.
+    removed_volume: HashMap<TokenId, f64>,
+}
+
+impl FillSummary {
+    /// Returns the number of distinct orders that were filled or partially
+    /// filled.
+    pub fn num_filled_orders(&self) -> usize {
+        self.filled_orders.len()
+    }
+
+    /// Returns the total amount of a token that was sold by filled orders and
+    /// thus removed from the orderbook.
+    pub fn removed_volume(&self, token: TokenId) -> f64 {
+        self.removed_volume.get(&token).copied().unwrap_or(0.0)
+    }
+
+    /// Returns an iterator over the total removed volume for each token that
+    /// was sold by filled orders.
+    pub fn removed_volumes(&self) -> impl Iterator<Item = (TokenId, f64)> + '_ {
+        self.removed_volume
+            .iter()
+            .map(|(&token, &volume)| (token, volume))
+    }
+
+    /// Records that an order was filled by the specified sell amount.
+    fn record_fill(&mut self, order: &Order, amount: f64) {
+        self.filled_orders.insert((order.user, order.index));
+        *self.removed_volume.entry(order.pair.sell).or_default() += amount;
+    }
+}
+
+/// Adds an edge to the projection graph for every token pair with orders,
+/// weighted by the pair's cheapest order.
+fn add_projection_edges(projection: &mut DiGraph<TokenId, f64>, orders: &OrderMap) {
+    projection.extend_with_edges(orders.all_pairs().map({
+        |(pair, orders)| {
+            let cheapest_order = orders
+                .last()
+                .expect("unexpected token pair in orders map without any orders");
+            (
+                node_index(pair.buy),
+                node_index(pair.sell),
+                cheapest_order.weight(),
+            )
+        }
+    }));
+}
+
 /// Create a node index from a token ID.
 fn node_index(token: TokenId) -> NodeIndex {
     NodeIndex::new(token.into())
@@ -421,7 +764,6 @@ pub struct IncompletePathError(pub TokenPair);
 mod tests {
     use super::*;
     use crate::data;
-    use crate::encoding::UserId;
     use assert_approx_eq::assert_approx_eq;
 
     /// Returns a `UserId` for a test user index.
@@ -563,6 +905,163 @@ mod tests {
         assert!(!orderbook.is_overlapping());
     }
 
+    #[test]
+    fn reports_removed_volume_when_reducing_overlapping_orders() {
+        let mut orderbook = orderbook! {
+            users {
+                @1 {
+                    token 0 => 10_000_000,
+                }
+                @2 {
+                    token 1 => 10_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 0 [1_000_000],
+                owner @2 buying 0 [1_000_000] selling 1 [2_000_000],
+            }
+        };
+        let total_volume = |orderbook: &Orderbook, token: TokenId| -> f64 {
+            orderbook
+                .orders
+                .all_pairs()
+                .filter(|(pair, _)| pair.sell == token)
+                .flat_map(|(_, orders)| orders.iter().map(|order| order.amount))
+                .sum()
+        };
+
+        let before = orderbook.clone();
+        let summary = orderbook.reduce_overlapping_orders();
+
+        assert!(!orderbook.is_overlapping());
+        assert_eq!(summary.num_filled_orders(), 2);
+        for token in 0..=1 {
+            assert!(summary.removed_volume(token) > 0.0);
+            assert_approx_eq!(
+                summary.removed_volume(token),
+                total_volume(&before, token) - total_volume(&orderbook, token)
+            );
+        }
+        assert_eq!(summary.removed_volumes().count(), 2);
+    }
+
+    #[test]
+    fn hypothetical_order_does_not_modify_original() {
+        //  /---1.0---v
+        // 0          1
+        let orderbook = orderbook! {
+            users {
+                @1 {
+                    token 1 => 1_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 0 [1_000_000] selling 1 [1_000_000],
+            }
+        };
+        let hypothetical_element = |pair: TokenPair, numerator: u128, denominator: u128| Element {
+            user: user_id(2),
+            balance: denominator.into(),
+            pair,
+            valid: crate::encoding::Validity {
+                from: 0,
+                to: u32::max_value(),
+            },
+            price: crate::encoding::Price {
+                numerator,
+                denominator,
+            },
+            remaining_sell_amount: denominator,
+        };
+
+        let pair = TokenPair { buy: 1, sell: 0 };
+        let volume = 1_500_000.0;
+        let with_order = orderbook.with_hypothetical_order(hypothetical_element(
+            TokenPair { buy: 0, sell: 1 },
+            1_000_000,
+            2_000_000,
+        ));
+        assert_eq!(orderbook.num_orders(), 1);
+        assert_eq!(with_order.num_orders(), 2);
+        assert!(orderbook.clone().fill_market_order(pair, volume).is_none());
+        assert!(with_order.clone().fill_market_order(pair, volume).is_some());
+        assert!(
+            with_order.clone().fill_market_order(pair, 0.0).unwrap()
+                > orderbook.clone().fill_market_order(pair, 0.0).unwrap()
+        );
+
+        let with_overlapping_order = orderbook.with_hypothetical_order(hypothetical_element(
+            TokenPair { buy: 1, sell: 0 },
+            500_000,
+            1_000_000,
+        ));
+        assert!(!orderbook.is_overlapping());
+        assert!(with_overlapping_order.is_overlapping());
+
+        let with_new_token = orderbook.with_hypothetical_order(hypothetical_element(
+            TokenPair { buy: 1, sell: 2 },
+            1_000_000,
+            1_000_000,
+        ));
+        assert!(with_new_token
+            .clone()
+            .fill_market_order(TokenPair { buy: 2, sell: 1 }, 0.0)
+            .is_some());
+    }
+
+    #[test]
+    fn reports_overlapping_pairs() {
+        //  /---0.5---v
+        // 0          1 --1.0--> 2
+        // ^---1.0---/
+        let orderbook = orderbook! {
+            users {
+                @1 {
+                    token 0 => 1_000_000,
+                }
+                @2 {
+                    token 1 => 2_000_000,
+                    token 2 => 1_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 0 [1_000_000],
+                owner @2 buying 0 [1_000_000] selling 1 [2_000_000],
+                owner @2 buying 1 [1_000_000] selling 2 [1_000_000],
+            }
+        };
+
+        assert!(orderbook.is_overlapping());
+        assert_eq!(
+            orderbook
+                .overlapping_pairs()
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            vec![TokenPair { buy: 0, sell: 1 }, TokenPair { buy: 1, sell: 0 }]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn reports_no_overlapping_pairs_for_non_overlapping_orderbook() {
+        let orderbook = orderbook! {
+            users {
+                @1 {
+                    token 1 => 1_000_000,
+                    token 2 => 100_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 2 [99_000_000],
+                owner @1 buying 2 [101_000_000] selling 1 [1_000_000],
+            }
+        };
+
+        assert!(!orderbook.is_overlapping());
+        assert!(orderbook.overlapping_pairs().is_empty());
+    }
+
     #[test]
     fn fills_market_order_with_correct_price() {
         //    /-101.0--v
@@ -654,6 +1153,173 @@ mod tests {
         assert_eq!(orderbook.num_orders(), 0);
     }
 
+    #[test]
+    fn fills_market_orders_against_consumed_orderbook() {
+        //    /-101.0--v
+        //   /--105.0--v
+        //  /---111.0--v
+        // 1           2
+        // ^--.0101---/
+        // ^--.0105--/
+        // ^--.0110-/
+        let orderbook = orderbook! {
+            users {
+                @1 {
+                    token 1 => 1_000_000,
+                    token 2 => 100_000_000,
+                }
+                @2 {
+                    token 1 => 1_000_000,
+                    token 2 => 100_000_000,
+                }
+                @3 {
+                    token 1 => 1_000_000,
+                    token 2 => 100_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 2 [99_000_000],
+                owner @2 buying 1 [1_000_000] selling 2 [95_000_000],
+                owner @3 buying 1 [1_000_000] selling 2 [90_000_000],
+
+                owner @2 buying 2 [101_000_000] selling 1 [1_000_000],
+                owner @1 buying 2 [105_000_000] selling 1 [1_000_000],
+                owner @3 buying 2 [110_000_000] selling 1 [1_000_000],
+            }
+        };
+
+        let pair = TokenPair { buy: 2, sell: 1 };
+        let volume = 750_000.0;
+        let alone = orderbook.clone().fill_market_order(pair, volume).unwrap();
+        assert_approx_eq!(alone, 99.0 / FEE_FACTOR.powi(2));
+
+        let prices = orderbook.clone().fill_market_orders(&[
+            (pair, volume),
+            (pair, volume),
+            (TokenPair { buy: 3, sell: 1 }, volume),
+        ]);
+        assert_approx_eq!(prices[0].unwrap(), alone);
+        assert_approx_eq!(prices[1].unwrap(), 95.0 / FEE_FACTOR.powi(2));
+        assert!(prices[1].unwrap() < alone);
+        assert_eq!(prices[2], None);
+    }
+
+    #[test]
+    fn restoring_snapshot_undoes_fills() {
+        let mut orderbook = orderbook! {
+            users {
+                @1 {
+                    token 1 => 1_000_000,
+                    token 2 => 100_000_000,
+                }
+                @2 {
+                    token 2 => 100_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 2 [99_000_000],
+                owner @2 buying 1 [1_000_000] selling 2 [95_000_000],
+                owner @1 buying 2 [101_000_000] selling 1 [1_000_000],
+            }
+        };
+
+        let pair = TokenPair { buy: 2, sell: 1 };
+        let volume = 1_500_000.0;
+        let snapshot = orderbook.snapshot();
+        let price = orderbook.fill_market_order(pair, volume).unwrap();
+        assert_eq!(orderbook.num_orders(), 1);
+
+        orderbook.restore(&snapshot);
+        assert_eq!(orderbook.num_orders(), 3);
+        assert_approx_eq!(orderbook.fill_market_order(pair, volume).unwrap(), price);
+
+        orderbook.restore(&snapshot);
+        assert_approx_eq!(
+            orderbook.get_projected_pair_weight(TokenPair { buy: 1, sell: 2 }),
+            (FEE_FACTOR / 99.0).log2()
+        );
+        assert_approx_eq!(orderbook.fill_market_order(pair, volume).unwrap(), price);
+
+        orderbook.restore(&snapshot);
+        assert!(orderbook.journal.is_empty());
+        orderbook.release(snapshot);
+        orderbook.fill_market_order(pair, volume).unwrap();
+        assert!(orderbook.journal.is_empty());
+    }
+
+    #[test]
+    fn restoring_outer_snapshot_undoes_fills_of_released_nested_snapshot() {
+        let mut orderbook = orderbook! {
+            users {
+                @1 {
+                    token 2 => 100_000_000,
+                }
+                @2 {
+                    token 2 => 100_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 2 [99_000_000],
+                owner @2 buying 1 [1_000_000] selling 2 [95_000_000],
+            }
+        };
+
+        let pair = TokenPair { buy: 2, sell: 1 };
+        let volume = 750_000.0;
+        let outer = orderbook.snapshot();
+        let price = orderbook.fill_market_order(pair, volume).unwrap();
+        let inner = orderbook.snapshot();
+        orderbook.fill_market_order(pair, volume).unwrap();
+        orderbook.release(inner);
+        assert!(!orderbook.journal.is_empty());
+
+        orderbook.restore(&outer);
+        assert_eq!(orderbook.num_orders(), 2);
+        assert_approx_eq!(orderbook.fill_market_order(pair, volume).unwrap(), price);
+
+        orderbook.release(outer);
+        assert!(orderbook.journal.is_empty());
+        orderbook.fill_market_order(pair, volume).unwrap();
+        assert!(orderbook.journal.is_empty());
+    }
+
+    #[test]
+    fn hypothetical_order_starts_without_journal() {
+        let mut orderbook = orderbook! {
+            users {
+                @1 {
+                    token 1 => 1_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 2 [1_000_000] selling 1 [1_000_000],
+            }
+        };
+
+        let _snapshot = orderbook.snapshot();
+        orderbook
+            .fill_market_order(TokenPair { buy: 1, sell: 2 }, 1000.0)
+            .unwrap();
+        assert!(!orderbook.journal.is_empty());
+
+        let hypothetical = orderbook.with_hypothetical_order(Element {
+            user: user_id(2),
+            balance: 1_000_000.into(),
+            pair: TokenPair { buy: 1, sell: 2 },
+            valid: crate::encoding::Validity {
+                from: 0,
+                to: u32::max_value(),
+            },
+            price: crate::encoding::Price {
+                numerator: 1_000_000,
+                denominator: 1_000_000,
+            },
+            remaining_sell_amount: 1_000_000,
+        });
+        assert!(hypothetical.journal.is_empty());
+        assert_eq!(hypothetical.snapshots, 0);
+    }
+
     #[test]
     fn removes_drained_and_balanceless_orders() {
         let mut orderbook = orderbook! {
@@ -738,7 +1404,9 @@ mod tests {
         );
         assert_approx_eq!(transient_price, 0.5 * FEE_FACTOR.powi(4));
 
-        let filled = orderbook.fill_path(&path).unwrap();
+        let filled = orderbook
+            .fill_path(&path, &mut FillSummary::default())
+            .unwrap();
         assert_eq!(filled, (capacity, transient_price));
 
         assert_approx_eq!(
@@ -802,4 +1470,79 @@ mod tests {
             10_000_000.0 - capacity / transient_price
         );
     }
+
+    #[test]
+    fn fills_market_order_with_decimals_like_atoms() {
+        let dai_weth = TokenPair { buy: 7, sell: 1 };
+        let orderbook = Orderbook::read(*data::DEFAULT_ORDERBOOK).unwrap();
+        assert_eq!(
+            orderbook
+                .clone()
+                .fill_market_order_with_decimals(dai_weth, 10.0, TokenDecimals(18)),
+            orderbook.clone().fill_market_order(dai_weth, 10.0 * 1e18),
+        );
+
+        let orderbook = orderbook! {
+            users {
+                @1 {
+                    token 2 => 100_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 1 [1_000_000] selling 2 [99_000_000],
+            }
+        };
+        let pair = TokenPair { buy: 2, sell: 1 };
+        let price = orderbook
+            .clone()
+            .fill_market_order_with_decimals(pair, 0.5, TokenDecimals(6))
+            .unwrap();
+        assert_approx_eq!(
+            price,
+            orderbook
+                .clone()
+                .fill_market_order(pair, 500_000.0)
+                .unwrap()
+        );
+        assert_approx_eq!(price, 99.0 / FEE_FACTOR.powi(2));
+    }
+
+    #[test]
+    fn clearing_prices_respect_matched_order_limits() {
+        // 0 --0.5--> 1
+        // ^--1.0---/
+        //
+        // 2 --1.0--> 3
+        let orderbook = orderbook! {
+            users {
+                @1 {
+                    token 1 => 2_000_000,
+                }
+                @2 {
+                    token 0 => 1_000_000,
+                }
+                @3 {
+                    token 3 => 1_000_000,
+                }
+            }
+            orders {
+                owner @1 buying 0 [1_000_000] selling 1 [2_000_000],
+                owner @2 buying 1 [1_000_000] selling 0 [1_000_000],
+                owner @3 buying 2 [1_000_000] selling 3 [1_000_000],
+            }
+        };
+
+        let prices = orderbook.clearing_prices();
+
+        // NOTE: The order selling token 0 is completely filled by the order
+        // selling token 1, which remains partially filled and therefore sets
+        // the clearing price.
+        assert_eq!(prices.len(), 2);
+        assert_approx_eq!(prices[&0], 1.0);
+        assert_approx_eq!(prices[&1], 0.5 * FEE_FACTOR);
+
+        for &(sell, buy, limit) in &[(1, 0, 0.5), (0, 1, 1.0)] {
+            assert!(prices[&sell] / prices[&buy] >= limit * FEE_FACTOR - 1e-9);
+        }
+    }
 }
@@ -53,6 +53,22 @@ impl OrderMap {
         })
     }
 
+    /// Inserts an order into the order map, keeping the orders for its token
+    /// pair sorted by descending price.
+    pub fn insert_order(&mut self, order: Order) {
+        let pair_orders = self
+            .0
+            .entry(order.pair.sell)
+            .or_default()
+            .entry(order.pair.buy)
+            .or_default();
+        let index = pair_orders
+            .iter()
+            .position(|other| Order::cmp_descending_prices(other, &order) == cmp::Ordering::Greater)
+            .unwrap_or(pair_orders.len());
+        pair_orders.insert(index, order);
+    }
+
     /// Returns an iterator over the collection of orders for each token pair.
     fn all_pairs_mut(&mut self) -> impl Iterator<Item = (TokenPair, &'_ mut Vec<Order>)> + '_ {
         self.0.iter_mut().flat_map(|(&sell, o)| {
@@ -94,6 +110,17 @@ impl OrderMap {
         self.orders_for_pair_mut(pair)?.last_mut()
     }
 
+    /// Adds an order as the cheapest order of its token pair, undoing its
+    /// removal with `remove_pair_order`.
+    pub fn push_pair_order(&mut self, order: Order) {
+        self.0
+            .entry(order.pair.sell)
+            .or_default()
+            .entry(order.pair.buy)
+            .or_default()
+            .push(order);
+    }
+
     /// Removes the current cheapest order pair from the mapping.
     pub fn remove_pair_order(&mut self, pair: TokenPair) -> Option<Order> {
         let sell_orders = self.0.get_mut(&pair.sell)?;
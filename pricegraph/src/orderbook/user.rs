@@ -35,6 +35,16 @@ impl User {
         self.balances.get(&token).copied().unwrap_or(0.0)
     }
 
+    /// Sets the balance for the given token, removing it if the user no longer
+    /// has any balance.
+    pub fn set_balance(&mut self, token: TokenId, balance: f64) {
+        if balance > 0.0 {
+            self.balances.insert(token, balance);
+        } else {
+            self.balances.remove(&token);
+        }
+    }
+
     /// Deducts an amount from the balance for the given token. Returns the new
     /// balance or `None` if the user no longer has any balance.
     pub fn deduct_from_balance(&mut self, token: TokenId, amount: f64) -> Option<f64> {